@@ -0,0 +1,124 @@
+//! ADTS framing of AAC, ISO/IEC 13818-7 and 14496-3, as produced by most
+//! encoders for standalone streams.
+
+/// Sampling frequencies by `sampling_frequency_index`.
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Fixed header fields shared by every frame of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AdtsConfig {
+    /// MPEG-4 audio object type, the ADTS profile plus one.
+    pub object_type: u8,
+    pub sample_rate_index: u8,
+    pub channel_config: u8,
+}
+
+/// One ADTS frame, split into its configuration and the raw access unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AdtsFrame<'a> {
+    pub config: AdtsConfig,
+    pub payload: &'a [u8],
+}
+
+impl AdtsConfig {
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.sample_rate_index as usize]
+    }
+
+    /// The 2-byte `AudioSpecificConfig` of the decoder configuration.
+    pub fn audio_specific_config(&self) -> [u8; 2] {
+        let config =
+            (self.object_type as u16) << 11 | (self.sample_rate_index as u16) << 7 | (self.channel_config as u16) << 3;
+        config.to_be_bytes()
+    }
+}
+
+/// Splits `data` into its ADTS frames. Returns `None` unless `data` is made of
+/// complete frames only, each holding a single raw data block with a sampling
+/// frequency and channel configuration that can be signaled in MP4.
+///
+/// Frames with `protection_absent` unset carry a CRC after the 7-byte header,
+/// which is dropped like the header.
+pub(crate) fn split_frames(data: &[u8]) -> Option<Vec<AdtsFrame<'_>>> {
+    let mut frames = Vec::new();
+    let mut data = data;
+    while !data.is_empty() {
+        let header = data.get(..7)?;
+        // syncword, then MPEG version and layer, which is always 0
+        if header[0] != 0xff || header[1] & 0xf6 != 0xf0 {
+            return None;
+        }
+        let protection_absent = header[1] & 1 != 0;
+        let config = AdtsConfig {
+            object_type: (header[2] >> 6) + 1,
+            sample_rate_index: (header[2] >> 2) & 0xf,
+            channel_config: (header[2] & 1) << 2 | header[3] >> 6,
+        };
+        let frame_length = ((header[3] as usize & 3) << 11) | (header[4] as usize) << 3 | (header[5] as usize) >> 5;
+        let raw_data_blocks = (header[6] & 3) + 1;
+        let header_length = if protection_absent { 7 } else { 9 };
+        // a channel configuration of zero is only defined by an in-band PCE
+        if config.sample_rate_index as usize >= SAMPLE_RATES.len()
+            || config.channel_config == 0
+            || raw_data_blocks != 1
+            || frame_length <= header_length
+        {
+            return None;
+        }
+        let frame = data.get(..frame_length)?;
+        frames.push(AdtsFrame {
+            config,
+            payload: &frame[header_length..],
+        });
+        data = &data[frame_length..];
+    }
+    Some(frames)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds an ADTS frame of AAC LC around `payload`, with a dummy CRC when
+    /// `crc` is set.
+    pub(crate) fn adts_frame(sample_rate_index: u8, channel_config: u8, crc: bool, payload: &[u8]) -> Vec<u8> {
+        let header_length = if crc { 9 } else { 7 };
+        let frame_length = header_length + payload.len();
+        let mut frame = vec![
+            0xff,
+            0xf0 | !crc as u8,
+            1 << 6 | sample_rate_index << 2 | channel_config >> 2,
+            (channel_config & 3) << 6 | (frame_length >> 11) as u8,
+            (frame_length >> 3) as u8,
+            (frame_length << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        if crc {
+            frame.extend_from_slice(&[0x12, 0x34]);
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_split_frames() {
+        let mut data = adts_frame(4, 2, false, &[1, 2, 3]);
+        data.extend(adts_frame(4, 2, true, &[4, 5]));
+        let frames = split_frames(&data).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload, [1, 2, 3]);
+        assert_eq!(frames[1].payload, [4, 5]);
+        let config = frames[0].config;
+        assert_eq!(config, frames[1].config);
+        assert_eq!(config.sample_rate(), 44100);
+        assert_eq!(config.audio_specific_config(), [0x12, 0x10]);
+
+        assert!(split_frames(&data[..data.len() - 1]).is_none());
+        assert!(split_frames(&adts_frame(4, 0, false, &[1])).is_none());
+        assert!(split_frames(&adts_frame(13, 2, false, &[1])).is_none());
+        assert!(split_frames(&[0; 16]).is_none());
+        assert_eq!(split_frames(&[]), Some(vec![]));
+    }
+}
@@ -0,0 +1,41 @@
+//! The QuickTime `tapt` track aperture mode dimensions box, which Final Cut
+//! and other QuickTime tools read for the display geometry of a video track.
+
+use crate::boxes::BoxWriter;
+
+/// Builds a `tapt` box holding the clean aperture (`clef`), production
+/// aperture (`prof`) and encoded pixels (`enof`) dimensions, as widths and
+/// heights in pixels.
+pub(crate) fn tapt_box(clean: (f64, f64), production: (f64, f64), encoded: (f64, f64)) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"tapt");
+    for (fourcc, (width, height)) in [(b"clef", clean), (b"prof", production), (b"enof", encoded)] {
+        w.begin_full(fourcc, 0, 0)
+            .u32(fixed_16_16(width))
+            .u32(fixed_16_16(height))
+            .end();
+    }
+    w.end().finish()
+}
+
+fn fixed_16_16(value: f64) -> u32 {
+    (value * 65536.0).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boxes::{find_path, is_box, read_u32};
+
+    #[test]
+    fn test_tapt_box() {
+        let tapt = tapt_box((1272.5, 720.0), (1272.5, 720.0), (1280.0, 720.0));
+        assert!(is_box(&tapt, b"tapt"));
+        assert_eq!(tapt.len(), 8 + 3 * 20);
+        let clef = find_path(&tapt, &[b"tapt", b"clef"]).unwrap();
+        assert_eq!(read_u32(clef[1].payload(&tapt), 4), 1272 << 16 | 0x8000);
+        let enof = find_path(&tapt, &[b"tapt", b"enof"]).unwrap();
+        assert_eq!(read_u32(enof[1].payload(&tapt), 4), 1280 << 16);
+        assert_eq!(read_u32(enof[1].payload(&tapt), 8), 720 << 16);
+    }
+}
@@ -0,0 +1,270 @@
+//! Helpers to build and patch serialized ISO BMFF boxes.
+//!
+//! minimp4 serializes the whole `moov` box in one buffer when the muxer is
+//! closed. The muxer hands that buffer to these helpers to splice in the boxes
+//! minimp4 itself does not know about before it reaches the writer.
+
+use std::ops::Range;
+
+/// Size of a compact box header: 32-bit size followed by the box type.
+const HEADER_SIZE: usize = 8;
+
+/// Serializes nested boxes, mirroring the `ATOM`/`END_ATOM` macros of minimp4.
+#[derive(Default)]
+pub(crate) struct BoxWriter {
+    buf: Vec<u8>,
+    stack: Vec<usize>,
+}
+
+impl BoxWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&mut self, fourcc: &[u8; 4]) -> &mut Self {
+        self.stack.push(self.buf.len());
+        self.buf.extend_from_slice(&[0; 4]);
+        self.buf.extend_from_slice(fourcc);
+        self
+    }
+
+    pub fn begin_full(&mut self, fourcc: &[u8; 4], version: u8, flags: u32) -> &mut Self {
+        self.begin(fourcc).u32((version as u32) << 24 | (flags & 0xff_ffff))
+    }
+
+    pub fn end(&mut self) -> &mut Self {
+        let start = self.stack.pop().expect("unbalanced box");
+        let size = (self.buf.len() - start) as u32;
+        self.buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+        self
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    /// Number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        assert!(self.stack.is_empty(), "unbalanced box");
+        std::mem::take(&mut self.buf)
+    }
+}
+
+/// Location of a box inside a serialized buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BoxRange {
+    pub fourcc: [u8; 4],
+    /// Offset of the box header.
+    pub start: usize,
+    /// Offset of the first payload byte, right after the header.
+    pub payload: usize,
+    /// Offset right after the last payload byte.
+    pub end: usize,
+}
+
+impl BoxRange {
+    /// Range of the nested boxes, skipping the fields some boxes carry before
+    /// their children.
+    pub fn children(&self) -> Range<usize> {
+        let skip = match &self.fourcc {
+            b"meta" => 4,
+            b"stsd" | b"dref" | b"urim" => 8,
+            b"mp4a" => 28,
+            b"avc1" | b"avc3" | b"hvc1" | b"hev1" => 78,
+            _ => 0,
+        };
+        (self.payload + skip).min(self.end)..self.end
+    }
+
+    #[cfg(test)]
+    pub fn payload<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.payload..self.end]
+    }
+}
+
+/// Iterates over the boxes laid out back to back in `data[range]`.
+pub(crate) struct BoxIter<'a> {
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> BoxIter<'a> {
+    pub fn new(data: &'a [u8], range: Range<usize>) -> Self {
+        Self {
+            data,
+            pos: range.start,
+            end: range.end.min(data.len()),
+        }
+    }
+}
+
+impl Iterator for BoxIter<'_> {
+    type Item = BoxRange;
+
+    fn next(&mut self) -> Option<BoxRange> {
+        if self.end - self.pos.min(self.end) < HEADER_SIZE {
+            return None;
+        }
+        let start = self.pos;
+        let size = read_u32(self.data, start) as usize;
+        let fourcc = self.data[start + 4..start + 8].try_into().unwrap();
+        let (payload, size) = match size {
+            0 => (start + HEADER_SIZE, self.end - start),
+            1 if self.end - start >= HEADER_SIZE + 8 => {
+                (start + HEADER_SIZE + 8, read_u64(self.data, start + 8) as usize)
+            }
+            size => (start + HEADER_SIZE, size),
+        };
+        if size < payload - start || size > self.end - start {
+            self.pos = self.end;
+            return None;
+        }
+        self.pos = start + size;
+        Some(BoxRange {
+            fourcc,
+            start,
+            payload,
+            end: start + size,
+        })
+    }
+}
+
+/// Returns `true` when `data` holds exactly one complete box of the given type.
+pub(crate) fn is_box(data: &[u8], fourcc: &[u8; 4]) -> bool {
+    data.len() >= HEADER_SIZE && &data[4..8] == fourcc && read_u32(data, 0) as usize == data.len()
+}
+
+/// Iterates over the children of `parent`.
+#[cfg(test)]
+pub(crate) fn children<'a>(data: &'a [u8], parent: &BoxRange) -> BoxIter<'a> {
+    BoxIter::new(data, parent.children())
+}
+
+/// Looks up the first box matching `path`, starting at the top level of
+/// `data`. Returns the chain of boxes leading to it, outermost first.
+pub(crate) fn find_path(data: &[u8], path: &[&[u8; 4]]) -> Option<Vec<BoxRange>> {
+    find_child_path(data, &[], path)
+}
+
+/// Like [`find_path`], but starts below the last box of `parent`.
+pub(crate) fn find_child_path(data: &[u8], parent: &[BoxRange], path: &[&[u8; 4]]) -> Option<Vec<BoxRange>> {
+    let mut chain = parent.to_vec();
+    for fourcc in path {
+        let range = chain.last().map_or(0..data.len(), BoxRange::children);
+        let found = BoxIter::new(data, range).find(|b| &b.fourcc == *fourcc)?;
+        chain.push(found);
+    }
+    Some(chain)
+}
+
+/// Replaces `data[range]` with `bytes`, fixing up the sizes of every box in
+/// `ancestors` (which must all enclose `range`). Ranges looked up before the
+/// call are stale afterwards.
+pub(crate) fn splice(data: &mut Vec<u8>, ancestors: &[BoxRange], range: Range<usize>, bytes: &[u8]) {
+    let delta = bytes.len() as i64 - range.len() as i64;
+    data.splice(range, bytes.iter().copied());
+    for ancestor in ancestors {
+        if ancestor.payload - ancestor.start > HEADER_SIZE {
+            let size = read_u64(data, ancestor.start + 8) as i64 + delta;
+            data[ancestor.start + 8..ancestor.start + 16].copy_from_slice(&(size as u64).to_be_bytes());
+        } else {
+            let size = read_u32(data, ancestor.start) as i64 + delta;
+            data[ancestor.start..ancestor.start + 4].copy_from_slice(&(size as u32).to_be_bytes());
+        }
+    }
+}
+
+/// Appends `bytes` as the last child of the innermost box of `chain`.
+pub(crate) fn append_child(data: &mut Vec<u8>, chain: &[BoxRange], bytes: &[u8]) {
+    let end = chain.last().expect("empty box chain").end;
+    splice(data, chain, end..end, bytes);
+}
+
+/// Appends `bytes` to the `container` child of the innermost box of `parent`,
+/// creating that container as the last child of `parent` when missing.
+pub(crate) fn append_to_container(data: &mut Vec<u8>, parent: &[BoxRange], container: &[u8; 4], bytes: &[u8]) {
+    match find_child_path(data, parent, &[container]) {
+        Some(chain) => append_child(data, &chain, bytes),
+        None => {
+            let wrapped = BoxWriter::new().begin(container).bytes(bytes).end().finish();
+            append_child(data, parent, &wrapped);
+        }
+    }
+}
+
+/// A `free` box of `size` bytes, header included, which must be 8 or more.
+pub(crate) fn free_box(size: usize) -> Vec<u8> {
+    BoxWriter::new()
+        .begin(b"free")
+        .bytes(&vec![0; size - HEADER_SIZE])
+        .end()
+        .finish()
+}
+
+pub(crate) fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64(data: &[u8], pos: usize) -> u64 {
+    u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_patch() {
+        let mut moov = BoxWriter::new()
+            .begin(b"moov")
+            .begin_full(b"mvhd", 0, 0)
+            .u32(42)
+            .end()
+            .begin(b"udta")
+            .end()
+            .end()
+            .finish();
+        assert!(is_box(&moov, b"moov"));
+        assert_eq!(moov.len(), 8 + 16 + 8);
+
+        let udta = find_path(&moov, &[b"moov", b"udta"]).unwrap();
+        let child = BoxWriter::new().begin(b"free").u8(0).end().finish();
+        append_child(&mut moov, &udta, &child);
+        assert!(is_box(&moov, b"moov"));
+
+        let free = find_path(&moov, &[b"moov", b"udta", b"free"]).unwrap();
+        assert_eq!(free.last().unwrap().payload(&moov), &[0]);
+        let mvhd = find_path(&moov, &[b"moov", b"mvhd"]).unwrap();
+        assert_eq!(read_u32(mvhd[1].payload(&moov), 4), 42);
+        assert!(find_path(&moov, &[b"moov", b"trak"]).is_none());
+
+        let root = find_path(&moov, &[b"moov"]).unwrap();
+        append_to_container(&mut moov, &root, b"udta", &child);
+        let root = find_path(&moov, &[b"moov"]).unwrap();
+        append_to_container(&mut moov, &root, b"edts", &child);
+        assert!(is_box(&moov, b"moov"));
+        let udta = find_path(&moov, &[b"moov", b"udta"]).unwrap();
+        assert_eq!(udta[1].end - udta[1].payload, 2 * child.len());
+        let edts = find_path(&moov, &[b"moov", b"edts", b"free"]).unwrap();
+        assert_eq!(edts[2].payload(&moov), &[0]);
+    }
+}
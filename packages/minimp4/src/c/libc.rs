@@ -60,7 +60,7 @@ unsafe extern "C" fn minimp4_memset(s: *mut c_void, c: c_int, n: size_t) -> *mut
 unsafe extern "C" fn __assert_fail(s: *mut c_void, c: c_int, n: size_t) -> *mut c_void {
     fprintf(
         libc::STDERR_FILENO as *mut libc::FILE,
-        b"assertion \"%s\" failed: file \"%s\", line %d\n\0".as_ptr() as *const c_char,
+        c"assertion \"%s\" failed: file \"%s\", line %d\n".as_ptr(),
         s,
         c,
         n,
@@ -573,7 +573,7 @@ unsafe extern "C" fn minimp4_vector_alloc_tail(mut h: *mut minimp4_vector_t, mut
     (*h).bytes += bytes;
     p
 }
-unsafe extern "C" fn minimp4_vector_put(
+pub unsafe extern "C" fn minimp4_vector_put(
     mut h: *mut minimp4_vector_t,
     mut buf: *const c_void,
     mut bytes: c_int,
@@ -872,7 +872,7 @@ unsafe extern "C" fn get_duration(mut tr: *const track_t) -> c_uint {
     }
     sum_duration
 }
-unsafe extern "C" fn write_pending_data(mut mux: *mut MP4E_mux_t, mut tr: *mut track_t) -> c_int {
+pub unsafe extern "C" fn write_pending_data(mut mux: *mut MP4E_mux_t, mut tr: *mut track_t) -> c_int {
     if (*tr).pending_sample.bytes > 0 as c_int
         && (*tr).smpl.bytes as c_ulong >= ::core::mem::size_of::<sample_t>() as c_ulong
     {
@@ -1605,7 +1605,7 @@ pub unsafe extern "C" fn MP4E_set_text_comment(mut mux: *mut MP4E_mux_t, mut com
     }
     0 as c_int
 }
-unsafe extern "C" fn mp4e_flush_index(mut mux: *mut MP4E_mux_t) -> c_int {
+pub unsafe extern "C" fn mp4e_flush_index(mut mux: *mut MP4E_mux_t) -> c_int {
     let mut stack_base: [*mut c_uchar; 20] = [std::ptr::null_mut::<c_uchar>(); 20];
     let mut stack: *mut *mut c_uchar = stack_base.as_mut_ptr();
     let mut base: *mut c_uchar = std::ptr::null_mut::<c_uchar>();
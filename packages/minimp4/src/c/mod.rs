@@ -6,6 +6,18 @@ mod wasm;
 #[macro_use]
 mod libc;
 
+// lints c2rust output trips on newer toolchains, kept here as the file is
+// regenerated by the transpiler
+#[allow(
+    static_mut_refs,
+    clippy::collapsible_match,
+    clippy::eq_op,
+    clippy::if_same_then_else,
+    clippy::missing_safety_doc,
+    clippy::single_match,
+    clippy::unnecessary_mut_passed,
+    clippy::wildcard_in_or_patterns
+)]
 mod minimp4_c;
 
 pub use self::minimp4_c::*;
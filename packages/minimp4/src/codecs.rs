@@ -0,0 +1,100 @@
+//! RFC 6381 codec strings, the `CODECS` attribute of HLS playlists and the
+//! `codecs` of DASH manifests, following ISO/IEC 14496-15 Annex E for H.264
+//! and H.265 and RFC 6381 3.3 for MPEG-4 audio.
+
+use crate::sps;
+
+/// MPEG-4 audio, whose codec string carries the audio object type.
+const MPEG4_AUDIO: u32 = 0x40;
+
+/// `avc1.PPCCLL` of `sps`, an H.264 SPS without start code: profile,
+/// constraint flags and level in hex, `avc3` with in-band parameter sets.
+pub(crate) fn avc(sps: &[u8], in_band: bool) -> Option<String> {
+    let [profile, constraints, level] = sps.get(1..4)?.try_into().ok()?;
+    let entry = if in_band { "avc3" } else { "avc1" };
+    Some(format!("{entry}.{profile:02x}{constraints:02x}{level:02x}"))
+}
+
+/// `hvc1.P.C.TL.B` of `sps`, an H.265 SPS without start code: profile space
+/// and profile, compatibility flags in reverse bit order, tier and level,
+/// then the constraint bytes up to the last non-zero one, `hev1` with
+/// in-band parameter sets.
+pub(crate) fn hevc(sps: &[u8], in_band: bool) -> Option<String> {
+    let profile = sps::h265_general_profile(sps)?;
+    let entry = if in_band { "hev1" } else { "hvc1" };
+    let space = ["", "A", "B", "C"][(profile[0] >> 6) as usize];
+    let tier = if profile[0] & 0x20 != 0 { 'H' } else { 'L' };
+    let compatibility = u32::from_be_bytes(profile[1..5].try_into().unwrap()).reverse_bits();
+    let mut codec = format!(
+        "{entry}.{space}{}.{compatibility:x}.{tier}{}",
+        profile[0] & 0x1f,
+        profile[11]
+    );
+    let constraints = &profile[5..11];
+    let used = constraints
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |last| last + 1);
+    for byte in &constraints[..used] {
+        codec.push_str(&format!(".{byte:x}"));
+    }
+    Some(codec)
+}
+
+/// `mp4a.OO` of an audio track of `object_type_indication`, in hex, followed
+/// for MPEG-4 audio by the audio object type of `audio_specific_config`.
+pub(crate) fn mp4a(object_type_indication: u32, audio_specific_config: &[u8]) -> String {
+    let mut codec = format!("mp4a.{object_type_indication:x}");
+    if object_type_indication == MPEG4_AUDIO {
+        let object_type = match audio_specific_config {
+            // escape to the 6 bits that follow
+            [first, second, ..] if first >> 3 == 31 => Some(32 + ((first & 7) << 3 | second >> 5)),
+            [first, ..] if first >> 3 != 31 => Some(first >> 3),
+            _ => None,
+        };
+        if let Some(object_type) = object_type {
+            codec.push_str(&format!(".{object_type}"));
+        }
+    }
+    codec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avc() {
+        let sps = [0x67, 0x64, 0x00, 0x28, 0xac];
+        assert_eq!(avc(&sps, false).as_deref(), Some("avc1.640028"));
+        assert_eq!(avc(&sps, true).as_deref(), Some("avc3.640028"));
+        assert_eq!(avc(&sps[..3], false), None);
+    }
+
+    #[test]
+    fn test_hevc() {
+        // Main profile, level 4.1, progressive source and frame only
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x7b,
+        ];
+        assert_eq!(hevc(&sps, false).as_deref(), Some("hvc1.1.6.L123.90"));
+        assert_eq!(hevc(&sps, true).as_deref(), Some("hev1.1.6.L123.90"));
+        // Main 10, high tier, profile space 1
+        let sps = [
+            0x42, 0x01, 0x01, 0x62, 0x20, 0x00, 0x00, 0x03, 0x00, 0xb0, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x99,
+        ];
+        assert_eq!(hevc(&sps, false).as_deref(), Some("hvc1.A2.4.H153.b0"));
+        assert_eq!(hevc(&sps[..8], false), None);
+    }
+
+    #[test]
+    fn test_mp4a() {
+        // AAC LC and HE-AAC v2 at 44.1 kHz stereo
+        assert_eq!(mp4a(0x40, &[0x12, 0x10]), "mp4a.40.2");
+        assert_eq!(mp4a(0x40, &[0xeb, 0x8a, 0x08]), "mp4a.40.29");
+        // escaped object type 42, USAC
+        assert_eq!(mp4a(0x40, &[0xf9, 0x46, 0x40]), "mp4a.40.42");
+        assert_eq!(mp4a(0x40, &[]), "mp4a.40");
+        assert_eq!(mp4a(0x6b, &[]), "mp4a.6b");
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    mem::zeroed,
+    slice::from_raw_parts_mut,
+};
+
+use crate::{
+    boxes::{find_child_path, find_path, read_u32, BoxIter, BoxRange},
+    c::{c_void, MP4D_close, MP4D_demux_t, MP4D_frame_offset, MP4D_open, MP4D_track_t},
+    edit::{read_elst, EditListEntry},
+    Minimp4Error, Minimp4Result, Mp4Metadata,
+};
+
+/// Reads the sample tables of an MP4 file with the minimp4 demuxer.
+///
+/// The whole index is parsed when the demuxer is created, the accessors then
+/// only look up the parsed tables without touching the input again.
+pub struct Mp4Demuxer<R> {
+    /// Boxed so the token handed to minimp4 stays valid when the demuxer moves.
+    reader: Box<R>,
+    demux: Box<MP4D_demux_t>,
+    metadata: Mp4Metadata,
+    next_track_id: u32,
+    movie_timescale: u32,
+    /// Every `trak`, in file order as minimp4 lists them.
+    traks: Vec<Trak>,
+}
+
+/// A sample of a track, see [`Mp4Demuxer::sample`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Sample {
+    pub offset: u64,
+    pub size: u32,
+    /// Decode time and duration, in track timescale units.
+    pub timestamp: u32,
+    pub duration: u32,
+    pub sync: bool,
+}
+
+/// What the demuxer reads of a `trak` beyond the tables minimp4 parses.
+#[derive(Debug, Clone, Default)]
+struct Trak {
+    edit_list: Vec<EditListEntry>,
+    /// The first entry of its `stsd`, header included.
+    sample_entry: Option<Vec<u8>>,
+    /// Numbers of the samples listed by its `stss`, counted from 1, or
+    /// `None` without one, all samples being sync samples then.
+    sync_samples: Option<Vec<u32>>,
+    /// Whether its `ctts` gives any sample a composition offset.
+    composition_offsets: bool,
+}
+
+impl<R: Read + Seek> Mp4Demuxer<R> {
+    /// Parses the index of the file read from `reader`. Fails with
+    /// [`Minimp4Error::InvalidFile`] when it cannot be read or holds no track.
+    pub fn new(reader: R) -> Minimp4Result<Self> {
+        let mut reader = Box::new(reader);
+        let file_size = reader.seek(SeekFrom::End(0)).map_err(|_| Minimp4Error::InvalidFile)?;
+        // minimp4 trusts the box sizes it reads, check them first
+        let moov = read_moov(&mut *reader, file_size)?;
+        let mut demux: Box<MP4D_demux_t> = Box::new(unsafe { zeroed() });
+        let token = &mut *reader as *mut R as *mut c_void;
+        if unsafe { MP4D_open(&mut *demux, Some(Self::read), token, file_size as i64) } == 0 {
+            return Err(Minimp4Error::InvalidFile);
+        }
+        let metadata = moov.as_deref().map(Mp4Metadata::read).unwrap_or_default();
+        let next_track_id = moov.as_deref().and_then(next_track_id).unwrap_or(0);
+        let movie_timescale = moov.as_deref().and_then(movie_timescale).unwrap_or(0);
+        let traks = moov.as_deref().map(traks).unwrap_or_default();
+        Ok(Self {
+            reader,
+            demux,
+            metadata,
+            next_track_id,
+            movie_timescale,
+            traks,
+        })
+    }
+
+    /// Movie metadata, which minimp4 does not parse, read from the `moov` box
+    /// along with the index.
+    pub fn metadata(&self) -> &Mp4Metadata {
+        &self.metadata
+    }
+
+    /// `next_track_ID` of the `mvhd`, the ID a track added to the file by an
+    /// editor would get, 0 without a readable `mvhd`.
+    pub fn next_track_id(&self) -> u32 {
+        self.next_track_id
+    }
+
+    /// Timescale of the `mvhd`, the unit of the segment durations of the
+    /// edit lists, 0 without a readable `mvhd`.
+    pub fn movie_timescale(&self) -> u32 {
+        self.movie_timescale
+    }
+
+    /// Media timescale of `track`, the unit of the media times of its edit
+    /// list. Fails with [`Minimp4Error::BadArguments`] if there is no such
+    /// track.
+    pub fn track_timescale(&self, track: u32) -> Minimp4Result<u32> {
+        Ok(self.track(track)?.timescale)
+    }
+
+    /// Entries of the `elst` of `track`, empty for a track without edit
+    /// list, or whose `elst` cannot be read. Fails with
+    /// [`Minimp4Error::BadArguments`] if there is no such track.
+    pub fn edit_list(&self, track: u32) -> Minimp4Result<&[EditListEntry]> {
+        self.track(track)?;
+        Ok(self
+            .traks
+            .get(track as usize)
+            .map_or(&[], |trak| trak.edit_list.as_slice()))
+    }
+
+    /// The first sample entry of `track`, such as its `avc1` or `mp4a` box.
+    pub(crate) fn sample_entry(&self, track: u32) -> Option<&[u8]> {
+        self.traks.get(track as usize)?.sample_entry.as_deref()
+    }
+
+    /// Whether sample `sample` of `track`, counted from 0, is a sync sample.
+    pub(crate) fn is_sync_sample(&self, track: u32, sample: u32) -> bool {
+        match self
+            .traks
+            .get(track as usize)
+            .and_then(|trak| trak.sync_samples.as_ref())
+        {
+            Some(sync_samples) => sync_samples.binary_search(&(sample + 1)).is_ok(),
+            None => true,
+        }
+    }
+
+    /// Whether `track` has samples presented at another time than decoded.
+    pub(crate) fn has_composition_offsets(&self, track: u32) -> bool {
+        self.traks
+            .get(track as usize)
+            .is_some_and(|trak| trak.composition_offsets)
+    }
+
+    /// Position, size and timing of sample `sample` of `track`. Fails with
+    /// [`Minimp4Error::BadArguments`] if there is no such sample.
+    pub(crate) fn sample(&self, track: u32, sample: u32) -> Minimp4Result<Sample> {
+        if sample >= self.track(track)?.sample_count {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let (mut size, mut timestamp, mut duration) = (0, 0, 0);
+        let offset =
+            unsafe { MP4D_frame_offset(&*self.demux, track, sample, &mut size, &mut timestamp, &mut duration) };
+        Ok(Sample {
+            offset,
+            size,
+            timestamp,
+            duration,
+            sync: self.is_sync_sample(track, sample),
+        })
+    }
+
+    /// Reads the data of `sample` from the input, failing with
+    /// [`Minimp4Error::InvalidFile`] if it cannot be read.
+    pub(crate) fn read_sample(&mut self, sample: &Sample) -> Minimp4Result<Vec<u8>> {
+        let mut data = vec![0; sample.size as usize];
+        self.reader
+            .seek(SeekFrom::Start(sample.offset))
+            .and_then(|_| self.reader.read_exact(&mut data))
+            .map_err(|_| Minimp4Error::InvalidFile)?;
+        Ok(data)
+    }
+
+    pub fn track_count(&self) -> u32 {
+        self.demux.track_count
+    }
+
+    /// Number of samples of `track`, as listed in its `stsz` box. Fails with
+    /// [`Minimp4Error::BadArguments`] if there is no such track.
+    pub fn sample_count(&self, track: u32) -> Minimp4Result<u32> {
+        Ok(self.track(track)?.sample_count)
+    }
+
+    pub(crate) fn track(&self, track: u32) -> Minimp4Result<&MP4D_track_t> {
+        if track >= self.demux.track_count {
+            return Err(Minimp4Error::BadArguments);
+        }
+        Ok(unsafe { &*self.demux.track.add(track as usize) })
+    }
+
+    pub fn into_inner(self) -> R {
+        let mut this = std::mem::ManuallyDrop::new(self);
+        unsafe {
+            MP4D_close(&mut *this.demux);
+            drop(std::ptr::read(&this.demux));
+            *std::ptr::read(&this.reader)
+        }
+    }
+
+    extern "C" fn read(offset: i64, buffer: *mut c_void, size: u64, token: *mut c_void) -> i32 {
+        let reader = unsafe { &mut *(token as *mut R) };
+        let buf = unsafe { from_raw_parts_mut(buffer as *mut u8, size as usize) };
+        let result = reader
+            .seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| reader.read_exact(buf));
+        result.is_err() as i32
+    }
+}
+
+/// Reads the top-level `moov` box of a file of `file_size` bytes, `None`
+/// when the boxes before it cannot be read. Fails with
+/// [`Minimp4Error::InvalidFile`] when the `moov` runs past the end of the
+/// file or holds a box that runs past its parent.
+fn read_moov<R: Read + Seek>(reader: &mut R, file_size: u64) -> Minimp4Result<Option<Vec<u8>>> {
+    let invalid = |_| Minimp4Error::InvalidFile;
+    let mut pos = 0;
+    while pos + 8 <= file_size {
+        let mut header = [0; 16];
+        reader.seek(SeekFrom::Start(pos)).map_err(invalid)?;
+        reader.read_exact(&mut header[..8]).map_err(invalid)?;
+        let is_moov = &header[4..8] == b"moov";
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => file_size - pos,
+            1 => match reader.read_exact(&mut header[8..]) {
+                Ok(()) => u64::from_be_bytes(header[8..].try_into().unwrap()),
+                Err(_) if is_moov => return Err(Minimp4Error::InvalidFile),
+                Err(_) => return Ok(None),
+            },
+            size => size as u64,
+        };
+        if size < 8 || size > file_size - pos {
+            return if is_moov {
+                Err(Minimp4Error::InvalidFile)
+            } else {
+                Ok(None)
+            };
+        }
+        if is_moov {
+            let mut moov = vec![0; size as usize];
+            reader.seek(SeekFrom::Start(pos)).map_err(invalid)?;
+            reader.read_exact(&mut moov).map_err(invalid)?;
+            let range = BoxIter::new(&moov, 0..moov.len()).next();
+            if !range.is_some_and(|moov_box| boxes_fit(&moov, &moov_box)) {
+                return Err(Minimp4Error::InvalidFile);
+            }
+            return Ok(Some(moov));
+        }
+        pos += size;
+    }
+    Ok(None)
+}
+
+/// Whether the children of `parent`, and theirs for the boxes minimp4
+/// descends into, each lie within their parent. Padding too short for a box
+/// header may follow the last child, as long as it is zero.
+fn boxes_fit(data: &[u8], parent: &BoxRange) -> bool {
+    const CONTAINERS: [&[u8; 4]; 19] = [
+        b"moov", b"trak", b"mdia", b"tref", b"minf", b"dinf", b"stbl", b"stsd", b"edts", b"mvex", b"udta", b"meta",
+        b"ilst", b"mp4a", b"avc1", b"avc3", b"hvc1", b"hev1", b"dref",
+    ];
+    let range = parent.children();
+    let mut pos = range.start;
+    for child in BoxIter::new(data, range.clone()) {
+        if CONTAINERS.contains(&&child.fourcc) && !boxes_fit(data, &child) {
+            return false;
+        }
+        pos = child.end;
+    }
+    range.end - pos < 8 && data[pos..range.end].iter().all(|byte| *byte == 0)
+}
+
+/// The last field of the `mvhd` of `moov`, `next_track_ID` in both versions.
+fn next_track_id(moov: &[u8]) -> Option<u32> {
+    let mvhd = find_path(moov, &[b"moov", b"mvhd"])?;
+    let payload = &moov[mvhd[1].payload..mvhd[1].end];
+    let size = if *payload.first()? == 1 { 112 } else { 100 };
+    (payload.len() >= size).then(|| read_u32(payload, size - 4))
+}
+
+/// The `timescale` of the `mvhd` of `moov`, after its creation and
+/// modification times of 4 or 8 bytes.
+fn movie_timescale(moov: &[u8]) -> Option<u32> {
+    let mvhd = find_path(moov, &[b"moov", b"mvhd"])?;
+    let payload = &moov[mvhd[1].payload..mvhd[1].end];
+    let at = if *payload.first()? == 1 { 20 } else { 12 };
+    (payload.len() >= at + 4).then(|| read_u32(payload, at))
+}
+
+/// Every `trak` of `moov`.
+fn traks(moov: &[u8]) -> Vec<Trak> {
+    let Some(root) = find_path(moov, &[b"moov"]) else {
+        return vec![];
+    };
+    let stbl = [b"mdia", b"minf", b"stbl"];
+    BoxIter::new(moov, root[0].children())
+        .filter(|b| &b.fourcc == b"trak")
+        .map(|trak| {
+            let trak = [root[0], trak];
+            let table = |fourcc: &[u8; 4]| {
+                find_child_path(moov, &trak, &[&stbl[..], &[fourcc]].concat())
+                    .map(|path| &moov[path[5].payload..path[5].end])
+            };
+            let edit_list = find_child_path(moov, &trak, &[b"edts", b"elst"])
+                .and_then(|elst| read_elst(&moov[elst[3].payload..elst[3].end]))
+                .unwrap_or_default();
+            let sample_entry = find_child_path(moov, &trak, &[&stbl[..], &[b"stsd"]].concat())
+                .and_then(|stsd| BoxIter::new(moov, stsd[5].children()).next())
+                .map(|entry| moov[entry.start..entry.end].to_vec());
+            Trak {
+                edit_list,
+                sample_entry,
+                sync_samples: table(b"stss").map(|stss| entries(stss, 4).map(|entry| read_u32(entry, 0)).collect()),
+                composition_offsets: table(b"ctts")
+                    .is_some_and(|ctts| entries(ctts, 8).any(|entry| read_u32(entry, 4) != 0)),
+            }
+        })
+        .collect()
+}
+
+/// The `size` byte entries of the table `payload` of a full box, as many as
+/// its entry count gives and fit.
+fn entries(payload: &[u8], size: usize) -> impl Iterator<Item = &[u8]> {
+    let count = if payload.len() < 8 {
+        0
+    } else {
+        read_u32(payload, 4) as usize
+    };
+    payload.get(8..).unwrap_or_default().chunks_exact(size).take(count)
+}
+
+impl<R> Drop for Mp4Demuxer<R> {
+    fn drop(&mut self) {
+        unsafe { MP4D_close(&mut *self.demux) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::Mp4Muxer;
+
+    #[test]
+    fn test_sample_count() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+
+        let demuxer = Mp4Demuxer::new(buffer).unwrap();
+        assert_eq!(demuxer.track_count(), 1);
+        assert_eq!(demuxer.sample_count(0), Ok(128));
+        assert_eq!(demuxer.sample_count(1), Err(Minimp4Error::BadArguments));
+        assert!(!demuxer.into_inner().into_inner().is_empty());
+
+        let truncated = Cursor::new(&include_bytes!("./fixtures/h264_output.mp4")[..100]);
+        assert!(matches!(Mp4Demuxer::new(truncated), Err(Minimp4Error::InvalidFile)));
+    }
+
+    #[test]
+    fn test_next_track_id() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        let track = mp4muxer.init_id3_track(1000).unwrap();
+        let tag = crate::id3::tests::title_tag("now playing");
+        mp4muxer.write_id3_sample(track, &tag, 0).unwrap();
+        mp4muxer.close();
+
+        let demuxer = Mp4Demuxer::new(buffer).unwrap();
+        assert_eq!(demuxer.track_count(), 2);
+        assert_eq!(demuxer.next_track_id(), 3);
+
+        let single = Cursor::new(include_bytes!("./fixtures/h264_output.mp4"));
+        assert_eq!(Mp4Demuxer::new(single).unwrap().next_track_id(), 2);
+    }
+
+    #[test]
+    fn test_truncated_moov() {
+        let data = include_bytes!("./fixtures/h264_output.mp4");
+        let trak = find_path(data, &[b"moov", b"trak"]).unwrap();
+        let moov = trak[0];
+        for cut in moov.start + 1..moov.end {
+            let truncated = Cursor::new(&data[..cut]);
+            assert!(
+                matches!(Mp4Demuxer::new(truncated), Err(Minimp4Error::InvalidFile)),
+                "cut at {cut}"
+            );
+            if cut - moov.start < 8 {
+                continue;
+            }
+            // the moov shortened to end there, cutting the boxes inside it short
+            let mut shortened = data[..cut].to_vec();
+            shortened[moov.start..moov.start + 4].copy_from_slice(&((cut - moov.start) as u32).to_be_bytes());
+            let demuxer = Mp4Demuxer::new(Cursor::new(shortened));
+            if cut < trak[1].end {
+                assert!(matches!(demuxer, Err(Minimp4Error::InvalidFile)), "moov cut at {cut}");
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Edit lists, which minimp4 does not write, mapping the media of a track to
+//! the movie timeline.
+
+use crate::boxes::{find_child_path, read_u32, read_u64, splice, BoxRange, BoxWriter};
+
+/// `media_rate` of an edit played at normal speed, 1.0 in 16.16 fixed point.
+pub(crate) const NORMAL_RATE: i32 = 0x0001_0000;
+
+/// One `elst` entry: `segment_duration` of the movie timeline, in movie
+/// timescale units, filled as `media_time`, in track timescale units, and
+/// `media_rate`, in 16.16 fixed point, select. ISO/IEC 14496-12 knows
+/// three kinds of entries, made by the constructors of the same name:
+///
+/// - a normal edit plays the media from `media_time` on at normal speed,
+///   `media_rate` 0x10000, for the segment;
+/// - an empty edit, of `media_time` -1, shows nothing for the segment, as
+///   a delay before the media starts;
+/// - a dwell, of `media_rate` 0, holds the picture at `media_time` for the
+///   segment, for a freeze frame.
+///
+/// Other rates, which would play the media faster or slower, are not
+/// allowed by the standard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditListEntry {
+    pub segment_duration: u64,
+    pub media_time: i64,
+    pub media_rate: i32,
+}
+
+impl EditListEntry {
+    /// An edit playing the media from `media_time` for `segment_duration`.
+    pub fn normal(segment_duration: u64, media_time: i64) -> Self {
+        Self {
+            segment_duration,
+            media_time,
+            media_rate: NORMAL_RATE,
+        }
+    }
+
+    /// An edit showing nothing for `segment_duration`.
+    pub fn empty(segment_duration: u64) -> Self {
+        Self {
+            segment_duration,
+            media_time: -1,
+            media_rate: NORMAL_RATE,
+        }
+    }
+
+    /// An edit holding the media at `media_time` for `segment_duration`.
+    pub fn dwell(segment_duration: u64, media_time: i64) -> Self {
+        Self {
+            segment_duration,
+            media_time,
+            media_rate: 0,
+        }
+    }
+
+    /// Whether the entry is one of the three kinds: a rate of 0 or normal
+    /// speed, a media time of -1 only at normal speed, and no other
+    /// negative media time.
+    pub(crate) fn is_valid(&self) -> bool {
+        match (self.media_time, self.media_rate) {
+            (-1, NORMAL_RATE) => true,
+            (time, NORMAL_RATE | 0) => time >= 0,
+            _ => false,
+        }
+    }
+}
+
+/// Reads the entries of the `elst` full box payload `elst`, `None` when it
+/// is truncated or of an unknown version.
+pub(crate) fn read_elst(elst: &[u8]) -> Option<Vec<EditListEntry>> {
+    let wide = match elst.first()? {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    let count = read_u32(elst.get(..8)?, 4) as usize;
+    let stride = if wide { 20 } else { 12 };
+    let entries = elst.get(8..8 + count.checked_mul(stride)?)?;
+    let edits = entries
+        .chunks_exact(stride)
+        .map(|entry| match wide {
+            true => EditListEntry {
+                segment_duration: read_u64(entry, 0),
+                media_time: read_u64(entry, 8) as i64,
+                media_rate: read_u32(entry, 16) as i32,
+            },
+            false => EditListEntry {
+                segment_duration: read_u32(entry, 0) as u64,
+                media_time: read_u32(entry, 4) as i32 as i64,
+                media_rate: read_u32(entry, 8) as i32,
+            },
+        })
+        .collect();
+    Some(edits)
+}
+
+/// Builds an `edts` box holding `edits`, in the 64-bit version of `elst`
+/// only when needed.
+pub(crate) fn edts_box(edits: &[EditListEntry]) -> Vec<u8> {
+    let wide = edits
+        .iter()
+        .any(|edit| edit.segment_duration > u32::MAX as u64 || edit.media_time > i32::MAX as i64);
+    let mut w = BoxWriter::new();
+    w.begin(b"edts")
+        .begin_full(b"elst", wide as u8, 0)
+        .u32(edits.len() as u32);
+    for edit in edits {
+        if wide {
+            w.u32((edit.segment_duration >> 32) as u32)
+                .u32(edit.segment_duration as u32)
+                .u32((edit.media_time >> 32) as u32)
+                .u32(edit.media_time as u32);
+        } else {
+            w.u32(edit.segment_duration as u32).u32(edit.media_time as u32);
+        }
+        w.u32(edit.media_rate as u32); // media_rate_integer and fraction
+    }
+    w.end().end().finish()
+}
+
+/// Inserts `edts`, or another box that goes first, into `trak`, the last box
+/// of `chain`, right after its `tkhd` as ISO/IEC 14496-12 orders them.
+pub(crate) fn insert_after_tkhd(moov: &mut Vec<u8>, chain: &[BoxRange], edts: &[u8]) {
+    let at = find_child_path(moov, chain, &[b"tkhd"])
+        .map_or(chain[chain.len() - 1].children().start, |tkhd| tkhd[tkhd.len() - 1].end);
+    splice(moov, chain, at..at, edts);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boxes::{find_path, is_box, read_u32};
+
+    #[test]
+    fn test_edts_box() {
+        let edts = edts_box(&[EditListEntry {
+            segment_duration: 1000,
+            media_time: 2048,
+            media_rate: NORMAL_RATE,
+        }]);
+        assert!(is_box(&edts, b"edts"));
+        let elst = find_path(&edts, &[b"edts", b"elst"]).unwrap();
+        let elst = elst[1].payload(&edts);
+        assert_eq!(elst.len(), 20);
+        assert_eq!(read_u32(elst, 4), 1);
+        assert_eq!(read_u32(elst, 8), 1000);
+        assert_eq!(read_u32(elst, 12), 2048);
+        assert_eq!(read_u32(elst, 16), 0x0001_0000);
+
+        let edts = edts_box(&[
+            EditListEntry {
+                segment_duration: 500,
+                media_time: -1,
+                media_rate: NORMAL_RATE,
+            },
+            EditListEntry {
+                segment_duration: 1 << 32,
+                media_time: 0,
+                media_rate: NORMAL_RATE,
+            },
+        ]);
+        let elst = find_path(&edts, &[b"edts", b"elst"]).unwrap();
+        let elst = elst[1].payload(&edts);
+        assert_eq!(elst[0], 1);
+        assert_eq!(elst.len(), 8 + 2 * 20);
+        assert_eq!(read_u32(elst, 16), u32::MAX);
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(EditListEntry::normal(1000, 0).is_valid());
+        assert!(EditListEntry::empty(1000).is_valid());
+        assert!(EditListEntry::dwell(1000, 3600).is_valid());
+        // an empty dwell, a negative media time, half speed
+        assert!(!EditListEntry::dwell(1000, -1).is_valid());
+        assert!(!EditListEntry::normal(1000, -2).is_valid());
+        let slow = EditListEntry {
+            media_rate: NORMAL_RATE / 2,
+            ..EditListEntry::normal(1000, 0)
+        };
+        assert!(!slow.is_valid());
+    }
+
+    #[test]
+    fn test_read_elst() {
+        let edits = [
+            EditListEntry {
+                segment_duration: 500,
+                media_time: -1,
+                media_rate: NORMAL_RATE,
+            },
+            EditListEntry {
+                segment_duration: 1000,
+                media_time: 2048,
+                media_rate: 0,
+            },
+        ];
+        let edts = edts_box(&edits);
+        let elst = find_path(&edts, &[b"edts", b"elst"]).unwrap();
+        assert_eq!(read_elst(elst[1].payload(&edts)).as_deref(), Some(&edits[..]));
+        let wide = [EditListEntry {
+            segment_duration: 1 << 32,
+            ..edits[0]
+        }];
+        let edts = edts_box(&wide);
+        let elst = find_path(&edts, &[b"edts", b"elst"]).unwrap();
+        let elst = elst[1].payload(&edts);
+        assert_eq!(read_elst(elst).as_deref(), Some(&wide[..]));
+        assert_eq!(read_elst(&elst[..elst.len() - 1]), None);
+    }
+}
@@ -14,6 +14,9 @@ pub use sys::AACENC_InfoStruct as InfoStruct;
 pub struct EncoderError(sys::AACENC_ERROR);
 
 impl EncoderError {
+    /// The error of parameters [`Encoder::supports`] rejects.
+    pub(crate) const UNSUPPORTED_PARAMETER: Self = EncoderError(sys::AACENC_ERROR_AACENC_UNSUPPORTED_PARAMETER);
+
     fn message(&self) -> &'static str {
         match self.0 {
             sys::AACENC_ERROR_AACENC_OK => "Ok",
@@ -51,6 +54,8 @@ impl Display for EncoderError {
     }
 }
 
+impl std::error::Error for EncoderError {}
+
 fn check(e: sys::AACENC_ERROR) -> Result<(), EncoderError> {
     if e == sys::AACENC_ERROR_AACENC_OK {
         Ok(())
@@ -94,15 +99,22 @@ pub struct EncoderParams {
     pub bit_rate: BitRate,
     pub sample_rate: u32,
     pub channel_count: u32,
+    pub transport: TransportFormat,
 }
 
 pub struct Encoder {
     handle: EncoderHandle,
 }
 
-#[derive(Debug)]
-pub enum Transport {
+/// Framing of the encoder output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransportFormat {
+    /// Every access unit behind a 7-byte ADTS header, for standalone `.aac`
+    /// files and streams.
     Adts,
+    /// Bare access units, as stored in MP4 with the audio specific config of
+    /// [`Encoder::info`] in the sample entry. The muxer always uses this.
+    #[default]
     Raw,
 }
 
@@ -113,9 +125,33 @@ pub struct EncodeInfo {
 }
 
 impl Encoder {
+    /// Lowest sample rate of AAC, in Hz.
+    pub const MIN_SAMPLE_RATE: u32 = 8000;
+    /// Highest sample rate of AAC, in Hz.
+    pub const MAX_SAMPLE_RATE: u32 = 96000;
+    pub const MIN_CHANNELS: u32 = 1;
+    /// The encoder is set up for stereo at most.
+    pub const MAX_CHANNELS: u32 = 2;
+    /// Samples per channel of one AAC LC frame.
+    pub const FRAME_LENGTH: u32 = 1024;
+
+    /// Whether the sample rate and channel count of `params` are within the
+    /// limits above, which [`Encoder::new`] checks. fdk-aac may still reject
+    /// a rate in range that is not one of the rates of ISO/IEC 14496-3.
+    pub const fn supports(params: &EncoderParams) -> bool {
+        params.sample_rate >= Self::MIN_SAMPLE_RATE
+            && params.sample_rate <= Self::MAX_SAMPLE_RATE
+            && params.channel_count >= Self::MIN_CHANNELS
+            && params.channel_count <= Self::MAX_CHANNELS
+    }
+
+    /// Fails with `AACENC_UNSUPPORTED_PARAMETER` unless
+    /// [`Encoder::supports`] the parameters.
     pub fn new(params: EncoderParams) -> Result<Self, EncoderError> {
-        let handle = EncoderHandle::alloc(0, 2 /* hardcode stereo */)?;
-        assert!(params.channel_count == 1 || params.channel_count == 2);
+        if !Self::supports(&params) {
+            return Err(EncoderError::UNSUPPORTED_PARAMETER);
+        }
+        let handle = EncoderHandle::alloc(0, Self::MAX_CHANNELS as usize)?;
         unsafe {
             // hardcode MPEG-4 AAC Low Complexity for now:
             check(sys::aacEncoder_SetParam(handle.ptr, sys::AACENC_PARAM_AACENC_AOT, 2))?;
@@ -148,10 +184,14 @@ impl Encoder {
                 params.sample_rate,
             ))?;
 
+            let transmux = match params.transport {
+                TransportFormat::Raw => 0,
+                TransportFormat::Adts => 2,
+            };
             check(sys::aacEncoder_SetParam(
                 handle.ptr,
                 sys::AACENC_PARAM_AACENC_TRANSMUX,
-                0,
+                transmux,
             ))?;
 
             // hardcode SBR off for now
@@ -187,11 +227,27 @@ impl Encoder {
     }
 
     pub fn encode(&self, input: &[i16], output: &mut [u8]) -> Result<EncodeInfo, EncoderError> {
-        let input_len = cmp::min(i32::max_value() as usize, input.len()) as i32;
+        let input_len = cmp::min(i32::MAX as usize, input.len()) as i32;
+        self.encode_samples(input, input_len, output)
+    }
 
+    /// Encodes what the encoder still holds once the input is over, one
+    /// frame per call, returning an empty output when done.
+    pub fn flush(&self, output: &mut [u8]) -> Result<EncodeInfo, EncoderError> {
+        match self.encode_samples(&[], -1, output) {
+            Err(EncoderError(sys::AACENC_ERROR_AACENC_ENCODE_EOF)) => Ok(EncodeInfo {
+                input_consumed: 0,
+                output_size: 0,
+            }),
+            result => result,
+        }
+    }
+
+    /// Encodes `input_len` samples of `input`, -1 to flush.
+    fn encode_samples(&self, input: &[i16], input_len: i32, output: &mut [u8]) -> Result<EncodeInfo, EncoderError> {
         let mut input_buf = input.as_ptr() as *mut i16;
         let mut input_buf_ident: c_int = sys::AACENC_BufferIdentifier_IN_AUDIO_DATA as c_int;
-        let mut input_buf_size: c_int = input_len as c_int;
+        let mut input_buf_size: c_int = cmp::min(i32::MAX as usize, input.len()) as c_int;
         let mut input_buf_el_size: c_int = mem::size_of::<i16>() as c_int;
         let input_desc = sys::AACENC_BufDesc {
             numBufs: 1,
@@ -234,3 +290,33 @@ impl Debug for Encoder {
         write!(f, "Encoder {{ handle: {:?} }}", self.handle.ptr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_limits() {
+        let params = EncoderParams {
+            bit_rate: BitRate::Cbr(128000),
+            sample_rate: 48000,
+            channel_count: 2,
+            transport: TransportFormat::Raw,
+        };
+        assert!(Encoder::new(params).is_ok());
+        for params in [
+            EncoderParams {
+                channel_count: Encoder::MAX_CHANNELS + 1,
+                ..params
+            },
+            EncoderParams {
+                sample_rate: Encoder::MIN_SAMPLE_RATE - 1,
+                ..params
+            },
+        ] {
+            assert!(!Encoder::supports(&params));
+            assert!(Encoder::new(params).is_err());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    error,
+    fmt::{self, Display},
+    io,
+    os::raw::c_int,
+};
+
+use crate::c::{MP4E_STATUS_FILE_WRITE_ERROR, MP4E_STATUS_NO_MEMORY, MP4E_STATUS_OK, MP4E_STATUS_ONLY_ONE_DSI_ALLOWED};
+#[cfg(feature = "aac")]
+use crate::enc::EncoderError;
+
+/// Errors reported by the muxer, mirroring the `MP4E_STATUS_*` codes of
+/// minimp4, and by the demuxer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Minimp4Error {
+    BadArguments,
+    NoMemory,
+    FileWriteError,
+    OnlyOneDsiAllowed,
+    /// A video slice came before the parameter sets needed to decode it, as
+    /// for a stream joined mid-GOP. Nothing of it could be stored.
+    MissingParameterSets,
+    /// An SPS gave the video a picture size other than the one of its track,
+    /// see `Mp4Muxer::set_check_resolution`. Nothing of it was stored.
+    ResolutionMismatch,
+    /// The abort flag of [`crate::Mp4Muxer::set_abort_flag`] was set. The
+    /// frames written before are complete.
+    Aborted,
+    /// Reading an input of the muxer failed. Where the error of the reader
+    /// is at hand it is passed on as [`Error::Io`] instead.
+    ReadError,
+    /// The demuxer input could not be read or is not an MP4 file.
+    InvalidFile,
+    /// A track of the input of [`crate::remux`] cannot be written by the
+    /// muxer: a codec other than H.264, H.265 or audio of an `mp4a` sample
+    /// entry, samples with composition offsets, or empty samples.
+    UnsupportedTrack,
+}
+
+pub type Minimp4Result<T> = Result<T, Minimp4Error>;
+
+impl Minimp4Error {
+    /// Maps the status returned by a minimp4 muxer function, anything unknown
+    /// being reported as [`Minimp4Error::BadArguments`].
+    pub(crate) fn check(status: c_int) -> Minimp4Result<()> {
+        match status {
+            MP4E_STATUS_OK => Ok(()),
+            MP4E_STATUS_NO_MEMORY => Err(Minimp4Error::NoMemory),
+            MP4E_STATUS_FILE_WRITE_ERROR => Err(Minimp4Error::FileWriteError),
+            MP4E_STATUS_ONLY_ONE_DSI_ALLOWED => Err(Minimp4Error::OnlyOneDsiAllowed),
+            _ => Err(Minimp4Error::BadArguments),
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Minimp4Error::BadArguments => "Bad arguments.",
+            Minimp4Error::NoMemory => "Memory allocation failed.",
+            Minimp4Error::FileWriteError => "Writing to the output failed.",
+            Minimp4Error::OnlyOneDsiAllowed => "Only one decoder specific info is allowed per track.",
+            Minimp4Error::MissingParameterSets => {
+                "The stream must start with SPS/PPS (and VPS for H.265) or an IDR preceded by them."
+            }
+            Minimp4Error::ResolutionMismatch => "The stream changes to a resolution other than the one of its track.",
+            Minimp4Error::Aborted => "The write was aborted.",
+            Minimp4Error::ReadError => "Reading the input failed.",
+            Minimp4Error::InvalidFile => "The input is not a readable MP4 file.",
+            Minimp4Error::UnsupportedTrack => "The input has a track the muxer cannot write.",
+        }
+    }
+}
+
+impl Display for Minimp4Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl error::Error for Minimp4Error {}
+
+/// Error of the operations that go beyond the muxer, telling its failures
+/// apart from those of the AAC encoder and of the I/O, for callers that
+/// handle them differently: the methods setting up and writing audio through
+/// the encoder and the [`crate::Recorder`] return it. Every part converts into it, so
+/// that `?` works on all of them in a function returning it.
+#[derive(Debug)]
+pub enum Error {
+    /// The muxer or demuxer failed.
+    Muxer(Minimp4Error),
+    /// The AAC encoder failed.
+    #[cfg(feature = "aac")]
+    Encoder(EncoderError),
+    /// Reading an input or writing the output failed.
+    Io(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Muxer(error) => write!(f, "{error}"),
+            #[cfg(feature = "aac")]
+            Error::Encoder(error) => write!(f, "AAC encoding failed: {error}"),
+            Error::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Muxer(error) => Some(error),
+            #[cfg(feature = "aac")]
+            Error::Encoder(error) => Some(error),
+            Error::Io(error) => Some(error),
+        }
+    }
+}
+
+impl From<Minimp4Error> for Error {
+    fn from(error: Minimp4Error) -> Self {
+        Error::Muxer(error)
+    }
+}
+
+#[cfg(feature = "aac")]
+impl From<EncoderError> for Error {
+    fn from(error: EncoderError) -> Self {
+        Error::Encoder(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
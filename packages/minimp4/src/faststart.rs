@@ -0,0 +1,177 @@
+//! Faststart output: the file is staged, in memory or in a temporary file,
+//! as minimp4 writes it, and copied to the output on close with the `moov`
+//! moved ahead of the media data.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::relocate::shift_moov_offsets;
+
+/// Where [`crate::Mp4Muxer::set_faststart`] keeps the file until the `moov`
+/// is known.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FaststartStrategy {
+    /// The whole file is held in memory, which is the fastest but needs as
+    /// much RAM as the output is large.
+    Memory,
+    /// The file goes to a temporary file of [`std::env::temp_dir`], removed
+    /// once the output is written. Memory use stays that of the index, the
+    /// price is writing and reading all media data once more, which a
+    /// multi-gigabyte capture cannot avoid.
+    #[default]
+    TempFile,
+}
+
+trait Storage: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Storage for T {}
+
+/// The file staged so far.
+pub(crate) struct Staging {
+    storage: Box<dyn Storage>,
+    /// The temporary file, removed on drop.
+    path: Option<PathBuf>,
+}
+
+impl Staging {
+    pub fn new(strategy: FaststartStrategy) -> io::Result<Self> {
+        match strategy {
+            FaststartStrategy::Memory => Ok(Self {
+                storage: Box::new(Cursor::new(Vec::new())),
+                path: None,
+            }),
+            FaststartStrategy::TempFile => {
+                let (file, path) = temp_file()?;
+                Ok(Self {
+                    storage: Box::new(file),
+                    path: Some(path),
+                })
+            }
+        }
+    }
+
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.storage.seek(SeekFrom::Start(offset))?;
+        self.storage.write_all(buf)
+    }
+
+    /// Copies the staged file to `output` from its start, the `moov` moved
+    /// ahead of the first `mdat` and its chunk offsets shifted to match.
+    /// Files whose `moov` cannot be moved, because it already comes first,
+    /// is missing or its offsets would outgrow an `stco`, are copied as
+    /// they are.
+    pub fn finish<W: Write + Seek>(mut self, output: &mut W) -> io::Result<()> {
+        let size = self.storage.seek(SeekFrom::End(0))?;
+        let top = self.top_level_boxes(size)?;
+        let moov = top.iter().position(|(fourcc, _)| fourcc == b"moov");
+        let mdat = top.iter().position(|(fourcc, _)| fourcc == b"mdat");
+        let mut layout: Vec<_> = top.iter().map(|(_, range)| range.clone()).collect();
+        let mut moov_data = None;
+        if let (Some(moov), Some(mdat)) = (moov, mdat) {
+            let range = layout[moov].clone();
+            let mut data = vec![0; (range.end - range.start) as usize];
+            self.storage.seek(SeekFrom::Start(range.start))?;
+            self.storage.read_exact(&mut data)?;
+            let delta = data.len() as i64;
+            if mdat < moov && shift_moov_offsets(&mut data, delta).is_ok() {
+                layout.remove(moov);
+                moov_data = Some((mdat, data));
+            }
+        }
+        output.seek(SeekFrom::Start(0))?;
+        for (index, range) in layout.into_iter().enumerate() {
+            if let Some((_, moov)) = moov_data.as_ref().filter(|(at, _)| *at == index) {
+                output.write_all(moov)?;
+            }
+            self.storage.seek(SeekFrom::Start(range.start))?;
+            let length = range.end - range.start;
+            if io::copy(&mut (&mut self.storage).take(length), output)? != length {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fourcc and range of the top-level boxes of the `size` bytes staged,
+    /// anything unreadable at the end as a box of its own.
+    fn top_level_boxes(&mut self, size: u64) -> io::Result<Vec<([u8; 4], std::ops::Range<u64>)>> {
+        let mut boxes = vec![];
+        let mut pos = 0;
+        while pos < size {
+            let mut header = [0; 16];
+            let available = (size - pos).min(16) as usize;
+            self.storage.seek(SeekFrom::Start(pos))?;
+            self.storage.read_exact(&mut header[..available])?;
+            let length = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+                0 => size - pos,
+                1 if available == 16 => u64::from_be_bytes(header[8..].try_into().unwrap()),
+                length => length as u64,
+            };
+            let fourcc = if available < 8 || length < 8 || length > size - pos {
+                [0; 4]
+            } else {
+                header[4..8].try_into().unwrap()
+            };
+            let end = if fourcc == [0; 4] { size } else { pos + length };
+            boxes.push((fourcc, pos..end));
+            pos = end;
+        }
+        Ok(boxes)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Creates a new file of a name no other muxer uses in the temporary
+/// directory.
+fn temp_file() -> io::Result<(File, PathBuf)> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = format!("minimp4-{}-{count}-{nanos}.mp4.tmp", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_file_removed() {
+        let staging = Staging::new(FaststartStrategy::TempFile).unwrap();
+        let path = staging.path.clone().unwrap();
+        assert!(path.exists());
+        drop(staging);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_finish_without_moov() {
+        let mut staging = Staging::new(FaststartStrategy::Memory).unwrap();
+        let data = b"\0\0\0\x10mdat01234567trailing";
+        staging.write_at(0, data).unwrap();
+        let mut output = Cursor::new(vec![]);
+        staging.finish(&mut output).unwrap();
+        assert_eq!(output.into_inner(), data);
+    }
+}
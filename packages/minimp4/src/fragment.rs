@@ -0,0 +1,553 @@
+//! Movie fragment rewriting for [`Mp4Mode::Fragmented`](crate::Mp4Mode::Fragmented).
+//!
+//! minimp4 leaves the `trex` defaults at zero and repeats the sample duration
+//! and flags in every fragment. These helpers fill in the `trex` boxes and
+//! rewrite each `moof` so that a `trun` only carries the fields that differ
+//! from those defaults.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::boxes::{find_child_path, find_path, read_u32, splice, BoxIter, BoxWriter};
+
+/// `sample_depends_on` 1 and `sample_is_non_sync_sample`, the flags minimp4
+/// gives every video sample but the first one of a fragment starting with a
+/// keyframe.
+pub(crate) const VIDEO_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+const TFHD_BASE_DATA_OFFSET: u32 = 0x01;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x02;
+const TFHD_DEFAULT_DURATION: u32 = 0x08;
+const TFHD_DEFAULT_SIZE: u32 = 0x10;
+const TFHD_DEFAULT_FLAGS: u32 = 0x20;
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x2_0000;
+
+const SAMPLE_IS_NON_SYNC: u32 = 0x1_0000;
+
+const TRUN_DATA_OFFSET: u32 = 0x01;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x04;
+const TRUN_DURATION: u32 = 0x100;
+const TRUN_SIZE: u32 = 0x200;
+const TRUN_FLAGS: u32 = 0x400;
+const TRUN_COMPOSITION_OFFSET: u32 = 0x800;
+
+/// `prft` flags: the time is the one the following `moof` was written at.
+const PRFT_MOOF_WRITTEN: u32 = 0x04;
+/// Seconds from the NTP epoch, 1900, to the Unix one.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Extended type of the Smooth Streaming `tfxd` box, timing a fragment.
+pub(crate) const TFXD_UUID: [u8; 16] = [
+    0x6d, 0x1d, 0x9b, 0x05, 0x42, 0xd5, 0x44, 0xe6, 0x80, 0xe2, 0x14, 0x1d, 0xaf, 0xf7, 0x57, 0xb2,
+];
+
+/// Sample defaults of a track, as stored in its `trex` box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TrackDefaults {
+    pub track_id: u32,
+    pub duration: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    duration: u32,
+    size: u32,
+    flags: u32,
+    composition_offset: u32,
+}
+
+/// Writes `defaults` into the matching `trex` boxes of `moov`, in place.
+pub(crate) fn patch_trex(moov: &mut [u8], defaults: &[TrackDefaults]) {
+    let Some(mvex) = find_path(moov, &[b"moov", b"mvex"]) else {
+        return;
+    };
+    let trexs: Vec<_> = BoxIter::new(moov, mvex[1].children())
+        .filter(|b| &b.fourcc == b"trex" && b.end - b.payload >= 24)
+        .collect();
+    for trex in trexs {
+        let track_id = read_u32(moov, trex.payload + 4);
+        if let Some(defaults) = defaults.iter().find(|d| d.track_id == track_id) {
+            let duration = trex.payload + 12;
+            moov[duration..duration + 4].copy_from_slice(&defaults.duration.to_be_bytes());
+            let flags = trex.payload + 20;
+            moov[flags..flags + 4].copy_from_slice(&defaults.flags.to_be_bytes());
+        }
+    }
+}
+
+/// Builds the `mehd` box advertising a total duration of `fragment_duration`
+/// in movie timescale units, in the 64-bit version only when needed.
+pub(crate) fn mehd_box(fragment_duration: u64) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    match u32::try_from(fragment_duration) {
+        Ok(duration) => w.begin_full(b"mehd", 0, 0).u32(duration),
+        Err(_) => w
+            .begin_full(b"mehd", 1, 0)
+            .u32((fragment_duration >> 32) as u32)
+            .u32(fragment_duration as u32),
+    };
+    w.end().finish()
+}
+
+/// Builds the `prft` box stating that the fragment starting at `media_time`
+/// of track `track_id`, in its timescale, was written at `time`.
+pub(crate) fn prft_box(track_id: u32, time: SystemTime, media_time: u64) -> Vec<u8> {
+    let ntp = ntp_timestamp(time);
+    let mut w = BoxWriter::new();
+    w.begin_full(b"prft", 1, PRFT_MOOF_WRITTEN)
+        .u32(track_id)
+        .u32((ntp >> 32) as u32)
+        .u32(ntp as u32)
+        .u32((media_time >> 32) as u32)
+        .u32(media_time as u32);
+    w.end().finish()
+}
+
+/// A DASH event waiting for the fragment its `emsg` goes ahead of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Event {
+    pub scheme_id: String,
+    pub value: String,
+    pub timescale: u32,
+    /// In `timescale` units, from the start of the presentation.
+    pub presentation_time: u64,
+    pub duration: u32,
+    pub id: u32,
+    pub message_data: Vec<u8>,
+    /// Written as version 0, relative to the fragment.
+    pub fragment_relative: bool,
+}
+
+/// Builds the `emsg` box of `event`: version 0, timed relative to a fragment
+/// starting at `fragment_start` in the event timescale, or version 1 with
+/// the presentation time as is without one. `fragment_start` must not be
+/// after the event nor more than `u32::MAX` ticks before it.
+pub(crate) fn emsg_box(event: &Event, fragment_start: Option<u64>) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    let null_terminated = |w: &mut BoxWriter, text: &str| {
+        w.bytes(text.as_bytes()).u8(0);
+    };
+    match fragment_start {
+        Some(start) => {
+            w.begin_full(b"emsg", 0, 0);
+            null_terminated(&mut w, &event.scheme_id);
+            null_terminated(&mut w, &event.value);
+            w.u32(event.timescale)
+                .u32((event.presentation_time - start) as u32)
+                .u32(event.duration)
+                .u32(event.id);
+        }
+        None => {
+            w.begin_full(b"emsg", 1, 0)
+                .u32(event.timescale)
+                .u32((event.presentation_time >> 32) as u32)
+                .u32(event.presentation_time as u32)
+                .u32(event.duration)
+                .u32(event.id);
+            null_terminated(&mut w, &event.scheme_id);
+            null_terminated(&mut w, &event.value);
+        }
+    }
+    w.bytes(&event.message_data).end().finish()
+}
+
+/// The `ftyp` of Smooth Streaming files, replacing the one of minimp4.
+pub(crate) fn ismv_ftyp() -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"ftyp").bytes(b"isml").u32(1).bytes(b"piffiso2isml");
+    w.end().finish()
+}
+
+/// Adds the Smooth Streaming `tfxd` box of a fragment starting at `time` and
+/// lasting `duration`, in track timescale units, to the `traf` of `moof`,
+/// moving the data offset of its `trun` past the grown box. Returns `None`
+/// for a `moof` without `traf`.
+pub(crate) fn add_tfxd(moof: &[u8], time: u64, duration: u64) -> Option<Vec<u8>> {
+    let mut moof = moof.to_vec();
+    let traf = find_path(&moof, &[b"moof", b"traf"])?;
+    let mut w = BoxWriter::new();
+    w.begin(b"uuid")
+        .bytes(&TFXD_UUID)
+        .u32(0x0100_0000) // version 1
+        .u32((time >> 32) as u32)
+        .u32(time as u32)
+        .u32((duration >> 32) as u32)
+        .u32(duration as u32);
+    let tfxd = w.end().finish();
+    splice(&mut moof, &traf, traf[1].end..traf[1].end, &tfxd);
+    if let Some(trun) = find_path(&moof, &[b"moof", b"traf", b"trun"]) {
+        let payload = trun[2].payload;
+        if read_u32(&moof, payload) & TRUN_DATA_OFFSET != 0 {
+            let data_offset = read_u32(&moof, payload + 8).wrapping_add(tfxd.len() as u32);
+            moof[payload + 8..payload + 12].copy_from_slice(&data_offset.to_be_bytes());
+        }
+    }
+    Some(moof)
+}
+
+/// Rewrites the `tfhd` of `moof`, starting at the output position `base`,
+/// from the `default-base-is-moof` flag minimp4 sets to an explicit
+/// `base_data_offset` of `base`. The data offset of its `trun` stays
+/// relative to the `moof` and only grows with it. `None` for a `moof` that
+/// is not laid out that way.
+pub(crate) fn set_base_data_offset(moof: &[u8], base: u64) -> Option<Vec<u8>> {
+    let mut moof = moof.to_vec();
+    let tfhd = find_path(&moof, &[b"moof", b"traf", b"tfhd"])?;
+    let payload = tfhd[2].payload;
+    let flags = read_u32(moof.get(..payload + 8)?, payload);
+    if flags & (TFHD_DEFAULT_BASE_IS_MOOF | TFHD_BASE_DATA_OFFSET) != TFHD_DEFAULT_BASE_IS_MOOF {
+        return None;
+    }
+    let flags = flags & !TFHD_DEFAULT_BASE_IS_MOOF | TFHD_BASE_DATA_OFFSET;
+    moof[payload..payload + 4].copy_from_slice(&flags.to_be_bytes());
+    // right after the track_ID
+    splice(&mut moof, &tfhd, payload + 8..payload + 8, &base.to_be_bytes());
+    if let Some(trun) = find_path(&moof, &[b"moof", b"traf", b"trun"]) {
+        let payload = trun[2].payload;
+        if read_u32(&moof, payload) & TRUN_DATA_OFFSET != 0 {
+            let data_offset = read_u32(&moof, payload + 8).wrapping_add(8);
+            moof[payload + 8..payload + 12].copy_from_slice(&data_offset.to_be_bytes());
+        }
+    }
+    Some(moof)
+}
+
+/// Builds the `mfra` random access index of Smooth Streaming files, one
+/// `tfra` per track listing the start time and `moof` position of its
+/// fragments starting with a sync sample, followed by the `mfro` giving its
+/// size so that readers find it from the end of the file.
+pub(crate) fn mfra_box(tracks: &[(u32, Vec<(u64, u64)>)]) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"mfra");
+    for (track_id, entries) in tracks {
+        // 1-byte traf, trun and sample numbers
+        w.begin_full(b"tfra", 1, 0)
+            .u32(*track_id)
+            .u32(0)
+            .u32(entries.len() as u32);
+        for (time, moof_offset) in entries {
+            w.u32((time >> 32) as u32)
+                .u32(*time as u32)
+                .u32((moof_offset >> 32) as u32)
+                .u32(*moof_offset as u32)
+                .u8(1)
+                .u8(1)
+                .u8(1);
+        }
+        w.end();
+    }
+    let size = w.position() + 16;
+    w.begin_full(b"mfro", 0, 0).u32(size as u32).end();
+    w.end().finish()
+}
+
+/// 64-bit NTP timestamp of `time`: seconds since 1900 in the high half and
+/// the fraction of a second in the low one. Times before 1900 map to 0.
+fn ntp_timestamp(time: SystemTime) -> u64 {
+    let since_1900 = match time.duration_since(UNIX_EPOCH) {
+        Ok(since_unix) => since_unix + Duration::from_secs(NTP_UNIX_OFFSET),
+        Err(before_unix) => Duration::from_secs(NTP_UNIX_OFFSET).saturating_sub(before_unix.duration()),
+    };
+    let fraction = ((since_1900.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    since_1900.as_secs() << 32 | fraction
+}
+
+/// Rewrites a `moof` holding a single `traf` so that its `tfhd` and `trun`
+/// omit what `defaults` already provide. Returns `None`, leaving the box to be
+/// written as is, for layouts it does not handle.
+pub(crate) fn optimize_moof(moof: &[u8], defaults: &[TrackDefaults]) -> Option<Vec<u8>> {
+    let Run {
+        mfhd,
+        track_id,
+        trex,
+        tfhd_flags,
+        version_flags,
+        data_offset,
+        samples,
+    } = parse_moof(moof, defaults)?;
+    let trun_flags = version_flags & 0xff_ffff;
+
+    // Flags of the first sample may differ, a keyframe usually starts the run.
+    let rest = if samples.len() > 1 { &samples[1..] } else { &samples[..] };
+    let all_equal = |f: fn(&Sample) -> u32| rest.iter().all(|s| f(s) == f(&rest[0]));
+    let mut tfhd_flags = tfhd_flags & TFHD_DEFAULT_BASE_IS_MOOF;
+    let mut trun_flags = trun_flags & (TRUN_DATA_OFFSET | TRUN_SIZE | TRUN_COMPOSITION_OFFSET);
+    let durations_equal = samples.iter().all(|s| s.duration == samples[0].duration);
+    if !durations_equal {
+        trun_flags |= TRUN_DURATION;
+    } else if samples[0].duration != trex.duration {
+        tfhd_flags |= TFHD_DEFAULT_DURATION;
+    }
+    let rest_flags = rest[0].flags;
+    if !all_equal(|s| s.flags) {
+        trun_flags |= TRUN_FLAGS;
+    } else {
+        if rest_flags != trex.flags {
+            tfhd_flags |= TFHD_DEFAULT_FLAGS;
+        }
+        if samples[0].flags != rest_flags {
+            trun_flags |= TRUN_FIRST_SAMPLE_FLAGS;
+        }
+    }
+
+    let mut w = BoxWriter::new();
+    w.begin(b"moof").bytes(mfhd).begin(b"traf");
+    w.begin_full(b"tfhd", 0, tfhd_flags).u32(track_id);
+    if tfhd_flags & TFHD_DEFAULT_DURATION != 0 {
+        w.u32(samples[0].duration);
+    }
+    if tfhd_flags & TFHD_DEFAULT_FLAGS != 0 {
+        w.u32(rest_flags);
+    }
+    w.end();
+    w.begin_full(b"trun", (version_flags >> 24) as u8, trun_flags)
+        .u32(samples.len() as u32);
+    let data_offset_pos = w.position();
+    if let Some(data_offset) = data_offset {
+        w.u32(data_offset);
+    }
+    if trun_flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+        w.u32(samples[0].flags);
+    }
+    for sample in &samples {
+        if trun_flags & TRUN_DURATION != 0 {
+            w.u32(sample.duration);
+        }
+        if trun_flags & TRUN_SIZE != 0 {
+            w.u32(sample.size);
+        }
+        if trun_flags & TRUN_FLAGS != 0 {
+            w.u32(sample.flags);
+        }
+        if trun_flags & TRUN_COMPOSITION_OFFSET != 0 {
+            w.u32(sample.composition_offset);
+        }
+    }
+    let mut optimized = w.end().end().end().finish();
+
+    // Relative to the moof, the data moved by as much as the moof shrank.
+    if let (Some(data_offset), true) = (data_offset, tfhd_flags & TFHD_DEFAULT_BASE_IS_MOOF != 0) {
+        let data_offset = (data_offset as i64 + optimized.len() as i64 - moof.len() as i64) as u32;
+        optimized[data_offset_pos..data_offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+    } else if data_offset.is_some() {
+        return None;
+    }
+    Some(optimized)
+}
+
+/// Track, duration and sync flag of the run of a `moof` written by minimp4,
+/// see [`optimize_moof`] for the layouts handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FragmentRun {
+    pub track_id: u32,
+    /// Sum of the sample durations, in track timescale units.
+    pub duration: u64,
+    /// Whether the first sample is a sync sample.
+    pub sync: bool,
+}
+
+pub(crate) fn read_moof(moof: &[u8], defaults: &[TrackDefaults]) -> Option<FragmentRun> {
+    let run = parse_moof(moof, defaults)?;
+    Some(FragmentRun {
+        track_id: run.track_id,
+        duration: run.samples.iter().map(|s| s.duration as u64).sum(),
+        sync: run.samples[0].flags & SAMPLE_IS_NON_SYNC == 0,
+    })
+}
+
+/// The single `traf` of a `moof`, with the defaults resolved for every sample.
+struct Run<'a> {
+    /// The whole `mfhd` box.
+    mfhd: &'a [u8],
+    track_id: u32,
+    trex: TrackDefaults,
+    tfhd_flags: u32,
+    /// Of the `trun`.
+    version_flags: u32,
+    data_offset: Option<u32>,
+    samples: Vec<Sample>,
+}
+
+/// Parses a `moof` holding a single `traf` of a track of `defaults`.
+fn parse_moof<'a>(moof: &'a [u8], defaults: &[TrackDefaults]) -> Option<Run<'a>> {
+    let root = find_path(moof, &[b"moof"])?;
+    let mfhd = find_child_path(moof, &root, &[b"mfhd"])?;
+    let traf = find_child_path(moof, &root, &[b"traf"])?;
+    if BoxIter::new(moof, traf[1].children()).count() != 2 {
+        return None;
+    }
+    let tfhd = find_child_path(moof, &traf, &[b"tfhd"])?;
+    let trun = find_child_path(moof, &traf, &[b"trun"])?;
+    let (tfhd, trun) = (&moof[tfhd[2].payload..tfhd[2].end], &moof[trun[2].payload..trun[2].end]);
+
+    let mut fields = Fields::new(tfhd);
+    let tfhd_flags = fields.next()? & 0xff_ffff;
+    let track_id = fields.next()?;
+    let trex = *defaults.iter().find(|d| d.track_id == track_id)?;
+    if tfhd_flags & (TFHD_BASE_DATA_OFFSET | TFHD_SAMPLE_DESCRIPTION_INDEX) != 0 {
+        return None;
+    }
+    let mut default = Sample {
+        duration: trex.duration,
+        flags: trex.flags,
+        ..Sample::default()
+    };
+    if tfhd_flags & TFHD_DEFAULT_DURATION != 0 {
+        default.duration = fields.next()?;
+    }
+    if tfhd_flags & TFHD_DEFAULT_SIZE != 0 {
+        default.size = fields.next()?;
+    }
+    if tfhd_flags & TFHD_DEFAULT_FLAGS != 0 {
+        default.flags = fields.next()?;
+    }
+
+    let mut fields = Fields::new(trun);
+    let version_flags = fields.next()?;
+    let trun_flags = version_flags & 0xff_ffff;
+    let sample_count = fields.next()?;
+    let data_offset = if trun_flags & TRUN_DATA_OFFSET != 0 {
+        Some(fields.next()?)
+    } else {
+        None
+    };
+    let first_flags = if trun_flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+        Some(fields.next()?)
+    } else {
+        None
+    };
+    let mut samples = Vec::with_capacity(sample_count as usize);
+    for index in 0..sample_count {
+        let mut sample = default;
+        if trun_flags & TRUN_DURATION != 0 {
+            sample.duration = fields.next()?;
+        }
+        if trun_flags & TRUN_SIZE != 0 {
+            sample.size = fields.next()?;
+        }
+        if trun_flags & TRUN_FLAGS != 0 {
+            sample.flags = fields.next()?;
+        } else if let (0, Some(flags)) = (index, first_flags) {
+            sample.flags = flags;
+        }
+        if trun_flags & TRUN_COMPOSITION_OFFSET != 0 {
+            sample.composition_offset = fields.next()?;
+        }
+        samples.push(sample);
+    }
+    if samples.is_empty() {
+        return None;
+    }
+    Some(Run {
+        mfhd: &moof[mfhd[1].start..mfhd[1].end],
+        track_id,
+        trex,
+        tfhd_flags,
+        version_flags,
+        data_offset,
+        samples,
+    })
+}
+
+/// Reads consecutive big-endian 32-bit fields.
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn next(&mut self) -> Option<u32> {
+        let value = self.data.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_be_bytes(value.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Durations and flags of the samples of a `moof`, resolving the `tfhd` and
+    /// `trex` defaults the way a player does.
+    pub(crate) fn resolve(moof: &[u8], trex: TrackDefaults) -> Vec<(u32, u32, u32)> {
+        let tfhd = find_path(moof, &[b"moof", b"traf", b"tfhd"]).unwrap();
+        let mut fields = Fields::new(tfhd[2].payload(moof));
+        let tfhd_flags = fields.next().unwrap() & 0xff_ffff;
+        assert_eq!(fields.next(), Some(trex.track_id));
+        let mut duration = trex.duration;
+        let mut flags = trex.flags;
+        if tfhd_flags & TFHD_DEFAULT_DURATION != 0 {
+            duration = fields.next().unwrap();
+        }
+        if tfhd_flags & TFHD_DEFAULT_FLAGS != 0 {
+            flags = fields.next().unwrap();
+        }
+        let trun = find_path(moof, &[b"moof", b"traf", b"trun"]).unwrap();
+        let mut fields = Fields::new(trun[2].payload(moof));
+        let trun_flags = fields.next().unwrap() & 0xff_ffff;
+        let count = fields.next().unwrap();
+        if trun_flags & TRUN_DATA_OFFSET != 0 {
+            fields.next();
+        }
+        let first_flags = (trun_flags & TRUN_FIRST_SAMPLE_FLAGS != 0).then(|| fields.next().unwrap());
+        (0..count)
+            .map(|index| {
+                let duration = if trun_flags & TRUN_DURATION != 0 {
+                    fields.next().unwrap()
+                } else {
+                    duration
+                };
+                let size = fields.next().unwrap();
+                let flags = match first_flags {
+                    Some(first) if index == 0 => first,
+                    _ => flags,
+                };
+                (duration, size, flags)
+            })
+            .collect()
+    }
+
+    fn moof(tfhd: &[u32], trun: &[u32]) -> Vec<u8> {
+        let mut w = BoxWriter::new();
+        w.begin(b"moof").begin_full(b"mfhd", 0, 0).u32(1).end().begin(b"traf");
+        w.begin(b"tfhd");
+        tfhd.iter().for_each(|v| {
+            w.u32(*v);
+        });
+        w.end().begin(b"trun");
+        trun.iter().for_each(|v| {
+            w.u32(*v);
+        });
+        w.end().end().end().finish()
+    }
+
+    #[test]
+    fn test_optimize_moof() {
+        let trex = TrackDefaults {
+            track_id: 1,
+            duration: 3600,
+            flags: VIDEO_SAMPLE_FLAGS,
+        };
+        // a keyframe as minimp4 writes it
+        let keyframe = moof(
+            &[0x20020, 1, VIDEO_SAMPLE_FLAGS],
+            &[0x305, 1, 84, 0x0200_0000, 3600, 1000],
+        );
+        let optimized = optimize_moof(&keyframe, &[trex]).unwrap();
+        assert_eq!(optimized.len(), keyframe.len() - 8);
+        assert_eq!(resolve(&optimized, trex), [(3600, 1000, 0x0200_0000)]);
+        let trun = find_path(&optimized, &[b"moof", b"traf", b"trun"]).unwrap();
+        assert_eq!(read_u32(trun[2].payload(&optimized), 8), 84 - 8);
+
+        let frame = moof(&[0x20020, 1, VIDEO_SAMPLE_FLAGS], &[0x301, 1, 80, 1800, 500]);
+        let optimized = optimize_moof(&frame, &[trex]).unwrap();
+        assert_eq!(resolve(&optimized, trex), [(1800, 500, VIDEO_SAMPLE_FLAGS)]);
+        assert_eq!(optimized.len(), frame.len() - 4);
+
+        assert!(optimize_moof(&frame, &[TrackDefaults { track_id: 2, ..trex }]).is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! Timed ID3 metadata: ID3v2 tags carried as the samples of a track, which
+//! HLS uses for "now playing" information and other in-stream metadata.
+//!
+//! minimp4 writes the track as an MPEG-4 private stream, the `trak` is then
+//! turned into a timed metadata one with a `urim` sample entry naming the
+//! ID3 scheme.
+
+use crate::boxes::{find_child_path, find_path, splice, BoxIter, BoxWriter};
+
+/// Scheme of timed ID3 metadata, the URI of its sample entry.
+pub(crate) const ID3_SCHEME: &str = "https://developer.apple.com/streaming/emsg-id3";
+
+/// ID3v2 header flag announcing a 10-byte footer after the frames.
+const FOOTER_PRESENT: u8 = 0x10;
+
+/// An ID3 track of [`crate::Mp4Muxer::init_id3_track`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Id3Track {
+    /// Index of the track in the muxer.
+    pub track: usize,
+    pub time_scale: u32,
+    /// Time of the first tag, where the media of the track starts.
+    pub start: Option<u64>,
+    /// Last tag and its time, written once the next one tells its duration.
+    pub pending: Option<(Vec<u8>, u64)>,
+}
+
+/// Whether `data` is exactly one ID3v2 tag: the "ID3" magic, a major version
+/// of 2 to 4, a syncsafe size and as many bytes as that size announces.
+pub(crate) fn is_id3_tag(data: &[u8]) -> bool {
+    let Some([b'I', b'D', b'3', major, revision, flags, size @ ..]) = data.get(..10) else {
+        return false;
+    };
+    if !(2..=4).contains(major) || *revision == 0xff || size.iter().any(|byte| byte & 0x80 != 0) {
+        return false;
+    }
+    let size = size.iter().fold(0, |size, byte| size << 7 | *byte as usize);
+    let footer = if flags & FOOTER_PRESENT != 0 { 10 } else { 0 };
+    data.len() == 10 + size + footer
+}
+
+/// Turns the private stream `trak` starting at `trak_start` of `moov` into a
+/// timed metadata track of the ID3 scheme: `meta` handler, `nmhd` media
+/// header and `urim` sample entry.
+pub(crate) fn patch_trak(moov: &mut Vec<u8>, trak_start: usize) {
+    patch_private_trak(moov, trak_start, &urim_box(), &hdlr(b"meta", b"ID3"));
+}
+
+/// Turns the private stream `trak` starting at `trak_start` of `moov` into a
+/// track with an `nmhd` media header, such as the timed metadata and text
+/// tracks, replacing its sample entry by `sample_entry` and its handler by
+/// `hdlr`.
+pub(crate) fn patch_private_trak(moov: &mut Vec<u8>, trak_start: usize, sample_entry: &[u8], hdlr: &[u8]) {
+    // the enclosing boxes grow with every change and are looked up again
+    let chain = |moov: &[u8]| {
+        let root = find_path(moov, &[b"moov"])?;
+        let trak = BoxIter::new(moov, root[0].children()).find(|b| b.start == trak_start)?;
+        Some(vec![root[0], trak])
+    };
+    // from the back, the ones in front stay where they are
+    let stsd_path = [b"mdia", b"minf", b"stbl", b"stsd"];
+    if let Some(stsd) = chain(moov).and_then(|chain| find_child_path(moov, &chain, &stsd_path)) {
+        let stsd_box = &stsd[stsd.len() - 1];
+        if let Some(entry) = BoxIter::new(moov, stsd_box.children()).next() {
+            splice(moov, &stsd, entry.start..entry.end, sample_entry);
+        }
+    }
+    if let Some(minf) = chain(moov).and_then(|chain| find_child_path(moov, &chain, &[b"mdia", b"minf"])) {
+        let at = minf[minf.len() - 1].children().start;
+        let nmhd = BoxWriter::new().begin_full(b"nmhd", 0, 0).end().finish();
+        splice(moov, &minf, at..at, &nmhd);
+    }
+    if let Some(old) = chain(moov).and_then(|chain| find_child_path(moov, &chain, &[b"mdia", b"hdlr"])) {
+        let hdlr_box = old[old.len() - 1];
+        splice(moov, &old[..old.len() - 1], hdlr_box.start..hdlr_box.end, hdlr);
+    }
+}
+
+fn urim_box() -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"urim").bytes(&[0; 6]).u16(1); // reserved and data_reference_index
+    w.begin_full(b"uri ", 0, 0).bytes(ID3_SCHEME.as_bytes()).u8(0).end();
+    w.end().finish()
+}
+
+/// `hdlr` box of `handler_type`, named `name`.
+pub(crate) fn hdlr(handler_type: &[u8; 4], name: &[u8]) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin_full(b"hdlr", 0, 0)
+        .u32(0) // pre_defined
+        .bytes(handler_type)
+        .bytes(&[0; 12])
+        .bytes(name)
+        .u8(0);
+    w.end().finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An ID3v2.4 tag holding a `TIT2` title frame.
+    pub(crate) fn title_tag(title: &str) -> Vec<u8> {
+        let mut frame = b"TIT2".to_vec();
+        frame.extend_from_slice(&(title.len() as u32 + 1).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 3]); // flags and UTF-8 encoding
+        frame.extend_from_slice(title.as_bytes());
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        let size = frame.len() as u32;
+        tag.extend((0..4).rev().map(|i| (size >> (7 * i)) as u8 & 0x7f));
+        tag.extend_from_slice(&frame);
+        tag
+    }
+
+    #[test]
+    fn test_is_id3_tag() {
+        let tag = title_tag("now playing");
+        assert!(is_id3_tag(&tag));
+        assert!(!is_id3_tag(&tag[..tag.len() - 1]));
+        assert!(!is_id3_tag(b"ID3"));
+        let mut version = tag.clone();
+        version[3] = 5;
+        assert!(!is_id3_tag(&version));
+        let mut size = tag.clone();
+        size[9] |= 0x80;
+        assert!(!is_id3_tag(&size));
+        let mut magic = tag;
+        magic[0] = b'X';
+        assert!(!is_id3_tag(&magic));
+    }
+}
@@ -0,0 +1,69 @@
+//! The `iods` box, the MPEG-4 Systems initial object descriptor.
+//!
+//! ISO/IEC 14496-14 makes the box optional and modern players ignore it, but
+//! some legacy hardware players and MPEG-4 Part 1 tooling refuse files
+//! without one.
+
+use crate::{
+    boxes::BoxWriter,
+    c::{
+        e_audio, e_video, track_t, MP4_OBJECT_TYPE_AUDIO_ISO_IEC_13818_7_LC_PROFILE,
+        MP4_OBJECT_TYPE_AUDIO_ISO_IEC_13818_7_MAIN_PROFILE, MP4_OBJECT_TYPE_AUDIO_ISO_IEC_13818_7_SSR_PROFILE,
+        MP4_OBJECT_TYPE_AUDIO_ISO_IEC_14496_3,
+    },
+};
+
+/// `MP4_IOD_Tag` of ISO/IEC 14496-1.
+const MP4_IOD_TAG: u8 = 0x10;
+
+/// Profile level indication for a capability the presentation does not use.
+const PROFILE_NONE: u8 = 0xff;
+/// Profile level indication for a capability outside of the MPEG-4 profiles,
+/// such as H.264 or H.265 video.
+const PROFILE_UNSPECIFIED: u8 = 0xfe;
+/// AAC Profile, levels 2 (up to stereo) and 4 (up to 5.1), at up to 48 kHz.
+const AAC_PROFILE_L2: u8 = 0x29;
+const AAC_PROFILE_L4: u8 = 0x2a;
+
+/// Builds the `iods` box for a movie holding `tracks`.
+///
+/// The audio and visual profile level indications are derived from the codec
+/// of the first track of each kind, all other capabilities are left unused.
+pub(crate) fn iods_box<'a>(tracks: impl IntoIterator<Item = &'a track_t>) -> Vec<u8> {
+    let mut audio_profile = PROFILE_NONE;
+    let mut visual_profile = PROFILE_NONE;
+    for track in tracks {
+        let info = &track.info;
+        if info.track_media_kind == e_audio && audio_profile == PROFILE_NONE {
+            audio_profile = match info.object_type_indication {
+                MP4_OBJECT_TYPE_AUDIO_ISO_IEC_14496_3
+                | MP4_OBJECT_TYPE_AUDIO_ISO_IEC_13818_7_MAIN_PROFILE
+                | MP4_OBJECT_TYPE_AUDIO_ISO_IEC_13818_7_LC_PROFILE
+                | MP4_OBJECT_TYPE_AUDIO_ISO_IEC_13818_7_SSR_PROFILE => {
+                    if unsafe { info.u.a.channelcount } <= 2 {
+                        AAC_PROFILE_L2
+                    } else {
+                        AAC_PROFILE_L4
+                    }
+                }
+                _ => PROFILE_UNSPECIFIED,
+            };
+        } else if info.track_media_kind == e_video {
+            visual_profile = PROFILE_UNSPECIFIED;
+        }
+    }
+
+    BoxWriter::new()
+        .begin_full(b"iods", 0, 0)
+        .u8(MP4_IOD_TAG)
+        .u8(7) // descriptor size
+        // ObjectDescriptorID 1, no URL, no inline profiles, reserved bits set
+        .u16(0x004f)
+        .u8(PROFILE_NONE) // OD
+        .u8(PROFILE_NONE) // scene
+        .u8(audio_profile)
+        .u8(visual_profile)
+        .u8(PROFILE_NONE) // graphics
+        .end()
+        .finish()
+}
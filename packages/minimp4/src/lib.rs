@@ -1,158 +1,5393 @@
+mod adts;
+mod aperture;
+mod boxes;
 mod c;
+mod codecs;
+mod demux;
+mod edit;
 #[cfg(feature = "aac")]
 pub mod enc;
+mod error;
+mod faststart;
+mod fragment;
+mod id3;
+mod iods;
+mod metadata;
+mod nal;
+mod progress;
+#[cfg(feature = "aac")]
+mod recorder;
+mod relocate;
+mod remux;
+mod sink;
+mod sps;
+mod ticks;
+mod trick;
+mod validate;
+#[cfg(feature = "vp9")]
+mod vp9;
+mod webvtt;
 mod writer;
 
 use std::{
+    collections::BTreeMap,
     convert::TryInto,
     ffi::CString,
-    io::{Seek, SeekFrom, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     mem::size_of,
     os::raw::c_void,
     ptr::null_mut,
     slice::from_raw_parts,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, SystemTime},
+};
+
+use c::{
+    e_audio, e_private, e_video, minimp4_vector_put, minimp4_vector_t, mp4_h26x_write_close, mp4e_flush_index,
+    sample_t, track_t, write_pending_data, MP4E_close, MP4E_put_sample, MP4E_set_text_comment,
+    MP4E_SAMPLE_RANDOM_ACCESS, MP4_OBJECT_TYPE_HEVC,
 };
+pub use c::{mp4_h26x_write_init, mp4_h26x_writer_t, MP4E_mux_t, MP4E_open};
+pub use demux::Mp4Demuxer;
+pub use edit::EditListEntry;
+#[cfg(feature = "aac")]
+use enc::{BitRate, Encoder, EncoderError, EncoderParams, TransportFormat};
+pub use error::{Error, Minimp4Error, Minimp4Result};
+pub use faststart::FaststartStrategy;
+use fragment::{FragmentRun, TrackDefaults};
+use libc::calloc;
+use metadata::MetadataTag;
+pub use metadata::Mp4Metadata;
+pub use nal::Codec;
+use nal::StreamInfo;
+pub use progress::MuxProgress;
+use progress::Progress;
+#[cfg(feature = "aac")]
+pub use recorder::{Recorder, RecorderConfig};
+pub use relocate::shift_chunk_offsets;
+pub use remux::remux;
+pub use sink::ChunkedWriter;
+pub use ticks::Rounding;
+use ticks::TickClock;
+pub use validate::{validate_stream, VideoStreamInfo};
+#[cfg(feature = "vp9")]
+pub use vp9::VpccConfig;
+use writer::{write_mp4, write_mp4_frame_with_duration, AccessUnitWriter};
+#[cfg(feature = "aac")]
+use writer::{write_mp4_access_unit, write_mp4_with_audio, AudioTrack};
+
+/// Layout of the output, selected with [`Mp4Muxer::set_mode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mp4Mode {
+    /// All samples go to a single `mdat` followed by the `moov`. The `mdat`
+    /// size is patched on close, which requires seeking back in the output.
+    #[default]
+    Default,
+    /// Every sample is written in its own `mdat` and the `moov` is appended on
+    /// close. The output is written strictly sequentially, so it can go to a
+    /// sink that cannot seek, such as [`ChunkedWriter`].
+    Sequential,
+    /// The `moov` is written before the first sample and every sample goes to
+    /// its own movie fragment (`moof` + `mdat`), for streaming and for files
+    /// that stay playable if the recording is cut short. Also strictly
+    /// sequential. The sample duration and flags shared by most samples are
+    /// stored once in the `trex` box and left out of the fragments.
+    Fragmented,
+}
 
-use c::{mp4_h26x_write_init, mp4_h26x_writer_t, MP4E_close, MP4E_mux_t, MP4E_open, MP4E_set_text_comment};
+/// How the `tfhd` of every fragment of [`Mp4Mode::Fragmented`] output
+/// locates the sample data, selected with [`Mp4Muxer::set_fragment_base`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FragmentBase {
+    /// The `default-base-is-moof` flag: data offsets count from the start
+    /// of the `moof`, so a fragment reads the same wherever it ends up, as
+    /// CMAF requires and DASH and HLS segmenters expect.
+    #[default]
+    Moof,
+    /// An explicit `base_data_offset`, the position of the `moof` in the
+    /// output, for readers older than the flag. The fragments then only
+    /// read right at the position they were written at: cut into segments
+    /// or served by byte range, they point at the wrong data, and CMAF
+    /// does not allow them.
+    BaseDataOffset,
+}
+
+/// Where the H.264/H.265 parameter sets are stored, selected with
+/// [`Mp4Muxer::set_parameter_sets`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParameterSets {
+    /// Parameter sets are stripped from the samples and only stored in the
+    /// `avcC`/`hvcC` decoder configuration of an `avc1`/`hvc1` sample entry.
+    /// This is the smallest output and the one every player supports, but all
+    /// parameter sets of the stream end up in the configuration, so they must
+    /// not change in a way a decoder cannot follow.
+    #[default]
+    OutOfBand,
+    /// Parameter sets are kept in the samples as they appear in the stream and
+    /// the sample entry becomes `avc3`/`hev1`. The decoder configuration only
+    /// holds the distinct parameter sets preceding the first keyframe, later
+    /// ones are left to the samples. Every keyframe then
+    /// carries its own copy, typically a few dozen bytes, which lets the
+    /// parameters change mid-stream and streams be spliced, but some players,
+    /// notably Apple ones for HEVC, only accept the out-of-band entries.
+    InBand,
+}
+
+/// How the `emsg` boxes of [`Mp4Muxer::queue_event`] time their events,
+/// selected with [`Mp4Muxer::set_event_time`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EventTime {
+    /// Version 1 boxes carrying the presentation time of the event as is,
+    /// the form CMAF requires.
+    #[default]
+    Absolute,
+    /// Version 0 boxes carrying the time of the event from the start of the
+    /// fragment they precede, for older DASH players.
+    FragmentRelative,
+}
+
+/// Layout of the PCM read by [`Mp4Muxer::write_audio_from_reader`]: samples
+/// of every channel interleaved, in the channel count and sample rate of
+/// `init_audio`.
 #[cfg(feature = "aac")]
-use enc::{BitRate, EncoderParams};
-use libc::malloc;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    /// Signed 16-bit little-endian, as in WAV files.
+    S16Le,
+    /// Signed 16-bit big-endian, as in AIFF files.
+    S16Be,
+    /// 32-bit little-endian floats in [-1, 1], clipped beyond.
+    F32Le,
+}
+
 #[cfg(feature = "aac")]
-use writer::write_mp4_with_audio;
-use writer::{write_mp4, write_mp4_frame_with_duration};
+impl PcmFormat {
+    fn sample_size(self) -> usize {
+        match self {
+            PcmFormat::S16Le | PcmFormat::S16Be => 2,
+            PcmFormat::F32Le => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> i16 {
+        match self {
+            PcmFormat::S16Le => i16::from_le_bytes([bytes[0], bytes[1]]),
+            PcmFormat::S16Be => i16::from_be_bytes([bytes[0], bytes[1]]),
+            PcmFormat::F32Le => {
+                let value = f32::from_le_bytes(bytes.try_into().unwrap());
+                (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+            }
+        }
+    }
+}
+
+/// Samples written to the tracks of a muxer, see [`Mp4Muxer::summary`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mp4Summary {
+    pub tracks: Vec<TrackSummary>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackSummary {
+    pub sample_count: u32,
+    /// Sum of the sample durations, in `timescale` units.
+    pub duration: u64,
+    pub timescale: u32,
+    /// Distinct parameter sets stored in the decoder configuration of a video
+    /// track, zero for other tracks. H.264 has no VPS.
+    pub vps_count: u32,
+    pub sps_count: u32,
+    pub pps_count: u32,
+}
+
+/// Bytes of a group of pictures of fragmented output, see
+/// [`Mp4Muxer::byte_ranges`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Decode time of the keyframe starting the range, in movie timescale
+    /// units (milliseconds).
+    pub media_time: u64,
+    pub byte_offset: u64,
+    pub byte_length: u64,
+}
+
+/// A sample of a table given to [`Mp4Muxer::write_sample_table`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableSample {
+    /// Position of the sample data in the output.
+    pub offset: u64,
+    pub size: u32,
+    /// In track timescale units.
+    pub duration: u32,
+    /// Whether the sample is a sync sample, listed in the `stss`.
+    pub keyframe: bool,
+}
 
 pub struct Mp4Muxer<W> {
     writer: W,
-    muxer: *mut MP4E_mux_t,
-    muxer_writer: *mut mp4_h26x_writer_t,
-    str_buffer: Vec<CString>,
+    /// First error of `writer`, see [`Mp4Muxer::last_io_error`].
+    io_error: Option<io::Error>,
+    mode: Mp4Mode,
+    raw: RawMuxer,
+    /// Metadata set so far, the last value of every tag, ordered so that the
+    /// output does not depend on the order of the calls.
+    metadata: BTreeMap<MetadataTag, String>,
+    /// `mvhd` creation and modification time, in seconds since 1904.
+    creation_time: Option<u32>,
+    /// iTunes-style `ilst` items and other `udta` boxes copied verbatim.
+    itunes_items: Vec<Vec<u8>>,
+    udta_boxes: Vec<Vec<u8>>,
+    /// `©too` item of `set_encoder_tag`, `None` when never called.
+    encoder_tag: Option<Option<String>>,
+    /// Edit lists, by track index.
+    track_edits: Vec<(usize, Vec<EditListEntry>)>,
+    /// Durations of `set_track_duration`, by track index, in movie
+    /// timescale units.
+    track_durations: Vec<(usize, u64)>,
+    /// `tapt` box of `set_clean_aperture`, with the index of the video track.
+    track_aperture: Option<(usize, Vec<u8>)>,
+    keep_aud: bool,
+    keep_parameter_sets: bool,
+    check_resolution: bool,
+    /// `vmhd` graphics mode and opcolor, when not the default copy and black.
+    video_media_header: Option<(u16, [u16; 3])>,
+    parameter_sets: ParameterSets,
+    write_iods: bool,
+    id3_tracks: Vec<id3::Id3Track>,
+    /// Strategy of `set_faststart`, and the file staged with it once open.
+    faststart: Option<FaststartStrategy>,
+    staging: Option<faststart::Staging>,
+    /// Size of the `free` box reserved for the `moov` ahead of the `mdat`.
+    reserved_moov_size: Option<u32>,
+    /// Output position of that box, known once the `ftyp` is written.
+    reserved_moov_at: u64,
+    flush_on_close: bool,
+    webvtt_track: Option<webvtt::WebVttTrack>,
+    #[cfg(feature = "vp9")]
+    vp9_track: Option<vp9::Vp9Track>,
+    write_degradation_priorities: bool,
+    write_trick_mode_track: bool,
+    /// Track indices with the scheme URI and value of their `kind` boxes.
+    track_kinds: Vec<(usize, String, String)>,
+    assembler: Option<AccessUnitWriter>,
+    progress: Progress,
+    stream_info: StreamInfo,
+    video_timescale: u32,
+    duration_rounding: Rounding,
+    error_diffusion: bool,
+    /// Frame durations of the H.264 or H.265 track so far.
+    video_clock: TickClock,
+    /// Timescale of the audio track, its sample rate when `None`.
+    audio_timescale: Option<u32>,
+    /// Track of `write_adts_aac` and the configuration of its first frame.
+    adts_track: Option<(i32, adts::AdtsConfig)>,
+    /// `trex` defaults of fragmented output, fixed when the `moov` is written.
+    fragment_defaults: Vec<TrackDefaults>,
+    /// `trex` default sample flags of `set_default_sample_flags`, by track
+    /// index.
+    default_sample_flags: Vec<(usize, u32)>,
+    /// Total duration advertised by the `mehd` of fragmented output.
+    fragment_duration: Option<Duration>,
+    fragment_base: FragmentBase,
+    /// Ranges of `byte_ranges`, the last one growing with every fragment.
+    byte_ranges: Vec<ByteRange>,
+    /// Duration of the fragments of every track so far, in its timescale.
+    fragment_times: Vec<u64>,
+    /// Clock of the `prft` boxes of fragmented output, when written.
+    reference_clock: Option<Box<dyn FnMut() -> SystemTime>>,
+    /// Events of `queue_event` waiting for the next fragment.
+    events: Vec<fragment::Event>,
+    event_time: EventTime,
+    next_event_id: u32,
+    smooth_streaming: bool,
+    /// Start time and `moof` position of the fragments of every track that
+    /// start with a sync sample, for the `mfra` of Smooth Streaming output.
+    random_access: Vec<(u32, Vec<(u64, u64)>)>,
+    /// Output positions after which the boxes rewritten on the way moved the
+    /// data, with the total shift from there on.
+    shifts: Vec<(i64, i64)>,
     #[cfg(feature = "aac")]
     encoder_params: Option<EncoderParams>,
+    #[cfg(feature = "aac")]
+    audio: Option<AudioTrack>,
+    #[cfg(feature = "aac")]
+    interleave_window: Duration,
+    #[cfg(feature = "aac")]
+    interleave: bool,
 }
 
+/// QuickTime graphics modes accepted for the `vmhd` box: copy, dither copy,
+/// blend, transparent, straight alpha, premultiplied white alpha,
+/// premultiplied black alpha, composition and straight alpha blend.
+const GRAPHICS_MODES: [u16; 9] = [0x0000, 0x0040, 0x0020, 0x0024, 0x0100, 0x0101, 0x0102, 0x0103, 0x0104];
+
+/// Frames between two progress reports, see [`Mp4Muxer::set_progress_interval`].
+const DEFAULT_PROGRESS_INTERVAL: u64 = 30;
+
+/// iTunes-style "encoded by" item.
+const ENCODER_TAG: &[u8; 4] = b"\xa9too";
+
+/// Timescale of the `mvhd` of minimp4, in ticks per second.
+const MOVIE_TIMESCALE: u64 = 1000;
+
+/// Timescale minimp4 uses for the tracks it creates.
+const DEFAULT_TIMESCALE: u32 = 90000;
+
+/// Output position of the first sample of [`Mp4Mode::Default`], after the
+/// `ftyp` and the room minimp4 keeps for a 64-bit `mdat` header.
+const MDAT_DATA_START: u64 = 40;
+
 impl<W: Write + Seek> Mp4Muxer<W> {
+    /// Creates a muxer, runs `init` to configure it and set up its tracks and
+    /// `f` to write the samples, then closes it and returns the writer, the
+    /// [`Mp4Summary`] and the result of `f`.
+    ///
+    /// The muxer is closed even when `f` returns early with an error; should
+    /// it panic, the file is still finalized while unwinding. This saves the
+    /// explicit [`Mp4Muxer::close`] whose omission leaves a file without index.
+    pub fn with<I, F, R>(writer: W, init: I, f: F) -> (W, Mp4Summary, R)
+    where
+        I: FnOnce(&mut Self),
+        F: FnOnce(&mut Self) -> R,
+    {
+        struct CloseGuard<'a, W: Write + Seek>(&'a mut Mp4Muxer<W>);
+
+        impl<W: Write + Seek> Drop for CloseGuard<'_, W> {
+            fn drop(&mut self) {
+                self.0.close();
+            }
+        }
+
+        let mut muxer = Self::new(writer);
+        let guard = CloseGuard(&mut muxer);
+        init(guard.0);
+        let result = f(guard.0);
+        let summary = guard.0.summary();
+        drop(guard);
+        (muxer.writer, summary, result)
+    }
+
     pub fn new(writer: W) -> Self {
         unsafe {
             Self {
                 writer,
-                muxer: null_mut(),
-                muxer_writer: malloc(size_of::<mp4_h26x_writer_t>()) as *mut mp4_h26x_writer_t,
-                str_buffer: Vec::new(),
+                io_error: None,
+                mode: Mp4Mode::default(),
+                raw: RawMuxer {
+                    muxer: null_mut(),
+                    writer: calloc(1, size_of::<mp4_h26x_writer_t>()) as *mut mp4_h26x_writer_t,
+                },
+                metadata: BTreeMap::new(),
+                creation_time: None,
+                itunes_items: Vec::new(),
+                udta_boxes: Vec::new(),
+                encoder_tag: None,
+                track_edits: Vec::new(),
+                track_durations: Vec::new(),
+                track_aperture: None,
+                keep_aud: false,
+                keep_parameter_sets: false,
+                check_resolution: false,
+                video_media_header: None,
+                parameter_sets: ParameterSets::default(),
+                write_iods: false,
+                id3_tracks: Vec::new(),
+                faststart: None,
+                staging: None,
+                reserved_moov_size: None,
+                reserved_moov_at: 0,
+                flush_on_close: true,
+                webvtt_track: None,
+                #[cfg(feature = "vp9")]
+                vp9_track: None,
+                write_degradation_priorities: false,
+                write_trick_mode_track: false,
+                track_kinds: Vec::new(),
+                assembler: None,
+                progress: Progress::new(DEFAULT_PROGRESS_INTERVAL),
+                stream_info: StreamInfo::default(),
+                video_timescale: DEFAULT_TIMESCALE,
+                duration_rounding: Rounding::Floor,
+                error_diffusion: false,
+                video_clock: TickClock::default(),
+                audio_timescale: None,
+                adts_track: None,
+                fragment_defaults: Vec::new(),
+                default_sample_flags: Vec::new(),
+                fragment_duration: None,
+                fragment_base: FragmentBase::default(),
+                byte_ranges: Vec::new(),
+                fragment_times: Vec::new(),
+                reference_clock: None,
+                events: Vec::new(),
+                event_time: EventTime::default(),
+                next_event_id: 0,
+                smooth_streaming: false,
+                random_access: Vec::new(),
+                shifts: Vec::new(),
                 #[cfg(feature = "aac")]
                 encoder_params: None,
+                #[cfg(feature = "aac")]
+                audio: None,
+                #[cfg(feature = "aac")]
+                interleave_window: Duration::ZERO,
+                #[cfg(feature = "aac")]
+                interleave: true,
             }
         }
     }
 
-    pub fn init_video(&mut self, width: i32, height: i32, is_hevc: bool, track_name: &str) {
-        self.str_buffer.push(CString::new(track_name).unwrap());
-        unsafe {
-            if self.muxer.is_null() {
-                let self_ptr = self as *mut Self as *mut c_void;
-                self.muxer = MP4E_open(0, 0, self_ptr, Some(Self::write));
+    /// Wraps a minimp4 muxer set up outside of this type, with [`MP4E_open`]
+    /// and [`mp4_h26x_write_init`], taking over `muxer` and `writer_ptr`, the
+    /// state of the minimp4 H.26x writer of its video track. Fails with
+    /// [`Minimp4Error::BadArguments`] if either is null.
+    ///
+    /// The write callback `muxer` was opened with is replaced: minimp4 goes
+    /// on writing to `writer` instead, at the offsets it counts from the
+    /// start of its output, so `writer` must hold what was written so far,
+    /// the `ftyp` at least. The mode is the one `muxer` was opened for, every
+    /// other setting is at its default.
+    ///
+    /// Both are then owned by the muxer and freed exactly once, as for a
+    /// muxer created with [`Mp4Muxer::new`]: `muxer` by [`Mp4Muxer::close`],
+    /// which writes the index, or when the muxer is dropped unclosed, which
+    /// frees it without writing anything; `writer_ptr` when the muxer is
+    /// dropped, with `free`.
+    ///
+    /// # Safety
+    ///
+    /// `muxer` must come from `MP4E_open` and not be closed yet. `writer_ptr`
+    /// must come from `malloc` or `calloc` and hold an `mp4_h26x_writer_t`:
+    /// either set up with `mp4_h26x_write_init` for `muxer`, or zeroed for
+    /// [`Mp4Muxer::init_video`] to set up when there is no video track yet.
+    /// Neither may be used or freed by anything else afterwards.
+    pub unsafe fn from_raw(
+        writer: W,
+        muxer: *mut MP4E_mux_t,
+        writer_ptr: *mut mp4_h26x_writer_t,
+    ) -> Minimp4Result<Self> {
+        if muxer.is_null() || writer_ptr.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let mut this = Self::new(writer);
+        this.raw = RawMuxer {
+            muxer,
+            writer: writer_ptr,
+        };
+        this.mode = if (*muxer).enable_fragmentation != 0 {
+            Mp4Mode::Fragmented
+        } else if (*muxer).sequential_mode_flag != 0 {
+            Mp4Mode::Sequential
+        } else {
+            Mp4Mode::Default
+        };
+        Ok(this)
+    }
+
+    /// Selects the output layout. Must be called before the first track is
+    /// initialized, fails with [`Minimp4Error::BadArguments`] afterwards and
+    /// for modes other than [`Mp4Mode::Default`] with
+    /// [`Mp4Muxer::set_reserved_moov_space`].
+    pub fn set_mode(&mut self, mode: Mp4Mode) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() || (mode != Mp4Mode::Default && self.reserved_moov_size.is_some()) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Whether the output of the mode selected with [`Mp4Muxer::set_mode`]
+    /// seeks back over data already written, which only [`Mp4Mode::Default`]
+    /// does, to patch the size of its `mdat` on close and, with
+    /// [`Mp4Muxer::set_reserved_moov_space`], to write the `moov` into the
+    /// room ahead of it. With [`Mp4Muxer::set_faststart`] that seeking
+    /// happens in the staged copy and the output is written in one pass, so
+    /// [`Mp4Mode::Default`] does not seek either. Whenever this is false every
+    /// write follows the previous one, so a sink that cannot seek, such as
+    /// [`ChunkedWriter`], will do.
+    pub fn requires_seek(&self) -> bool {
+        self.mode == Mp4Mode::Default && self.faststart.is_none()
+    }
+
+    /// Writes the `moov` ahead of the media data, so that players can start
+    /// a file served over HTTP before it is fully downloaded. The `moov`
+    /// is only known on close, so the file is staged as `strategy` selects
+    /// until then and the output only written on close, in one sequential
+    /// pass: the seeking of [`Mp4Mode::Default`] happens in the staged
+    /// copy. `None`, the default, writes the output as it comes.
+    ///
+    /// Must be called before the first track is initialized, fails with
+    /// [`Minimp4Error::BadArguments`] afterwards and in
+    /// [`Mp4Mode::Fragmented`], whose `moov` comes first anyway. A temporary
+    /// file that cannot be created fails with
+    /// [`Minimp4Error::FileWriteError`], the error kept as
+    /// [`Mp4Muxer::last_io_error`]. Offsets that would outgrow an `stco` with
+    /// the `moov` in front leave it at the end.
+    pub fn set_faststart(&mut self, strategy: Option<FaststartStrategy>) -> Minimp4Result<()> {
+        let conflicts = self.mode == Mp4Mode::Fragmented || self.reserved_moov_size.is_some();
+        if !self.raw.muxer.is_null() || (strategy.is_some() && conflicts) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.staging = match strategy.map(faststart::Staging::new).transpose() {
+            Ok(staging) => staging,
+            Err(error) => {
+                self.io_error.get_or_insert(error);
+                return Err(Minimp4Error::FileWriteError);
             }
+        };
+        self.faststart = strategy;
+        Ok(())
+    }
+
+    /// Reserves `size` bytes for the `moov` right after the `ftyp`, as a
+    /// `free` box the `moov` takes the place of on close, for faststart
+    /// output written in a single pass: unlike [`Mp4Muxer::set_faststart`]
+    /// the media data goes straight to the output and nothing is copied.
+    /// The size is that of [`Mp4Muxer::moov_size`] just before close, from a
+    /// first pass over the same input into a writer that discards it, which
+    /// costs no more than building the index. A `moov` smaller than the
+    /// reservation is followed by a `free` box of the rest, which needs at
+    /// least its 8-byte header: reserve exactly the size or 8 bytes more.
+    /// `None`, the default, reserves nothing. The data of
+    /// [`Mp4Muxer::write_media_data`] goes after the reservation as well.
+    ///
+    /// A `moov` that does not fit is written at the end instead, the `free`
+    /// box left in place, so the file still plays but is no faststart file,
+    /// and close keeps an error of kind [`io::ErrorKind::InvalidInput`] for
+    /// [`Mp4Muxer::last_io_error`].
+    ///
+    /// Only for [`Mp4Mode::Default`], before the first track is initialized
+    /// and without [`Mp4Muxer::set_faststart`]; fails with
+    /// [`Minimp4Error::BadArguments`] otherwise, and for a size below the 8
+    /// bytes of a box header or beyond 32 bits.
+    pub fn set_reserved_moov_space(&mut self, size: Option<u64>) -> Minimp4Result<()> {
+        let size = size
+            .map(u32::try_from)
+            .transpose()
+            .map_err(|_| Minimp4Error::BadArguments)?;
+        let valid = size.is_none_or(|size| size >= 8 && self.mode == Mp4Mode::Default && self.faststart.is_none());
+        if !self.raw.muxer.is_null() || !valid {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.reserved_moov_size = size;
+        Ok(())
+    }
+
+    /// Sets whether [`Mp4Muxer::close`] flushes the writer once the file is
+    /// complete, as it does by default. Turned off, the last writes of the
+    /// file may stay in the buffers of a writer such as a `BufWriter` after
+    /// close, and flushing them, along with reporting whatever error that
+    /// brings, is up to the caller: for writers batching their own writes
+    /// that flush at a point of their choosing.
+    pub fn set_flush_on_close(&mut self, flush: bool) {
+        self.flush_on_close = flush;
+    }
+
+    /// Keeps access unit delimiters in the sample data.
+    ///
+    /// Delimiters are always used to find where an access unit ends, but by
+    /// default they are stripped from the samples: ISO/IEC 14496-15 does not
+    /// require them and some players fail on samples that start with one. When
+    /// kept, the samples are assembled by the muxer itself instead of minimp4,
+    /// which skips the SPS/PPS id renumbering minimp4 does for H.264. Must be
+    /// called before [`Mp4Muxer::init_video`], fails with
+    /// [`Minimp4Error::BadArguments`] afterwards.
+    pub fn set_keep_aud(&mut self, keep: bool) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.keep_aud = keep;
+        Ok(())
+    }
+
+    /// Keeps every parameter set in the samples exactly as it appears in the
+    /// stream, repeats included, for bit-exact remuxing and players that
+    /// expect them in front of every keyframe. Off by default.
+    ///
+    /// With [`ParameterSets::InBand`] the samples already keep them verbatim
+    /// and this changes nothing. With [`ParameterSets::OutOfBand`] the sample
+    /// entry stays `avc1`/`hvc1` and still holds every distinct parameter
+    /// set, so the copies in the samples must match those and nothing may
+    /// change mid-stream. As with [`Mp4Muxer::set_keep_aud`], the samples are
+    /// then assembled by the muxer without SPS/PPS id renumbering. Must be
+    /// called before [`Mp4Muxer::init_video`], fails with
+    /// [`Minimp4Error::BadArguments`] afterwards.
+    pub fn set_keep_parameter_sets(&mut self, keep: bool) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.keep_parameter_sets = keep;
+        Ok(())
+    }
+
+    /// Checks the picture size of every SPS of the stream against the width
+    /// and height of [`Mp4Muxer::init_video`]. An `avc1` or `hvc1` track has
+    /// a single resolution, so a stream changing it, as adaptive encoders do,
+    /// fails with [`Minimp4Error::ResolutionMismatch`] at the first SPS that
+    /// differs, before anything of it is written. The declared size may be
+    /// the cropped one or the coded one, whole macroblocks for H.264.
+    /// [`ParameterSets::InBand`] tracks may change resolution and are never
+    /// checked.
+    ///
+    /// Off by default, which saves parsing every SPS and keeps accepting
+    /// streams muxed with a nominal size, as the dimensions of `init_video`
+    /// were never checked before. Must be called before
+    /// [`Mp4Muxer::init_video`], fails with [`Minimp4Error::BadArguments`]
+    /// afterwards.
+    pub fn set_check_resolution(&mut self, check: bool) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.check_resolution = check;
+        Ok(())
+    }
+
+    /// Selects whether parameter sets stay in the samples, and with that the
+    /// sample entry type. Must be called before [`Mp4Muxer::init_video`],
+    /// fails with [`Minimp4Error::BadArguments`] afterwards.
+    pub fn set_parameter_sets(&mut self, parameter_sets: ParameterSets) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.parameter_sets = parameter_sets;
+        Ok(())
+    }
+
+    /// Sets the graphics mode and the red, green and blue opcolor of the
+    /// `vmhd` video media header, which tell compositing applications how to
+    /// draw the video over what lies below. Players ignore them and the
+    /// default is copy mode (`0`) with a black opcolor, as every other muxer
+    /// writes. Fails with [`Minimp4Error::BadArguments`] for a graphics mode
+    /// that is not a QuickTime transfer mode.
+    pub fn set_video_media_header(&mut self, graphics_mode: u16, opcolor: [u16; 3]) -> Minimp4Result<()> {
+        if !GRAPHICS_MODES.contains(&graphics_mode) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.video_media_header = Some((graphics_mode, opcolor)).filter(|header| *header != (0, [0; 3]));
+        Ok(())
+    }
+
+    /// Writes a QuickTime `tapt` box giving the video track a clean aperture
+    /// of `width` by `height` pixels, the part of the coded picture meant to
+    /// be shown, for Final Cut and other QuickTime tools. Other players ignore
+    /// the box, which is left out by default.
+    ///
+    /// The encoded pixels dimensions are the coded size of
+    /// [`Mp4Muxer::init_video`]. No `pasp` is written, so the pixels are square
+    /// and the production aperture is the clean one. Must be called after
+    /// `init_video`; fails with [`Minimp4Error::BadArguments`] before, or for
+    /// dimensions that are not positive or exceed the coded size.
+    pub fn set_clean_aperture(&mut self, width: f64, height: f64) -> Minimp4Result<()> {
+        let Some((index, track)) = self
+            .tracks()
+            .iter()
+            .enumerate()
+            .find(|(_, track)| track.info.track_media_kind == e_video)
+        else {
+            return Err(Minimp4Error::BadArguments);
+        };
+        let coded = unsafe { track.info.u.v };
+        let fits = |value: f64, coded: i32| value > 0.0 && value <= coded as f64;
+        if !fits(width, coded.width) || !fits(height, coded.height) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let encoded = (coded.width as f64, coded.height as f64);
+        self.track_aperture = Some((index, aperture::tapt_box((width, height), (width, height), encoded)));
+        Ok(())
+    }
+
+    /// Sets the timescale, in ticks per second, of the video track, 90 kHz by
+    /// default. Frame durations are then stored in these units: with a
+    /// timescale that is not a multiple of the frame rate they get rounded
+    /// as [`Mp4Muxer::set_duration_rounding`] says. Must be called before
+    /// [`Mp4Muxer::init_video`], fails with [`Minimp4Error::BadArguments`]
+    /// afterwards or for a zero timescale.
+    pub fn set_video_timescale(&mut self, timescale: u32) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() || timescale == 0 {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.video_timescale = timescale;
+        Ok(())
+    }
+
+    /// Sets how the frame durations of the video track, a frame rate or a
+    /// 90 kHz duration, are rounded to a whole number of ticks of its
+    /// timescale, [`Rounding::Floor`] by default. Must be called before
+    /// [`Mp4Muxer::init_video`], fails with [`Minimp4Error::BadArguments`]
+    /// afterwards.
+    ///
+    /// Each duration rounded on its own, the error adds up over the stream:
+    /// 30 fps at a timescale of 1000 come out as 33 ticks a frame, and ten
+    /// minutes of them 6 seconds short. See
+    /// [`Mp4Muxer::set_error_diffusion`] for durations that add up.
+    pub fn set_duration_rounding(&mut self, rounding: Rounding) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.duration_rounding = rounding;
+        Ok(())
+    }
+
+    /// Rounds the end of every frame of the video track, rather than its
+    /// duration, so that the remainders are carried over to the next frames
+    /// and the durations add up to the exact length of the stream rounded
+    /// once. 30 fps at a timescale of 1000 then come out as 33 and 34 ticks
+    /// in turn, the stream keeping its length however long it is. Off by
+    /// default, which keeps every frame of a constant frame rate the same
+    /// duration. Must be called before [`Mp4Muxer::init_video`], fails with
+    /// [`Minimp4Error::BadArguments`] afterwards.
+    pub fn set_error_diffusion(&mut self, diffuse: bool) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.error_diffusion = diffuse;
+        Ok(())
+    }
+
+    /// Sets the timescale of the audio track, by default its sample rate, at
+    /// which every AAC frame lasts exactly 1024 ticks. Must be
+    /// called before `init_audio` or the first [`Mp4Muxer::write_adts_aac`],
+    /// fails with [`Minimp4Error::BadArguments`] afterwards or for a zero
+    /// timescale.
+    pub fn set_audio_timescale(&mut self, timescale: u32) -> Minimp4Result<()> {
+        #[cfg(feature = "aac")]
+        let encoder_set_up = self.encoder_params.is_some();
+        #[cfg(not(feature = "aac"))]
+        let encoder_set_up = false;
+        if encoder_set_up || self.adts_track.is_some() || timescale == 0 {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.audio_timescale = Some(timescale);
+        Ok(())
+    }
+
+    /// Sets how far the video may run ahead of the audio in
+    /// [`Mp4Muxer::write_video_with_audio`] before the pending audio is
+    /// written, zero by default.
+    ///
+    /// Each track is stored in runs of consecutive samples. A zero window
+    /// writes the audio after every video frame, keeping the tracks tightly
+    /// interleaved for live streaming where a player starts from the first
+    /// bytes. A larger window, a second or so for archival files, makes fewer
+    /// and longer runs that are cheaper to read sequentially, but a player
+    /// seeking or starting playback has to read up to one window of video
+    /// before it gets to the matching audio. Ignored once interleaving is
+    /// turned off with [`Mp4Muxer::set_interleave`].
+    #[cfg(feature = "aac")]
+    pub fn set_interleave_window(&mut self, window: Duration) {
+        self.interleave_window = window;
+    }
+
+    /// Whether [`Mp4Muxer::write_video_with_audio`] interleaves the audio
+    /// with the video, as it does by default, or writes all of the audio
+    /// after the video of the call, each track one contiguous run of the
+    /// `mdat`.
+    ///
+    /// Files without interleaving play fine from a local disk, where the
+    /// player seeks between the two runs, but not over progressive download
+    /// or any other stream read front to back: the audio only arrives after
+    /// the whole of the video.
+    #[cfg(feature = "aac")]
+    pub fn set_interleave(&mut self, interleave: bool) {
+        self.interleave = interleave;
+    }
+
+    /// Adds the video track, of H.265 when `is_hevc` is set and of H.264
+    /// otherwise, followed by the AAC track of an earlier
+    /// [`Mp4Muxer::init_audio`]. Fails when the encoder of that track cannot
+    /// be set up, or minimp4 cannot add it.
+    pub fn init_video(&mut self, width: i32, height: i32, is_hevc: bool, track_name: &str) -> Result<(), Error> {
+        // minimp4 has no track names
+        let _ = track_name;
+        self.open();
+        unsafe {
             mp4_h26x_write_init(
-                self.muxer_writer,
-                self.muxer,
+                self.raw.writer,
+                self.raw.muxer,
                 width,
                 height,
                 if is_hevc { 1 } else { 0 },
             );
+            let tracks = (*self.raw.muxer).tracks.data as *mut track_t;
+            (*tracks.add((*self.raw.writer).mux_track_id as usize)).info.time_scale = self.video_timescale;
         }
+        self.video_clock = TickClock::new(self.duration_rounding, self.error_diffusion);
+        let in_band = self.parameter_sets == ParameterSets::InBand;
+        if self.check_resolution && !in_band && width > 0 && height > 0 {
+            self.stream_info.declared_size = Some((width as u32, height as u32));
+        }
+        if self.keep_aud || self.keep_parameter_sets || in_band {
+            let codec = if is_hevc { Codec::H265 } else { Codec::H264 };
+            self.assembler = Some(AccessUnitWriter::new(
+                codec,
+                self.keep_aud,
+                self.keep_parameter_sets,
+                in_band,
+            ));
+        }
+        #[cfg(feature = "aac")]
+        self.add_audio_track()?;
+        Ok(())
     }
 
+    /// Sets up the AAC track written by [`Mp4Muxer::write_video_with_audio`].
+    /// When called before [`Mp4Muxer::init_video`], the track is added right
+    /// after the video one, and [`Mp4Muxer::init_video`] reports the errors
+    /// of adding it. Fails with [`Error::Encoder`] when the encoder cannot be
+    /// set up for the parameters, right away for those outside the limits of
+    /// [`Encoder::supports`], and with [`Error::Muxer`] when minimp4 cannot
+    /// add the track.
     #[cfg(feature = "aac")]
-    pub fn init_audio(&mut self, bit_rate: u32, sample_rate: u32, channel_count: u32) {
-        self.encoder_params = Some(EncoderParams {
+    pub fn init_audio(&mut self, bit_rate: u32, sample_rate: u32, channel_count: u32) -> Result<(), Error> {
+        let encoder_params = EncoderParams {
             bit_rate: BitRate::Cbr(bit_rate),
             sample_rate,
             channel_count,
+            transport: TransportFormat::Raw,
+        };
+        if !Encoder::supports(&encoder_params) {
+            return Err(EncoderError::UNSUPPORTED_PARAMETER.into());
+        }
+        self.encoder_params = Some(encoder_params);
+        if !self.raw.muxer.is_null() {
+            self.add_audio_track()?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "aac")]
+    fn add_audio_track(&mut self) -> Result<(), Error> {
+        if let (None, Some(encoder_params)) = (&self.audio, self.encoder_params) {
+            let time_scale = self.audio_timescale.unwrap_or(encoder_params.sample_rate);
+            self.audio = Some(AudioTrack::new(self.raw.muxer, encoder_params, time_scale)?);
+        }
+        Ok(())
+    }
+
+    /// Calls `callback` with the progress of the mux every
+    /// [`Mp4Muxer::set_progress_interval`] video frames, 30 by default, to
+    /// drive a progress bar during long muxes.
+    ///
+    /// The callback runs on the Rust side of the video write methods, between
+    /// two NAL units, and never from within minimp4: should it panic, the
+    /// panic reaches the caller of the write method like any other.
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(MuxProgress) + 'static) {
+        self.progress.set_callback(Box::new(callback));
+    }
+
+    /// Sets the number of video frames between two calls of the progress
+    /// callback. Fails with [`Minimp4Error::BadArguments`] for zero.
+    pub fn set_progress_interval(&mut self, frames: u32) -> Minimp4Result<()> {
+        if frames == 0 {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.progress.set_interval(frames as u64);
+        Ok(())
+    }
+
+    /// Makes the video write methods fail with [`Minimp4Error::Aborted`] once
+    /// `flag` is set, from any thread, to cancel a mux running over its time
+    /// budget.
+    ///
+    /// minimp4 cannot be interrupted within a call, so the flag is checked on
+    /// the Rust side at frame boundaries only, before the first slice of
+    /// every video frame, once the parameter sets and SEI ahead of it are
+    /// written: the frames written up to there are complete, and audio
+    /// interleaved by [`Mp4Muxer::write_video_with_audio`] stops at the last
+    /// interleaving point. The muxer stays usable, so
+    /// [`Mp4Muxer::close`] still finalizes the partial file into a playable
+    /// one, and clearing the flag resumes writing.
+    pub fn set_abort_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.progress.set_abort_flag(flag);
+    }
+
+    /// Writes an Annex B stream at 60 frames per second.
+    ///
+    /// `data` holds NAL units behind `00 00 01` or `00 00 00 01` start codes,
+    /// of H.264 (ITU-T H.264 Annex B) for a track initialized with `is_hevc`
+    /// unset and of H.265 (ITU-T H.265 Annex B) otherwise. Its first NAL unit
+    /// is checked against the codec of the track: data that does not start
+    /// with one, or looks like the other codec, fails with
+    /// [`Minimp4Error::BadArguments`] before anything is written.
+    ///
+    /// The stream must start with its parameter sets, SPS and PPS for H.264
+    /// and additionally a VPS for H.265, before the first slice, typically
+    /// ahead of an IDR picture. A stream joined mid-GOP fails with
+    /// [`Minimp4Error::MissingParameterSets`] at the first slice, leaving the
+    /// track untouched: skip the data up to the next parameter sets and try
+    /// again. Pictures between the parameter sets and the first keyframe
+    /// cannot be decoded and are dropped.
+    ///
+    /// The samples are stored in decode order and no `ctts` is written, every
+    /// composition time being the decode time, also for the pictures of a
+    /// stream with B-frames.
+    pub fn write_video(&mut self, data: &[u8]) -> Minimp4Result<()> {
+        self.write_video_with_fps(data, 60)
+    }
+
+    /// Writes an Annex B stream, with the requirements of
+    /// [`Mp4Muxer::write_video`], interleaved with `pcm` encoded to AAC.
+    /// The audio follows the video every [`Mp4Muxer::set_interleave_window`],
+    /// after every frame by default, or comes after all of it with
+    /// [`Mp4Muxer::set_interleave`] off.
+    ///
+    /// Fails with [`Error::Encoder`] when encoding the audio fails, and with
+    /// [`Error::Muxer`] for the failures of
+    /// [`Mp4Muxer::write_video_with_fps`].
+    #[cfg(feature = "aac")]
+    pub fn write_video_with_audio(&mut self, data: &[u8], fps: u32, pcm: &[u8]) -> Result<(), Error> {
+        let fps = check_fps(fps)?;
+        self.bind();
+        let audio = self.audio.as_ref().expect("init_audio must be called first");
+        let mp4wr = unsafe { self.raw.writer.as_mut().unwrap() };
+        // without interleaving the audio waits for the end of the video
+        let window = match self.interleave {
+            true => (self.interleave_window.as_micros() * 9 / 100)
+                .try_into()
+                .unwrap_or(u64::MAX),
+            false => u64::MAX,
+        };
+        write_mp4_with_audio(
+            mp4wr,
+            self.assembler.as_mut(),
+            &mut self.progress,
+            &mut self.stream_info,
+            &mut self.video_clock,
+            fps,
+            data,
+            pcm,
+            audio,
+            window,
+        )
+    }
+
+    /// Writes an Annex B stream, with the requirements of
+    /// [`Mp4Muxer::write_video`], at `fps` frames per second. Fails with
+    /// [`Minimp4Error::BadArguments`] for a zero `fps` or one beyond
+    /// `i32::MAX`, before anything is written.
+    pub fn write_video_with_fps(&mut self, data: &[u8], fps: u32) -> Minimp4Result<()> {
+        let fps = check_fps(fps)?;
+        self.bind();
+        let mp4wr = unsafe { self.raw.writer.as_mut().unwrap() };
+        write_mp4(
+            mp4wr,
+            self.assembler.as_mut(),
+            &mut self.progress,
+            &mut self.stream_info,
+            &mut self.video_clock,
+            fps,
+            data,
+        )
+    }
+
+    /// Writes a frame made of a single Annex B NAL unit, optionally preceded
+    /// by its access unit delimiter, lasting `duration_90khz` ticks of 90 kHz
+    /// whatever the timescale of the video track.
+    ///
+    /// `data` is only borrowed for the call and the wrapper never copies it,
+    /// so it may point straight into a memory-mapped input. The NAL unit is
+    /// handed to minimp4 in place, which makes the one copy needed to turn it
+    /// into a length-prefixed sample (H.264 additionally goes through its
+    /// SPS id rewriting) before passing it to the writer. With
+    /// [`Mp4Muxer::set_keep_aud`] the sample is assembled in a buffer reused
+    /// across frames instead, and [`Mp4Mode::Sequential`] holds every sample
+    /// in memory until the next one starts.
+    ///
+    /// Parameter sets are written as frames of their own, which must come
+    /// first as for [`Mp4Muxer::write_video`]. Data not of the codec of the
+    /// track, or of more than one NAL unit besides the delimiter, such as an
+    /// SPS and its PPS or several slices of a picture, fails with
+    /// [`Minimp4Error::BadArguments`] before anything is written.
+    pub fn write_frame_with_duration(&mut self, data: &[u8], duration_90khz: u32) -> Minimp4Result<()> {
+        self.bind();
+        let mp4wr = unsafe { self.raw.writer.as_mut().unwrap() };
+        write_mp4_frame_with_duration(
+            mp4wr,
+            self.assembler.as_mut(),
+            &mut self.progress,
+            &mut self.stream_info,
+            &mut self.video_clock,
+            duration_90khz,
+            data,
+        )
+    }
+
+    /// Adds a VP9 video track of `width` by `height` pixels, fed by
+    /// [`Mp4Muxer::write_vp9_frame`], with a `vp09` sample entry whose `vpcC`
+    /// box carries `vpcc_config`. VP9 has no parameter sets to read these
+    /// from, so they have to match the stream. The track has the timescale of
+    /// [`Mp4Muxer::set_video_timescale`]; it is the only VP9 track of the
+    /// muxer and comes in addition to any H.264 or H.265 one. Fails with
+    /// [`Minimp4Error::BadArguments`] for a zero dimension, a configuration
+    /// that is not one of VP9, or when there already is a VP9 track.
+    #[cfg(feature = "vp9")]
+    pub fn init_video_vp9(&mut self, width: u16, height: u16, vpcc_config: VpccConfig) -> Minimp4Result<()> {
+        use c::{MP4E_add_track, MP4E_track_t, MP4E_track_t_AVConfig, MP4E_track_t_AVConfig_VideoConfig};
+
+        if width == 0 || height == 0 || !vpcc_config.is_valid() || self.vp9_track.is_some() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.open();
+        let track = MP4E_track_t {
+            object_type_indication: vp9::MP4_OBJECT_TYPE_VP9,
+            language: *b"und\0",
+            track_media_kind: e_video,
+            time_scale: self.video_timescale,
+            default_duration: 0,
+            u: MP4E_track_t_AVConfig {
+                v: MP4E_track_t_AVConfig_VideoConfig {
+                    width: width as i32,
+                    height: height as i32,
+                },
+            },
+        };
+        let track = unsafe { MP4E_add_track(self.raw.muxer, &track) };
+        Minimp4Error::check(track.min(0))?;
+        self.vp9_track = Some(vp9::Vp9Track {
+            track: track as usize,
+            width,
+            height,
+            config: vpcc_config,
+            clock: TickClock::new(self.duration_rounding, self.error_diffusion),
         });
+        Ok(())
+    }
+
+    /// Writes `frame`, one temporal unit of the VP9 stream as an IVF or WebM
+    /// container holds it, lasting `duration_90khz` ticks of 90 kHz. A
+    /// superframe, hidden alternate reference frames followed by the frame
+    /// shown, is one sample as a whole, and it is a sync sample when its
+    /// first frame is a key frame. Fails with [`Minimp4Error::BadArguments`]
+    /// without a track of [`Mp4Muxer::init_video_vp9`], for a zero duration
+    /// and for data that is not VP9 or does not show exactly one frame, in
+    /// which case nothing is written.
+    #[cfg(feature = "vp9")]
+    pub fn write_vp9_frame(&mut self, frame: &[u8], duration_90khz: u32) -> Minimp4Result<()> {
+        use c::MP4E_SAMPLE_DEFAULT;
+
+        self.bind();
+        let Some(vp9) = &self.vp9_track else {
+            return Err(Minimp4Error::BadArguments);
+        };
+        let (track, mut clock) = (vp9.track, vp9.clock);
+        let sync = vp9::is_sync_sample(frame)?;
+        let time_scale = self.tracks()[track].info.time_scale as u64;
+        let duration = i32::try_from(clock.next((duration_90khz as u64 * time_scale, 90000)))
+            .ok()
+            .filter(|&duration| duration != 0)
+            .ok_or(Minimp4Error::BadArguments)?;
+        let kind = if sync {
+            MP4E_SAMPLE_RANDOM_ACCESS
+        } else {
+            MP4E_SAMPLE_DEFAULT
+        };
+        Minimp4Error::check(unsafe {
+            MP4E_put_sample(
+                self.raw.muxer,
+                track as i32,
+                frame.as_ptr() as *const c_void,
+                frame.len() as i32,
+                duration,
+                kind as i32,
+            )
+        })?;
+        if let Some(vp9) = &mut self.vp9_track {
+            vp9.clock = clock;
+        }
+        Ok(())
     }
 
-    pub fn write_video(&self, data: &[u8]) {
-        self.write_video_with_fps(data, 60);
+    /// Whether the track with index `index` is the one of
+    /// [`Mp4Muxer::init_video_vp9`].
+    fn is_vp9_track(&self, index: usize) -> bool {
+        #[cfg(feature = "vp9")]
+        return self.vp9_track.as_ref().is_some_and(|vp9| vp9.track == index);
+        #[cfg(not(feature = "vp9"))]
+        {
+            let _ = index;
+            false
+        }
     }
 
+    /// Writes one access unit in Annex B framing, parameter sets included,
+    /// lasting `duration_90khz` ticks of 90 kHz.
     #[cfg(feature = "aac")]
-    pub fn write_video_with_audio(&self, data: &[u8], fps: u32, pcm: &[u8]) {
-        assert!(self.encoder_params.is_some());
-        let mp4wr = unsafe { self.muxer_writer.as_mut().unwrap() };
-        let fps = fps.try_into().unwrap();
-        let encoder_params = self.encoder_params.unwrap();
-        write_mp4_with_audio(mp4wr, fps, data, pcm, encoder_params)
+    pub(crate) fn write_access_unit(&mut self, data: &[u8], duration_90khz: u32) -> Minimp4Result<()> {
+        self.bind();
+        let mp4wr = unsafe { self.raw.writer.as_mut().unwrap() };
+        write_mp4_access_unit(
+            mp4wr,
+            self.assembler.as_mut(),
+            &mut self.progress,
+            &mut self.stream_info,
+            &mut self.video_clock,
+            duration_90khz,
+            data,
+        )
     }
 
-    pub fn write_video_with_fps(&self, data: &[u8], fps: u32) {
-        let mp4wr = unsafe { self.muxer_writer.as_mut().unwrap() };
-        let fps = fps.try_into().unwrap();
-        write_mp4(mp4wr, fps, data);
+    /// Encodes one frame of interleaved `pcm` to the track of `init_audio`.
+    /// Fails with [`Minimp4Error::BadArguments`] without such a track.
+    #[cfg(feature = "aac")]
+    pub(crate) fn write_pcm_frame(&mut self, pcm: &[i16]) -> Result<(), Error> {
+        self.bind();
+        let audio = self.audio.as_ref().ok_or(Minimp4Error::BadArguments)?;
+        audio.write_frame(self.raw.muxer, pcm)
     }
 
-    pub fn write_frame_with_duration(&self, data: &[u8], duration_90KHz: u32) {
-        let mp4wr = unsafe { self.muxer_writer.as_mut().unwrap() };
-        write_mp4_frame_with_duration(mp4wr, duration_90KHz, data);
+    /// Starts a new audio segment, after a discontinuity of the source, with
+    /// a fresh AAC encoder, so that no encoder state of the previous segment
+    /// bleeds into the next one as artifacts at the join. The encoder is
+    /// flushed first: the audio it still holds, including a frame only
+    /// partially passed to it, is written padded with silence.
+    ///
+    /// The new encoder starts with its own delay, the priming of the previous
+    /// segment stays at its start. Fails with [`Minimp4Error::BadArguments`]
+    /// without the track of `init_audio`, and with [`Error::Encoder`] when
+    /// the encoder fails.
+    #[cfg(feature = "aac")]
+    pub fn reset_audio_encoder(&mut self) -> Result<(), Error> {
+        self.bind();
+        let audio = self.audio.as_mut().ok_or(Minimp4Error::BadArguments)?;
+        audio.reset(self.raw.muxer)
     }
 
-    pub fn write_comment(&mut self, comment: &str) {
-        self.str_buffer.push(CString::new(comment).unwrap());
-        unsafe {
-            MP4E_set_text_comment(self.muxer, self.str_buffer.last().unwrap().as_ptr());
+    /// Fills a dropout of the audio source with `duration` of silence on the
+    /// track of `init_audio`, so that the audio written after it stays in
+    /// sync with the video instead of sliding earlier. The silence is
+    /// encoded as real AAC frames, as many as make up `duration` to the
+    /// nearest frame, 1024 samples each.
+    ///
+    /// Encoded silence costs a few bytes per frame but plays the same in
+    /// every player and leaves the edit list of
+    /// [`Mp4Muxer::write_audio_from_reader`] alone, whereas an empty edit in
+    /// the middle of a track, which would cost nothing, is skipped over or
+    /// mishandled by many players. Fails with [`Minimp4Error::BadArguments`]
+    /// without the track of `init_audio`, and with [`Error::Encoder`] when
+    /// the encoder fails.
+    #[cfg(feature = "aac")]
+    pub fn insert_audio_silence(&mut self, duration: Duration) -> Result<(), Error> {
+        let audio = self.audio.as_ref().ok_or(Minimp4Error::BadArguments)?;
+        let samples = duration.as_nanos() * audio.sample_rate() as u128 / 1_000_000_000;
+        let frames = (samples + 512) / 1024;
+        let silence = vec![0; audio.frame_length()];
+        for _ in 0..frames {
+            self.write_pcm_frame(&silence)?;
         }
+        Ok(())
     }
-    pub fn close(&self) -> &W {
-        unsafe {
-            MP4E_close(self.muxer);
+
+    /// Samples of all channels making up one frame of the track of
+    /// `init_audio`, once it is set up.
+    #[cfg(feature = "aac")]
+    pub(crate) fn pcm_frame_length(&self) -> Option<usize> {
+        self.audio.as_ref().map(AudioTrack::frame_length)
+    }
+
+    /// Encodes and writes the PCM read from `reader`, laid out as `format`
+    /// says, to the track of `init_audio`, reading it in chunks rather than
+    /// as a whole as [`Mp4Muxer::write_video_with_audio`] takes it, for long
+    /// recordings. Every AAC frame is written as soon as it is encoded.
+    ///
+    /// At the end of the input the last partial frame is padded with silence
+    /// and the encoder flushed, and an edit list trims the track to the
+    /// length of the PCM, leaving out the padding and the encoder delay, for
+    /// gapless playback. This is meant for the whole audio of the file in a
+    /// single call. Fails with [`Minimp4Error::BadArguments`] without an
+    /// audio track, with [`Error::Io`], the error of `reader`, when reading
+    /// fails and with [`Error::Encoder`] when the encoder does.
+    #[cfg(feature = "aac")]
+    pub fn write_audio_from_reader<R: Read>(&mut self, mut reader: R, format: PcmFormat) -> Result<(), Error> {
+        self.bind();
+        let frame_length = self.pcm_frame_length().ok_or(Minimp4Error::BadArguments)?;
+        let mut buffer = vec![0; 16 * frame_length * format.sample_size()];
+        // bytes short of a sample and samples short of a frame
+        let mut filled = 0;
+        let mut pcm = Vec::with_capacity(2 * frame_length);
+        let mut samples: u64 = 0;
+        loop {
+            let read = match reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+            filled += read;
+            let whole = filled - filled % format.sample_size();
+            pcm.extend(
+                buffer[..whole]
+                    .chunks_exact(format.sample_size())
+                    .map(|b| format.decode(b)),
+            );
+            buffer.copy_within(whole..filled, 0);
+            filled -= whole;
+            samples += (whole / format.sample_size()) as u64;
+            let frames = pcm.len() / frame_length;
+            for frame in pcm[..frames * frame_length].chunks_exact(frame_length) {
+                self.write_pcm_frame(frame)?;
+            }
+            pcm.drain(..frames * frame_length);
         }
-        &self.writer
+        if !pcm.is_empty() {
+            pcm.resize(frame_length, 0);
+            self.write_pcm_frame(&pcm)?;
+        }
+        let audio = self.audio.as_ref().unwrap();
+        audio.flush(self.raw.muxer)?;
+        let channels = (frame_length / 1024) as u64;
+        let edit = EditListEntry {
+            segment_duration: samples / channels * 1000 / audio.sample_rate() as u64,
+            media_time: audio.delay() as i64,
+            media_rate: edit::NORMAL_RATE,
+        };
+        let track = audio.track_id() as usize;
+        self.track_edits.retain(|(t, _)| *t != track);
+        self.track_edits.push((track, vec![edit]));
+        Ok(())
     }
 
-    pub fn write_data(&mut self, offset: i64, buf: &[u8]) -> u64 {
-        self.writer.seek(SeekFrom::Start(offset as u64)).unwrap();
-        self.writer.write(buf).unwrap_or(0) as u64
+    /// Writes AAC in ADTS framing, as most encoders and `.aac` files deliver
+    /// it, to an AAC track of its own without re-encoding. The track is added
+    /// on the first call, with the decoder configuration derived from the
+    /// ADTS header and the sample rate of the header as timescale; use
+    /// [`Mp4Muxer::set_audio_timescale`] before for another one.
+    ///
+    /// `adts_frame` holds one or more complete ADTS frames, with or without
+    /// CRC, each lasting `duration_ticks` in the timescale of the track, or
+    /// the 1024 samples of an AAC LC frame at the sample rate of the header
+    /// when zero. The headers are stripped and every frame becomes a sample.
+    /// Fails with [`Minimp4Error::BadArguments`], without writing anything,
+    /// for data that is not made of ADTS frames, for frames holding several
+    /// raw data blocks or no channel configuration, and for frames whose
+    /// profile, sample rate or channels differ from the first frame.
+    pub fn write_adts_aac(&mut self, adts_frame: &[u8], duration_ticks: u32) -> Minimp4Result<()> {
+        self.bind();
+        let frames = adts::split_frames(adts_frame).ok_or(Minimp4Error::BadArguments)?;
+        let Some(config) = frames.first().map(|frame| frame.config) else {
+            return Ok(());
+        };
+        let consistent = self.adts_track.is_none_or(|(_, first)| first == config);
+        if !consistent || frames.iter().any(|frame| frame.config != config) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let time_scale = self.audio_timescale.unwrap_or(config.sample_rate());
+        let duration_ticks = match duration_ticks {
+            0 => (1024 * time_scale as u64 / config.sample_rate() as u64) as u32,
+            duration => duration,
+        };
+        let track_id = match self.adts_track {
+            Some((track_id, _)) => track_id,
+            None => {
+                self.open();
+                let channel_count = match config.channel_config {
+                    7 => 8,
+                    channels => channels as u32,
+                };
+                let dsi = config.audio_specific_config();
+                let track_id = writer::add_aac_track(self.raw.muxer, time_scale, duration_ticks, channel_count, &dsi)?;
+                self.adts_track = Some((track_id, config));
+                track_id
+            }
+        };
+        for frame in frames {
+            Minimp4Error::check(unsafe {
+                MP4E_put_sample(
+                    self.raw.muxer,
+                    track_id,
+                    frame.payload.as_ptr() as *const c_void,
+                    frame.payload.len() as i32,
+                    duration_ticks as i32,
+                    MP4E_SAMPLE_RANDOM_ACCESS as i32,
+                )
+            })?;
+        }
+        Ok(())
     }
 
-    extern "C" fn write(offset: i64, buffer: *const c_void, size: u64, token: *mut c_void) -> i32 {
-        let p_self = token as *mut Self;
+    /// Adds a timed metadata track carrying ID3v2 tags, as HLS uses for "now
+    /// playing" information, and returns its index for
+    /// [`Mp4Muxer::write_id3_sample`]. The sample entry is a `urim` naming
+    /// the `https://developer.apple.com/streaming/emsg-id3` scheme, and tag
+    /// times are in `timescale` ticks per second. Fails with
+    /// [`Minimp4Error::BadArguments`] for a zero timescale and in
+    /// [`Mp4Mode::Fragmented`], whose fragments cannot leave the gaps
+    /// between tags.
+    pub fn init_id3_track(&mut self, timescale: u32) -> Minimp4Result<u32> {
+        use c::{MP4E_add_track, MP4E_track_t, MP4E_track_t_AVConfig, MP4_OBJECT_TYPE_USER_PRIVATE};
+
+        if timescale == 0 || self.mode == Mp4Mode::Fragmented {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.open();
+        let track = MP4E_track_t {
+            object_type_indication: MP4_OBJECT_TYPE_USER_PRIVATE,
+            language: *b"und\0",
+            track_media_kind: e_private,
+            time_scale: timescale,
+            default_duration: 0,
+            u: unsafe { std::mem::zeroed::<MP4E_track_t_AVConfig>() },
+        };
+        let track = unsafe { MP4E_add_track(self.raw.muxer, &track) };
+        Minimp4Error::check(track.min(0))?;
+        self.id3_tracks.push(id3::Id3Track {
+            track: track as usize,
+            time_scale: timescale,
+            start: None,
+            pending: None,
+        });
+        Ok(track as u32)
+    }
+
+    /// Adds the ID3v2 tag `id3_frame` to the track of
+    /// [`Mp4Muxer::init_id3_track`] with index `track`, presented from
+    /// `time_ticks` until the next tag, or for the last one until the end of
+    /// the longest other track. Times must increase; the track starts with
+    /// an empty edit up to the first tag. Fails with
+    /// [`Minimp4Error::BadArguments`] for another track, a time not after the
+    /// previous one or 2^31 ticks or more after it, or data that is not a
+    /// single ID3v2 tag starting with the `ID3` magic.
+    pub fn write_id3_sample(&mut self, track: u32, id3_frame: &[u8], time_ticks: u64) -> Minimp4Result<()> {
+        self.bind();
+        let Some(index) = self.id3_tracks.iter().position(|id3| id3.track == track as usize) else {
+            return Err(Minimp4Error::BadArguments);
+        };
+        if !id3::is_id3_tag(id3_frame) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let id3 = &mut self.id3_tracks[index];
+        if let Some((_, pending_time)) = &id3.pending {
+            let duration = time_ticks
+                .checked_sub(*pending_time)
+                .filter(|&duration| duration != 0)
+                .and_then(|duration| i32::try_from(duration).ok())
+                .ok_or(Minimp4Error::BadArguments)?;
+            let (tag, _) = id3.pending.take().unwrap();
+            Minimp4Error::check(Self::put_sync_sample(self.raw.muxer, track as i32, &tag, duration))?;
+        }
+        let id3 = &mut self.id3_tracks[index];
+        id3.start.get_or_insert(time_ticks);
+        id3.pending = Some((id3_frame.to_vec(), time_ticks));
+        Ok(())
+    }
+
+    fn put_sync_sample(muxer: *mut MP4E_mux_t, track: i32, data: &[u8], duration: i32) -> i32 {
         unsafe {
-            let buf = from_raw_parts(buffer as *const u8, size as usize);
-            ((*p_self).write_data(offset, buf) != size) as i32
+            MP4E_put_sample(
+                muxer,
+                track,
+                data.as_ptr() as *const c_void,
+                data.len() as i32,
+                duration,
+                MP4E_SAMPLE_RANDOM_ACCESS as i32,
+            )
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::io::Cursor;
+    /// Adds a WebVTT subtitle track, the `wvtt` sample entry CMAF and DASH
+    /// deliver subtitles with, and returns its index. Its `vttC` holds the
+    /// bare `WEBVTT` header, cues are written with
+    /// [`Mp4Muxer::write_vtt_cue`] and times are in milliseconds. Fails with
+    /// [`Minimp4Error::BadArguments`] when there already is a WebVTT track.
+    pub fn init_webvtt_track(&mut self) -> Minimp4Result<u32> {
+        use c::{MP4E_add_track, MP4E_track_t, MP4E_track_t_AVConfig, MP4_OBJECT_TYPE_USER_PRIVATE};
 
-    use super::*;
+        if self.webvtt_track.is_some() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.open();
+        let track = MP4E_track_t {
+            object_type_indication: MP4_OBJECT_TYPE_USER_PRIVATE,
+            language: *b"und\0",
+            track_media_kind: e_private,
+            time_scale: webvtt::TIMESCALE,
+            default_duration: 0,
+            u: unsafe { std::mem::zeroed::<MP4E_track_t_AVConfig>() },
+        };
+        let track = unsafe { MP4E_add_track(self.raw.muxer, &track) };
+        Minimp4Error::check(track.min(0))?;
+        self.webvtt_track = Some(webvtt::WebVttTrack::new(track as usize));
+        Ok(track as u32)
+    }
 
-    #[test]
-    fn test_muxer() {
-        let mut muxer = Mp4Muxer::new(Cursor::new(Vec::new()));
-        muxer.init_video(1280, 720, false, "test");
-        muxer.write_video(&[0; 100]);
-        muxer.write_comment("test comment");
-        muxer.close();
-        assert_eq!(muxer.writer.into_inner().len(), 257);
+    /// Adds the cue `cue_text`, the payload of a WebVTT cue without its
+    /// timings, shown from `start` for `duration` on the track of
+    /// [`Mp4Muxer::init_webvtt_track`]. Cues must come in the order they
+    /// start and may overlap, the samples then carry every cue shown during
+    /// them. The timeline has no gaps: `vtte` empty cues fill the time
+    /// between cues, from the start of the file on. A sample is written once
+    /// no later cue can change it, at the latest on close. Fails with
+    /// [`Minimp4Error::BadArguments`] without a WebVTT track, for a cue that
+    /// starts before the previous one, lasts no millisecond or more than
+    /// 2^31 of them, or that many after the end of the previous samples.
+    pub fn write_vtt_cue(&mut self, cue_text: &str, start: Duration, duration: Duration) -> Minimp4Result<()> {
+        self.bind();
+        let Some(webvtt) = &mut self.webvtt_track else {
+            return Err(Minimp4Error::BadArguments);
+        };
+        let start_ms = start.as_millis().try_into().unwrap_or(u64::MAX);
+        let end = start.checked_add(duration).ok_or(Minimp4Error::BadArguments)?;
+        let end_ms = end.as_millis().try_into().unwrap_or(u64::MAX);
+        let samples = webvtt.add_cue(cue_text, start_ms, end_ms)?;
+        let track = webvtt.track;
+        self.put_vtt_samples(track, &samples)
     }
 
-    #[test]
-    #[cfg(feature = "aac")]
-    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
-    fn test_mux_h264_audio() {
-        use std::{fs::write, path::Path};
-        let mut buffer = Cursor::new(vec![]);
-        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
-        let h264 = include_bytes!("./fixtures/input.264");
-        let pcm = include_bytes!("./fixtures/input.pcm");
-        mp4muxer.init_video(1280, 720, false, "h264 stream");
-        mp4muxer.init_audio(128000, 44100, 2);
-        mp4muxer.write_video_with_audio(h264, 25, pcm);
-        mp4muxer.write_comment("test comment");
-        mp4muxer.close();
-        // write with audio has not stable output, need to be check later
-        write(Path::new("./src/fixtures/h264_output.tmp"), buffer.into_inner()).unwrap();
+    fn put_vtt_samples(&self, track: usize, samples: &[(Vec<u8>, u32)]) -> Minimp4Result<()> {
+        for (data, duration) in samples {
+            Minimp4Error::check(Self::put_sync_sample(
+                self.raw.muxer,
+                track as i32,
+                data,
+                *duration as i32,
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last tag of every ID3 track, lasting until the end of the
+    /// longest other track or a tick, and the edit delaying the track to its
+    /// first tag.
+    fn finish_id3_tracks(&mut self) {
+        let durations = self
+            .tracks()
+            .iter()
+            .filter(|track| track.info.track_media_kind != e_private)
+            .map(|track| {
+                let duration: u64 = samples(track).iter().map(|sample| sample.duration as u64).sum();
+                (duration, track.info.time_scale as u64)
+            })
+            .collect::<Vec<_>>();
+        for index in 0..self.id3_tracks.len() {
+            let id3 = &mut self.id3_tracks[index];
+            let Some((tag, time)) = id3.pending.take() else {
+                continue;
+            };
+            let time_scale = id3.time_scale as u64;
+            let end = durations
+                .iter()
+                .map(|(duration, other)| duration * time_scale / other)
+                .max()
+                .unwrap_or(0);
+            let duration = end.saturating_sub(time).clamp(1, i32::MAX as u64) as i32;
+            let (track, start) = (id3.track, id3.start.unwrap_or(0));
+            if Self::put_sync_sample(self.raw.muxer, track as i32, &tag, duration) != 0 || start == 0 {
+                continue;
+            }
+            let media: u64 = samples(&self.tracks()[track])
+                .iter()
+                .map(|sample| sample.duration as u64)
+                .sum();
+            let edits = vec![
+                EditListEntry {
+                    segment_duration: start * MOVIE_TIMESCALE / time_scale,
+                    media_time: -1,
+                    media_rate: edit::NORMAL_RATE,
+                },
+                EditListEntry {
+                    segment_duration: media * MOVIE_TIMESCALE / time_scale,
+                    media_time: 0,
+                    media_rate: edit::NORMAL_RATE,
+                },
+            ];
+            self.track_edits.retain(|(t, _)| *t != track);
+            self.track_edits.push((track, edits));
+        }
+    }
+
+    /// Appends `data` to the `mdat` as is and returns the output position it
+    /// starts at, for the samples of [`Mp4Muxer::write_sample_table`] to
+    /// point into: a remux copies the media of its source in as few calls as
+    /// it likes and shifts the source offsets by the difference. Only for
+    /// [`Mp4Mode::Default`], whose single `mdat` can hold data of any layout;
+    /// fails with [`Minimp4Error::BadArguments`] in the other modes and
+    /// before the muxer is initialized.
+    ///
+    /// With [`Mp4Muxer::set_reserved_moov_space`] the position returned is
+    /// the one before the reservation, as are those of the samples: the
+    /// data lands that many bytes later and the chunk offsets are moved
+    /// along when the `moov` is written.
+    pub fn write_media_data(&mut self, data: &[u8]) -> Minimp4Result<u64> {
+        if self.raw.muxer.is_null() || self.mode != Mp4Mode::Default {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.bind();
+        let position = unsafe { (*self.raw.muxer).write_pos };
+        if self.write_data(self.shifted(position), data) != data.len() as u64 {
+            return Err(Minimp4Error::FileWriteError);
+        }
+        unsafe { (*self.raw.muxer).write_pos += data.len() as i64 };
+        Ok(position as u64)
+    }
+
+    /// Adds `samples` to the sample table of `track`, the index of the track
+    /// in the order the tracks were initialized, after the samples it already
+    /// holds. The whole table is stored at once, without going through
+    /// minimp4 sample by sample, for remuxes that only change the container
+    /// and keep the media laid out as it is.
+    ///
+    /// Nothing checks the data itself: the track must have its decoder
+    /// configuration, from the parameter sets passed to
+    /// [`Mp4Muxer::write_video`] for instance, and the samples must point at
+    /// media of the track. Only for [`Mp4Mode::Default`]; fails with
+    /// [`Minimp4Error::BadArguments`] otherwise, as well as for a table whose
+    /// samples are empty, last no time, lie outside the media written or
+    /// overlap one another, or the samples the track already holds, or run
+    /// backwards in the output.
+    pub fn write_sample_table(&mut self, track: u32, samples: &[TableSample]) -> Minimp4Result<()> {
+        let track = track as usize;
+        if self.mode != Mp4Mode::Default || track >= self.tracks().len() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.bind();
+        let written = unsafe { (*self.raw.muxer).write_pos as u64 };
+        let last = self::samples(&self.tracks()[track]).last();
+        let mut end = last.map_or(MDAT_DATA_START, |sample| sample.offset + sample.size);
+        for sample in samples {
+            if sample.size == 0 || sample.duration == 0 || sample.offset < end {
+                return Err(Minimp4Error::BadArguments);
+            }
+            end = sample.offset + sample.size as u64;
+        }
+        if end > written {
+            return Err(Minimp4Error::BadArguments);
+        }
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let table: Vec<sample_t> = samples
+            .iter()
+            .map(|sample| sample_t {
+                size: sample.size as u64,
+                offset: sample.offset,
+                duration: sample.duration,
+                flag_random_access: sample.keyframe as u32,
+            })
+            .collect();
+        let put = unsafe {
+            let track = ((*self.raw.muxer).tracks.data as *mut track_t).add(track);
+            minimp4_vector_put(
+                &mut (*track).smpl,
+                table.as_ptr() as *const c_void,
+                (table.len() * size_of::<sample_t>()) as i32,
+            )
+        };
+        if put.is_null() {
+            return Err(Minimp4Error::NoMemory);
+        }
+        Ok(())
+    }
+
+    /// Sets the movie comment, which minimp4 writes as an iTunes-style
+    /// `©cmt` item. Setting it again replaces it; it must be set before the
+    /// `moov` is written, on close or for [`Mp4Mode::Fragmented`] with the
+    /// first sample. minimp4 takes the comment as a C string, so it is cut
+    /// at its first NUL.
+    pub fn write_comment(&mut self, comment: &str) {
+        let comment = comment.split('\0').next().unwrap_or_default();
+        self.metadata.insert(MetadataTag::Comment, comment.to_owned());
+        self.set_text_comment();
+    }
+
+    /// Hands the comment to minimp4, which keeps a copy of its own.
+    fn set_text_comment(&mut self) {
+        if let (false, Some(comment)) = (self.raw.muxer.is_null(), self.metadata.get(&MetadataTag::Comment)) {
+            let comment = CString::new(comment.as_str()).expect("the comment is cut at its first NUL");
+            unsafe {
+                MP4E_set_text_comment(self.raw.muxer, comment.as_ptr());
+            }
+        }
+    }
+
+    /// Sets a comment on a single track, written on close as a QuickTime
+    /// `©cmt` text box in the `udta` of its `trak`, where
+    /// [`Mp4Muxer::write_comment`] describes the whole movie. `track` is the
+    /// index of the track in the order the tracks were initialized, as in
+    /// [`Mp4Muxer::summary`]. Fails with [`Minimp4Error::BadArguments`] if
+    /// there is no such track or if `text` holds a NUL byte or does not fit
+    /// the 64 KiB of the box. Setting a comment again replaces it.
+    pub fn set_track_comment(&mut self, track: u32, text: &str) -> Minimp4Result<()> {
+        let track = track as usize;
+        if track >= self.tracks().len() || text.contains('\0') || text.len() > u16::MAX as usize {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.metadata.insert(MetadataTag::TrackComment(track), text.to_owned());
+        Ok(())
+    }
+
+    /// Sets the role of a track, written on close as a `kind` box in the
+    /// `udta` of its `trak`: a `scheme` URI and a `value` of that scheme,
+    /// such as `urn:mpeg:dash:role:2011` with `main`, `alternate`,
+    /// `commentary`, `caption`, `subtitle` or `description`, which
+    /// accessibility-aware players use to pick audio description or caption
+    /// tracks among the tracks of an alternate group. `track` is an index as
+    /// in [`Mp4Muxer::summary`].
+    ///
+    /// A track may have roles of several schemes, setting one of the same
+    /// scheme again replaces it. Fails with [`Minimp4Error::BadArguments`] if
+    /// there is no such track, `scheme` is empty or either string holds a
+    /// NUL byte.
+    pub fn set_track_kind(&mut self, track: u32, scheme: &str, value: &str) -> Minimp4Result<()> {
+        let track = track as usize;
+        if track >= self.tracks().len() || scheme.is_empty() || scheme.contains('\0') || value.contains('\0') {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let kind = (track, scheme.to_owned(), value.to_owned());
+        match self.track_kinds.iter_mut().find(|(t, s, _)| *t == track && s == scheme) {
+            Some(existing) => *existing = kind,
+            None => self.track_kinds.push(kind),
+        }
+        Ok(())
+    }
+
+    /// Sets the edit list of `track`, an index as in [`Mp4Muxer::summary`],
+    /// written on close as its `edts` box: normal, empty and dwell entries
+    /// as [`EditListEntry`] describes them, segment durations in the movie
+    /// timescale of minimp4, milliseconds, and media times in the timescale
+    /// of the track. An empty list removes the edit list of the track, so
+    /// that it plays its media from the start. Setting a list again replaces
+    /// it, as does an edit the muxer sets on its own afterwards, such as the
+    /// priming delay of [`Mp4Muxer::write_audio_from_reader`]. Fails with
+    /// [`Minimp4Error::BadArguments`] if there is no such track or for an
+    /// entry of none of the three kinds, a rate of neither 0 nor 0x10000 or
+    /// a negative media time other than the -1 of an empty edit at normal
+    /// speed.
+    pub fn set_edit_list(&mut self, track: u32, edits: &[EditListEntry]) -> Minimp4Result<()> {
+        let track = track as usize;
+        if track >= self.tracks().len() || !edits.iter().all(EditListEntry::is_valid) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.track_edits.retain(|(t, _)| *t != track);
+        if !edits.is_empty() {
+            self.track_edits.push((track, edits.to_vec()));
+        }
+        Ok(())
+    }
+
+    /// Gives `track`, an index as in [`Mp4Muxer::summary`], the edit list of
+    /// track `source_track` of `demuxer`, so that a remux keeps the A/V
+    /// offset and trimming the source corrected for. Segment durations are
+    /// converted from the movie timescale of the source to the one of
+    /// minimp4 and media times from the timescale of the source track to
+    /// the one of `track`, rounding down; rates are kept. A source track
+    /// without edit list removes the one of `track`. The list is written on
+    /// close; an edit the muxer sets on its own afterwards, such as the
+    /// priming delay of [`Mp4Muxer::write_audio_from_reader`], replaces it.
+    /// Fails with [`Minimp4Error::BadArguments`] if either track
+    /// does not exist.
+    pub fn copy_edit_list_from<R: Read + Seek>(
+        &mut self,
+        track: u32,
+        demuxer: &Mp4Demuxer<R>,
+        source_track: u32,
+    ) -> Minimp4Result<()> {
+        let Some(target) = self.tracks().get(track as usize) else {
+            return Err(Minimp4Error::BadArguments);
+        };
+        let edits = demuxer.edit_list(source_track)?;
+        let source_timescale = demuxer.track_timescale(source_track)? as u128;
+        let movie_timescale = demuxer.movie_timescale() as u128;
+        let time_scale = target.info.time_scale as u128;
+        let convert = |value: u128, from: u128, to: u128| match from {
+            0 => 0,
+            from => value * to / from,
+        };
+        let edits = edits
+            .iter()
+            .map(|edit| EditListEntry {
+                segment_duration: convert(edit.segment_duration as u128, movie_timescale, MOVIE_TIMESCALE as u128)
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+                media_time: match edit.media_time {
+                    time if time < 0 => time,
+                    time => convert(time as u128, source_timescale, time_scale)
+                        .try_into()
+                        .unwrap_or(i64::MAX),
+                },
+                media_rate: edit.media_rate,
+            })
+            .collect::<Vec<_>>();
+        let track = track as usize;
+        self.track_edits.retain(|(t, _)| *t != track);
+        if !edits.is_empty() {
+            self.track_edits.push((track, edits));
+        }
+        Ok(())
+    }
+
+    /// Gives `track`, an index as in [`Mp4Muxer::summary`], a presentation
+    /// duration longer than its samples last, to match the exact length of
+    /// another track for instance. The samples keep their durations; on
+    /// close the edit list of the track, the one set for it or else a normal
+    /// edit playing all of its media, gets an empty edit appended for the
+    /// difference, and the `tkhd`, along with the `mvhd` if shorter, takes
+    /// the duration. Players show nothing of the track during the padding:
+    /// the last picture goes away, audio is silent.
+    ///
+    /// `duration` is stored in milliseconds, the movie timescale of minimp4,
+    /// and must not be shorter than the samples written so far: call this
+    /// once they are all written. Should samples written afterwards, or the
+    /// edit list, reach beyond it, no padding is added. Fails with
+    /// [`Minimp4Error::BadArguments`] if there is no such track, for a
+    /// shorter duration and in [`Mp4Mode::Fragmented`], whose `moov` goes
+    /// out before the samples.
+    pub fn set_track_duration(&mut self, track: u32, duration: Duration) -> Minimp4Result<()> {
+        let index = track as usize;
+        let Some(track) = self.tracks().get(index) else {
+            return Err(Minimp4Error::BadArguments);
+        };
+        let media: u64 = samples(track).iter().map(|sample| sample.duration as u64).sum();
+        let shorter = duration.as_nanos() * (track.info.time_scale as u128) < media as u128 * 1_000_000_000;
+        if self.mode == Mp4Mode::Fragmented || shorter {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let duration = duration.as_millis().try_into().unwrap_or(u64::MAX);
+        self.track_durations.retain(|(t, _)| *t != index);
+        self.track_durations.push((index, duration));
+        Ok(())
+    }
+
+    /// The edit list written for track `index`: the one set for it, padded
+    /// up to the duration of [`Mp4Muxer::set_track_duration`], if any.
+    fn edit_list(&self, index: usize) -> Option<Vec<EditListEntry>> {
+        let edits = self
+            .track_edits
+            .iter()
+            .find(|(t, _)| *t == index)
+            .map(|(_, edits)| edits);
+        let Some(&(_, duration)) = self.track_durations.iter().find(|(t, _)| *t == index) else {
+            return edits.cloned();
+        };
+        let mut edits = edits.cloned().unwrap_or_else(|| {
+            let track = &self.tracks()[index];
+            let media: u64 = samples(track).iter().map(|sample| sample.duration as u64).sum();
+            vec![EditListEntry::normal(
+                media * MOVIE_TIMESCALE / track.info.time_scale.max(1) as u64,
+                0,
+            )]
+        });
+        let total: u64 = edits.iter().map(|edit| edit.segment_duration).sum();
+        if duration > total {
+            edits.push(EditListEntry::empty(duration - total));
+        }
+        Some(edits)
+    }
+
+    /// Sets a QuickTime metadata item, keyed by a reverse-DNS name such as
+    /// `com.apple.quicktime.make`.
+    ///
+    /// Items are written on close as a `moov/meta` box holding a `keys` table
+    /// and an `ilst` whose items refer to the keys by index, the layout iPhone
+    /// recordings use for make, model, software and location. This is separate
+    /// from the iTunes-style `udta` tags used by [`Mp4Muxer::write_comment`].
+    /// Setting a key again replaces its value.
+    pub fn set_qt_metadata(&mut self, key: &str, value: &str) -> Minimp4Result<()> {
+        if !metadata::is_reverse_dns(key) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.metadata
+            .insert(MetadataTag::QuickTime(key.to_owned()), value.to_owned());
+        Ok(())
+    }
+
+    /// Sets the recording location, written on close as an ISO 6709 string in
+    /// the `moov/udta/©xyz` box as mobile devices do.
+    ///
+    /// `latitude` and `longitude` are in decimal degrees, positive north and
+    /// east, and `altitude` is in meters above sea level.
+    pub fn set_location(&mut self, latitude: f64, longitude: f64, altitude: Option<f64>) -> Minimp4Result<()> {
+        let location = metadata::iso6709(latitude, longitude, altitude).ok_or(Minimp4Error::BadArguments)?;
+        self.metadata.insert(MetadataTag::Location, location);
+        Ok(())
+    }
+
+    /// Sets the iTunes-style `©too` "encoded by" item of `moov/udta/meta`.
+    ///
+    /// minimp4 writes none, and neither is one written unless set here, so
+    /// that the output only depends on the input; `None` also drops one
+    /// carried over by [`Mp4Muxer::copy_metadata_from`], for reproducible
+    /// files that do not tell the encoder.
+    pub fn set_encoder_tag(&mut self, value: Option<&str>) {
+        self.encoder_tag = Some(value.map(str::to_owned));
+    }
+
+    /// Copies the movie metadata of `demuxer` for a remux: comment, location,
+    /// creation time and QuickTime metadata items go through the setters of
+    /// this muxer, replacing what they had set, while the other iTunes-style
+    /// items and `udta` boxes are written back verbatim. Per-track metadata
+    /// is not copied.
+    ///
+    /// Call this after the first track is initialized; fails with
+    /// [`Minimp4Error::BadArguments`] before.
+    /// A creation time beyond 2040, which needs a 64-bit `mvhd`, is dropped.
+    pub fn copy_metadata_from<R: Read + Seek>(&mut self, demuxer: &Mp4Demuxer<R>) -> Minimp4Result<()> {
+        if self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let metadata = demuxer.metadata();
+        if let Some(comment) = &metadata.comment {
+            self.write_comment(comment);
+        }
+        if let Some(location) = &metadata.location {
+            self.metadata.insert(MetadataTag::Location, location.clone());
+        }
+        self.creation_time = metadata.creation_time.and_then(|time| time.try_into().ok());
+        for (key, value) in &metadata.qt_metadata {
+            // keys of other files need not be reverse-DNS names
+            self.metadata.insert(MetadataTag::QuickTime(key.clone()), value.clone());
+        }
+        self.itunes_items.extend(metadata.itunes_items.iter().cloned());
+        self.udta_boxes.extend(metadata.udta_boxes.iter().cloned());
+        Ok(())
+    }
+
+    /// Writes an `iods` box, with profile level indications derived from the
+    /// track codecs, for legacy MPEG-4 players that require one. Off by
+    /// default as modern players do not need it and a few reject it.
+    pub fn set_write_iods(&mut self, write: bool) {
+        self.write_iods = write;
+    }
+
+    /// Writes an `stdp` box with the degradation priority of every video
+    /// sample, which some streaming servers use to pick the samples to drop
+    /// under congestion: 2 for keyframes, 1 for the other pictures that may be
+    /// referenced and 0 for non-reference pictures, dropped first. Off by
+    /// default as players ignore it. Fragmented output has no sample table
+    /// to hold it and never gets one.
+    pub fn set_write_degradation_priorities(&mut self, write: bool) {
+        self.write_degradation_priorities = write;
+    }
+
+    /// Writes a trick mode track for every video track: a second track of
+    /// its keyframes only, every one lasting until the next, for fast
+    /// forward and scrubbing by decoding keyframes alone. Its samples point
+    /// at the keyframes of the video track, so the media data is not
+    /// duplicated, only the sample table. Off by default.
+    ///
+    /// The trick mode track is disabled and shares an alternate group with
+    /// its video track, so players unaware of it keep playing the video
+    /// track. Few players look for one on their own: it is meant for
+    /// applications that pick it explicitly, and for DASH packagers that
+    /// signal it with a trick mode property in the manifest. A video track
+    /// that does not start with a keyframe gets none, and fragmented output
+    /// has no sample table to hold one.
+    pub fn set_write_trick_mode_track(&mut self, write: bool) {
+        self.write_trick_mode_track = write;
+    }
+
+    /// Returns `true` once at least one track holds at least one sample.
+    ///
+    /// minimp4 leaves tracks without samples out of the `moov` box, so closing
+    /// a muxer that never received a sample produces a file whose `moov` only
+    /// carries the `mvhd` header and no `trak` at all. Many players reject such
+    /// files, so check this before calling [`Mp4Muxer::close`].
+    pub fn is_ready_to_close(&self) -> bool {
+        self.tracks().iter().any(has_samples)
+    }
+
+    /// Returns the number of samples and the duration of every track written
+    /// so far, in the order the tracks were created. Empty once the muxer is
+    /// closed.
+    pub fn summary(&self) -> Mp4Summary {
+        let tracks = self
+            .tracks()
+            .iter()
+            .map(|track| {
+                let samples = samples(track);
+                // minimp4 keeps the decoder specific info of audio tracks
+                // with the SPS
+                let parameter_sets = |list| {
+                    if track.info.track_media_kind == e_video {
+                        items_count(list)
+                    } else {
+                        0
+                    }
+                };
+                TrackSummary {
+                    sample_count: samples.len() as u32,
+                    duration: samples.iter().map(|sample| sample.duration as u64).sum(),
+                    timescale: track.info.time_scale,
+                    vps_count: parameter_sets(&track.vvps),
+                    sps_count: parameter_sets(&track.vsps),
+                    pps_count: parameter_sets(&track.vpps),
+                }
+            })
+            .collect();
+        Mp4Summary { tracks }
+    }
+
+    /// Returns the number of NAL units of the H.264 or H.265 input left out
+    /// of the video samples, so that the NAL units written add up to those
+    /// in the samples and these: access unit delimiters, unless kept with
+    /// [`Mp4Muxer::set_keep_aud`], parameter sets only kept in the decoder
+    /// configuration, unless kept with [`Mp4Muxer::set_keep_parameter_sets`],
+    /// repeats included, and whatever comes ahead of the
+    /// first keyframe, which cannot be decoded. The NAL unit a write fails
+    /// on, such as a slice without parameter sets, is not counted.
+    pub fn skipped_nals(&self) -> usize {
+        self.stream_info.skipped.values().sum()
+    }
+
+    /// The NAL units of [`Mp4Muxer::skipped_nals`] by header type, the
+    /// `nal_unit_type` of H.264 or H.265.
+    pub fn skipped_nals_by_type(&self) -> &BTreeMap<u8, usize> {
+        &self.stream_info.skipped
+    }
+
+    /// RFC 6381 codec string of `track`, an index as in
+    /// [`Mp4Muxer::summary`], for the `CODECS` of an HLS playlist or the
+    /// `codecs` of a DASH manifest: `avc1.640028` for H.264, `hvc1.1.6.L120.90`
+    /// style for H.265, the profile, tier and level read from its first SPS,
+    /// with the `avc3`/`hev1` entry types of [`ParameterSets::InBand`], and
+    /// `mp4a.40.2` for AAC, the audio object type read from its decoder
+    /// specific info, `vp09.PP.LL.DD` for VP9, from its `vpcC`
+    /// configuration, and `wvtt` for WebVTT. `None` without such a track, for tracks of other kinds
+    /// and before the configuration is known, that is before the first SPS of
+    /// a video track.
+    pub fn codec_string(&self, track: u32) -> Option<String> {
+        let index = track as usize;
+        let track = self.tracks().get(index)?;
+        let config = first_item(&track.vsps);
+        let in_band = self.parameter_sets == ParameterSets::InBand;
+        let kind = track.info.track_media_kind;
+        #[cfg(feature = "vp9")]
+        if let Some(vp9) = self.vp9_track.as_ref().filter(|vp9| vp9.track == index) {
+            return Some(vp9.config.codec_string());
+        }
+        if self.webvtt_track.as_ref().is_some_and(|webvtt| webvtt.track == index) {
+            return Some("wvtt".to_string());
+        }
+        if kind == e_video && track.info.object_type_indication == MP4_OBJECT_TYPE_HEVC {
+            codecs::hevc(config?, in_band)
+        } else if kind == e_video {
+            codecs::avc(config?, in_band)
+        } else if kind == e_audio {
+            Some(codecs::mp4a(
+                track.info.object_type_indication,
+                config.unwrap_or_default(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Sanity-checks what has been written so far, returning every problem
+    /// found. The `stsz`, `stts`, `stss` and `stco` tables are all built from
+    /// one list of samples, so they cannot disagree on the sample count; the
+    /// checks are on the content of that list and on the decoder
+    /// configurations:
+    ///
+    /// - every track holds samples, tracks without any are left out of the
+    ///   file,
+    /// - every track has a decoder configuration, the parameter sets of a
+    ///   video track or the decoder specific info of an audio track,
+    /// - sample durations are non-zero,
+    /// - a video track starts with a keyframe,
+    /// - samples follow each other in the output and lie within the data
+    ///   written.
+    ///
+    /// Fragmented output only keeps the decoder configurations, so only these
+    /// are checked.
+    pub fn verify(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        let fragmented = self.mode == Mp4Mode::Fragmented;
+        let written = if self.raw.muxer.is_null() {
+            0
+        } else {
+            unsafe { (*self.raw.muxer).write_pos as u64 }
+        };
+        for (index, track) in self.tracks().iter().enumerate() {
+            let info = &track.info;
+            let video = info.track_media_kind == e_video;
+            let configured = if info.track_media_kind == e_private || self.is_vp9_track(index) {
+                // ID3 tracks have no decoder configuration, nor VP9 ones
+                // beyond the vpcC written with the moov
+                true
+            } else if !video {
+                track.vsps.bytes > 0
+            } else {
+                let hevc = info.object_type_indication == MP4_OBJECT_TYPE_HEVC;
+                track.vsps.bytes > 0 && track.vpps.bytes > 0 && (!hevc || track.vvps.bytes > 0)
+            };
+            if !configured {
+                problems.push(format!("track {index} has no decoder configuration"));
+            }
+            if fragmented {
+                continue;
+            }
+
+            let samples = samples(track);
+            if samples.is_empty() {
+                problems.push(format!("track {index} has no samples"));
+                continue;
+            }
+            let zero_durations = samples.iter().filter(|sample| sample.duration == 0).count();
+            if zero_durations > 0 {
+                problems.push(format!("track {index} has {zero_durations} samples without duration"));
+            }
+            if video && samples[0].flag_random_access == 0 {
+                problems.push(format!("track {index} does not start with a keyframe"));
+            }
+            let mut end = 0;
+            for (number, sample) in samples.iter().enumerate() {
+                if sample.offset < end {
+                    problems.push(format!("sample {number} of track {index} overlaps the previous one"));
+                }
+                end = sample.offset + sample.size;
+                if end > written {
+                    problems.push(format!("sample {number} of track {index} lies beyond the written data"));
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Advertises the total duration of a fragmented file in a `mehd` box, so
+    /// that players show the whole timeline from the first fragment on, as
+    /// for VOD served as fragments. Live streams, whose duration is unknown,
+    /// leave it out, which is the default: the `mehd` minimp4 writes with
+    /// the duration of no sample at all is dropped.
+    ///
+    /// The `moov` goes out ahead of the fragments, so the duration has to be
+    /// known up front: this fails with [`Minimp4Error::BadArguments`] once the
+    /// `moov` is written. It is stored in milliseconds, the movie timescale of
+    /// minimp4, and only written for [`Mp4Mode::Fragmented`].
+    pub fn set_fragment_duration(&mut self, duration: Duration) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() && unsafe { (*self.raw.muxer).fragments_count } != 0 {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.fragment_duration = Some(duration);
+        Ok(())
+    }
+
+    /// Selects how the fragments of [`Mp4Mode::Fragmented`] output locate
+    /// their sample data, relative to their `moof` by default. Like the
+    /// `mehd`, this is fixed once the `moov` is written: fails with
+    /// [`Minimp4Error::BadArguments`] afterwards.
+    pub fn set_fragment_base(&mut self, base: FragmentBase) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() && unsafe { (*self.raw.muxer).fragments_count } != 0 {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.fragment_base = base;
+        Ok(())
+    }
+
+    /// Sets the default sample flags of the `trex` box of `track`, an index as
+    /// in [`Mp4Muxer::summary`], for [`Mp4Mode::Fragmented`] output. The
+    /// fragments only carry the flags of the samples that differ from it, so
+    /// any value keeps every sample marked right; the one most samples share
+    /// makes the smallest fragments.
+    ///
+    /// By default the flags come from the content: video defaults to a
+    /// non-sync sample depending on others, `0x01010000`, and keyframes
+    /// override it in their fragment, while audio samples are all sync and
+    /// default to zero. A video track of keyframes only is better off with
+    /// `0x02000000`, a sync sample depending on no other. Must be called
+    /// before the `moov` is written, fails with [`Minimp4Error::BadArguments`]
+    /// afterwards or when there is no such track.
+    pub fn set_default_sample_flags(&mut self, track: u32, flags: u32) -> Minimp4Result<()> {
+        let track = track as usize;
+        let moov_written = !self.raw.muxer.is_null() && unsafe { (*self.raw.muxer).fragments_count } != 0;
+        if moov_written || track >= self.tracks().len() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.default_sample_flags.retain(|(index, _)| *index != track);
+        self.default_sample_flags.push((track, flags));
+        Ok(())
+    }
+
+    /// Writes a `prft` producer reference time box ahead of every fragment of
+    /// [`Mp4Mode::Fragmented`] output, mapping the decode time of its first
+    /// sample to the wall-clock time the fragment was written at, as read
+    /// from [`SystemTime::now`]. LL-DASH and LL-HLS players use it to measure
+    /// and keep their latency to the live edge. Off by default; other modes
+    /// have no fragments and never get one.
+    pub fn set_write_producer_reference_time(&mut self, write: bool) {
+        self.reference_clock = match write {
+            true => Some(Box::new(SystemTime::now)),
+            false => None,
+        };
+    }
+
+    /// Writes the `prft` boxes of
+    /// [`Mp4Muxer::set_write_producer_reference_time`] with the time of
+    /// `clock` rather than the system clock, to match the time base of the
+    /// rest of a streaming setup. It is called once per fragment, as the
+    /// fragment is written.
+    pub fn set_producer_reference_clock(&mut self, clock: impl FnMut() -> SystemTime + 'static) {
+        self.reference_clock = Some(Box::new(clock));
+    }
+
+    /// Writes [`Mp4Mode::Fragmented`] output as an ISMV file for Microsoft
+    /// Smooth Streaming: an `ftyp` of the `isml` and `piff` brands, a `tfxd`
+    /// box with the start time and duration of every fragment and, on close,
+    /// an `mfra` index of the fragments starting with a sync sample, ending
+    /// with its `mfro`. Off by default, other modes are left as they are.
+    ///
+    /// The `tfrf` box announcing the fragments that follow is only of use to
+    /// live servers looking ahead of the fragment written and is left out.
+    /// Must be called before anything is written, fails with
+    /// [`Minimp4Error::BadArguments`] afterwards.
+    pub fn set_smooth_streaming(&mut self, smooth_streaming: bool) -> Minimp4Result<()> {
+        if !self.raw.muxer.is_null() {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.smooth_streaming = smooth_streaming;
+        Ok(())
+    }
+
+    /// Selects the `emsg` version of the events queued from now on, version 1
+    /// with absolute times by default.
+    pub fn set_event_time(&mut self, event_time: EventTime) {
+        self.event_time = event_time;
+    }
+
+    /// Queues a DASH event, such as an ad marker or a SCTE-35 cue, to be
+    /// written in an `emsg` box ahead of the next fragment of
+    /// [`Mp4Mode::Fragmented`] output, and returns its id. The event belongs
+    /// to the fragments of the first video track, or of the first track
+    /// without video, as [`Mp4Muxer::byte_ranges`].
+    ///
+    /// `presentation_time` and `duration` are in ticks of `timescale` from
+    /// the start of the presentation, a `duration` of `u32::MAX` standing for
+    /// an unknown one. The event cannot start before the fragment it goes
+    /// with, and with [`EventTime::FragmentRelative`] not more than
+    /// `u32::MAX` ticks after it. Other modes, a zero timescale, an empty
+    /// scheme, a NUL character in the scheme or value, no track yet and
+    /// times out of those bounds fail with [`Minimp4Error::BadArguments`].
+    /// Events still queued when the muxer is closed are dropped.
+    pub fn queue_event(
+        &mut self,
+        scheme_id: &str,
+        value: &str,
+        timescale: u32,
+        presentation_time: u64,
+        duration: u32,
+        message_data: &[u8],
+    ) -> Minimp4Result<u32> {
+        self.bind();
+        if self.mode != Mp4Mode::Fragmented
+            || timescale == 0
+            || scheme_id.is_empty()
+            || scheme_id.contains('\0')
+            || value.contains('\0')
+        {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let start = self.event_fragment_start(timescale).ok_or(Minimp4Error::BadArguments)?;
+        let delta = presentation_time.checked_sub(start).ok_or(Minimp4Error::BadArguments)?;
+        if self.event_time == EventTime::FragmentRelative && delta > u32::MAX as u64 {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let id = self.next_event_id;
+        self.next_event_id = self.next_event_id.wrapping_add(1);
+        self.events.push(fragment::Event {
+            scheme_id: scheme_id.to_string(),
+            value: value.to_string(),
+            timescale,
+            presentation_time,
+            duration,
+            id,
+            message_data: message_data.to_vec(),
+            fragment_relative: self.event_time == EventTime::FragmentRelative,
+        });
+        Ok(id)
+    }
+
+    /// Byte range of every group of pictures of [`Mp4Mode::Fragmented`]
+    /// output, for the `EXT-X-BYTERANGE` tags of an HLS playlist addressing
+    /// the file: a range starts at the `moof` of a video keyframe and runs up
+    /// to the next one, covering the audio fragments in between. Without a
+    /// video track every fragment of the first track starts a range. Fragments before the
+    /// first video keyframe are left out, the init segment is the
+    /// `ftyp` and `moov` ahead of the first range.
+    ///
+    /// The list grows as the fragments are written and is complete once the
+    /// muxer is closed. Offsets are those of the output, `media_time` is in
+    /// movie timescale units, 1000 per second. Other modes have no
+    /// fragments and return an empty list.
+    pub fn byte_ranges(&self) -> &[ByteRange] {
+        &self.byte_ranges
+    }
+
+    /// Size in bytes of the `moov` box closing the muxer now would write,
+    /// boxes added on top of minimp4's included, so that a faststart pass can
+    /// reserve exactly that much room ahead of the samples. minimp4 builds
+    /// its index as on close and it is measured instead of written, after
+    /// [`Mp4Mode::Sequential`] output caught up with the data of the last
+    /// samples, which it otherwise holds back until the next ones. Samples
+    /// written later grow it, and so does an access unit still waiting for
+    /// the next one in [`Mp4Muxer::write_access_unit`].
+    ///
+    /// Fails with [`Minimp4Error::BadArguments`] before the muxer is
+    /// initialized, once closed, and in [`Mp4Mode::Fragmented`], where the
+    /// `moov` is written ahead of the first sample.
+    pub fn moov_size(&mut self) -> Minimp4Result<u64> {
+        if self.raw.muxer.is_null() || self.mode == Mp4Mode::Fragmented {
+            return Err(Minimp4Error::BadArguments);
+        }
+        self.bind();
+        // the data of the last sample of every track waits in sequential
+        // mode and would go to the measure, so it is written out first as
+        // the next sample would
+        for track in 0..self.tracks().len() {
+            let tracks = unsafe { (*self.raw.muxer).tracks.data as *mut track_t };
+            Minimp4Error::check(unsafe { write_pending_data(self.raw.muxer, tracks.add(track)) })?;
+        }
+        let mut measure = MoovMeasure {
+            muxer: self as *const Self,
+            size: None,
+        };
+        let result = unsafe {
+            let (token, callback, write_pos) = (
+                (*self.raw.muxer).token,
+                (*self.raw.muxer).write_callback,
+                (*self.raw.muxer).write_pos,
+            );
+            (*self.raw.muxer).token = &mut measure as *mut MoovMeasure<W> as *mut c_void;
+            (*self.raw.muxer).write_callback = Some(Self::measure);
+            let result = mp4e_flush_index(self.raw.muxer);
+            (*self.raw.muxer).token = token;
+            (*self.raw.muxer).write_callback = callback;
+            (*self.raw.muxer).write_pos = write_pos;
+            result
+        };
+        Minimp4Error::check(result)?;
+        measure.size.ok_or(Minimp4Error::BadArguments)
+    }
+
+    /// Writes the `ftyp` and a `moov` holding every track initialized so far
+    /// but no sample, the init segment of CMAF and DASH, ahead of the first
+    /// fragment. Only for [`Mp4Mode::Fragmented`], once the tracks are set up
+    /// and before any sample has been written; fails with
+    /// [`Minimp4Error::BadArguments`] otherwise or when called twice.
+    ///
+    /// The decoder configuration comes from the parameter sets written so
+    /// far, so pass the SPS/PPS (and VPS) to [`Mp4Muxer::write_video`] first.
+    /// The `trex` defaults are the duration and flags known at that point,
+    /// fragments that differ from them carry their own.
+    pub fn write_init_segment(&mut self) -> Minimp4Result<()> {
+        self.bind();
+        if self.mode != Mp4Mode::Fragmented
+            || self.raw.muxer.is_null()
+            || unsafe { (*self.raw.muxer).fragments_count } != 0
+        {
+            return Err(Minimp4Error::BadArguments);
+        }
+        unsafe {
+            // minimp4 writes the moov with the first fragment unless one was
+            // already counted
+            (*self.raw.muxer).fragments_count = 1;
+            Minimp4Error::check(mp4e_flush_index(self.raw.muxer))
+        }
+    }
+
+    /// Finalizes the file and flushes the writer, unless turned off with
+    /// [`Mp4Muxer::set_flush_on_close`]; an error of the writer is kept for
+    /// [`Mp4Muxer::last_io_error`]. Calling it again, or calling it on a
+    /// muxer that was never initialized, does nothing.
+    pub fn close(&mut self) -> &W {
+        self.bind();
+        if let Some(assembler) = &mut self.assembler {
+            assembler.flush(unsafe { self.raw.writer.as_mut().unwrap() }, &mut self.stream_info);
+        }
+        self.finish_id3_tracks();
+        if let Some(webvtt) = &mut self.webvtt_track {
+            let samples = webvtt.finish();
+            let track = webvtt.track;
+            let _ = self.put_vtt_samples(track, &samples);
+        }
+        let end = match self.raw.muxer.is_null() {
+            true => 0,
+            false => self.shifted(unsafe { (*self.raw.muxer).write_pos }),
+        };
+        unsafe {
+            MP4E_close(self.raw.muxer);
+        }
+        if self.smooth_streaming && self.mode == Mp4Mode::Fragmented && end > 0 {
+            let mfra = fragment::mfra_box(&std::mem::take(&mut self.random_access));
+            self.write_data(end, &mfra);
+        }
+        if let Some(staging) = self.staging.take() {
+            if let Err(error) = staging.finish(&mut self.writer) {
+                self.io_error.get_or_insert(error);
+            }
+        }
+        if self.flush_on_close && !self.raw.muxer.is_null() {
+            if let Err(error) = self.writer.flush() {
+                self.io_error.get_or_insert(error);
+            }
+        }
+        self.raw.muxer = null_mut();
+        &self.writer
+    }
+
+    /// Points the minimp4 write callback at this muxer, wherever it lives
+    /// now, ahead of anything that may write.
+    fn bind(&mut self) {
+        if !self.raw.muxer.is_null() {
+            unsafe {
+                (*self.raw.muxer).token = self as *mut Self as *mut c_void;
+                (*self.raw.muxer).write_callback = Some(Self::write);
+            }
+        }
+    }
+
+    /// Opens the minimp4 muxer for the selected mode, once.
+    fn open(&mut self) {
+        if self.raw.muxer.is_null() {
+            let self_ptr = self as *mut Self as *mut c_void;
+            let fragmented = (self.mode == Mp4Mode::Fragmented) as i32;
+            let sequential = (self.mode == Mp4Mode::Sequential) as i32 | fragmented;
+            self.raw.muxer = unsafe { MP4E_open(sequential, fragmented, self_ptr, Some(Self::write)) };
+            self.set_text_comment();
+        }
+    }
+
+    /// Splices the boxes minimp4 does not write itself into its `moov`.
+    fn patch_moov(&self, moov: &[u8]) -> Vec<u8> {
+        let mut moov = moov.to_vec();
+        fragment::patch_trex(&mut moov, &self.fragment_defaults);
+        if let Some(mehd) = boxes::find_path(&moov, &[b"moov", b"mvex", b"mehd"]) {
+            // minimp4 writes the duration of the samples so far, none yet
+            let duration = self
+                .fragment_duration
+                .map(|duration| fragment::mehd_box(duration.as_millis().try_into().unwrap_or(u64::MAX)));
+            boxes::splice(
+                &mut moov,
+                &mehd[..2],
+                mehd[2].start..mehd[2].end,
+                &duration.unwrap_or_default(),
+            );
+        }
+        if let Some((graphics_mode, opcolor)) = self.video_media_header {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            let traks: Vec<_> = boxes::BoxIter::new(&moov, root[0].children())
+                .filter(|b| &b.fourcc == b"trak")
+                .collect();
+            for trak in traks {
+                let Some(vmhd) = boxes::find_child_path(&moov, &[root[0], trak], &[b"mdia", b"minf", b"vmhd"]) else {
+                    continue;
+                };
+                // after version and flags
+                let fields = vmhd[4].payload + 4;
+                let values = [graphics_mode, opcolor[0], opcolor[1], opcolor[2]];
+                for (index, value) in values.iter().enumerate() {
+                    moov[fields + 2 * index..fields + 2 * index + 2].copy_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            let traks: Vec<_> = boxes::BoxIter::new(&moov, root[0].children())
+                .filter(|b| &b.fourcc == b"trak")
+                .collect();
+            for trak in traks {
+                let path = [b"mdia", b"minf", b"stbl", b"stsd", b"hvc1", b"hvcC"];
+                let Some(hvcc) = boxes::find_child_path(&moov, &[root[0], trak], &path) else {
+                    continue;
+                };
+                // numTemporalLayers, temporalIdNested and lengthSizeMinusOne
+                if let Some(byte) = moov[hvcc[7].payload..hvcc[7].end].get_mut(21) {
+                    self.stream_info.temporal_layers.patch_hvcc(byte);
+                }
+            }
+        }
+        if self.parameter_sets == ParameterSets::InBand {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            let traks: Vec<_> = boxes::BoxIter::new(&moov, root[0].children())
+                .filter(|b| &b.fourcc == b"trak")
+                .collect();
+            for trak in traks {
+                let path = [b"mdia", b"minf", b"stbl", b"stsd"];
+                let Some(stsd) = boxes::find_child_path(&moov, &[root[0], trak], &path) else {
+                    continue;
+                };
+                let entries: Vec<_> = boxes::BoxIter::new(&moov, stsd[5].children()).collect();
+                for entry in entries {
+                    let fourcc = match &entry.fourcc {
+                        b"avc1" => b"avc3",
+                        b"hvc1" => b"hev1",
+                        _ => continue,
+                    };
+                    moov[entry.start + 4..entry.start + 8].copy_from_slice(fourcc);
+                }
+            }
+        }
+        if self.write_iods {
+            // minimp4 leaves tracks without samples out of the moov
+            let iods = iods::iods_box(self.tracks().iter().filter(|track| has_samples(track)));
+            let mvhd = boxes::find_path(&moov, &[b"moov", b"mvhd"]).unwrap();
+            boxes::splice(&mut moov, &mvhd[..1], mvhd[1].end..mvhd[1].end, &iods);
+        }
+        for (trak, index) in self.traks(&moov).into_iter().rev() {
+            if self.id3_tracks.iter().any(|id3| id3.track == index) {
+                id3::patch_trak(&mut moov, trak.start);
+            }
+            if self.webvtt_track.as_ref().is_some_and(|webvtt| webvtt.track == index) {
+                webvtt::patch_trak(&mut moov, trak.start);
+            }
+            #[cfg(feature = "vp9")]
+            if let Some(vp9) = self.vp9_track.as_ref().filter(|vp9| vp9.track == index) {
+                vp9::patch_trak(&mut moov, trak.start, vp9);
+            }
+        }
+        for (trak, _) in self.traks(&moov).into_iter().rev() {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            let path = [b"mdia", b"minf", b"stbl", b"stsz"];
+            if let Some(stsz) = boxes::find_child_path(&moov, &[root[0], trak], &path) {
+                if let Some(compact) = compact_stsz(&moov[stsz[5].start..stsz[5].end]) {
+                    boxes::splice(&mut moov, &stsz[..5], stsz[5].start..stsz[5].end, &compact);
+                }
+            }
+        }
+        // in reverse so that the ranges of earlier traks stay valid
+        for (trak, index) in self.traks(&moov).into_iter().rev() {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            // in one go, the range of the trak is stale after a splice
+            let mut udta = vec![];
+            if let Some(comment) = self.metadata.get(&MetadataTag::TrackComment(index)) {
+                udta.extend(metadata::udta_text_box(b"\xa9cmt", comment));
+            }
+            for (_, scheme, value) in self.track_kinds.iter().filter(|(t, _, _)| *t == index) {
+                udta.extend(metadata::kind_box(scheme, value));
+            }
+            if !udta.is_empty() {
+                boxes::append_to_container(&mut moov, &[root[0], trak], b"udta", &udta);
+            }
+            if let Some(edits) = self.edit_list(index) {
+                edit::insert_after_tkhd(&mut moov, &[root[0], trak], &edit::edts_box(&edits));
+            }
+            if let Some(&(_, duration)) = self.track_durations.iter().find(|(t, _)| *t == index) {
+                if let Some(tkhd) = boxes::find_child_path(&moov, &[root[0], trak], &[b"tkhd"]) {
+                    // after creation and modification time, track_ID and a
+                    // reserved field
+                    let tkhd = tkhd[2];
+                    let wide = moov[tkhd.payload] == 1;
+                    let pos = tkhd.payload + if wide { 28 } else { 20 };
+                    write_duration(&mut moov[pos..tkhd.end], wide, duration);
+                }
+            }
+            if self.write_degradation_priorities && self.mode != Mp4Mode::Fragmented {
+                let track = &self.tracks()[index];
+                let stbl = boxes::find_child_path(&moov, &[root[0], trak], &[b"mdia", b"minf", b"stbl"])
+                    .filter(|_| track.info.track_media_kind == e_video);
+                if let Some(stbl) = stbl {
+                    // pictures minimp4 dropped ahead of the first keyframe
+                    // are at the front
+                    let priorities = &self.stream_info.priorities;
+                    let count = samples(track).len();
+                    if let Some(first) = priorities.len().checked_sub(count) {
+                        boxes::append_child(&mut moov, &stbl, &nal::stdp_box(&priorities[first..]));
+                    }
+                }
+            }
+            // right after the tkhd, ahead of the edts as QuickTime orders them
+            if let Some((_, tapt)) = self.track_aperture.as_ref().filter(|(t, _)| *t == index) {
+                edit::insert_after_tkhd(&mut moov, &[root[0], trak], tapt);
+            }
+        }
+        if self.write_trick_mode_track && self.mode != Mp4Mode::Fragmented {
+            // each right after its video trak, in reverse so that the ranges
+            // of earlier traks stay valid
+            for (trak, index) in self.traks(&moov).into_iter().rev() {
+                let track = &self.tracks()[index];
+                if track.info.track_media_kind != e_video {
+                    continue;
+                }
+                let samples: Vec<_> = samples(track)
+                    .iter()
+                    .map(|sample| trick::VideoSample {
+                        offset: sample.offset,
+                        size: sample.size as u32,
+                        duration: sample.duration,
+                        sync: sample.flag_random_access != 0,
+                    })
+                    .collect();
+                let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+                let track_id = max_track_id(&moov) + 1;
+                trick::add_trick_trak(&mut moov, root[0], trak, &samples, track_id);
+            }
+        }
+        {
+            // next_track_ID, the last field of the mvhd, has to be above the
+            // ID of every tkhd for editors to add tracks without collisions
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            let max_track_id = max_track_id(&moov);
+            let mvhd = boxes::find_child_path(&moov, &root, &[b"mvhd"]).unwrap();
+            let next_track_id = mvhd[1].end - 4;
+            if boxes::read_u32(&moov, next_track_id) <= max_track_id {
+                moov[next_track_id..next_track_id + 4].copy_from_slice(&(max_track_id + 1).to_be_bytes());
+            }
+        }
+        if let Some(duration) = self.track_durations.iter().map(|(_, duration)| *duration).max() {
+            // after creation and modification time and the timescale
+            let mvhd = boxes::find_path(&moov, &[b"moov", b"mvhd"]).unwrap();
+            let wide = moov[mvhd[1].payload] == 1;
+            let pos = mvhd[1].payload + if wide { 24 } else { 16 };
+            let current = match wide {
+                true => boxes::read_u64(&moov, pos),
+                false => boxes::read_u32(&moov, pos) as u64,
+            };
+            if current < duration {
+                write_duration(&mut moov[pos..mvhd[1].end], wide, duration);
+            }
+        }
+        if let Some(creation_time) = self.creation_time {
+            // minimp4 writes a version 0 mvhd, creation then modification
+            // time after version and flags
+            let mvhd = boxes::find_path(&moov, &[b"moov", b"mvhd"]).unwrap();
+            let times = mvhd[1].payload + 4;
+            for pos in [times, times + 4] {
+                moov[pos..pos + 4].copy_from_slice(&creation_time.to_be_bytes());
+            }
+        }
+        let mut itunes_items = self.itunes_items.clone();
+        if let Some(encoder_tag) = &self.encoder_tag {
+            itunes_items.retain(|item| item.get(4..8) != Some(ENCODER_TAG));
+            if let Some(encoder_tag) = encoder_tag {
+                itunes_items.push(metadata::itunes_text_item(ENCODER_TAG, encoder_tag));
+            }
+        }
+        if !itunes_items.is_empty() {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            match boxes::find_child_path(&moov, &root, &[b"udta", b"meta", b"ilst"]) {
+                Some(ilst) => {
+                    // at the same offset, which stays valid as the boxes
+                    // around start before it
+                    for item in itunes_items.iter().rev() {
+                        boxes::append_child(&mut moov, &ilst, item);
+                    }
+                }
+                None => {
+                    let meta = metadata::itunes_meta_box(&itunes_items);
+                    boxes::append_to_container(&mut moov, &root, b"udta", &meta);
+                }
+            }
+        }
+        for udta_box in &self.udta_boxes {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            boxes::append_to_container(&mut moov, &root, b"udta", udta_box);
+        }
+        if let Some(location) = self.metadata.get(&MetadataTag::Location) {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            boxes::append_to_container(
+                &mut moov,
+                &root,
+                b"udta",
+                &metadata::udta_text_box(b"\xa9xyz", location),
+            );
+        }
+        let qt_metadata: Vec<_> = self
+            .metadata
+            .iter()
+            .filter_map(|(tag, value)| match tag {
+                MetadataTag::QuickTime(key) => Some((key.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+        if !qt_metadata.is_empty() {
+            let root = boxes::find_path(&moov, &[b"moov"]).unwrap();
+            boxes::append_child(&mut moov, &root, &metadata::qt_meta_box(&qt_metadata));
+        }
+        moov
+    }
+
+    /// The `trak` boxes of `moov`, with the index of the tracks they were
+    /// written for.
+    fn traks(&self, moov: &[u8]) -> Vec<(boxes::BoxRange, usize)> {
+        let included = self
+            .tracks()
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| self.mode == Mp4Mode::Fragmented || has_samples(track))
+            .map(|(index, _)| index);
+        let root = boxes::find_path(moov, &[b"moov"]).unwrap();
+        boxes::BoxIter::new(moov, root[0].children())
+            .filter(|b| &b.fourcc == b"trak")
+            .zip(included)
+            .collect()
+    }
+
+    fn tracks(&self) -> &[track_t] {
+        if self.raw.muxer.is_null() {
+            return &[];
+        }
+        unsafe {
+            let tracks = &(*self.raw.muxer).tracks;
+            if tracks.data.is_null() {
+                return &[];
+            }
+            from_raw_parts(
+                tracks.data as *const track_t,
+                tracks.bytes as usize / size_of::<track_t>(),
+            )
+        }
+    }
+
+    pub fn write_data(&mut self, offset: i64, buf: &[u8]) -> u64 {
+        let result = match &mut self.staging {
+            Some(staging) => staging.write_at(offset as u64, buf),
+            None => self
+                .writer
+                .seek(SeekFrom::Start(offset as u64))
+                .and_then(|_| self.writer.write_all(buf)),
+        };
+        match result {
+            Ok(()) => buf.len() as u64,
+            Err(error) => {
+                self.io_error.get_or_insert(error);
+                0
+            }
+        }
+    }
+
+    /// Returns the first error of the writer, if any, leaving it in place.
+    ///
+    /// minimp4 only learns that a write failed, which the write methods
+    /// report as [`Minimp4Error::FileWriteError`] where minimp4 passes it on,
+    /// so the error itself is kept here. Later errors, which usually follow
+    /// from the first one, are dropped.
+    pub fn last_io_error(&self) -> Option<&io::Error> {
+        self.io_error.as_ref()
+    }
+
+    /// Takes the error of [`Mp4Muxer::last_io_error`], so that a later error
+    /// of the writer is kept again.
+    pub fn take_io_error(&mut self) -> Option<io::Error> {
+        self.io_error.take()
+    }
+
+    /// Sample defaults of every track for the `trex` boxes: the duration of
+    /// the first sample and the flags of `set_default_sample_flags` or, for
+    /// video, the flags of a non-key frame.
+    fn track_defaults(&self) -> Vec<TrackDefaults> {
+        self.tracks()
+            .iter()
+            .enumerate()
+            .map(|(index, track)| TrackDefaults {
+                track_id: index as u32 + 1,
+                duration: track.info.default_duration,
+                flags: match self.default_sample_flags.iter().find(|(track, _)| *track == index) {
+                    Some((_, flags)) => *flags,
+                    None if track.info.track_media_kind == e_video => fragment::VIDEO_SAMPLE_FLAGS,
+                    None => 0,
+                },
+            })
+            .collect()
+    }
+
+    /// Index of the track whose fragments start the byte ranges and carry
+    /// the events: the first video track, or the first track without video.
+    fn reference_track(&self) -> Option<usize> {
+        let tracks = self.tracks();
+        match tracks.iter().position(|track| track.info.track_media_kind == e_video) {
+            Some(index) => Some(index),
+            None => (!tracks.is_empty()).then_some(0),
+        }
+    }
+
+    /// Start of the next fragment of the reference track, in `timescale`.
+    fn event_fragment_start(&self, timescale: u32) -> Option<u64> {
+        let index = self.reference_track()?;
+        let time_scale = self.tracks()[index].info.time_scale as u128;
+        let time = self.fragment_time(index as u32 + 1) as u128;
+        u64::try_from(time * timescale as u128 / time_scale).ok()
+    }
+
+    /// Opens a [`ByteRange`] at `position` when the `run` of a fragment
+    /// starts with a sync sample of the reference track.
+    fn start_byte_range(&mut self, run: &FragmentRun, position: u64) {
+        let Some(index) = self.reference_track() else {
+            return;
+        };
+        let tracks = self.tracks();
+        if run.track_id as usize == index + 1 && run.sync {
+            self.byte_ranges.push(ByteRange {
+                media_time: self.fragment_time(run.track_id) * MOVIE_TIMESCALE / tracks[index].info.time_scale as u64,
+                byte_offset: position,
+                byte_length: 0,
+            });
+        }
+    }
+
+    /// Decode time of the next fragment of track `track_id`, in its timescale.
+    fn fragment_time(&self, track_id: u32) -> u64 {
+        self.fragment_times.get(track_id as usize - 1).copied().unwrap_or(0)
+    }
+
+    /// Maps an offset of the output as minimp4 sees it to the actual output.
+    fn shifted(&self, offset: i64) -> i64 {
+        let index = self.shifts.partition_point(|(pos, _)| *pos <= offset);
+        offset + index.checked_sub(1).map_or(0, |index| self.shifts[index].1)
+    }
+
+    extern "C" fn write(offset: i64, buffer: *const c_void, size: u64, token: *mut c_void) -> i32 {
+        let this = unsafe { &mut *(token as *mut Self) };
+        let buf = unsafe { from_raw_parts(buffer as *const u8, size as usize) };
+        let mut run = None;
+        // where the moof starts in what is written
+        let mut moof_start = 0;
+        let rewritten = if boxes::is_box(buf, b"moov") {
+            if this.mode == Mp4Mode::Fragmented {
+                this.fragment_defaults = this.track_defaults();
+            }
+            Some(this.patch_moov(buf))
+        } else if this.mode == Mp4Mode::Fragmented && boxes::is_box(buf, b"moof") {
+            run = fragment::read_moof(buf, &this.fragment_defaults);
+            let mut moof = fragment::optimize_moof(buf, &this.fragment_defaults);
+            if let (true, Some(run)) = (this.smooth_streaming, &run) {
+                let time = this.fragment_time(run.track_id);
+                moof = fragment::add_tfxd(moof.as_deref().unwrap_or(buf), time, run.duration).or(moof);
+            }
+            let mut prefix = vec![];
+            if let Some(run) = &run {
+                if let Some(clock) = &mut this.reference_clock {
+                    prefix = fragment::prft_box(run.track_id, clock(), this.fragment_time(run.track_id));
+                }
+                if this.reference_track() == Some(run.track_id as usize - 1) {
+                    for event in std::mem::take(&mut this.events) {
+                        let start = match event.fragment_relative {
+                            true => this.event_fragment_start(event.timescale),
+                            false => None,
+                        };
+                        prefix.extend_from_slice(&fragment::emsg_box(&event, start));
+                    }
+                }
+            }
+            moof_start = prefix.len();
+            match prefix.is_empty() {
+                true => moof,
+                false => {
+                    prefix.extend_from_slice(moof.as_deref().unwrap_or(buf));
+                    Some(prefix)
+                }
+            }
+        } else if this.smooth_streaming && this.mode == Mp4Mode::Fragmented && boxes::is_box(buf, b"ftyp") {
+            Some(fragment::ismv_ftyp())
+        } else if let (Some(size), true) = (this.reserved_moov_size, boxes::is_box(buf, b"ftyp")) {
+            this.reserved_moov_at = buf.len() as u64;
+            Some([buf, &boxes::free_box(size as usize)].concat())
+        } else {
+            None
+        };
+        let position = this.shifted(offset);
+        if let (Some(size), true) = (this.reserved_moov_size, boxes::is_box(buf, b"moov")) {
+            let moov = rewritten.unwrap_or_else(|| buf.to_vec());
+            return this.write_reserved_moov(moov, size, position);
+        }
+        let rewritten = match (this.fragment_base, &run) {
+            (FragmentBase::BaseDataOffset, Some(_)) => {
+                let moof = &rewritten.as_deref().unwrap_or(buf)[moof_start..];
+                let base = (position + moof_start as i64) as u64;
+                match fragment::set_base_data_offset(moof, base) {
+                    Some(moof) => Some([&rewritten.as_deref().unwrap_or(buf)[..moof_start], &moof].concat()),
+                    None => rewritten,
+                }
+            }
+            _ => rewritten,
+        };
+        let data = rewritten.as_deref().unwrap_or(buf);
+        if let Some(run) = run {
+            this.start_byte_range(&run, position as u64);
+            if this.smooth_streaming && run.sync {
+                let entry = (this.fragment_time(run.track_id), (position + moof_start as i64) as u64);
+                match this
+                    .random_access
+                    .iter_mut()
+                    .find(|(track_id, _)| *track_id == run.track_id)
+                {
+                    Some((_, entries)) => entries.push(entry),
+                    None => this.random_access.push((run.track_id, vec![entry])),
+                }
+            }
+            let index = run.track_id as usize - 1;
+            if this.fragment_times.len() <= index {
+                this.fragment_times.resize(index + 1, 0);
+            }
+            this.fragment_times[index] += run.duration;
+        }
+        if let (Mp4Mode::Fragmented, false, Some(range)) =
+            (this.mode, boxes::is_box(buf, b"moov"), this.byte_ranges.last_mut())
+        {
+            range.byte_length = position as u64 + data.len() as u64 - range.byte_offset;
+        }
+        let written = this.write_data(position, data);
+        if data.len() != buf.len() {
+            let shift = position - offset + data.len() as i64 - buf.len() as i64;
+            this.shifts.push((offset + buf.len() as i64, shift));
+        }
+        (written != data.len() as u64) as i32
+    }
+
+    /// Writes `moov` to the room of [`Mp4Muxer::set_reserved_moov_space`],
+    /// its chunk offsets shifted past the reservation, or at `position`, the
+    /// end of the file, when it does not fit.
+    fn write_reserved_moov(&mut self, mut moov: Vec<u8>, size: u32, position: i64) -> i32 {
+        if relocate::shift_moov_offsets(&mut moov, size as i64).is_err() {
+            let error = io::Error::new(io::ErrorKind::InvalidInput, "chunk offsets outgrow the stco");
+            self.io_error.get_or_insert(error);
+        }
+        // a free box needs its header in what is left
+        let rest = (size as usize)
+            .checked_sub(moov.len())
+            .filter(|rest| *rest == 0 || *rest >= 8);
+        let (position, data) = match rest {
+            Some(0) => (self.reserved_moov_at, moov),
+            Some(rest) => (self.reserved_moov_at, [moov, boxes::free_box(rest)].concat()),
+            None => {
+                let message = format!("moov of {} bytes does not fit the {size} reserved", moov.len());
+                self.io_error
+                    .get_or_insert(io::Error::new(io::ErrorKind::InvalidInput, message));
+                (position as u64, moov)
+            }
+        };
+        let written = self.write_data(position as i64, &data);
+        (written != data.len() as u64) as i32
+    }
+
+    /// Write callback of [`Mp4Muxer::moov_size`], keeping the size of the
+    /// `moov` as [`Mp4Muxer::write`] would write it and dropping the data.
+    extern "C" fn measure(_offset: i64, buffer: *const c_void, size: u64, token: *mut c_void) -> i32 {
+        let measure = unsafe { &mut *(token as *mut MoovMeasure<W>) };
+        let buf = unsafe { from_raw_parts(buffer as *const u8, size as usize) };
+        if boxes::is_box(buf, b"moov") {
+            let this = unsafe { &*measure.muxer };
+            measure.size = Some(this.patch_moov(buf).len() as u64);
+        }
+        0
+    }
+}
+
+/// The minimp4 state a [`Mp4Muxer`] owns: the muxer until it is closed,
+/// and the H.26x writer of its video track, zeroed until
+/// [`Mp4Muxer::init_video`] sets it up. Dropping it frees both without
+/// writing anything, a muxer dropped before close leaves its file without
+/// index.
+struct RawMuxer {
+    muxer: *mut MP4E_mux_t,
+    writer: *mut mp4_h26x_writer_t,
+}
+
+impl Drop for RawMuxer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.muxer.is_null() {
+                // minimp4 writes no index when closing fragmented output
+                (*self.muxer).enable_fragmentation = 1;
+                MP4E_close(self.muxer);
+            }
+            mp4_h26x_write_close(self.writer);
+            libc::free(self.writer as *mut c_void);
+        }
+    }
+}
+
+/// Token of [`Mp4Muxer::measure`].
+struct MoovMeasure<W> {
+    muxer: *const Mp4Muxer<W>,
+    size: Option<u64>,
+}
+
+/// `fps` as the writer takes it, failing with [`Minimp4Error::BadArguments`]
+/// for zero, which has no frame duration, and beyond `i32::MAX`.
+fn check_fps(fps: u32) -> Minimp4Result<i32> {
+    i32::try_from(fps)
+        .ok()
+        .filter(|&fps| fps != 0)
+        .ok_or(Minimp4Error::BadArguments)
+}
+
+/// `stsz`, a table of minimp4, in the compact form when all its samples have
+/// the same size, as for PCM: the `sample_size` field alone, without a table.
+fn compact_stsz(stsz: &[u8]) -> Option<Vec<u8>> {
+    let payload = stsz.get(8..).filter(|payload| payload.len() >= 12)?;
+    let count = boxes::read_u32(payload, 8);
+    let sizes = payload.get(12..12 + 4 * count as usize)?;
+    let first = sizes.get(..4)?;
+    if boxes::read_u32(payload, 4) != 0 || sizes.chunks(4).any(|size| size != first) {
+        return None;
+    }
+    Some(
+        boxes::BoxWriter::new()
+            .begin_full(b"stsz", 0, 0)
+            .bytes(first)
+            .u32(count)
+            .end()
+            .finish(),
+    )
+}
+
+/// Writes `duration` to the start of `field`, in 8 bytes when `wide` and
+/// else in 4, clamped to fit.
+fn write_duration(field: &mut [u8], wide: bool, duration: u64) {
+    match wide {
+        true => field[..8].copy_from_slice(&duration.to_be_bytes()),
+        false => field[..4].copy_from_slice(&u32::try_from(duration).unwrap_or(u32::MAX).to_be_bytes()),
+    }
+}
+
+/// The highest `track_ID` of the `tkhd` boxes of `moov`, 0 without any.
+fn max_track_id(moov: &[u8]) -> u32 {
+    let root = boxes::find_path(moov, &[b"moov"]).unwrap();
+    boxes::BoxIter::new(moov, root[0].children())
+        .filter(|b| &b.fourcc == b"trak")
+        .filter_map(|trak| boxes::find_child_path(moov, &[root[0], trak], &[b"tkhd"]))
+        .filter_map(|tkhd| {
+            // after creation and modification time of 4 or 8 bytes
+            let pos = tkhd[2].payload + if moov[tkhd[2].payload] == 1 { 20 } else { 12 };
+            (pos + 4 <= tkhd[2].end).then(|| boxes::read_u32(moov, pos))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn has_samples(track: &track_t) -> bool {
+    !samples(track).is_empty()
+}
+
+fn samples(track: &track_t) -> &[sample_t] {
+    if track.smpl.data.is_null() {
+        return &[];
+    }
+    unsafe {
+        from_raw_parts(
+            track.smpl.data as *const sample_t,
+            track.smpl.bytes as usize / size_of::<sample_t>(),
+        )
+    }
+}
+
+/// First item of a minimp4 list of parameter sets.
+fn first_item(list: &minimp4_vector_t) -> Option<&[u8]> {
+    if list.data.is_null() {
+        return None;
+    }
+    let data = unsafe { from_raw_parts(list.data, list.bytes as usize) };
+    let size = u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize;
+    data.get(2..2 + size)
+}
+
+/// Number of items of a minimp4 list of parameter sets, each stored behind a
+/// 16-bit size.
+fn items_count(list: &minimp4_vector_t) -> u32 {
+    if list.data.is_null() {
+        return 0;
+    }
+    let data = unsafe { from_raw_parts(list.data, list.bytes as usize) };
+    let (mut pos, mut count) = (0, 0);
+    while pos + 2 < data.len() {
+        pos += 2 + u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_muxer() {
+        let mut muxer = Mp4Muxer::new(Cursor::new(Vec::new()));
+        muxer.init_video(1280, 720, false, "test").unwrap();
+        assert_eq!(muxer.write_video(&[0; 100]), Err(Minimp4Error::BadArguments));
+        muxer.write_comment("test comment");
+        muxer.close();
+        assert_eq!(muxer.writer.into_inner().len(), 257);
+    }
+
+    #[test]
+    fn test_flush_on_close() {
+        let mux = |flush| {
+            let writer = io::BufWriter::with_capacity(1 << 20, Cursor::new(vec![]));
+            let h264 = include_bytes!("./fixtures/input.264");
+            let mp4muxer = mux_with(
+                writer,
+                h264,
+                false,
+                |mp4muxer| mp4muxer.set_flush_on_close(flush),
+                |_| {},
+            );
+            assert!(mp4muxer.last_io_error().is_none());
+            mp4muxer.writer
+        };
+        let flushed = mux(true);
+        assert!(flushed.buffer().is_empty());
+        assert_eq!(
+            flushed.get_ref().get_ref(),
+            include_bytes!("./fixtures/h264_output.mp4")
+        );
+        // the caller flushes the rest
+        let mut buffered = mux(false);
+        assert!(!buffered.buffer().is_empty());
+        buffered.flush().unwrap();
+        assert_eq!(
+            buffered.get_ref().get_ref(),
+            include_bytes!("./fixtures/h264_output.mp4")
+        );
+    }
+
+    #[test]
+    fn test_is_ready_to_close() {
+        let mut muxer = Mp4Muxer::new(Cursor::new(Vec::new()));
+        assert!(!muxer.is_ready_to_close());
+        muxer.init_video(1280, 720, false, "test").unwrap();
+        assert!(!muxer.is_ready_to_close());
+        muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        assert!(muxer.is_ready_to_close());
+        muxer.close();
+    }
+
+    #[test]
+    fn test_qt_metadata() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            mp4muxer.set_qt_metadata("make", "Apple"),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.set_qt_metadata("com.apple.quicktime.make", "Apple").unwrap();
+        mp4muxer.set_qt_metadata("com.apple.quicktime.model", "iPhone").unwrap();
+        mp4muxer
+            .set_qt_metadata("com.apple.quicktime.make", "Apple Inc.")
+            .unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let keys = boxes::find_path(&buffer, &[b"moov", b"meta", b"keys"]).unwrap();
+        assert_eq!(boxes::read_u32(keys[2].payload(&buffer), 4), 2);
+        let ilst = boxes::find_path(&buffer, &[b"moov", b"meta", b"ilst"]).unwrap();
+        let item = boxes::children(&buffer, &ilst[2]).next().unwrap();
+        assert_eq!(item.fourcc, 1u32.to_be_bytes());
+        assert!(item.payload(&buffer).ends_with(b"Apple Inc."));
+    }
+
+    #[test]
+    fn test_location() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(mp4muxer.set_location(91.0, 0.0, None), Err(Minimp4Error::BadArguments));
+        mp4muxer.set_location(37.3318, -122.0312, Some(21.0)).unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.write_comment("test comment");
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let xyz = boxes::find_path(&buffer, &[b"moov", b"udta", b"\xa9xyz"]).unwrap();
+        assert_eq!(&xyz[2].payload(&buffer)[4..], b"+37.3318-122.0312+21.000/");
+        assert!(boxes::find_path(&buffer, &[b"moov", b"udta", b"meta", b"ilst"]).is_some());
+    }
+
+    #[test]
+    fn test_write_frames() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        // every NAL unit is passed as a slice borrowed from the input
+        let mut starts: Vec<_> = nal::NalIter::new(h264)
+            .map(|nal| nal.as_ptr() as usize - h264.as_ptr() as usize - 3)
+            .collect();
+        starts.push(h264.len());
+        // SPS and PPS at once, and no NAL unit at all
+        assert_eq!(
+            mp4muxer.write_frame_with_duration(&h264[starts[0]..starts[2]], 90000 / 25),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            mp4muxer.write_frame_with_duration(&h264[4..8], 90000 / 25),
+            Err(Minimp4Error::BadArguments)
+        );
+        for frame in starts.windows(2) {
+            mp4muxer
+                .write_frame_with_duration(&h264[frame[0]..frame[1]], 90000 / 25)
+                .unwrap();
+        }
+        mp4muxer.write_comment("test comment");
+        mp4muxer.close();
+        assert_eq!(buffer.into_inner(), include_bytes!("./fixtures/h264_output.mp4"));
+    }
+
+    #[test]
+    fn test_codec_string() {
+        use adts::tests::adts_frame;
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1920, 1080, false, "h264 stream").unwrap();
+        assert_eq!(mp4muxer.codec_string(0), None);
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer
+            .write_adts_aac(&adts_frame(4, 2, false, &[0x21; 64]), 0)
+            .unwrap();
+        assert_eq!(mp4muxer.codec_string(0).as_deref(), Some("avc1.640028"));
+        assert_eq!(mp4muxer.codec_string(1).as_deref(), Some("mp4a.40.2"));
+        assert_eq!(mp4muxer.codec_string(2), None);
+        mp4muxer.close();
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.set_parameter_sets(ParameterSets::InBand).unwrap();
+        mp4muxer.init_video(1920, 1080, true, "h265 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.265"), 25)
+            .unwrap();
+        assert_eq!(mp4muxer.codec_string(0).as_deref(), Some("hev1.1.6.L120.90"));
+        mp4muxer.close();
+    }
+
+    #[test]
+    fn test_invalid_fps() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let output = mux(h264, false, |mp4muxer| {
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            for fps in [0, i32::MAX as u32 + 1] {
+                assert_eq!(
+                    mp4muxer.write_video_with_fps(h264, fps),
+                    Err(Minimp4Error::BadArguments)
+                );
+            }
+            assert_eq!(mp4muxer.summary().tracks[0].sample_count, 0);
+        });
+        assert_eq!(output, include_bytes!("./fixtures/h264_output.mp4"));
+    }
+
+    #[test]
+    fn test_check_resolution() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let samples = |mp4: Vec<u8>| Mp4Demuxer::new(Cursor::new(mp4)).unwrap().sample_count(0);
+        // the stream is 1920x1080, coded as 1920x1088
+        let checked = |width, height| {
+            move |muxer: &mut Mp4Muxer<_>| {
+                muxer.set_check_resolution(true).unwrap();
+                muxer.init_video(width, height, false, "h264 stream").unwrap();
+            }
+        };
+        assert_eq!(samples(mux(h264, false, checked(1920, 1080))), Ok(128));
+        assert_eq!(samples(mux(h264, false, checked(1920, 1088))), Ok(128));
+        assert_eq!(samples(mux(h264, false, |_| {})), Ok(128));
+        let in_band = |muxer: &mut Mp4Muxer<_>| {
+            muxer.set_check_resolution(true).unwrap();
+            muxer.set_parameter_sets(ParameterSets::InBand).unwrap();
+        };
+        assert_eq!(samples(mux(h264, false, in_band)), Ok(128));
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        checked(1280, 720)(&mut mp4muxer);
+        assert_eq!(
+            mp4muxer.write_video_with_fps(h264, 25),
+            Err(Minimp4Error::ResolutionMismatch)
+        );
+        assert_eq!(mp4muxer.summary().tracks[0].sample_count, 0);
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1920, 1080, false, "h264 stream").unwrap();
+        assert_eq!(mp4muxer.set_check_resolution(true), Err(Minimp4Error::BadArguments));
+    }
+
+    #[test]
+    fn test_parameter_sets_in_band() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_parameter_sets(ParameterSets::InBand).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            mp4muxer.set_parameter_sets(ParameterSets::OutOfBand),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.write_video_with_fps(h264, 25).unwrap();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let path = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd", b"avc3", b"avcC"];
+        let avcc = boxes::find_path(&buffer, &path).unwrap();
+        // a single SPS and PPS, although the stream repeats them
+        let avcc = avcc[7].payload(&buffer);
+        assert_eq!(avcc[5] & 0x1f, 1);
+        let sps_size = u16::from_be_bytes([avcc[6], avcc[7]]) as usize;
+        assert_eq!(avcc[8 + sps_size], 1);
+
+        let mdat = boxes::find_path(&buffer, &[b"mdat"]).unwrap();
+        let sample = mdat[0].payload(&buffer);
+        let sps = nal::NalIter::new(h264).next().unwrap();
+        assert_eq!(boxes::read_u32(sample, 0) as usize, sps.len());
+        assert_eq!(&sample[4..4 + sps.len()], sps);
+    }
+
+    #[test]
+    fn test_keep_parameter_sets() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        for (parameter_sets, entry) in [(ParameterSets::OutOfBand, b"avc1"), (ParameterSets::InBand, b"avc3")] {
+            let mut buffer = Cursor::new(vec![]);
+            let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+            mp4muxer.set_parameter_sets(parameter_sets).unwrap();
+            mp4muxer.set_keep_parameter_sets(true).unwrap();
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            mp4muxer.write_video_with_fps(h264, 25).unwrap();
+            let keyframes: Vec<_> = samples(&mp4muxer.tracks()[0])
+                .iter()
+                .filter(|sample| sample.flag_random_access != 0)
+                .map(|sample| sample.offset as usize..(sample.offset + sample.size) as usize)
+                .collect();
+            mp4muxer.close();
+            let buffer = buffer.into_inner();
+
+            assert!(keyframes.len() > 1);
+            for keyframe in keyframes {
+                let sample = &buffer[keyframe];
+                let sps_size = boxes::read_u32(sample, 0) as usize;
+                let pps = &sample[4 + sps_size..];
+                assert_eq!(Codec::H264.classify(&sample[4..4 + sps_size]), nal::NalKind::Sps);
+                assert_eq!(Codec::H264.classify(&pps[4..]), nal::NalKind::Pps);
+            }
+            let stsd = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"];
+            assert!(boxes::find_path(&buffer, &[&stsd[..], &[entry]].concat()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_skipped_nals() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let input = nal::NalIter::new(h264).count();
+        for keep in [false, true] {
+            let mut buffer = Cursor::new(vec![]);
+            let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+            mp4muxer.set_keep_parameter_sets(keep).unwrap();
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            assert_eq!(mp4muxer.set_keep_parameter_sets(!keep), Err(Minimp4Error::BadArguments));
+            mp4muxer.write_video_with_fps(h264, 25).unwrap();
+            mp4muxer.close();
+            let (skipped, by_type) = (mp4muxer.skipped_nals(), mp4muxer.skipped_nals_by_type().clone());
+            let buffer = buffer.into_inner();
+
+            // the samples of the only track fill the mdat
+            let mdat = boxes::find_path(&buffer, &[b"mdat"]).unwrap();
+            let mut data = mdat[0].payload(&buffer);
+            let mut muxed = 0;
+            while !data.is_empty() {
+                data = &data[4 + boxes::read_u32(data, 0) as usize..];
+                muxed += 1;
+            }
+            assert_eq!(muxed + skipped, input);
+            if keep {
+                // the SEI ahead of the first keyframe goes with it
+                assert_eq!(skipped, 0);
+            } else {
+                // both SPS and PPS, and the SEI ahead of the first keyframe
+                assert_eq!(by_type, BTreeMap::from([(6, 1), (7, 2), (8, 2)]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_video_timescale() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(mp4muxer.set_video_timescale(0), Err(Minimp4Error::BadArguments));
+        mp4muxer.set_video_timescale(1000).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(mp4muxer.set_video_timescale(90000), Err(Minimp4Error::BadArguments));
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let mdhd = boxes::find_path(&buffer, &[b"moov", b"trak", b"mdia", b"mdhd"]).unwrap();
+        let mdhd = mdhd[3].payload(&buffer);
+        assert_eq!(boxes::read_u32(mdhd, 12), 1000);
+        assert_eq!(boxes::read_u32(mdhd, 16), 128 * 40);
+        let path = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stts"];
+        let stts = boxes::find_path(&buffer, &path).unwrap();
+        assert_eq!(boxes::read_u32(stts[5].payload(&buffer), 12), 40);
+    }
+
+    #[test]
+    fn test_error_diffusion() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let media_duration = |rounding, diffuse, keep_aud| {
+            let mut buffer = Cursor::new(vec![]);
+            let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+            mp4muxer.set_video_timescale(1000).unwrap();
+            mp4muxer.set_duration_rounding(rounding).unwrap();
+            mp4muxer.set_error_diffusion(diffuse).unwrap();
+            mp4muxer.set_keep_aud(keep_aud).unwrap();
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            assert_eq!(mp4muxer.set_error_diffusion(true), Err(Minimp4Error::BadArguments));
+            mp4muxer.write_video_with_fps(h264, 30).unwrap();
+            mp4muxer.close();
+            let buffer = buffer.into_inner();
+            let mdhd = boxes::find_path(&buffer, &[b"moov", b"trak", b"mdia", b"mdhd"]).unwrap();
+            boxes::read_u32(mdhd[3].payload(&buffer), 16)
+        };
+        // 128 frames of 33.3 ticks, 4266.7 in all
+        assert_eq!(media_duration(Rounding::Floor, false, false), 128 * 33);
+        assert_eq!(media_duration(Rounding::Ceil, false, false), 128 * 34);
+        assert_eq!(media_duration(Rounding::Floor, true, false), 4266);
+        assert_eq!(media_duration(Rounding::Nearest, true, false), 4267);
+        assert_eq!(media_duration(Rounding::Nearest, true, true), 4267);
+    }
+
+    #[test]
+    fn test_with() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let (buffer, summary, result) = Mp4Muxer::with(
+            Cursor::new(vec![]),
+            |muxer| muxer.init_video(1280, 720, false, "h264 stream").unwrap(),
+            |muxer| {
+                muxer.write_video_with_fps(h264, 25).unwrap();
+                muxer.write_comment("test comment");
+                // closing early is harmless
+                muxer.close();
+                Err::<(), _>(Minimp4Error::BadArguments)
+            },
+        );
+        assert_eq!(result, Err(Minimp4Error::BadArguments));
+        assert_eq!(buffer.into_inner(), include_bytes!("./fixtures/h264_output.mp4"));
+        assert!(summary.tracks.is_empty());
+
+        let (buffer, summary, result) = Mp4Muxer::with(
+            Cursor::new(vec![]),
+            |muxer| muxer.init_video(1280, 720, false, "h264 stream").unwrap(),
+            |muxer| muxer.write_video_with_fps(h264, 25),
+        );
+        result.unwrap();
+        assert_eq!(
+            summary.tracks,
+            [TrackSummary {
+                sample_count: 128,
+                duration: 128 * 3600,
+                timescale: 90000,
+                vps_count: 0,
+                sps_count: 1,
+                pps_count: 1,
+            }]
+        );
+        assert!(boxes::find_path(buffer.get_ref(), &[b"moov", b"trak"]).is_some());
+    }
+
+    #[test]
+    fn test_multiple_pps() {
+        // the fixture PPS with pic_parameter_set_id 1 and CAVLC instead of CABAC,
+        // minimp4 would merge PPS that only differ by their id
+        const SECOND_PPS: [u8; 6] = [0x68, 0x52, 0xf8, 0xf2, 0xc8, 0xb0];
+        let mut h264 = Vec::new();
+        for (index, nal) in nal::NalIter::new(include_bytes!("./fixtures/input.264")).enumerate() {
+            h264.extend_from_slice(&[0, 0, 0, 1]);
+            h264.extend_from_slice(nal);
+            if index == 1 {
+                h264.extend_from_slice(&[0, 0, 0, 1]);
+                h264.extend_from_slice(&SECOND_PPS);
+            }
+        }
+
+        for parameter_sets in [ParameterSets::OutOfBand, ParameterSets::InBand] {
+            let (buffer, summary, result) = Mp4Muxer::with(
+                Cursor::new(vec![]),
+                |muxer| {
+                    muxer.set_parameter_sets(parameter_sets).unwrap();
+                    muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                },
+                |muxer| muxer.write_video_with_fps(&h264, 25),
+            );
+            result.unwrap();
+            assert_eq!((summary.tracks[0].sps_count, summary.tracks[0].pps_count), (1, 2));
+
+            let buffer = buffer.into_inner();
+            let stsd = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"];
+            let stsd = boxes::find_path(&buffer, &stsd).unwrap();
+            let entry = boxes::BoxIter::new(&buffer, stsd[5].children()).next().unwrap();
+            let avcc = boxes::find_child_path(&buffer, &[entry], &[b"avcC"]).unwrap();
+            let avcc = avcc[1].payload(&buffer);
+            let sps_size = u16::from_be_bytes([avcc[6], avcc[7]]) as usize;
+            assert_eq!(avcc[8 + sps_size], 2);
+        }
+    }
+
+    #[test]
+    fn test_fragmented() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let (buffer, _, result) = Mp4Muxer::with(
+            Cursor::new(vec![]),
+            |muxer| {
+                muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+                muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                muxer.set_location(37.3318, -122.0312, None).unwrap();
+            },
+            |muxer| muxer.write_video_with_fps(h264, 25),
+        );
+        result.unwrap();
+        let buffer = buffer.into_inner();
+
+        let trex = boxes::find_path(&buffer, &[b"moov", b"mvex", b"trex"]).unwrap();
+        let trex = trex[2].payload(&buffer);
+        let trex = fragment::TrackDefaults {
+            track_id: boxes::read_u32(trex, 4),
+            duration: boxes::read_u32(trex, 12),
+            flags: boxes::read_u32(trex, 20),
+        };
+        assert_eq!(trex.duration, 3600);
+
+        // walk the fragments the way a player does, the data offsets must still
+        // point at the samples after the rewriting shifted everything
+        let top_level: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len()).collect();
+        let moofs: Vec<_> = top_level.iter().filter(|b| &b.fourcc == b"moof").collect();
+        let keyframes: Vec<_> = nal::NalIter::new(h264)
+            .filter_map(|nal| match Codec::H264.classify(nal) {
+                nal::NalKind::Slice { keyframe, .. } => Some(keyframe),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moofs.len(), keyframes.len());
+        for (index, moof) in moofs.iter().enumerate() {
+            let data = &buffer[moof.start..moof.end];
+            let samples = fragment::tests::resolve(data, trex);
+            assert_eq!(samples.len(), 1);
+            let (duration, size, flags) = samples[0];
+            assert_eq!(duration, 3600);
+            assert_eq!(flags == fragment::VIDEO_SAMPLE_FLAGS, !keyframes[index]);
+            let trun = boxes::find_path(data, &[b"moof", b"traf", b"trun"]).unwrap();
+            let data_offset = boxes::read_u32(trun[2].payload(data), 8) as usize;
+            let sample = &buffer[moof.start + data_offset..][..size as usize];
+            assert_eq!(boxes::read_u32(sample, 0) as usize + 4, size as usize);
+            assert_eq!(&buffer[moof.end + 4..moof.end + 8], b"mdat");
+        }
+    }
+
+    #[test]
+    fn test_fragment_base() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+        mp4muxer.set_fragment_base(FragmentBase::BaseDataOffset).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.set_write_producer_reference_time(true);
+        mp4muxer.write_video_with_fps(h264, 25).unwrap();
+        assert_eq!(
+            mp4muxer.set_fragment_base(FragmentBase::Moof),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        // the offsets now count from the position of every moof in the file
+        let moofs: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len())
+            .filter(|b| &b.fourcc == b"moof")
+            .collect();
+        assert_eq!(moofs.len(), 128);
+        for moof in moofs {
+            let data = &buffer[moof.start..moof.end];
+            let tfhd = boxes::find_path(data, &[b"moof", b"traf", b"tfhd"]).unwrap();
+            let tfhd = tfhd[2].payload(data);
+            assert_eq!(boxes::read_u32(tfhd, 0) & 0x2_0001, 0x1);
+            let base = boxes::read_u64(tfhd, 8) as usize;
+            assert_eq!(base, moof.start);
+            let trun = boxes::find_path(data, &[b"moof", b"traf", b"trun"]).unwrap();
+            let data_offset = boxes::read_u32(trun[2].payload(data), 8) as usize;
+            assert_eq!(base + data_offset, moof.end + 8);
+            assert_eq!(&buffer[moof.end + 4..moof.end + 8], b"mdat");
+        }
+    }
+
+    #[test]
+    fn test_default_sample_flags() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let keyframes: Vec<_> = nal::NalIter::new(h264)
+            .filter_map(|nal| match Codec::H264.classify(nal) {
+                nal::NalKind::Slice { keyframe, .. } => Some(keyframe),
+                _ => None,
+            })
+            .collect();
+        let mux = |flags: Option<u32>| {
+            let configure = |muxer: &mut Mp4Muxer<_>| {
+                muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+                assert_eq!(muxer.set_default_sample_flags(0, 0), Err(Minimp4Error::BadArguments));
+                muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                if let Some(flags) = flags {
+                    muxer.set_default_sample_flags(0, flags).unwrap();
+                }
+            };
+            let finish = |muxer: &mut Mp4Muxer<_>| {
+                assert_eq!(muxer.set_default_sample_flags(0, 0), Err(Minimp4Error::BadArguments));
+            };
+            let buffer = mux_with(Cursor::new(vec![]), h264, false, configure, finish)
+                .writer
+                .into_inner();
+
+            let trex = boxes::find_path(&buffer, &[b"moov", b"mvex", b"trex"]).unwrap();
+            let trex = trex[2].payload(&buffer);
+            let trex = fragment::TrackDefaults {
+                track_id: boxes::read_u32(trex, 4),
+                duration: boxes::read_u32(trex, 12),
+                flags: boxes::read_u32(trex, 20),
+            };
+            let flags: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len())
+                .filter(|b| &b.fourcc == b"moof")
+                .map(|moof| fragment::tests::resolve(&buffer[moof.start..moof.end], trex)[0].2)
+                .collect();
+            (trex.flags, flags)
+        };
+
+        let (derived, flags) = mux(None);
+        assert_eq!(derived, fragment::VIDEO_SAMPLE_FLAGS);
+        // keyframes alone are sync samples
+        assert_eq!(flags.len(), keyframes.len());
+        for (flags, keyframe) in flags.iter().zip(&keyframes) {
+            assert_eq!(flags & 0x1_0000 == 0, *keyframe);
+        }
+        assert!(keyframes[0] && keyframes.iter().any(|keyframe| !keyframe));
+        let (explicit, same) = mux(Some(0x0200_0000));
+        assert_eq!(explicit, 0x0200_0000);
+        assert_eq!(same, flags);
+    }
+
+    #[test]
+    fn test_byte_ranges() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mux = |mode| {
+            let muxer = mux_with(
+                Cursor::new(vec![]),
+                h264,
+                false,
+                |muxer| muxer.set_mode(mode).unwrap(),
+                |_| {},
+            );
+            (muxer.byte_ranges().to_vec(), muxer.writer.into_inner())
+        };
+        assert!(mux(Mp4Mode::Default).0.is_empty());
+
+        let (ranges, buffer) = mux(Mp4Mode::Fragmented);
+        let keyframes: Vec<_> = nal::NalIter::new(h264)
+            .filter_map(|nal| match Codec::H264.classify(nal) {
+                nal::NalKind::Slice { keyframe, .. } => Some(keyframe),
+                _ => None,
+            })
+            .enumerate()
+            .filter_map(|(index, keyframe)| keyframe.then_some(index as u64))
+            .collect();
+        assert_eq!(ranges.len(), keyframes.len());
+        let moov = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let mut end = moov[0].end as u64;
+        for (range, frame) in ranges.iter().zip(&keyframes) {
+            assert_eq!(range.media_time, frame * 40);
+            assert_eq!(range.byte_offset, end);
+            let segment = &buffer[range.byte_offset as usize..][..range.byte_length as usize];
+            assert_eq!(&segment[4..8], b"moof");
+            end += range.byte_length;
+        }
+        assert_eq!(end, buffer.len() as u64);
+    }
+
+    #[test]
+    fn test_id3_track() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        assert_eq!(mp4muxer.init_id3_track(0), Err(Minimp4Error::BadArguments));
+        let track = mp4muxer.init_id3_track(1000).unwrap();
+        assert_eq!(track, 1);
+        let tag = id3::tests::title_tag("now playing");
+        assert_eq!(
+            mp4muxer.write_id3_sample(0, &tag, 1000),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            mp4muxer.write_id3_sample(track, b"not a tag", 1000),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.write_id3_sample(track, &tag, 1000).unwrap();
+        assert_eq!(
+            mp4muxer.write_id3_sample(track, &tag, 1000),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.write_id3_sample(track, &tag, 3000).unwrap();
+        assert_eq!(mp4muxer.verify(), Ok(()));
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let root = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let trak = boxes::children(&buffer, &root[0])
+            .filter(|b| &b.fourcc == b"trak")
+            .nth(1)
+            .unwrap();
+        let chain = [root[0], trak];
+        let hdlr = boxes::find_child_path(&buffer, &chain, &[b"mdia", b"hdlr"]).unwrap();
+        assert_eq!(&hdlr[3].payload(&buffer)[8..12], b"meta");
+        assert!(boxes::find_child_path(&buffer, &chain, &[b"mdia", b"minf", b"nmhd"]).is_some());
+        let path = [b"mdia", b"minf", b"stbl", b"stsd", b"urim", b"uri "];
+        let uri = boxes::find_child_path(&buffer, &chain, &path).unwrap();
+        assert_eq!(
+            &uri[7].payload(&buffer)[4..],
+            b"https://developer.apple.com/streaming/emsg-id3\0"
+        );
+        // 2 s until the next tag, and up to the end of the 5.12 s of video
+        let stts = boxes::find_child_path(&buffer, &chain, &[b"mdia", b"minf", b"stbl", b"stts"]).unwrap();
+        let stts = stts[5].payload(&buffer);
+        assert_eq!(boxes::read_u32(stts, 4), 2);
+        assert_eq!(boxes::read_u32(stts, 12), 2000);
+        assert_eq!(boxes::read_u32(stts, 20), 2120);
+        let elst = boxes::find_child_path(&buffer, &chain, &[b"edts", b"elst"]).unwrap();
+        let elst = elst[3].payload(&buffer);
+        assert_eq!(boxes::read_u32(elst, 4), 2);
+        assert_eq!((boxes::read_u32(elst, 8), boxes::read_u32(elst, 12)), (1000, u32::MAX));
+        assert_eq!((boxes::read_u32(elst, 20), boxes::read_u32(elst, 24)), (4120, 0));
+        let stsd = [b"mdia", b"minf", b"stbl", b"stsz"];
+        let stsz = boxes::find_child_path(&buffer, &chain, &stsd).unwrap();
+        assert_eq!(boxes::read_u32(stsz[5].payload(&buffer), 4), tag.len() as u32);
+    }
+
+    #[test]
+    fn test_webvtt_track() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        let cue = |ms| Duration::from_millis(ms);
+        assert_eq!(
+            mp4muxer.write_vtt_cue("no track", cue(0), cue(1000)),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        let track = mp4muxer.init_webvtt_track().unwrap();
+        assert_eq!(track, 1);
+        assert_eq!(mp4muxer.init_webvtt_track(), Err(Minimp4Error::BadArguments));
+        mp4muxer.write_vtt_cue("Hello", cue(500), cue(1500)).unwrap();
+        mp4muxer.write_vtt_cue("world", cue(3000), cue(1000)).unwrap();
+        assert_eq!(
+            mp4muxer.write_vtt_cue("earlier", cue(2000), cue(1000)),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(mp4muxer.codec_string(track).as_deref(), Some("wvtt"));
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let root = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let trak = boxes::children(&buffer, &root[0])
+            .filter(|b| &b.fourcc == b"trak")
+            .nth(1)
+            .unwrap();
+        let chain = [root[0], trak];
+        let hdlr = boxes::find_child_path(&buffer, &chain, &[b"mdia", b"hdlr"]).unwrap();
+        assert_eq!(&hdlr[3].payload(&buffer)[8..12], b"text");
+        assert!(boxes::find_child_path(&buffer, &chain, &[b"mdia", b"minf", b"nmhd"]).is_some());
+        let path = [b"mdia", b"minf", b"stbl", b"stsd", b"wvtt"];
+        let wvtt = boxes::find_child_path(&buffer, &chain, &path).unwrap();
+        // after the 8 bytes of the sample entry
+        let entry = wvtt[6].payload(&buffer);
+        let vttc = boxes::BoxIter::new(entry, 8..entry.len()).next().unwrap();
+        assert_eq!((&vttc.fourcc, vttc.payload(entry)), (b"vttC", &b"WEBVTT"[..]));
+        // empty, first cue, empty, second cue
+        let stts = boxes::find_child_path(&buffer, &chain, &[b"mdia", b"minf", b"stbl", b"stts"]).unwrap();
+        let stts = stts[5].payload(&buffer);
+        let entries: Vec<_> = (0..boxes::read_u32(stts, 4) as usize)
+            .map(|index| {
+                (
+                    boxes::read_u32(stts, 8 + 8 * index),
+                    boxes::read_u32(stts, 12 + 8 * index),
+                )
+            })
+            .collect();
+        assert_eq!(entries, [(1, 500), (1, 1500), (2, 1000)]);
+    }
+
+    #[test]
+    fn test_copy_edit_list() {
+        // the reference output with an empty edit and a trim
+        let edits = [
+            EditListEntry {
+                segment_duration: 500,
+                media_time: -1,
+                media_rate: edit::NORMAL_RATE,
+            },
+            EditListEntry {
+                segment_duration: 2000,
+                media_time: 9000,
+                media_rate: edit::NORMAL_RATE,
+            },
+        ];
+        let mut source = include_bytes!("./fixtures/h264_output.mp4").to_vec();
+        let root = boxes::find_path(&source, &[b"moov"]).unwrap();
+        let trak = boxes::children(&source, &root[0])
+            .find(|b| &b.fourcc == b"trak")
+            .unwrap();
+        edit::insert_after_tkhd(&mut source, &[root[0], trak], &edit::edts_box(&edits));
+        let demuxer = Mp4Demuxer::new(Cursor::new(source)).unwrap();
+        assert_eq!(demuxer.edit_list(0), Ok(&edits[..]));
+        assert_eq!(demuxer.edit_list(1), Err(Minimp4Error::BadArguments));
+        assert_eq!(
+            (demuxer.movie_timescale(), demuxer.track_timescale(0)),
+            (1000, Ok(90000))
+        );
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_video_timescale(45000).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            mp4muxer.copy_edit_list_from(1, &demuxer, 0),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            mp4muxer.copy_edit_list_from(0, &demuxer, 1),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.copy_edit_list_from(0, &demuxer, 0).unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+
+        let remuxed = Mp4Demuxer::new(buffer).unwrap();
+        let expected = [
+            edits[0],
+            EditListEntry {
+                media_time: 4500,
+                ..edits[1]
+            },
+        ];
+        assert_eq!(remuxed.edit_list(0), Ok(&expected[..]));
+        let plain = Mp4Demuxer::new(Cursor::new(include_bytes!("./fixtures/h264_output.mp4"))).unwrap();
+        assert_eq!(plain.edit_list(0), Ok(&[][..]));
+    }
+
+    #[test]
+    fn test_set_edit_list() {
+        // a delay, the first second, a freeze frame on it, then the rest
+        let edits = [
+            EditListEntry::empty(500),
+            EditListEntry::normal(1000, 0),
+            EditListEntry::dwell(2000, 86400),
+            EditListEntry::normal(4120, 90000),
+        ];
+        let mux = |edits: &[EditListEntry]| {
+            let output = mux(include_bytes!("./fixtures/input.264"), false, |mp4muxer| {
+                mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                assert_eq!(mp4muxer.set_edit_list(1, edits), Err(Minimp4Error::BadArguments));
+                mp4muxer.set_edit_list(0, &[EditListEntry::empty(100)]).unwrap();
+                mp4muxer.set_edit_list(0, edits).unwrap();
+            });
+            Mp4Demuxer::new(Cursor::new(output)).unwrap()
+        };
+        assert_eq!(mux(&edits).edit_list(0), Ok(&edits[..]));
+        assert_eq!(mux(&[]).edit_list(0), Ok(&[][..]));
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        let double_speed = EditListEntry {
+            media_rate: 2 * edit::NORMAL_RATE,
+            ..edits[1]
+        };
+        for invalid in [
+            double_speed,
+            EditListEntry::dwell(1000, -1),
+            EditListEntry::normal(1000, -5),
+        ] {
+            assert_eq!(
+                mp4muxer.set_edit_list(0, &[edits[0], invalid]),
+                Err(Minimp4Error::BadArguments)
+            );
+        }
+    }
+
+    #[test]
+    fn test_track_duration() {
+        let mux = |edits: &[EditListEntry], duration| {
+            let configure = |mp4muxer: &mut Mp4Muxer<_>| {
+                mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                mp4muxer.set_edit_list(0, edits).unwrap();
+            };
+            let finish = |mp4muxer: &mut Mp4Muxer<_>| {
+                // 128 frames of 40 ms
+                assert_eq!(
+                    mp4muxer.set_track_duration(0, Duration::from_millis(5119)),
+                    Err(Minimp4Error::BadArguments)
+                );
+                assert_eq!(
+                    mp4muxer.set_track_duration(1, duration),
+                    Err(Minimp4Error::BadArguments)
+                );
+                mp4muxer.set_track_duration(0, duration).unwrap();
+            };
+            let h264 = include_bytes!("./fixtures/input.264");
+            mux_with(Cursor::new(vec![]), h264, false, configure, finish)
+                .writer
+                .into_inner()
+        };
+        let buffer = mux(&[], Duration::from_millis(5500));
+        let demuxer = Mp4Demuxer::new(Cursor::new(&buffer)).unwrap();
+        let padded = [EditListEntry::normal(5120, 0), EditListEntry::empty(380)];
+        assert_eq!(demuxer.edit_list(0), Ok(&padded[..]));
+        let tkhd = boxes::find_path(&buffer, &[b"moov", b"trak", b"tkhd"]).unwrap();
+        assert_eq!(boxes::read_u32(tkhd[2].payload(&buffer), 20), 5500);
+        let mvhd = boxes::find_path(&buffer, &[b"moov", b"mvhd"]).unwrap();
+        assert_eq!(boxes::read_u32(mvhd[1].payload(&buffer), 16), 5500);
+
+        // an edit list of its own is padded to the duration
+        let delayed = [EditListEntry::empty(1000), EditListEntry::normal(5120, 0)];
+        let buffer = mux(&delayed, Duration::from_secs(7));
+        let demuxer = Mp4Demuxer::new(Cursor::new(&buffer)).unwrap();
+        let padded = [delayed[0], delayed[1], EditListEntry::empty(880)];
+        assert_eq!(demuxer.edit_list(0), Ok(&padded[..]));
+        // nothing to pad
+        let buffer = mux(&[], Duration::from_millis(5120));
+        let demuxer = Mp4Demuxer::new(Cursor::new(&buffer)).unwrap();
+        assert_eq!(demuxer.edit_list(0), Ok(&[EditListEntry::normal(5120, 0)][..]));
+    }
+
+    #[test]
+    fn test_faststart() {
+        let mux = |mode, faststart| {
+            mux(include_bytes!("./fixtures/input.264"), false, |mp4muxer| {
+                mp4muxer.set_mode(mode).unwrap();
+                mp4muxer.set_faststart(faststart).unwrap();
+                mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                assert_eq!(mp4muxer.set_faststart(None), Err(Minimp4Error::BadArguments));
+            })
+        };
+        let first_chunk = |mp4: &[u8]| {
+            let path = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco"];
+            let stco = boxes::find_path(mp4, &path).unwrap();
+            let offset = boxes::read_u32(stco[5].payload(mp4), 8) as usize;
+            mp4[offset..offset + 64].to_vec()
+        };
+        for mode in [Mp4Mode::Default, Mp4Mode::Sequential] {
+            let original = mux(mode, None);
+            for strategy in [FaststartStrategy::Memory, FaststartStrategy::TempFile] {
+                let faststart = mux(mode, Some(strategy));
+                let order: Vec<_> = boxes::BoxIter::new(&faststart, 0..faststart.len())
+                    .map(|b| b.fourcc)
+                    .filter(|fourcc| fourcc != b"free")
+                    .take(3)
+                    .collect();
+                assert_eq!(order, [*b"ftyp", *b"moov", *b"mdat"]);
+                assert_eq!(faststart.len(), original.len());
+                assert_eq!(first_chunk(&faststart), first_chunk(&original));
+                let demuxer = Mp4Demuxer::new(Cursor::new(faststart)).unwrap();
+                assert_eq!(demuxer.sample_count(0), Ok(128));
+            }
+        }
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+        assert_eq!(
+            mp4muxer.set_faststart(Some(FaststartStrategy::Memory)),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert!(mp4muxer.set_faststart(None).is_ok());
+    }
+
+    #[test]
+    fn test_reserved_moov_space() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mux = |reserved| {
+            let mut size = 0;
+            let configure = |mp4muxer: &mut Mp4Muxer<_>| mp4muxer.set_reserved_moov_space(reserved).unwrap();
+            let finish = |mp4muxer: &mut Mp4Muxer<_>| size = mp4muxer.moov_size().unwrap();
+            let mut mp4muxer = mux_with(Cursor::new(vec![]), h264, false, configure, finish);
+            let error = mp4muxer.take_io_error().map(|error| error.kind());
+            (mp4muxer.writer.into_inner(), size, error)
+        };
+        let order = |mp4: &[u8]| -> Vec<_> { boxes::BoxIter::new(mp4, 0..mp4.len()).map(|b| b.fourcc).collect() };
+        let (original, size, _) = mux(None);
+        let samples = |mp4: Vec<u8>| {
+            let mut demuxer = Mp4Demuxer::new(Cursor::new(mp4)).unwrap();
+            (0..demuxer.sample_count(0).unwrap())
+                .map(|n| {
+                    let sample = demuxer.sample(0, n).unwrap();
+                    demuxer.read_sample(&sample).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = samples(original.clone());
+
+        for (reserved, layout) in [
+            (size, &[b"ftyp", b"moov", b"free", b"mdat"][..]),
+            (size + 100, &[b"ftyp", b"moov", b"free", b"free", b"mdat"]),
+        ] {
+            let (mp4, _, error) = mux(Some(reserved));
+            assert_eq!(error, None);
+            assert_eq!(order(&mp4), layout.iter().map(|fourcc| **fourcc).collect::<Vec<_>>());
+            assert_eq!(mp4.len() as u64, original.len() as u64 + reserved - size);
+            assert_eq!(samples(mp4), expected);
+        }
+        // too small, or leaving no room for the header of a free box
+        for reserved in [size - 1, size + 4] {
+            let (mp4, _, error) = mux(Some(reserved));
+            assert_eq!(error, Some(io::ErrorKind::InvalidInput));
+            assert_eq!(order(&mp4), [*b"ftyp", *b"free", *b"free", *b"mdat", *b"moov"]);
+            assert_eq!(samples(mp4), expected);
+        }
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        assert_eq!(
+            mp4muxer.set_reserved_moov_space(Some(4)),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.set_reserved_moov_space(Some(4096)).unwrap();
+        assert_eq!(mp4muxer.set_mode(Mp4Mode::Sequential), Err(Minimp4Error::BadArguments));
+        assert_eq!(
+            mp4muxer.set_faststart(Some(FaststartStrategy::Memory)),
+            Err(Minimp4Error::BadArguments)
+        );
+    }
+
+    #[cfg(feature = "vp9")]
+    #[test]
+    fn test_vp9_track() {
+        use vp9::tests::{superframe, INTER_FRAME, KEY_FRAME};
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(
+            mp4muxer.write_vp9_frame(&KEY_FRAME, 3600),
+            Err(Minimp4Error::BadArguments)
+        );
+        let config = VpccConfig {
+            level: 31,
+            ..VpccConfig::default()
+        };
+        let invalid = VpccConfig { bit_depth: 9, ..config };
+        assert_eq!(
+            mp4muxer.init_video_vp9(1280, 720, invalid),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.init_video_vp9(1280, 720, config).unwrap();
+        assert_eq!(
+            mp4muxer.init_video_vp9(1280, 720, config),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.write_vp9_frame(&KEY_FRAME, 3600).unwrap();
+        mp4muxer.write_vp9_frame(&INTER_FRAME, 3600).unwrap();
+        // a hidden alternate reference along with the frame shown
+        let hidden = [0x84, 0x00];
+        mp4muxer
+            .write_vp9_frame(&superframe(&[&hidden, &INTER_FRAME]), 3600)
+            .unwrap();
+        assert_eq!(mp4muxer.write_vp9_frame(&hidden, 3600), Err(Minimp4Error::BadArguments));
+        assert_eq!(
+            mp4muxer.write_vp9_frame(&INTER_FRAME, 0),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(mp4muxer.summary().tracks[0].sample_count, 3);
+        assert_eq!(mp4muxer.codec_string(0).as_deref(), Some("vp09.00.31.08"));
+        assert_eq!(mp4muxer.verify(), Ok(()));
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let stbl = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+        let stsd = boxes::find_path(&buffer, &[stbl.as_slice(), &[b"stsd"]].concat()).unwrap();
+        assert_eq!(boxes::read_u32(stsd[5].payload(&buffer), 4), 1);
+        let vp09 = boxes::children(&buffer, &stsd[5]).next().unwrap();
+        assert_eq!(&vp09.fourcc, b"vp09");
+        let vp09_payload = vp09.payload(&buffer);
+        assert_eq!(&vp09_payload[24..28], [0x05, 0x00, 0x02, 0xd0]);
+        // after the 78 bytes of the visual sample entry
+        let vpcc = boxes::BoxIter::new(vp09_payload, 78..vp09_payload.len())
+            .next()
+            .unwrap();
+        assert_eq!(&vpcc.fourcc, b"vpcC");
+        assert_eq!(vpcc.payload(vp09_payload), [1, 0, 0, 0, 0, 31, 0x82, 1, 1, 1, 0, 0]);
+        let stss = boxes::find_path(&buffer, &[stbl.as_slice(), &[b"stss"]].concat()).unwrap();
+        let stss = stss[5].payload(&buffer);
+        assert_eq!((boxes::read_u32(stss, 4), boxes::read_u32(stss, 8)), (1, 1));
+        let stsz = boxes::find_path(&buffer, &[stbl.as_slice(), &[b"stsz"]].concat()).unwrap();
+        let stsz = stsz[5].payload(&buffer);
+        assert_eq!(boxes::read_u32(stsz, 8), 3);
+        assert_eq!(boxes::read_u32(stsz, 20), 2 + 2 + 4);
+    }
+
+    #[test]
+    fn test_sample_table() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let sei = nal::NalIter::new(h264).nth(2).unwrap();
+        let parameter_sets = &h264[..sei.as_ptr() as usize - h264.as_ptr() as usize - 3];
+        let mut source = Mp4Muxer::new(Cursor::new(vec![]));
+        source.init_video(1280, 720, false, "h264 stream").unwrap();
+        source.write_video_with_fps(h264, 25).unwrap();
+        let table: Vec<_> = samples(&source.tracks()[0])
+            .iter()
+            .map(|sample| TableSample {
+                offset: sample.offset,
+                size: sample.size as u32,
+                duration: sample.duration,
+                keyframe: sample.flag_random_access != 0,
+            })
+            .collect();
+        let end = table.last().map(|sample| sample.offset + sample.size as u64).unwrap();
+        let media = source.writer.get_ref()[MDAT_DATA_START as usize..end as usize].to_vec();
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(mp4muxer.write_media_data(&media), Err(Minimp4Error::BadArguments));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.write_video(parameter_sets).unwrap();
+        // the table is checked before anything is stored
+        let shifted: Vec<_> = table
+            .iter()
+            .map(|s| TableSample {
+                offset: s.offset + 1,
+                ..*s
+            })
+            .collect();
+        assert_eq!(mp4muxer.write_media_data(&media), Ok(MDAT_DATA_START));
+        let overlapping = [table[1], table[0]];
+        let empty = [TableSample { size: 0, ..table[0] }];
+        for invalid in [&shifted[..], &overlapping, &empty] {
+            assert_eq!(mp4muxer.write_sample_table(0, invalid), Err(Minimp4Error::BadArguments));
+        }
+        assert_eq!(mp4muxer.write_sample_table(1, &table), Err(Minimp4Error::BadArguments));
+        mp4muxer.write_sample_table(0, &table[..10]).unwrap();
+        assert_eq!(
+            mp4muxer.write_sample_table(0, &table[..10]),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.write_sample_table(0, &table[10..]).unwrap();
+        mp4muxer.write_comment("test comment");
+        mp4muxer.close();
+        assert_eq!(buffer.into_inner(), include_bytes!("./fixtures/h264_output.mp4"));
+
+        // the media goes after the reserved room, the chunk offsets with it
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_reserved_moov_space(Some(4096)).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.write_video(parameter_sets).unwrap();
+        assert_eq!(mp4muxer.write_media_data(&media), Ok(MDAT_DATA_START));
+        mp4muxer.write_sample_table(0, &table).unwrap();
+        mp4muxer.close();
+        assert_eq!(mp4muxer.take_io_error().map(|error| error.kind()), None);
+        let mut demuxer = Mp4Demuxer::new(buffer).unwrap();
+        for (index, sample) in table.iter().enumerate() {
+            let read = demuxer.sample(0, index as u32).unwrap();
+            let start = (sample.offset - MDAT_DATA_START) as usize;
+            assert_eq!(
+                demuxer.read_sample(&read).unwrap(),
+                media[start..start + sample.size as usize]
+            );
+        }
+    }
+
+    #[test]
+    fn test_producer_reference_time() {
+        let mut muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+        let mut seconds = 0;
+        muxer.set_producer_reference_clock(move || {
+            seconds += 1;
+            SystemTime::UNIX_EPOCH + Duration::from_millis(seconds * 1000 + 500)
+        });
+        muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        muxer.close();
+        let ranges = muxer.byte_ranges().to_vec();
+        let buffer = muxer.writer.into_inner();
+
+        let top: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len()).collect();
+        let prfts: Vec<_> = top.iter().filter(|b| &b.fourcc == b"prft").collect();
+        assert_eq!(prfts.len(), 128);
+        for (index, prft) in prfts.iter().enumerate() {
+            let next = top.iter().find(|b| b.start == prft.end).unwrap();
+            assert_eq!(&next.fourcc, b"moof");
+            let payload = prft.payload(&buffer);
+            assert_eq!(boxes::read_u32(payload, 0), 0x0100_0004);
+            assert_eq!(boxes::read_u32(payload, 4), 1);
+            let ntp = boxes::read_u64(payload, 8);
+            assert_eq!(ntp, (2_208_988_800 + index as u64 + 1) << 32 | 0x8000_0000);
+            assert_eq!(boxes::read_u64(payload, 16), index as u64 * 3600);
+        }
+        // the fragment starts with its prft
+        assert_eq!(&buffer[ranges[0].byte_offset as usize + 4..][..4], b"prft");
+    }
+
+    #[test]
+    fn test_smooth_streaming() {
+        let mut muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+        muxer.set_smooth_streaming(true).unwrap();
+        muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(muxer.set_smooth_streaming(false), Err(Minimp4Error::BadArguments));
+        muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        muxer.close();
+        let buffer = muxer.writer.into_inner();
+
+        let top: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len()).collect();
+        assert_eq!(top[0].payload(&buffer), b"isml\0\0\0\x01piffiso2isml");
+        let moofs: Vec<_> = top.iter().filter(|b| &b.fourcc == b"moof").collect();
+        assert_eq!(moofs.len(), 128);
+        for (index, moof) in moofs.iter().enumerate() {
+            let moof_data = &buffer[moof.start..moof.end];
+            let tfxd = boxes::find_path(moof_data, &[b"moof", b"traf", b"uuid"]).unwrap();
+            let tfxd = tfxd[2].payload(moof_data);
+            assert_eq!(tfxd[..16], fragment::TFXD_UUID);
+            assert_eq!(boxes::read_u32(tfxd, 16), 0x0100_0000);
+            assert_eq!(boxes::read_u64(tfxd, 20), index as u64 * 3600);
+            assert_eq!(boxes::read_u64(tfxd, 28), 3600);
+            // the samples still start right after the mdat header
+            let trun = boxes::find_path(moof_data, &[b"moof", b"traf", b"trun"]).unwrap();
+            let data_offset = boxes::read_u32(trun[2].payload(moof_data), 8) as usize;
+            assert_eq!(&buffer[moof.end + 4..moof.end + 8], b"mdat");
+            assert_eq!(moof.start + data_offset, moof.end + 8);
+        }
+
+        let mfra = top.last().unwrap();
+        assert_eq!(&mfra.fourcc, b"mfra");
+        let mfro = &buffer[buffer.len() - 16..];
+        assert_eq!(&mfro[4..8], b"mfro");
+        assert_eq!(boxes::read_u32(mfro, 12) as usize, mfra.end - mfra.start);
+        let tfra = boxes::find_path(&buffer[mfra.start..], &[b"mfra", b"tfra"]).unwrap();
+        let tfra = tfra[1].payload(&buffer[mfra.start..]);
+        assert_eq!(boxes::read_u32(tfra, 4), 1);
+        let keyframes = boxes::read_u32(tfra, 12) as usize;
+        assert!(keyframes > 0 && keyframes < 128);
+        for entry in tfra[16..].chunks(19) {
+            let time = boxes::read_u64(entry, 0);
+            let moof = moofs[(time / 3600) as usize];
+            assert_eq!(boxes::read_u64(entry, 8), moof.start as u64);
+            assert_eq!(entry[16..], [1, 1, 1]);
+        }
+        assert_eq!(tfra.len(), 16 + 19 * keyframes);
+    }
+
+    #[test]
+    fn test_queue_event() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mut muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        assert_eq!(
+            muxer.queue_event("urn:scte:scte35:2013:bin", "", 1000, 0, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+        // no track to attach to yet
+        assert_eq!(
+            muxer.queue_event("urn:scte:scte35:2013:bin", "", 1000, 0, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            muxer.queue_event("", "", 1000, 0, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            muxer.queue_event("urn:a\0", "", 1000, 0, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            muxer.queue_event("urn:a", "", 0, 0, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(muxer.queue_event("urn:a", "1", 1000, 500, 2000, b"cue"), Ok(0));
+
+        let mut starts: Vec<_> = nal::NalIter::new(h264)
+            .map(|nal| nal.as_ptr() as usize - h264.as_ptr() as usize - 3)
+            .collect();
+        starts.push(h264.len());
+        let frames: Vec<_> = starts.windows(2).map(|frame| &h264[frame[0]..frame[1]]).collect();
+        let (head, tail) = frames.split_at(frames.len() / 2);
+        for frame in head {
+            muxer.write_frame_with_duration(frame, 90000 / 25).unwrap();
+        }
+        // the fragment of the next sample starts at 40 ms per sample so far
+        let start = muxer.fragment_time(1) / 90;
+        assert!(start > 0);
+        assert_eq!(
+            muxer.queue_event("urn:a", "", 1000, start - 1, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        muxer.set_event_time(EventTime::FragmentRelative);
+        assert_eq!(
+            muxer.queue_event("urn:a", "", 1, start + u32::MAX as u64 + 1, 0, b""),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            muxer.queue_event("urn:b", "2", 1000, start + 20, u32::MAX, b"cue"),
+            Ok(1)
+        );
+        let written = muxer.writer.get_ref();
+        let fragments = boxes::BoxIter::new(written, 0..written.len())
+            .filter(|b| &b.fourcc == b"moof")
+            .count();
+        for frame in tail {
+            muxer.write_frame_with_duration(frame, 90000 / 25).unwrap();
+        }
+        muxer.close();
+        let buffer = muxer.writer.into_inner();
+
+        let top: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len()).collect();
+        let emsgs: Vec<_> = top.iter().filter(|b| &b.fourcc == b"emsg").collect();
+        assert_eq!(emsgs.len(), 2);
+        // each one right ahead of the next fragment once queued
+        let moofs: Vec<_> = top.iter().filter(|b| &b.fourcc == b"moof").collect();
+        assert_eq!(emsgs[0].end, moofs[0].start);
+        assert_eq!(emsgs[1].end, moofs[fragments].start);
+
+        let mut v1 = 0x0100_0000u32.to_be_bytes().to_vec();
+        for field in [1000, 0, 500, 2000, 0] {
+            v1.extend_from_slice(&u32::to_be_bytes(field));
+        }
+        v1.extend_from_slice(b"urn:a\x001\0cue");
+        assert_eq!(emsgs[0].payload(&buffer), v1);
+
+        let mut v0 = vec![0; 4];
+        v0.extend_from_slice(b"urn:b\x002\0");
+        for field in [1000, 20, u32::MAX, 1] {
+            v0.extend_from_slice(&u32::to_be_bytes(field));
+        }
+        v0.extend_from_slice(b"cue");
+        assert_eq!(emsgs[1].payload(&buffer), v0);
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        use std::{cell::RefCell, rc::Rc, time::Duration};
+
+        let reports = Rc::new(RefCell::new(vec![]));
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        assert_eq!(mp4muxer.set_progress_interval(0), Err(Minimp4Error::BadArguments));
+        let sink = reports.clone();
+        mp4muxer.set_progress_callback(move |progress| sink.borrow_mut().push(progress));
+        mp4muxer.set_progress_interval(50).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+
+        let reports = reports.borrow();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].frames_written, 50);
+        assert_eq!(reports[0].media_time, Duration::from_secs(2));
+        assert_eq!(reports[1].frames_written, 100);
+        assert_eq!(reports[1].media_time, Duration::from_secs(4));
+        assert!(reports[0].bytes_written > 0 && reports[0].bytes_written < reports[1].bytes_written);
+    }
+
+    #[test]
+    fn test_clean_aperture() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(
+            mp4muxer.set_clean_aperture(1280.0, 720.0),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            mp4muxer.set_clean_aperture(1281.0, 720.0),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            mp4muxer.set_clean_aperture(1280.0, 0.0),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            mp4muxer.set_clean_aperture(f64::NAN, 720.0),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.set_clean_aperture(1272.0, 716.0).unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let trak = boxes::find_path(&buffer, &[b"moov", b"trak"]).unwrap();
+        let children: Vec<_> = boxes::children(&buffer, &trak[1]).map(|b| b.fourcc).collect();
+        assert_eq!(&children[..3], [*b"tkhd", *b"tapt", *b"mdia"]);
+        let dimensions = |fourcc| {
+            let path = boxes::find_path(&buffer, &[b"moov", b"trak", b"tapt", fourcc]).unwrap();
+            let payload = path[3].payload(&buffer);
+            (boxes::read_u32(payload, 4) >> 16, boxes::read_u32(payload, 8) >> 16)
+        };
+        assert_eq!(dimensions(b"clef"), (1272, 716));
+        assert_eq!(dimensions(b"prof"), (1272, 716));
+        assert_eq!(dimensions(b"enof"), (1280, 720));
+    }
+
+    #[test]
+    fn test_degradation_priorities() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mux = |write| mux(h264, false, |mp4muxer| mp4muxer.set_write_degradation_priorities(write));
+        assert_eq!(mux(false), include_bytes!("./fixtures/h264_output.mp4"));
+
+        let buffer = mux(true);
+        let mut expected = StreamInfo::default();
+        nal::NalIter::new(h264).for_each(|nal| expected.observe(Codec::H264, nal));
+        let stbl = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+        let stdp = boxes::find_path(&buffer, &[&stbl[..], &[b"stdp"]].concat()).unwrap();
+        let priorities: Vec<_> = stdp[5].payload(&buffer)[4..]
+            .chunks(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(priorities.len(), 128);
+        assert_eq!(priorities, expected.priorities);
+        assert_eq!(priorities[0], nal::PRIORITY_KEYFRAME);
+    }
+
+    #[test]
+    fn test_trick_mode_track() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_write_trick_mode_track(true);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        let keyframes: Vec<_> = samples(&mp4muxer.tracks()[0])
+            .iter()
+            .filter(|sample| sample.flag_random_access != 0)
+            .map(|sample| (sample.offset as u32, sample.size as u32))
+            .collect();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let root = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let traks: Vec<_> = boxes::children(&buffer, &root[0])
+            .filter(|b| &b.fourcc == b"trak")
+            .collect();
+        assert_eq!(traks.len(), 2);
+        let tkhds: Vec<_> = traks
+            .iter()
+            .map(|trak| boxes::find_child_path(&buffer, &[root[0], *trak], &[b"tkhd"]).unwrap()[2])
+            .collect();
+        // flags, track_ID and alternate_group of the version 0 boxes
+        let fields = |tkhd: &boxes::BoxRange| {
+            let payload = tkhd.payload(&buffer);
+            (
+                boxes::read_u32(payload, 0) & 0xff_ffff,
+                boxes::read_u32(payload, 12),
+                u16::from_be_bytes([payload[34], payload[35]]),
+            )
+        };
+        assert_eq!(fields(&tkhds[0]), (7, 1, 1));
+        assert_eq!(fields(&tkhds[1]), (2, 2, 1));
+        let mvhd = boxes::find_path(&buffer, &[b"moov", b"mvhd"]).unwrap();
+        assert_eq!(boxes::read_u32(&buffer, mvhd[1].end - 4), 3);
+
+        let stbl = |trak, fourcc| {
+            let path = [b"mdia", b"minf", b"stbl", fourcc];
+            boxes::find_child_path(&buffer, &[root[0], trak], &path).map(|chain| chain[5].payload(&buffer))
+        };
+        assert!(keyframes.len() > 1);
+        let stsz = stbl(traks[1], b"stsz").unwrap();
+        let stco = stbl(traks[1], b"stco").unwrap();
+        assert_eq!(boxes::read_u32(stco, 4) as usize, keyframes.len());
+        for (index, (offset, size)) in keyframes.iter().enumerate() {
+            assert_eq!(boxes::read_u32(stco, 8 + 4 * index), *offset);
+            assert_eq!(boxes::read_u32(stsz, 12 + 4 * index), *size);
+        }
+        assert!(stbl(traks[1], b"stss").is_none());
+        // the keyframes last as long as the video track
+        let total = |stts: &[u8]| -> u32 {
+            (0..boxes::read_u32(stts, 4) as usize)
+                .map(|entry| boxes::read_u32(stts, 8 + 8 * entry) * boxes::read_u32(stts, 12 + 8 * entry))
+                .sum()
+        };
+        assert_eq!(
+            total(stbl(traks[1], b"stts").unwrap()),
+            total(stbl(traks[0], b"stts").unwrap())
+        );
+        assert_eq!(stbl(traks[0], b"stsd"), stbl(traks[1], b"stsd"));
+    }
+
+    #[test]
+    fn test_from_raw() {
+        extern "C" fn write(offset: i64, buffer: *const c_void, size: u64, token: *mut c_void) -> i32 {
+            let output = unsafe { &mut *(token as *mut Vec<u8>) };
+            let buffer = unsafe { from_raw_parts(buffer as *const u8, size as usize) };
+            output.resize(output.len().max(offset as usize + buffer.len()), 0);
+            output[offset as usize..][..buffer.len()].copy_from_slice(buffer);
+            0
+        }
+
+        let mut output = vec![];
+        let muxers = unsafe {
+            let muxer = MP4E_open(0, 0, &mut output as *mut Vec<u8> as *mut c_void, Some(write));
+            let writer = libc::malloc(size_of::<mp4_h26x_writer_t>()) as *mut mp4_h26x_writer_t;
+            mp4_h26x_write_init(writer, muxer, 1280, 720, 0);
+            assert!(matches!(
+                Mp4Muxer::from_raw(Cursor::new(vec![]), null_mut(), writer),
+                Err(Minimp4Error::BadArguments)
+            ));
+            vec![Mp4Muxer::from_raw(Cursor::new(output.clone()), muxer, writer).unwrap()]
+        };
+        // minimp4 follows the muxer wherever it is moved
+        let mut mp4muxer = Box::new(muxers.into_iter().next().unwrap());
+        assert_eq!(mp4muxer.mode, Mp4Mode::Default);
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.write_comment("test comment");
+        mp4muxer.close();
+        assert_eq!(
+            mp4muxer.writer.into_inner(),
+            include_bytes!("./fixtures/h264_output.mp4")
+        );
+    }
+
+    #[test]
+    fn test_metadata_replace() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        // kept until the muxer is open
+        mp4muxer.write_comment("first comment");
+        mp4muxer.set_location(0.0, 0.0, None).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.set_location(37.3318, -122.0312, None).unwrap();
+        mp4muxer.set_track_comment(0, "first").unwrap();
+        mp4muxer.set_track_comment(0, "second").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        let metadata_len = mp4muxer.metadata.len();
+        for _ in 0..3 {
+            mp4muxer.write_comment("test comment");
+        }
+        assert_eq!(mp4muxer.metadata.len(), metadata_len);
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let demuxer = Mp4Demuxer::new(Cursor::new(&buffer)).unwrap();
+        assert_eq!(demuxer.metadata().comment.as_deref(), Some("test comment"));
+        assert_eq!(demuxer.metadata().location.as_deref(), Some("+37.3318-122.0312/"));
+        let movie_udta = boxes::find_path(&buffer, &[b"moov", b"udta"]).unwrap();
+        let locations = boxes::children(&buffer, &movie_udta[1]).filter(|b| &b.fourcc == b"\xa9xyz");
+        assert_eq!(locations.count(), 1);
+        let udta = boxes::find_path(&buffer, &[b"moov", b"trak", b"udta", b"\xa9cmt"]).unwrap();
+        assert!(udta[3].payload(&buffer).ends_with(b"second"));
+    }
+
+    #[test]
+    fn test_moov_size() {
+        for mode in [Mp4Mode::Default, Mp4Mode::Sequential] {
+            let mut buffer = Cursor::new(vec![]);
+            let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+            assert_eq!(mp4muxer.moov_size(), Err(Minimp4Error::BadArguments));
+            mp4muxer.set_mode(mode).unwrap();
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            mp4muxer.set_location(37.3318, -122.0312, None).unwrap();
+            mp4muxer
+                .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+                .unwrap();
+            mp4muxer.write_comment("test comment");
+            let size = mp4muxer.moov_size().unwrap();
+            assert_eq!(mp4muxer.moov_size(), Ok(size));
+            mp4muxer.close();
+            let buffer = buffer.into_inner();
+
+            let moov = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+            assert_eq!((moov[0].end - moov[0].start) as u64, size, "{mode:?}");
+            assert_eq!(moov[0].end, buffer.len());
+            // measuring leaves the output as it was
+            let mut unmeasured = Mp4Muxer::new(Cursor::new(vec![]));
+            unmeasured.set_mode(mode).unwrap();
+            unmeasured.init_video(1280, 720, false, "h264 stream").unwrap();
+            unmeasured.set_location(37.3318, -122.0312, None).unwrap();
+            unmeasured
+                .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+                .unwrap();
+            unmeasured.write_comment("test comment");
+            unmeasured.close();
+            assert_eq!(unmeasured.writer.into_inner(), buffer, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn test_encoder_tag() {
+        let mux = |tag: Option<Option<&str>>| {
+            mux(include_bytes!("./fixtures/input.264"), false, |mp4muxer| {
+                if let Some(tag) = tag {
+                    mp4muxer.set_encoder_tag(tag);
+                }
+            })
+        };
+        assert_eq!(mux(None), include_bytes!("./fixtures/h264_output.mp4"));
+        assert_eq!(mux(Some(None)), include_bytes!("./fixtures/h264_output.mp4"));
+
+        let output = mux(Some(Some("minimp4")));
+        let demuxer = Mp4Demuxer::new(Cursor::new(output)).unwrap();
+        let metadata = demuxer.metadata();
+        assert_eq!(metadata.comment.as_deref(), Some("test comment"));
+        assert_eq!(
+            metadata.itunes_items,
+            [metadata::itunes_text_item(ENCODER_TAG, "minimp4")]
+        );
+    }
+
+    #[test]
+    fn test_copy_metadata() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mux = |source: Option<&Mp4Demuxer<Cursor<Vec<u8>>>>| {
+            let configure = |mp4muxer: &mut Mp4Muxer<_>| {
+                if let Some(source) = source {
+                    assert_eq!(mp4muxer.copy_metadata_from(source), Err(Minimp4Error::BadArguments));
+                }
+            };
+            let finish = |mp4muxer: &mut Mp4Muxer<_>| match source {
+                Some(source) => mp4muxer.copy_metadata_from(source).unwrap(),
+                None => {
+                    mp4muxer.set_location(37.3318, -122.0312, None).unwrap();
+                    mp4muxer.set_qt_metadata("com.apple.quicktime.make", "Apple").unwrap();
+                }
+            };
+            let mp4muxer = mux_with(Cursor::new(vec![]), h264, false, configure, finish);
+            Mp4Demuxer::new(Cursor::new(mp4muxer.writer.into_inner())).unwrap()
+        };
+        let source = mux(None);
+        let metadata = source.metadata().clone();
+        assert_eq!(metadata.comment.as_deref(), Some("test comment"));
+        assert_eq!(metadata.location.as_deref(), Some("+37.3318-122.0312/"));
+        assert_eq!(metadata.qt_metadata.len(), 1);
+        assert!(metadata.itunes_items.is_empty() && metadata.udta_boxes.is_empty());
+        assert_eq!(mux(Some(&source)).metadata(), &metadata);
+
+        // a comment read from a file can hold a NUL, minimp4 takes a C string
+        let finish = |mp4muxer: &mut Mp4Muxer<_>| mp4muxer.write_comment("first\0second");
+        let mp4muxer = mux_with(Cursor::new(vec![]), h264, false, |_| {}, finish);
+        let demuxer = Mp4Demuxer::new(mp4muxer.writer).unwrap();
+        assert_eq!(demuxer.metadata().comment.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_io_error() {
+        /// Fails every write past `limit` bytes.
+        struct FullDisk {
+            inner: Cursor<Vec<u8>>,
+            limit: u64,
+        }
+
+        impl Write for FullDisk {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.inner.position() + buf.len() as u64 > self.limit {
+                    return Err(io::Error::other("disk full"));
+                }
+                self.inner.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl Seek for FullDisk {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.inner.seek(pos)
+            }
+        }
+
+        let mut mp4muxer = Mp4Muxer::new(FullDisk {
+            inner: Cursor::new(vec![]),
+            limit: 10000,
+        });
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        assert_eq!(mp4muxer.last_io_error().unwrap().to_string(), "disk full");
+        assert!(mp4muxer.last_io_error().is_some());
+        assert_eq!(mp4muxer.take_io_error().unwrap().to_string(), "disk full");
+        assert!(mp4muxer.last_io_error().is_none());
+        mp4muxer.close();
+        assert!(mp4muxer.take_io_error().is_some());
+    }
+
+    #[test]
+    fn test_abort_flag() {
+        use std::sync::atomic::Ordering;
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_abort_flag(flag.clone());
+        let abort = flag.clone();
+        mp4muxer.set_progress_callback(move |_| abort.store(true, Ordering::Relaxed));
+        mp4muxer.set_progress_interval(50).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            mp4muxer.write_video_with_fps(include_bytes!("./fixtures/input.264"), 25),
+            Err(Minimp4Error::Aborted)
+        );
+        assert_eq!(mp4muxer.summary().tracks[0].sample_count, 50);
+        // the slice it stopped at is not counted either
+        assert_eq!(mp4muxer.stream_info.priorities.len(), 50);
+        mp4muxer.close();
+
+        let demuxer = Mp4Demuxer::new(Cursor::new(buffer.into_inner())).unwrap();
+        assert_eq!(demuxer.sample_count(0), Ok(50));
+    }
+
+    #[test]
+    fn test_video_media_header() {
+        let mux = |graphics_mode, opcolor| {
+            mux(include_bytes!("./fixtures/input.264"), false, |mp4muxer| {
+                mp4muxer.set_video_media_header(graphics_mode, opcolor).unwrap();
+            })
+        };
+        assert_eq!(mux(0, [0; 3]), include_bytes!("./fixtures/h264_output.mp4"));
+
+        let buffer = mux(0x0024, [0xffff, 0x8000, 0]);
+        assert_eq!(buffer.len(), include_bytes!("./fixtures/h264_output.mp4").len());
+        let vmhd = boxes::find_path(&buffer, &[b"moov", b"trak", b"mdia", b"minf", b"vmhd"]).unwrap();
+        assert_eq!(
+            vmhd[4].payload(&buffer),
+            [0, 0, 0, 1, 0x00, 0x24, 0xff, 0xff, 0x80, 0x00, 0x00, 0x00]
+        );
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        assert_eq!(
+            mp4muxer.set_video_media_header(0x0025, [0; 3]),
+            Err(Minimp4Error::BadArguments)
+        );
+    }
+
+    #[test]
+    fn test_constant_sample_size() {
+        use adts::tests::adts_frame;
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        let frames = adts_frame(4, 2, false, &[0x21; 64]).repeat(3);
+        mp4muxer.write_adts_aac(&frames, 1024).unwrap();
+        // at the 44.1 kHz of the headers
+        let track = mp4muxer.summary().tracks[0];
+        assert_eq!((track.timescale, track.duration), (44100, 3 * 1024));
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        // sample_size and sample_count, without a table
+        let path = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsz"];
+        let stsz = boxes::find_path(&buffer, &path).unwrap();
+        let stsz = stsz[5].payload(&buffer);
+        assert_eq!(stsz.len(), 12);
+        assert_eq!(boxes::read_u32(stsz, 4), 64);
+        assert_eq!(boxes::read_u32(stsz, 8), 3);
+        let demuxer = Mp4Demuxer::new(Cursor::new(buffer)).unwrap();
+        assert_eq!(demuxer.sample_count(0), Ok(3));
+    }
+
+    #[test]
+    fn test_adts_aac() {
+        use adts::tests::adts_frame;
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_audio_timescale(44100).unwrap();
+        assert_eq!(
+            mp4muxer.write_adts_aac(&[0xff, 0xf1, 0x50], 1024),
+            Err(Minimp4Error::BadArguments)
+        );
+        let mut frames = adts_frame(4, 2, false, &[0x21; 100]);
+        frames.extend(adts_frame(4, 2, true, &[0x21; 80]));
+        mp4muxer.write_adts_aac(&frames, 1024).unwrap();
+        assert_eq!(mp4muxer.set_audio_timescale(48000), Err(Minimp4Error::BadArguments));
+        // 48 kHz mono
+        assert_eq!(
+            mp4muxer.write_adts_aac(&adts_frame(3, 1, false, &[0x21; 90]), 1024),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer
+            .write_adts_aac(&adts_frame(4, 2, false, &[0x21; 90]), 0)
+            .unwrap();
+        assert_eq!(
+            mp4muxer.summary().tracks[0],
+            TrackSummary {
+                sample_count: 3,
+                duration: 3 * 1024,
+                timescale: 44100,
+                vps_count: 0,
+                sps_count: 0,
+                pps_count: 0,
+            }
+        );
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let stsz = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsz"];
+        let stsz = boxes::find_path(&buffer, &stsz).unwrap();
+        let stsz = stsz[5].payload(&buffer);
+        let sizes: Vec<_> = (0..3).map(|index| boxes::read_u32(stsz, 12 + 4 * index)).collect();
+        assert_eq!(sizes, [100, 80, 90]);
+        // the AudioSpecificConfig of AAC LC, 44.1 kHz stereo ends the esds
+        let esds = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd", b"mp4a", b"esds"];
+        let esds = boxes::find_path(&buffer, &esds).unwrap();
+        let esds = esds[7].payload(&buffer);
+        assert!(esds.windows(4).any(|w| w == [0x05, 0x02, 0x12, 0x10]));
+        assert_eq!(Mp4Demuxer::new(Cursor::new(buffer)).unwrap().sample_count(0), Ok(3));
+    }
+
+    #[test]
+    fn test_codec_mismatch() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let h265 = include_bytes!("./fixtures/input.265");
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1280, 720, true, "h265 stream").unwrap();
+        assert_eq!(mp4muxer.write_video_with_fps(h264, 25), Err(Minimp4Error::BadArguments));
+        assert!(!mp4muxer.is_ready_to_close());
+        mp4muxer.write_video_with_fps(h265, 25).unwrap();
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(mp4muxer.write_video(h265), Err(Minimp4Error::BadArguments));
+        assert!(!mp4muxer.is_ready_to_close());
+    }
+
+    #[test]
+    fn test_track_comment() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(mp4muxer.set_track_comment(0, "camera"), Err(Minimp4Error::BadArguments));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.set_track_comment(0, "front").unwrap();
+        mp4muxer.set_track_comment(0, "rear camera").unwrap();
+        assert_eq!(mp4muxer.set_track_comment(1, "camera"), Err(Minimp4Error::BadArguments));
+        assert_eq!(mp4muxer.set_track_comment(0, "a\0b"), Err(Minimp4Error::BadArguments));
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.write_comment("test comment");
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let cmt = boxes::find_path(&buffer, &[b"moov", b"trak", b"udta", b"\xa9cmt"]).unwrap();
+        assert_eq!(&cmt[3].payload(&buffer)[4..], b"rear camera");
+        assert!(boxes::find_path(&buffer, &[b"moov", b"udta", b"meta"]).is_some());
+        assert_eq!(Mp4Demuxer::new(Cursor::new(buffer)).unwrap().sample_count(0), Ok(128));
+    }
+
+    #[test]
+    fn test_track_kind() {
+        let role = "urn:mpeg:dash:role:2011";
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(
+            mp4muxer.set_track_kind(0, role, "main"),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.set_track_kind(0, role, "alternate").unwrap();
+        mp4muxer.set_track_kind(0, role, "main").unwrap();
+        mp4muxer.set_track_kind(0, "about:html-kind", "main").unwrap();
+        mp4muxer.set_track_comment(0, "front").unwrap();
+        assert_eq!(mp4muxer.set_track_kind(0, "", "main"), Err(Minimp4Error::BadArguments));
+        assert_eq!(
+            mp4muxer.set_track_kind(0, role, "a\0b"),
+            Err(Minimp4Error::BadArguments)
+        );
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let udta = boxes::find_path(&buffer, &[b"moov", b"trak", b"udta"]).unwrap();
+        // next to the comment, in the same udta
+        let kinds: Vec<_> = boxes::children(&buffer, &udta[2])
+            .map(|kind| (kind.fourcc, kind.payload(&buffer)[4..].to_vec()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (*b"\xa9cmt", b"front".to_vec()),
+                (*b"kind", b"urn:mpeg:dash:role:2011\0main\0".to_vec()),
+                (*b"kind", b"about:html-kind\0main\0".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(
+            mp4muxer.verify(),
+            Err(vec![
+                "track 0 has no decoder configuration".to_owned(),
+                "track 0 has no samples".to_owned()
+            ])
+        );
+        mp4muxer.write_video_with_fps(h264, 25).unwrap();
+        assert_eq!(mp4muxer.verify(), Ok(()));
+
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        let mut starts: Vec<_> = nal::NalIter::new(h264)
+            .map(|nal| nal.as_ptr() as usize - h264.as_ptr() as usize - 3)
+            .collect();
+        starts.push(h264.len());
+        for frame in starts.windows(2) {
+            mp4muxer
+                .write_frame_with_duration(&h264[frame[0]..frame[1]], 0)
+                .unwrap();
+        }
+        assert_eq!(
+            mp4muxer.verify(),
+            Err(vec!["track 0 has 128 samples without duration".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_missing_parameter_sets() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        // join the stream at its first P slice
+        let mut mid_gop = Vec::new();
+        for nal in nal::NalIter::new(h264).skip(4) {
+            mid_gop.extend_from_slice(&[0, 0, 0, 1]);
+            mid_gop.extend_from_slice(nal);
+        }
+        for keep_aud in [false, true] {
+            let mut buffer = Cursor::new(vec![]);
+            let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+            mp4muxer.set_keep_aud(keep_aud).unwrap();
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            assert_eq!(
+                mp4muxer.write_video_with_fps(&mid_gop, 25),
+                Err(Minimp4Error::MissingParameterSets)
+            );
+            assert!(!mp4muxer.is_ready_to_close());
+            // the next parameter sets let the track start
+            mp4muxer.write_video_with_fps(h264, 25).unwrap();
+            mp4muxer.close();
+            let demuxer = Mp4Demuxer::new(buffer).unwrap();
+            assert_eq!(demuxer.sample_count(0), Ok(128));
+        }
+    }
+
+    #[test]
+    fn test_init_segment() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let sei = nal::NalIter::new(h264).nth(2).unwrap();
+        let (parameter_sets, frames) = h264.split_at(sei.as_ptr() as usize - h264.as_ptr() as usize - 3);
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut muxer = Mp4Muxer::new(&mut buffer);
+        assert_eq!(muxer.write_init_segment(), Err(Minimp4Error::BadArguments));
+        muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+        muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        muxer.write_video_with_fps(parameter_sets, 25).unwrap();
+        assert_eq!(muxer.write_init_segment(), Ok(()));
+        assert_eq!(muxer.write_init_segment(), Err(Minimp4Error::BadArguments));
+
+        let init = muxer.writer.get_ref().clone();
+        let top_level: Vec<_> = boxes::BoxIter::new(&init, 0..init.len()).map(|b| b.fourcc).collect();
+        assert_eq!(top_level, [*b"ftyp", *b"moov"]);
+        let stbl = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+        let stsz = boxes::find_path(&init, &[&stbl[..], &[b"stsz"]].concat()).unwrap();
+        assert_eq!(boxes::read_u32(stsz[5].payload(&init), 8), 0);
+        let stts = boxes::find_path(&init, &[&stbl[..], &[b"stts"]].concat()).unwrap();
+        assert_eq!(boxes::read_u32(stts[5].payload(&init), 4), 0);
+        assert!(boxes::find_path(&init, &[&stbl[..], &[b"stsd", b"avc1", b"avcC"]].concat()).is_some());
+        assert!(boxes::find_path(&init, &[b"moov", b"mvex", b"trex"]).is_some());
+
+        muxer.write_video_with_fps(frames, 25).unwrap();
+        muxer.close();
+        let buffer = buffer.into_inner();
+        assert_eq!(&buffer[..init.len()], &init[..]);
+        let moofs: Vec<_> = boxes::BoxIter::new(&buffer, 0..buffer.len())
+            .filter(|b| &b.fourcc == b"moof")
+            .collect();
+        assert_eq!(moofs.len(), 128);
+        let sequence_numbers: Vec<_> = moofs
+            .iter()
+            .map(|moof| {
+                let mfhd = boxes::find_path(&buffer[moof.start..moof.end], &[b"moof", b"mfhd"]).unwrap();
+                boxes::read_u32(mfhd[1].payload(&buffer[moof.start..moof.end]), 4)
+            })
+            .collect();
+        assert!(sequence_numbers.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_fragment_duration() {
+        let mux = |duration: Option<Duration>| {
+            let configure = |muxer: &mut Mp4Muxer<_>| {
+                muxer.set_mode(Mp4Mode::Fragmented).unwrap();
+                if let Some(duration) = duration {
+                    muxer.set_fragment_duration(duration).unwrap();
+                }
+            };
+            let finish = |muxer: &mut Mp4Muxer<_>| {
+                assert_eq!(
+                    muxer.set_fragment_duration(Duration::from_secs(1)),
+                    Err(Minimp4Error::BadArguments)
+                );
+            };
+            let h264 = include_bytes!("./fixtures/input.264");
+            mux_with(Cursor::new(vec![]), h264, false, configure, finish)
+                .writer
+                .into_inner()
+        };
+        assert!(boxes::find_path(&mux(None), &[b"moov", b"mvex", b"mehd"]).is_none());
+
+        let buffer = mux(Some(Duration::from_millis(5120)));
+        let mvex = boxes::find_path(&buffer, &[b"moov", b"mvex"]).unwrap();
+        let children: Vec<_> = boxes::children(&buffer, &mvex[1]).map(|b| b.fourcc).collect();
+        assert_eq!(children, [*b"mehd", *b"trex"]);
+        let mehd = boxes::find_path(&buffer, &[b"moov", b"mvex", b"mehd"]).unwrap();
+        assert_eq!(mehd[2].payload(&buffer), [0, 0, 0, 0, 0, 0, 0x14, 0]);
+        assert!(boxes::is_box(&fragment::mehd_box(1 << 32), b"mehd"));
+        assert_eq!(fragment::mehd_box(1 << 32)[8], 1);
+    }
+
+    #[test]
+    fn test_temporal_layers() {
+        let h265 = include_bytes!("./fixtures/input.265");
+        // every other picture moved to sublayer 1
+        let mut stream = h265.to_vec();
+        let mut pictures = 0;
+        for nal in nal::NalIter::new(h265) {
+            if let nal::NalKind::Slice { first, keyframe: false } = Codec::H265.classify(nal) {
+                pictures += first as usize;
+                if pictures % 2 == 0 {
+                    stream[nal.as_ptr() as usize - h265.as_ptr() as usize + 1] = 2;
+                }
+            }
+        }
+        let output = mux(&stream, true, |_| {});
+        let path = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd", b"hvc1", b"hvcC"];
+        let hvcc = boxes::find_path(&output, &path).unwrap();
+        let byte = hvcc[7].payload(&output)[21];
+        assert_eq!(byte >> 3 & 7, 2);
+        assert_eq!(byte & 3, 3);
+
+        let mut layers = nal::TemporalLayers::default();
+        let mut byte = 0x03;
+        layers.observe(&[0x42, 0x01, 0x01]);
+        layers.patch_hvcc(&mut byte);
+        assert_eq!(byte, 0x03);
+        layers.observe(&[0x02, 0x03]);
+        layers.patch_hvcc(&mut byte);
+        assert_eq!(byte, 0x1f);
+    }
+
+    #[test]
+    fn test_iods() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.set_write_iods(true);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let moov = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let children: Vec<_> = boxes::children(&buffer, &moov[0]).map(|b| b.fourcc).collect();
+        assert_eq!(&children[..3], [*b"mvhd", *b"iods", *b"trak"]);
+        let iods = boxes::find_path(&buffer, &[b"moov", b"iods"]).unwrap();
+        assert_eq!(
+            iods[1].payload(&buffer),
+            [0, 0, 0, 0, 0x10, 7, 0x00, 0x4f, 0xff, 0xff, 0xff, 0xfe, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_chunked_sequential() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let mut reference = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut reference);
+        mp4muxer.set_mode(Mp4Mode::Sequential).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert_eq!(mp4muxer.set_mode(Mp4Mode::Default), Err(Minimp4Error::BadArguments));
+        mp4muxer.write_video_with_fps(h264, 25).unwrap();
+        mp4muxer.close();
+
+        let mut chunks = Vec::new();
+        let mut sink = ChunkedWriter::new(64 * 1024, |chunk: &[u8]| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        });
+        let mut mp4muxer = Mp4Muxer::new(&mut sink);
+        mp4muxer.set_mode(Mp4Mode::Sequential).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.write_video_with_fps(h264, 25).unwrap();
+        mp4muxer.close();
+        sink.finish().unwrap();
+
+        let (last, full) = chunks.split_last().unwrap();
+        assert!(full.iter().all(|chunk| chunk.len() == 64 * 1024));
+        assert!(!last.is_empty() && last.len() <= 64 * 1024);
+        let output = chunks.concat();
+        assert_eq!(output, reference.into_inner());
+        assert!(boxes::find_path(&output, &[b"moov", b"trak"]).is_some());
+    }
+
+    #[test]
+    fn test_requires_seek() {
+        for mode in [Mp4Mode::Default, Mp4Mode::Sequential, Mp4Mode::Fragmented] {
+            let mut sink = ChunkedWriter::new(16, |_: &[u8]| Ok(()));
+            let mut mp4muxer = Mp4Muxer::new(&mut sink);
+            mp4muxer.set_mode(mode).unwrap();
+            mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+            let _ = mp4muxer.write_video_with_fps(include_bytes!("./fixtures/input.264"), 25);
+            mp4muxer.close();
+            assert_eq!(mp4muxer.last_io_error().is_some(), mp4muxer.requires_seek(), "{mode:?}");
+        }
+        // the staged copy takes the seeking
+        let mut sink = ChunkedWriter::new(16, |_: &[u8]| Ok(()));
+        let mut mp4muxer = Mp4Muxer::new(&mut sink);
+        mp4muxer.set_faststart(Some(FaststartStrategy::Memory)).unwrap();
+        assert!(!mp4muxer.requires_seek());
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.close();
+        assert!(mp4muxer.last_io_error().is_none());
+    }
+
+    /// Inserts an access unit delimiter before every access unit of `stream`.
+    fn with_auds(codec: Codec, stream: &[u8], aud: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut after_slice = true;
+        for nal in nal::NalIter::new(stream) {
+            if after_slice {
+                output.extend_from_slice(&[0, 0, 0, 1]);
+                output.extend_from_slice(aud);
+            }
+            after_slice = matches!(codec.classify(nal), nal::NalKind::Slice { .. });
+            output.extend_from_slice(&[0, 0, 0, 1]);
+            output.extend_from_slice(nal);
+        }
+        output
+    }
+
+    /// Muxes `stream` as the fixtures were made, at 25 fps into a 1280x720
+    /// track with a comment, and returns the output. `configure` runs on the
+    /// new muxer, and the track is only added after it unless it added one
+    /// itself, to set it up before the stream is written.
+    fn mux(stream: &[u8], is_hevc: bool, configure: impl FnOnce(&mut Mp4Muxer<Cursor<Vec<u8>>>)) -> Vec<u8> {
+        mux_with(Cursor::new(vec![]), stream, is_hevc, configure, |_| {})
+            .writer
+            .into_inner()
+    }
+
+    /// [`mux`] into `writer`, with `finish` running on the muxer right before
+    /// it is closed, and returning the closed muxer.
+    fn mux_with<W: Write + Seek>(
+        writer: W,
+        stream: &[u8],
+        is_hevc: bool,
+        configure: impl FnOnce(&mut Mp4Muxer<W>),
+        finish: impl FnOnce(&mut Mp4Muxer<W>),
+    ) -> Mp4Muxer<W> {
+        let mut mp4muxer = Mp4Muxer::new(writer);
+        configure(&mut mp4muxer);
+        if mp4muxer.tracks().is_empty() {
+            mp4muxer
+                .init_video(1280, 720, is_hevc, if is_hevc { "h265 stream" } else { "h264 stream" })
+                .unwrap();
+        }
+        mp4muxer.write_video_with_fps(stream, 25).unwrap();
+        mp4muxer.write_comment("test comment");
+        finish(&mut mp4muxer);
+        mp4muxer.close();
+        mp4muxer
+    }
+
+    #[test]
+    fn test_aud() {
+        let h264 = with_auds(Codec::H264, include_bytes!("./fixtures/input.264"), &[0x09, 0xf0]);
+        let h265 = with_auds(Codec::H265, include_bytes!("./fixtures/input.265"), &[0x46, 0x01, 0x50]);
+        // stripped by default, leaving the output untouched
+        assert_eq!(mux(&h264, false, |_| {}), include_bytes!("./fixtures/h264_output.mp4"));
+        assert_eq!(mux(&h265, true, |_| {}), include_bytes!("./fixtures/h265_output.mp4"));
+
+        for (codec, stream, aud) in [
+            (Codec::H264, &h264, &[0x09, 0xf0][..]),
+            (Codec::H265, &h265, &[0x46, 0x01, 0x50][..]),
+        ] {
+            let output = mux(stream, codec == Codec::H265, |mp4muxer| {
+                mp4muxer.set_keep_aud(true).unwrap();
+                mp4muxer.init_video(1280, 720, codec == Codec::H265, "stream").unwrap();
+                assert_eq!(mp4muxer.set_keep_aud(false), Err(Minimp4Error::BadArguments));
+            });
+            let pictures = nal::NalIter::new(stream)
+                .filter(|nal| matches!(codec.classify(nal), nal::NalKind::Slice { first: true, .. }))
+                .count();
+            let path = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsz"];
+            let stsz = boxes::find_path(&output, &path).unwrap();
+            assert_eq!(boxes::read_u32(stsz[5].payload(&output), 8) as usize, pictures);
+            let mdat = boxes::find_path(&output, &[b"mdat"]).unwrap();
+            let first = mdat[0].payload(&output);
+            assert_eq!(boxes::read_u32(first, 0) as usize, aud.len());
+            assert_eq!(&first[4..4 + aud.len()], aud);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_reset_audio_encoder() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        assert!(matches!(
+            mp4muxer.reset_audio_encoder(),
+            Err(Error::Muxer(Minimp4Error::BadArguments))
+        ));
+        mp4muxer.init_audio(128000, 48000, 2).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        let frame = vec![0i16; mp4muxer.pcm_frame_length().unwrap()];
+        let audio_samples = |mp4muxer: &Mp4Muxer<_>| mp4muxer.summary().tracks[1].sample_count;
+        for _ in 0..10 {
+            mp4muxer.write_pcm_frame(&frame).unwrap();
+        }
+        let written = audio_samples(&mp4muxer);
+        mp4muxer.reset_audio_encoder().unwrap();
+        // what the encoder held back is out
+        let flushed = audio_samples(&mp4muxer);
+        assert!(flushed > written && flushed >= 10, "{written} {flushed}");
+        for _ in 0..10 {
+            mp4muxer.write_pcm_frame(&frame).unwrap();
+        }
+        assert!(audio_samples(&mp4muxer) > flushed);
+        mp4muxer.close();
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_insert_audio_silence() {
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        assert!(matches!(
+            mp4muxer.insert_audio_silence(Duration::from_secs(1)),
+            Err(Error::Muxer(Minimp4Error::BadArguments))
+        ));
+        mp4muxer.init_audio(128000, 48000, 2).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        let frame = vec![0x100i16; mp4muxer.pcm_frame_length().unwrap()];
+        for _ in 0..20 {
+            mp4muxer.write_pcm_frame(&frame).unwrap();
+        }
+        let audio_samples = |mp4muxer: &Mp4Muxer<_>| mp4muxer.summary().tracks[1].sample_count;
+        let before = audio_samples(&mp4muxer);
+        // 46.875 frames
+        mp4muxer.insert_audio_silence(Duration::from_secs(1)).unwrap();
+        assert_eq!(audio_samples(&mp4muxer), before + 47);
+        mp4muxer.insert_audio_silence(Duration::from_millis(10)).unwrap();
+        assert_eq!(audio_samples(&mp4muxer), before + 47);
+        mp4muxer.close();
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    fn test_unsupported_audio() {
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        // checked before any encoder is set up
+        assert!(matches!(mp4muxer.init_audio(128000, 4000, 3), Err(Error::Encoder(_))));
+        assert!(matches!(mp4muxer.init_audio(128000, 48000, 3), Err(Error::Encoder(_))));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        assert!(matches!(mp4muxer.init_audio(128000, 0, 2), Err(Error::Encoder(_))));
+        assert_eq!(mp4muxer.summary().tracks.len(), 1);
+        mp4muxer.close();
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_audio_from_reader() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        let pcm = vec![0u8; 1010 * 48 * 2 * 2];
+        assert!(matches!(
+            mp4muxer.write_audio_from_reader(&pcm[..], PcmFormat::S16Le),
+            Err(Error::Muxer(Minimp4Error::BadArguments))
+        ));
+        mp4muxer.init_audio(128000, 48000, 2).unwrap();
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        match mp4muxer.write_audio_from_reader(Broken, PcmFormat::S16Le) {
+            Err(Error::Io(error)) => assert_eq!(error.kind(), io::ErrorKind::BrokenPipe),
+            other => panic!("{other:?}"),
+        }
+        mp4muxer.write_audio_from_reader(&pcm[..], PcmFormat::S16Le).unwrap();
+        let audio = mp4muxer.summary().tracks[1];
+        // 47 frames and a partial one, plus what the encoder held back
+        assert!(audio.sample_count > 48, "{}", audio.sample_count);
+        mp4muxer.close();
+        let buffer = buffer.into_inner();
+
+        let root = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let traks: Vec<_> = boxes::children(&buffer, &root[0])
+            .filter(|b| &b.fourcc == b"trak")
+            .collect();
+        assert!(boxes::find_child_path(&buffer, &[root[0], traks[0]], &[b"edts"]).is_none());
+        let children: Vec<_> = boxes::children(&buffer, &traks[1]).map(|b| b.fourcc).collect();
+        assert_eq!(&children[..3], [*b"tkhd", *b"edts", *b"mdia"]);
+        let elst = boxes::find_child_path(&buffer, &[root[0], traks[1]], &[b"edts", b"elst"]).unwrap();
+        let elst = elst[3].payload(&buffer);
+        assert_eq!(boxes::read_u32(elst, 8), 1010);
+        assert!(boxes::read_u32(elst, 12) > 0);
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_audio_timescale() {
+        let mut mp4muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.init_audio(128000, 44100, 2).unwrap();
+        let pcm = include_bytes!("./fixtures/input.pcm");
+        assert!(matches!(
+            mp4muxer.write_video_with_audio(include_bytes!("./fixtures/input.264"), 0, pcm),
+            Err(Error::Muxer(Minimp4Error::BadArguments))
+        ));
+        mp4muxer
+            .write_video_with_audio(include_bytes!("./fixtures/input.264"), 25, pcm)
+            .unwrap();
+        let audio = mp4muxer.summary().tracks[1];
+        assert_eq!(audio.timescale, 44100);
+        assert_eq!(audio.duration, audio.sample_count as u64 * 1024);
+        mp4muxer.close();
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_mux_h264_audio() {
+        use std::{fs::write, path::Path};
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        let h264 = include_bytes!("./fixtures/input.264");
+        let pcm = include_bytes!("./fixtures/input.pcm");
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.init_audio(128000, 44100, 2).unwrap();
+        mp4muxer.write_video_with_audio(h264, 25, pcm).unwrap();
+        mp4muxer.write_comment("test comment");
+        mp4muxer.close();
+        // write with audio has not stable output, need to be check later
+        write(Path::new("./src/fixtures/h264_output.tmp"), buffer.into_inner()).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_interleave_window() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let pcm = include_bytes!("./fixtures/input.pcm");
+        let runs = |window, interleave| {
+            let (buffer, summary, result) = Mp4Muxer::with(
+                Cursor::new(vec![]),
+                |muxer| {
+                    muxer.set_interleave_window(window);
+                    muxer.set_interleave(interleave);
+                    muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                    muxer.init_audio(128000, 44100, 2).unwrap();
+                },
+                |muxer| muxer.write_video_with_audio(h264, 25, pcm),
+            );
+            result.unwrap();
+            let buffer = buffer.into_inner();
+            let moov = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+            let mut chunks = vec![];
+            for (track, trak) in boxes::children(&buffer, &moov[0])
+                .filter(|b| &b.fourcc == b"trak")
+                .enumerate()
+            {
+                let path = [b"mdia", b"minf", b"stbl", b"stco"];
+                let stco = boxes::find_child_path(&buffer, &[moov[0], trak], &path).unwrap();
+                let stco = stco[5].payload(&buffer);
+                for index in 0..boxes::read_u32(stco, 4) as usize {
+                    chunks.push((boxes::read_u32(stco, 8 + 4 * index), track));
+                }
+            }
+            chunks.sort();
+            let runs = 1 + chunks.windows(2).filter(|w| w[0].1 != w[1].1).count();
+            (summary, runs)
+        };
+
+        let (tight, tight_runs) = runs(Duration::ZERO, true);
+        let (loose, loose_runs) = runs(Duration::from_secs(1), true);
+        let (appended, appended_runs) = runs(Duration::ZERO, false);
+        assert_eq!(tight, loose);
+        assert_eq!(tight, appended);
+        assert!(tight_runs > 100);
+        assert!(loose_runs <= 2 * (128 / 25 + 1));
+        assert_eq!(appended_runs, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "aac")]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_transport_format() {
+        let pcm: Vec<i16> = (0..2048).map(|i| ((i as f32 / 20.0).sin() * 8000.0) as i16).collect();
+        let encode = |transport| {
+            let params = EncoderParams {
+                bit_rate: BitRate::Cbr(128000),
+                sample_rate: 44100,
+                channel_count: 2,
+                transport,
+            };
+            let encoder = enc::Encoder::new(params).unwrap();
+            let mut output = vec![0; 2048];
+            // the encoder delay takes a few frames to get through
+            let mut frames = vec![];
+            while frames.len() < 3 {
+                let info = encoder.encode(&pcm, &mut output).unwrap();
+                if info.output_size > 0 {
+                    frames.push(output[..info.output_size].to_vec());
+                }
+            }
+            frames
+        };
+        let is_adts = |frame: &[u8]| frame[0] == 0xff && frame[1] & 0xf6 == 0xf0;
+        assert!(encode(TransportFormat::Adts).iter().all(|frame| is_adts(frame)));
+        assert!(!encode(TransportFormat::Raw).iter().any(|frame| is_adts(frame)));
+
+        let (buffer, _, result) = Mp4Muxer::with(
+            Cursor::new(vec![]),
+            |muxer| {
+                muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+                muxer.init_audio(128000, 44100, 2).unwrap();
+            },
+            |muxer| {
+                let pcm = include_bytes!("./fixtures/input.pcm");
+                muxer.write_video_with_audio(include_bytes!("./fixtures/input.264"), 25, pcm)
+            },
+        );
+        result.unwrap();
+        let buffer = buffer.into_inner();
+        let moov = boxes::find_path(&buffer, &[b"moov"]).unwrap();
+        let audio = boxes::BoxIter::new(&buffer, moov[0].children())
+            .filter(|b| &b.fourcc == b"trak")
+            .nth(1)
+            .unwrap();
+        let path = [b"mdia", b"minf", b"stbl", b"stco"];
+        let stco = boxes::find_child_path(&buffer, &[moov[0], audio], &path).unwrap();
+        let offset = boxes::read_u32(stco[5].payload(&buffer), 8) as usize;
+        assert!(!is_adts(&buffer[offset..]));
     }
 
     #[test]
@@ -160,8 +5395,8 @@ mod tests {
         let mut buffer = Cursor::new(vec![]);
         let mut mp4muxer = Mp4Muxer::new(&mut buffer);
         let h264 = include_bytes!("./fixtures/input.264");
-        mp4muxer.init_video(1280, 720, false, "h264 stream");
-        mp4muxer.write_video_with_fps(h264, 25);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer.write_video_with_fps(h264, 25).unwrap();
         mp4muxer.write_comment("test comment");
         mp4muxer.close();
         let buffer = buffer.into_inner();
@@ -173,8 +5408,8 @@ mod tests {
         let mut buffer = Cursor::new(vec![]);
         let mut mp4muxer = Mp4Muxer::new(&mut buffer);
         let h265 = include_bytes!("./fixtures/input.265");
-        mp4muxer.init_video(1280, 720, true, "h265 stream");
-        mp4muxer.write_video_with_fps(h265, 25);
+        mp4muxer.init_video(1280, 720, true, "h265 stream").unwrap();
+        mp4muxer.write_video_with_fps(h265, 25).unwrap();
         mp4muxer.write_comment("test comment");
         mp4muxer.close();
         let buffer = buffer.into_inner();
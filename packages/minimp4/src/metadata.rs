@@ -0,0 +1,364 @@
+//! Movie metadata that minimp4 cannot write on its own, and its reading back
+//! for remuxing.
+
+use crate::boxes::{find_child_path, find_path, read_u32, read_u64, BoxIter, BoxRange, BoxWriter};
+
+/// Metadata items set on the muxer, each holding a single value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum MetadataTag {
+    /// The movie comment minimp4 writes.
+    Comment,
+    /// ISO 6709 string of the `©xyz` box.
+    Location,
+    /// `©cmt` of the track of this index.
+    TrackComment(usize),
+    /// Item of the QuickTime `meta` box, by key.
+    QuickTime(String),
+}
+
+/// `data` box type indicator for UTF-8 text, see the QuickTime well-known types.
+const DATA_TYPE_UTF8: u32 = 1;
+
+/// ISO 639-2/T `und` packed into 15 bits, as used by QuickTime user data text.
+const LANGUAGE_UND: u16 = 0x55c4;
+
+/// Returns `true` for reverse-DNS keys such as `com.apple.quicktime.make`:
+/// at least two non-empty dot separated labels of ASCII letters, digits, `-`
+/// or `_`.
+pub(crate) fn is_reverse_dns(key: &str) -> bool {
+    let mut labels = 0;
+    for label in key.split('.') {
+        if label.is_empty()
+            || !label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return false;
+        }
+        labels += 1;
+    }
+    labels >= 2
+}
+
+/// Builds the QuickTime `meta` box: a `keys` table listing every key, and an
+/// `ilst` item per key whose box type is the 1-based index into that table.
+pub(crate) fn qt_meta_box(entries: &[(String, String)]) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin_full(b"meta", 0, 0);
+    w.begin_full(b"hdlr", 0, 0)
+        .u32(0) // pre_defined
+        .bytes(b"mdta")
+        .u32(0)
+        .u32(0)
+        .u32(0) // reserved[3]
+        .u8(0) // empty name
+        .end();
+    w.begin_full(b"keys", 0, 0).u32(entries.len() as u32);
+    for (key, _) in entries {
+        w.u32(8 + key.len() as u32).bytes(b"mdta").bytes(key.as_bytes());
+    }
+    w.end();
+    w.begin(b"ilst");
+    for (index, (_, value)) in entries.iter().enumerate() {
+        w.begin(&(index as u32 + 1).to_be_bytes())
+            .begin(b"data")
+            .u32(DATA_TYPE_UTF8)
+            .u32(0) // default locale
+            .bytes(value.as_bytes())
+            .end()
+            .end();
+    }
+    w.end();
+    w.end().finish()
+}
+
+/// Formats a location as an ISO 6709 string, e.g. `+37.3318-122.0312+21.000/`.
+///
+/// Latitude and longitude always carry an explicit sign and a fixed number of
+/// integer digits (2 and 3) with four decimals, roughly 10 m of precision. The
+/// optional altitude is in meters. Returns `None` for coordinates out of range.
+pub(crate) fn iso6709(latitude: f64, longitude: f64, altitude: Option<f64>) -> Option<String> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return None;
+    }
+    let mut location = format!("{:+08.4}{:+09.4}", latitude, longitude);
+    if let Some(altitude) = altitude {
+        if !altitude.is_finite() {
+            return None;
+        }
+        location += &format!("{:+.3}", altitude);
+    }
+    location.push('/');
+    Some(location)
+}
+
+/// Builds a QuickTime user data text box (e.g. `©xyz`) holding `text`.
+pub(crate) fn udta_text_box(fourcc: &[u8; 4], text: &str) -> Vec<u8> {
+    BoxWriter::new()
+        .begin(fourcc)
+        .u16(text.len() as u16)
+        .u16(LANGUAGE_UND)
+        .bytes(text.as_bytes())
+        .end()
+        .finish()
+}
+
+/// Builds the `kind` box of a track role: the null-terminated `scheme` URI
+/// and `value`.
+pub(crate) fn kind_box(scheme: &str, value: &str) -> Vec<u8> {
+    BoxWriter::new()
+        .begin_full(b"kind", 0, 0)
+        .bytes(scheme.as_bytes())
+        .u8(0)
+        .bytes(value.as_bytes())
+        .u8(0)
+        .end()
+        .finish()
+}
+
+/// Movie metadata read by [`crate::Mp4Demuxer::metadata`], to be carried
+/// over to a new file with [`crate::Mp4Muxer::copy_metadata_from`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mp4Metadata {
+    /// The iTunes-style `©cmt` item of `moov/udta/meta`, as written by
+    /// [`crate::Mp4Muxer::write_comment`].
+    pub comment: Option<String>,
+    /// The ISO 6709 string of `moov/udta/©xyz`.
+    pub location: Option<String>,
+    /// Creation time of the `mvhd`, in seconds since 1904-01-01 UTC, or
+    /// `None` when left at zero.
+    pub creation_time: Option<u64>,
+    /// The UTF-8 items of the QuickTime `moov/meta` box, by key.
+    pub qt_metadata: Vec<(String, String)>,
+    /// Items of `moov/udta/meta/ilst` other than the comment, verbatim.
+    pub itunes_items: Vec<Vec<u8>>,
+    /// Children of `moov/udta` not covered above, verbatim.
+    pub udta_boxes: Vec<Vec<u8>>,
+}
+
+impl Mp4Metadata {
+    /// Reads the metadata of the `moov` box `moov`. Malformed or unknown
+    /// parts are skipped or kept verbatim, never an error.
+    pub(crate) fn read(moov: &[u8]) -> Self {
+        let mut metadata = Self::default();
+        let Some(root) = find_path(moov, &[b"moov"]) else {
+            return metadata;
+        };
+        if let Some(mvhd) = find_child_path(moov, &root, &[b"mvhd"]) {
+            let mvhd = &moov[mvhd[1].payload..mvhd[1].end];
+            let creation_time = match mvhd.first() {
+                Some(0) if mvhd.len() >= 8 => read_u32(mvhd, 4) as u64,
+                Some(1) if mvhd.len() >= 12 => read_u64(mvhd, 4),
+                _ => 0,
+            };
+            metadata.creation_time = Some(creation_time).filter(|&time| time != 0);
+        }
+        if let Some(udta) = find_child_path(moov, &root, &[b"udta"]) {
+            for child in BoxIter::new(moov, udta[1].children()) {
+                let payload = &moov[child.payload..child.end];
+                match &child.fourcc {
+                    b"\xa9xyz" if metadata.location.is_none() => match udta_text(payload) {
+                        Some(location) => metadata.location = Some(location),
+                        None => metadata.udta_boxes.push(moov[child.start..child.end].to_vec()),
+                    },
+                    b"meta" => metadata.read_itunes_items(moov, &child),
+                    _ => metadata.udta_boxes.push(moov[child.start..child.end].to_vec()),
+                }
+            }
+        }
+        if let Some(meta) = find_child_path(moov, &root, &[b"meta"]) {
+            metadata.qt_metadata = qt_items(moov, &meta[1]);
+        }
+        metadata
+    }
+
+    fn read_itunes_items(&mut self, moov: &[u8], meta: &BoxRange) {
+        let Some(ilst) = find_child_path(moov, &[*meta], &[b"ilst"]) else {
+            return;
+        };
+        for item in BoxIter::new(moov, ilst[1].children()) {
+            if &item.fourcc == b"\xa9cmt" && self.comment.is_none() {
+                let text = find_child_path(moov, &[item], &[b"data"])
+                    .and_then(|data| utf8_data(&moov[data[1].payload..data[1].end]));
+                if let Some(text) = text {
+                    self.comment = Some(text.trim_end_matches('\0').to_owned());
+                    continue;
+                }
+            }
+            self.itunes_items.push(moov[item.start..item.end].to_vec());
+        }
+    }
+}
+
+/// Text of a QuickTime user data text box payload.
+fn udta_text(payload: &[u8]) -> Option<String> {
+    let length = u16::from_be_bytes(payload.get(..2)?.try_into().unwrap()) as usize;
+    let text = payload.get(4..4 + length)?;
+    String::from_utf8(text.to_vec()).ok()
+}
+
+/// Value of a `data` box payload of UTF-8 type.
+fn utf8_data(payload: &[u8]) -> Option<String> {
+    if payload.len() < 8 || read_u32(payload, 0) != DATA_TYPE_UTF8 {
+        return None;
+    }
+    String::from_utf8(payload[8..].to_vec()).ok()
+}
+
+/// UTF-8 items of a QuickTime `meta` box, as built by [`qt_meta_box`].
+fn qt_items(moov: &[u8], meta: &BoxRange) -> Vec<(String, String)> {
+    let meta = [*meta];
+    let Some(keys) = find_child_path(moov, &meta, &[b"keys"]) else {
+        return vec![];
+    };
+    let keys = &moov[keys[1].payload..keys[1].end];
+    let mut names = vec![];
+    let mut pos = 8;
+    while pos + 8 <= keys.len() {
+        let size = read_u32(keys, pos) as usize;
+        let Some(key) = keys.get(pos + 8..pos + size.max(8)) else {
+            break;
+        };
+        names.push(String::from_utf8(key.to_vec()).ok());
+        pos += size.max(8);
+    }
+    let Some(ilst) = find_child_path(moov, &meta, &[b"ilst"]) else {
+        return vec![];
+    };
+    let mut items = vec![];
+    for item in BoxIter::new(moov, ilst[1].children()) {
+        let index = u32::from_be_bytes(item.fourcc) as usize;
+        let Some(Some(key)) = index.checked_sub(1).and_then(|index| names.get(index)) else {
+            continue;
+        };
+        let value =
+            find_child_path(moov, &[item], &[b"data"]).and_then(|data| utf8_data(&moov[data[1].payload..data[1].end]));
+        if let Some(value) = value {
+            items.push((key.clone(), value));
+        }
+    }
+    items
+}
+
+/// Builds an iTunes-style `ilst` item holding `text`.
+pub(crate) fn itunes_text_item(fourcc: &[u8; 4], text: &str) -> Vec<u8> {
+    BoxWriter::new()
+        .begin(fourcc)
+        .begin(b"data")
+        .u32(DATA_TYPE_UTF8)
+        .u32(0) // default locale
+        .bytes(text.as_bytes())
+        .end()
+        .end()
+        .finish()
+}
+
+/// Builds an iTunes-style `meta` box, as minimp4 writes for the comment,
+/// holding the already serialized `ilst` `items`.
+pub(crate) fn itunes_meta_box(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin_full(b"meta", 0, 0);
+    w.begin_full(b"hdlr", 0, 0)
+        .u32(0) // pre_defined
+        .bytes(b"mdir")
+        .bytes(b"appl")
+        .u32(0)
+        .u32(0) // reserved[3]
+        .u8(0) // empty name
+        .end();
+    w.begin(b"ilst");
+    for item in items {
+        w.bytes(item);
+    }
+    w.end();
+    w.end().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boxes::children;
+
+    #[test]
+    fn test_reverse_dns() {
+        assert!(is_reverse_dns("com.apple.quicktime.make"));
+        assert!(is_reverse_dns("org.example_1.key-2"));
+        assert!(!is_reverse_dns("make"));
+        assert!(!is_reverse_dns("com..make"));
+        assert!(!is_reverse_dns(".com.make"));
+        assert!(!is_reverse_dns("com.apple quicktime"));
+        assert!(!is_reverse_dns(""));
+    }
+
+    #[test]
+    fn test_iso6709() {
+        assert_eq!(iso6709(37.3318, -122.0312, None).unwrap(), "+37.3318-122.0312/");
+        assert_eq!(iso6709(-5.5, 7.25, Some(21.0)).unwrap(), "-05.5000+007.2500+21.000/");
+        assert_eq!(iso6709(0.0, 0.0, Some(-3.5)).unwrap(), "+00.0000+000.0000-3.500/");
+        assert!(iso6709(90.5, 0.0, None).is_none());
+        assert!(iso6709(0.0, -180.5, None).is_none());
+        assert!(iso6709(f64::NAN, 0.0, None).is_none());
+        assert!(iso6709(0.0, 0.0, Some(f64::INFINITY)).is_none());
+    }
+
+    #[test]
+    fn test_qt_meta_box() {
+        let meta = qt_meta_box(&[
+            ("com.apple.quicktime.make".into(), "Apple".into()),
+            ("com.apple.quicktime.model".into(), "iPhone".into()),
+        ]);
+        let keys = find_path(&meta, &[b"meta", b"keys"]).unwrap();
+        let keys = keys[1].payload(&meta);
+        assert_eq!(read_u32(keys, 4), 2);
+        assert_eq!(&keys[12..16], b"mdta");
+        assert_eq!(&keys[16..40], b"com.apple.quicktime.make");
+
+        let ilst = find_path(&meta, &[b"meta", b"ilst"]).unwrap();
+        let items: Vec<_> = children(&meta, &ilst[1]).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].fourcc, 2u32.to_be_bytes());
+        let data = find_path(&meta[items[1].payload..items[1].end], &[b"data"]).unwrap();
+        assert_eq!(&data[0].payload(&meta[items[1].payload..items[1].end])[8..], b"iPhone");
+    }
+
+    #[test]
+    fn test_read_metadata() {
+        let cmt = BoxWriter::new()
+            .begin(b"\xa9cmt")
+            .begin(b"data")
+            .u32(DATA_TYPE_UTF8)
+            .u32(0)
+            .bytes(b"comment\0")
+            .end()
+            .end()
+            .finish();
+        let too = itunes_text_item(b"\xa9too", "encoder");
+        let name = udta_text_box(b"\xa9nam", "name");
+        let moov = BoxWriter::new()
+            .begin(b"moov")
+            .begin_full(b"mvhd", 0, 0)
+            .u32(3_000_000_000)
+            .u32(3_000_000_001)
+            .end()
+            .begin(b"udta")
+            .bytes(&itunes_meta_box(&[cmt, too.clone()]))
+            .bytes(&udta_text_box(b"\xa9xyz", "+37.3318-122.0312/"))
+            .bytes(&name)
+            .end()
+            .bytes(&qt_meta_box(&[("com.apple.quicktime.make".into(), "Apple".into())]))
+            .end()
+            .finish();
+
+        let metadata = Mp4Metadata::read(&moov);
+        assert_eq!(metadata.comment.as_deref(), Some("comment"));
+        assert_eq!(metadata.location.as_deref(), Some("+37.3318-122.0312/"));
+        assert_eq!(metadata.creation_time, Some(3_000_000_000));
+        assert_eq!(
+            metadata.qt_metadata,
+            [("com.apple.quicktime.make".to_owned(), "Apple".to_owned())]
+        );
+        assert_eq!(metadata.itunes_items, [too]);
+        assert_eq!(metadata.udta_boxes, [name]);
+        assert_eq!(Mp4Metadata::read(&[]), Mp4Metadata::default());
+    }
+}
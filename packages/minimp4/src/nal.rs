@@ -0,0 +1,350 @@
+//! Annex B byte stream parsing shared by the video write paths.
+
+use std::collections::BTreeMap;
+
+use crate::{boxes::BoxWriter, sps, Minimp4Error, Minimp4Result};
+
+/// Video codecs of the Annex B streams accepted by the muxer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    H265,
+}
+
+/// Role of a NAL unit when assembling access units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NalKind {
+    Vps,
+    Sps,
+    Pps,
+    /// Access unit delimiter, H.264 type 9 and H.265 type 35.
+    Aud,
+    /// A slice. `first` is set for the first slice of a picture, `keyframe` for
+    /// IDR (H.264) or IRAP (H.265) pictures.
+    Slice {
+        first: bool,
+        keyframe: bool,
+    },
+    /// Any other NAL. `prefix` is set for the types that may only precede the
+    /// first slice of an access unit, such as SEI, and therefore start a new one
+    /// when they follow a slice.
+    Other {
+        prefix: bool,
+    },
+}
+
+impl Codec {
+    /// Header type of `nal`, which must not include the start code.
+    pub(crate) fn nal_type(self, nal: &[u8]) -> u8 {
+        match self {
+            Codec::H264 => nal[0] & 0x1f,
+            Codec::H265 => (nal[0] >> 1) & 0x3f,
+        }
+    }
+
+    pub(crate) fn classify(self, nal: &[u8]) -> NalKind {
+        let nal_type = self.nal_type(nal);
+        match (self, nal_type) {
+            (Codec::H264, 7) | (Codec::H265, 33) => NalKind::Sps,
+            (Codec::H264, 8) | (Codec::H265, 34) => NalKind::Pps,
+            (Codec::H265, 32) => NalKind::Vps,
+            (Codec::H264, 9) | (Codec::H265, 35) => NalKind::Aud,
+            // first_mb_in_slice is ue(v) coded, a leading 1 bit means zero
+            (Codec::H264, 1..=5) => NalKind::Slice {
+                first: nal.get(1).is_some_and(|b| b & 0x80 != 0),
+                keyframe: nal_type == 5,
+            },
+            (Codec::H265, 0..=31) => NalKind::Slice {
+                first: nal.get(2).is_some_and(|b| b & 0x80 != 0),
+                keyframe: (16..=21).contains(&nal_type),
+            },
+            (Codec::H264, 6 | 14..=18) | (Codec::H265, 39 | 41..=44 | 48..=55) => NalKind::Other { prefix: true },
+            _ => NalKind::Other { prefix: false },
+        }
+    }
+
+    /// Whether `nal`, a slice, belongs to a picture other pictures may
+    /// reference: a non-zero `nal_ref_idc` for H.264, a type other than the
+    /// sub-layer non-reference ones for H.265.
+    pub(crate) fn is_reference(self, nal: &[u8]) -> bool {
+        match self {
+            Codec::H264 => nal[0] >> 5 != 0,
+            Codec::H265 => {
+                let nal_type = self.nal_type(nal);
+                nal_type > 14 || nal_type % 2 == 1
+            }
+        }
+    }
+
+    /// Quick check that `nal` can be a NAL unit of this codec, to catch H.264
+    /// data written to an H.265 track and vice versa.
+    ///
+    /// The headers differ enough for the NAL units a stream starts with: an
+    /// H.264 SPS, PPS or delimiter read as H.265 has a reserved type or a
+    /// non-zero layer id, while an H.265 VPS, delimiter or SEI read as H.264
+    /// has an invalid type or a `nal_ref_idc` its type does not allow. Other
+    /// NAL units may pass for both.
+    pub(crate) fn accepts(self, nal: &[u8]) -> bool {
+        if nal[0] & 0x80 != 0 {
+            return false;
+        }
+        match self {
+            Codec::H264 => {
+                let ref_idc = nal[0] >> 5;
+                match self.nal_type(nal) {
+                    5 | 7 | 8 => ref_idc != 0,
+                    6 | 9..=12 => ref_idc == 0,
+                    1..=4 | 13 | 15 => true,
+                    _ => false,
+                }
+            }
+            Codec::H265 => {
+                let Some(&second) = nal.get(1) else {
+                    return false;
+                };
+                let layer_id = (nal[0] & 1) << 5 | second >> 3;
+                let temporal_id_plus1 = second & 7;
+                matches!(self.nal_type(nal), 0..=9 | 16..=21 | 32..=40) && layer_id == 0 && temporal_id_plus1 != 0
+            }
+        }
+    }
+}
+
+/// Degradation priorities of the `stdp` box: the higher, the more important
+/// the picture and the later it should be dropped.
+pub(crate) const PRIORITY_NON_REFERENCE: u16 = 0;
+pub(crate) const PRIORITY_REFERENCE: u16 = 1;
+pub(crate) const PRIORITY_KEYFRAME: u16 = 2;
+
+/// What the video write paths learn from the NAL units, for the boxes
+/// written on close.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct StreamInfo {
+    pub temporal_layers: TemporalLayers,
+    /// Degradation priority of every picture, in stream order.
+    pub priorities: Vec<u16>,
+    /// Size of the track every SPS is checked against, when checked.
+    pub declared_size: Option<(u32, u32)>,
+    /// NAL units left out of the samples, by header type.
+    pub skipped: BTreeMap<u8, usize>,
+}
+
+impl StreamInfo {
+    /// Fails with [`Minimp4Error::ResolutionMismatch`] when `nal` is an SPS
+    /// whose cropped and coded picture sizes both differ from the declared
+    /// size. An SPS that cannot be parsed passes.
+    pub fn check_sps(&self, codec: Codec, nal: &[u8]) -> Minimp4Result<()> {
+        let (Some(declared), NalKind::Sps) = (self.declared_size, codec.classify(nal)) else {
+            return Ok(());
+        };
+        match sps::picture_size(codec, nal) {
+            Some((cropped, coded)) if cropped != declared && coded != declared => Err(Minimp4Error::ResolutionMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a NAL unit of header type `nal_type` as left out of the samples.
+    pub fn skip(&mut self, nal_type: u8) {
+        *self.skipped.entry(nal_type).or_default() += 1;
+    }
+
+    pub fn observe(&mut self, codec: Codec, nal: &[u8]) {
+        if codec == Codec::H265 {
+            self.temporal_layers.observe(nal);
+        }
+        let NalKind::Slice { first, keyframe } = codec.classify(nal) else {
+            return;
+        };
+        let priority = if keyframe {
+            PRIORITY_KEYFRAME
+        } else if codec.is_reference(nal) {
+            PRIORITY_REFERENCE
+        } else {
+            PRIORITY_NON_REFERENCE
+        };
+        // a picture is as important as its most important slice
+        match self.priorities.last_mut() {
+            Some(last) if !first => *last = (*last).max(priority),
+            _ => self.priorities.push(priority),
+        }
+    }
+}
+
+/// Builds the `stdp` box of a sample table, holding one of the `PRIORITY_*`
+/// values per sample.
+pub(crate) fn stdp_box(priorities: &[u16]) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin_full(b"stdp", 0, 0);
+    for priority in priorities {
+        w.u16(*priority);
+    }
+    w.end().finish()
+}
+
+/// Temporal sublayers of an H.265 stream, for the `hvcC` fields minimp4
+/// leaves at zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct TemporalLayers {
+    /// Highest `TemporalId` of the NAL unit headers seen.
+    max_temporal_id: u8,
+    /// `sps_temporal_id_nesting_flag` of the last SPS.
+    nested: bool,
+}
+
+impl TemporalLayers {
+    /// Takes the header of `nal`, an H.265 NAL unit, into account.
+    pub fn observe(&mut self, nal: &[u8]) {
+        let Some(&second) = nal.get(1) else {
+            return;
+        };
+        self.max_temporal_id = self.max_temporal_id.max((second & 7).saturating_sub(1));
+        // sps_video_parameter_set_id, sps_max_sub_layers_minus1 and the flag
+        // make up the first byte after the header
+        if Codec::H265.nal_type(nal) == 33 {
+            if let Some(&first) = nal.get(2) {
+                self.nested = first & 1 != 0;
+            }
+        }
+    }
+
+    /// Sets `numTemporalLayers` and `temporalIdNested` in the byte of the
+    /// `hvcC` payload holding them with `lengthSizeMinusOne`, for a
+    /// temporally scalable stream only. Single-layer streams keep the zero of
+    /// minimp4, "unknown", which they are fine with.
+    pub fn patch_hvcc(&self, byte: &mut u8) {
+        if self.max_temporal_id > 0 {
+            let layers = (self.max_temporal_id + 1).min(7);
+            *byte = (*byte & 0xc3) | layers << 3 | (self.nested as u8) << 2;
+        }
+    }
+}
+
+/// Iterates over the NAL units of an Annex B byte stream, without their start
+/// codes and trailing zero bytes. Data before the first start code is skipped.
+pub(crate) struct NalIter<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NalIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        let pos = find_start_code(data, 0).map_or(data.len(), |(_, end)| end);
+        Self { data, pos }
+    }
+}
+
+impl<'a> Iterator for NalIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        while self.pos < self.data.len() {
+            let start = self.pos;
+            let (mut stop, next) = find_start_code(self.data, start).unwrap_or((self.data.len(), self.data.len()));
+            self.pos = next;
+            while stop > start && self.data[stop - 1] == 0 {
+                stop -= 1;
+            }
+            if stop > start {
+                return Some(&self.data[start..stop]);
+            }
+        }
+        None
+    }
+}
+
+/// Finds the next `00 00 01` start code at or after `from`. Returns the offset
+/// of its first zero byte and the offset right after it.
+fn find_start_code(data: &[u8], from: usize) -> Option<(usize, usize)> {
+    data.get(from..)?
+        .windows(3)
+        .position(|w| w == [0, 0, 1])
+        .map(|pos| (from + pos, from + pos + 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_nals() {
+        let stream = [
+            0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 0, 1, 0x65, 0x88, 0, 0,
+        ];
+        let nals: Vec<_> = NalIter::new(&stream).collect();
+        assert_eq!(nals, vec![&[0x67, 1, 2][..], &[0x68, 3], &[0x65, 0x88]]);
+        assert_eq!(NalIter::new(&[0; 16]).count(), 0);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let mut stream = StreamInfo::default();
+        // IDR, then a reference P slice split in two and a non-reference one
+        for nal in [&[0x65, 0x88][..], &[0x41, 0x9a], &[0x41, 0x40], &[0x01, 0x9e]] {
+            stream.observe(Codec::H264, nal);
+        }
+        assert_eq!(
+            stream.priorities,
+            [PRIORITY_KEYFRAME, PRIORITY_REFERENCE, PRIORITY_NON_REFERENCE]
+        );
+        // TRAIL_N and TRAIL_R
+        assert!(!Codec::H265.is_reference(&[0x00, 0x01]));
+        assert!(Codec::H265.is_reference(&[0x02, 0x01]));
+    }
+
+    #[test]
+    fn test_classify() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let kinds: Vec<_> = NalIter::new(h264)
+            .map(|nal| Codec::H264.classify(nal))
+            .take(5)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NalKind::Sps,
+                NalKind::Pps,
+                NalKind::Other { prefix: true },
+                NalKind::Slice {
+                    first: true,
+                    keyframe: true
+                },
+                NalKind::Slice {
+                    first: true,
+                    keyframe: false
+                },
+            ]
+        );
+        assert_eq!(Codec::H264.classify(&[0x09, 0xf0]), NalKind::Aud);
+        assert_eq!(Codec::H265.classify(&[0x46, 0x01, 0x50]), NalKind::Aud);
+    }
+
+    #[test]
+    fn test_accepts() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let h265 = include_bytes!("./fixtures/input.265");
+        assert!(NalIter::new(h264).all(|nal| Codec::H264.accepts(nal)));
+        assert!(NalIter::new(h265).all(|nal| Codec::H265.accepts(nal)));
+        // SPS and PPS leading the H.264 fixture, VPS and SEI the H.265 one
+        assert!(NalIter::new(h264).take(2).all(|nal| !Codec::H265.accepts(nal)));
+        let h265: Vec<_> = NalIter::new(h265).take(4).collect();
+        assert!(!Codec::H264.accepts(h265[0]) && !Codec::H264.accepts(h265[3]));
+        // delimiters
+        assert!(!Codec::H265.accepts(&[0x09, 0xf0]));
+        assert!(!Codec::H264.accepts(&[0x46, 0x01, 0x50]));
+
+        let h265 = include_bytes!("./fixtures/input.265");
+        let kinds: Vec<_> = NalIter::new(h265)
+            .map(|nal| Codec::H265.classify(nal))
+            .take(5)
+            .collect();
+        assert_eq!(kinds[..3], [NalKind::Vps, NalKind::Sps, NalKind::Pps]);
+        assert_eq!(kinds[3], NalKind::Other { prefix: true });
+        assert_eq!(
+            kinds[4],
+            NalKind::Slice {
+                first: true,
+                keyframe: true
+            }
+        );
+    }
+}
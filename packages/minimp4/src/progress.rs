@@ -0,0 +1,85 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{Minimp4Error, Minimp4Result};
+
+/// Progress of a mux, passed to the callback of
+/// [`Mp4Muxer::set_progress_callback`](crate::Mp4Muxer::set_progress_callback).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MuxProgress {
+    /// Bytes produced so far. Boxes rewritten on close, such as the `mdat`
+    /// size or the `moov`, are not included until written.
+    pub bytes_written: u64,
+    /// Video frames handed to the muxer.
+    pub frames_written: u64,
+    /// Sum of the durations of these frames.
+    pub media_time: Duration,
+}
+
+/// Progress counters of the video write path, with the callback reporting
+/// them and the flag aborting the write.
+pub(crate) struct Progress {
+    callback: Option<Box<dyn FnMut(MuxProgress)>>,
+    abort_flag: Option<Arc<AtomicBool>>,
+    interval: u64,
+    frames: u64,
+    /// In ticks of the video track timescale.
+    media_ticks: u64,
+}
+
+impl Progress {
+    pub fn new(interval: u64) -> Self {
+        Self {
+            callback: None,
+            abort_flag: None,
+            interval,
+            frames: 0,
+            media_ticks: 0,
+        }
+    }
+
+    pub fn set_callback(&mut self, callback: Box<dyn FnMut(MuxProgress)>) {
+        self.callback = Some(callback);
+    }
+
+    pub fn set_interval(&mut self, interval: u64) {
+        self.interval = interval;
+    }
+
+    pub fn set_abort_flag(&mut self, abort_flag: Arc<AtomicBool>) {
+        self.abort_flag = Some(abort_flag);
+    }
+
+    /// Fails with [`Minimp4Error::Aborted`] once the abort flag is set. Called
+    /// before the first slice of every frame.
+    pub fn check_abort(&self) -> Minimp4Result<()> {
+        match &self.abort_flag {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(Minimp4Error::Aborted),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a frame of `duration` ticks of `time_scale` and reports every
+    /// `interval` frames. `bytes_written` is read only when reporting.
+    pub fn frame(&mut self, duration: u32, time_scale: u32, bytes_written: impl FnOnce() -> u64) {
+        self.frames += 1;
+        self.media_ticks += duration as u64;
+        let Some(callback) = &mut self.callback else {
+            return;
+        };
+        if self.frames.is_multiple_of(self.interval) {
+            let media_time =
+                Duration::from_nanos((self.media_ticks as u128 * 1_000_000_000 / time_scale as u128) as u64);
+            callback(MuxProgress {
+                bytes_written: bytes_written(),
+                frames_written: self.frames,
+                media_time,
+            });
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Recording of a video and an audio source to MP4 without dealing with the
+//! muxer details.
+
+use std::{
+    io::{Seek, Write},
+    time::Duration,
+};
+
+use crate::{Error, Minimp4Error, Minimp4Result, Mp4Muxer};
+
+/// Duration given to the last frame of a recording made of a single frame,
+/// unless set with [`Recorder::set_final_frame_duration`].
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 30);
+
+/// Tracks of a [`Recorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecorderConfig {
+    pub width: i32,
+    pub height: i32,
+    /// H.265 video rather than H.264.
+    pub is_hevc: bool,
+    /// Rate of the PCM passed to [`Recorder::push_audio`], in Hz.
+    pub sample_rate: u32,
+    /// Channels of that PCM, 1 or 2.
+    pub channel_count: u32,
+    /// Bit rate of the AAC encoding, in bits per second.
+    pub bit_rate: u32,
+}
+
+/// Records encoded video frames and raw audio to an MP4 file: frames and
+/// samples go in, and [`Recorder::finish`] hands back the finished file.
+///
+/// Frame durations come from the presentation timestamps, the audio is
+/// encoded to AAC at the PCM sample rate, and both are written in timestamp
+/// order within the window of [`Recorder::set_reorder_window`], so pushing
+/// them as a capture delivers them interleaves the file. Dropping a recorder
+/// without finishing it leaves the file without index.
+pub struct Recorder<W: Write + Seek> {
+    muxer: Mp4Muxer<W>,
+    /// Timestamp of the first frame, where the recording starts.
+    start_pts: Option<u64>,
+    /// Frame waiting for the next timestamp to know its duration, with its
+    /// timestamp in 90 kHz ticks.
+    pending_frame: Option<(Vec<u8>, u64)>,
+    last_duration: Option<u32>,
+    /// Of `set_final_frame_duration`, in 90 kHz ticks.
+    final_duration: Option<u32>,
+    /// PCM short of a whole AAC frame.
+    pcm: Vec<i16>,
+    /// Audio frames queued so far, for their timestamps.
+    audio_frames: u64,
+    sample_rate: u32,
+    /// Samples not written yet, by timestamp in 90 kHz ticks from the start.
+    queue: Vec<(u64, Sample)>,
+    /// Latest timestamp queued.
+    newest: u64,
+    /// In 90 kHz ticks.
+    reorder_window: u64,
+}
+
+enum Sample {
+    /// An access unit and its duration in 90 kHz ticks.
+    Video(Vec<u8>, u32),
+    /// One AAC frame worth of PCM.
+    Audio(Vec<i16>),
+}
+
+impl<W: Write + Seek> Recorder<W> {
+    /// Sets up the tracks of `config`. Fails with [`Error::Encoder`] when the
+    /// AAC encoder cannot be set up for its sample rate and channel count.
+    pub fn new(writer: W, config: RecorderConfig) -> Result<Self, Error> {
+        let mut muxer = Mp4Muxer::new(writer);
+        muxer.init_audio(config.bit_rate, config.sample_rate, config.channel_count)?;
+        muxer.init_video(config.width, config.height, config.is_hevc, "video")?;
+        Ok(Self {
+            muxer,
+            start_pts: None,
+            pending_frame: None,
+            last_duration: None,
+            final_duration: None,
+            pcm: Vec::new(),
+            audio_frames: 0,
+            sample_rate: config.sample_rate,
+            queue: Vec::new(),
+            newest: 0,
+            reorder_window: 0,
+        })
+    }
+
+    /// Holds samples back for up to `window` of media time, zero by default,
+    /// so that audio and video pushed slightly out of order across the two
+    /// tracks, as live pipelines deliver them, still go to the file by
+    /// timestamp. Without it, a late audio frame lands after video frames
+    /// that follow it. Each track keeps its own order: video timestamps must
+    /// increase and audio is in push order anyway.
+    ///
+    /// The held samples stay in memory: about `window` times the video bit
+    /// rate plus the PCM data rate, 192 kB per second of window for 48 kHz
+    /// stereo, as the audio is held before encoding.
+    pub fn set_reorder_window(&mut self, window: Duration) {
+        self.reorder_window = to_90khz(window);
+    }
+
+    /// Sets how long the last frame lasts, which no following timestamp
+    /// tells. By default it lasts as long as the frame before it, the frame
+    /// duration of a constant frame rate stream, and 1/30 s when the
+    /// recording holds a single frame. Fails with
+    /// [`Minimp4Error::BadArguments`] for a zero duration, leaving the frame
+    /// unseen in some players, or one beyond 32 bits of 90 kHz ticks, about
+    /// 13 hours.
+    pub fn set_final_frame_duration(&mut self, duration: Duration) -> Minimp4Result<()> {
+        let duration = to_90khz(duration)
+            .try_into()
+            .ok()
+            .filter(|&duration| duration != 0)
+            .ok_or(Minimp4Error::BadArguments)?;
+        self.final_duration = Some(duration);
+        Ok(())
+    }
+
+    /// Adds a video frame presented at `pts`: one access unit of Annex B NAL
+    /// units, the first one preceded by the parameter sets. Timestamps must
+    /// increase, fails with [`Minimp4Error::BadArguments`] otherwise; the
+    /// recording starts at the first one whatever its value.
+    pub fn push_video_frame(&mut self, data: &[u8], pts: Duration) -> Result<(), Error> {
+        let pts = to_90khz(pts);
+        let start_pts = *self.start_pts.get_or_insert(pts);
+        if let Some((frame, frame_pts)) = self.pending_frame.take() {
+            let Some(duration) = pts.checked_sub(frame_pts).filter(|&duration| duration != 0) else {
+                self.pending_frame = Some((frame, frame_pts));
+                return Err(Minimp4Error::BadArguments.into());
+            };
+            let duration = duration.try_into().map_err(|_| Minimp4Error::BadArguments)?;
+            self.last_duration = Some(duration);
+            self.queue(frame_pts - start_pts, Sample::Video(frame, duration))?;
+        }
+        self.pending_frame = Some((data.to_vec(), pts));
+        Ok(())
+    }
+
+    /// Adds audio: interleaved signed 16-bit samples of every channel,
+    /// following the audio pushed before, the first sample at the start of
+    /// the recording. Whole AAC frames are encoded and written once out of
+    /// the reorder window, the rest waits for more.
+    pub fn push_audio(&mut self, pcm: &[i16]) -> Result<(), Error> {
+        let frame_length = self.muxer.pcm_frame_length().ok_or(Minimp4Error::BadArguments)?;
+        self.pcm.extend_from_slice(pcm);
+        while self.pcm.len() >= frame_length {
+            let frame = self.pcm.drain(..frame_length).collect();
+            let ts = self.audio_frames * 1024 * 90000 / self.sample_rate as u64;
+            self.audio_frames += 1;
+            self.queue(ts, Sample::Audio(frame))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last frame, lasting as set with
+    /// [`Recorder::set_final_frame_duration`] or as long as the one before
+    /// it, and the
+    /// remaining audio padded with silence to a whole frame, then closes the
+    /// file and returns the writer. Fails with [`Error::Io`] when writing the
+    /// file failed.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if let Some((frame, frame_pts)) = self.pending_frame.take() {
+            let duration = self
+                .final_duration
+                .or(self.last_duration)
+                .unwrap_or(to_90khz(DEFAULT_FRAME_DURATION) as u32);
+            let start_pts = self.start_pts.unwrap_or(frame_pts);
+            self.queue(frame_pts - start_pts, Sample::Video(frame, duration))?;
+        }
+        if !self.pcm.is_empty() {
+            let frame_length = self.muxer.pcm_frame_length().ok_or(Minimp4Error::BadArguments)?;
+            let mut frame = std::mem::take(&mut self.pcm);
+            frame.resize(frame_length, 0);
+            self.push_audio(&frame)?;
+        }
+        self.flush(u64::MAX)?;
+        self.muxer.close();
+        if let Some(error) = self.muxer.take_io_error() {
+            return Err(error.into());
+        }
+        Ok(self.muxer.writer)
+    }
+
+    /// Queues `sample` at `ts`, after the samples queued with the same
+    /// timestamp, and writes those out of the reorder window.
+    fn queue(&mut self, ts: u64, sample: Sample) -> Result<(), Error> {
+        let index = self.queue.partition_point(|(queued, _)| *queued <= ts);
+        self.queue.insert(index, (ts, sample));
+        self.newest = self.newest.max(ts);
+        self.flush(self.newest.saturating_sub(self.reorder_window))
+    }
+
+    /// Writes the queued samples up to timestamp `until`.
+    fn flush(&mut self, until: u64) -> Result<(), Error> {
+        let count = self.queue.partition_point(|(ts, _)| *ts <= until);
+        for (_, sample) in self.queue.drain(..count) {
+            match sample {
+                Sample::Video(frame, duration) => self.muxer.write_access_unit(&frame, duration)?,
+                Sample::Audio(pcm) => self.muxer.write_pcm_frame(&pcm)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_90khz(time: Duration) -> u64 {
+    (time.as_nanos() * 9 / 100_000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        nal::{Codec, NalIter, NalKind},
+        Mp4Demuxer,
+    };
+
+    /// Splits an H.264 Annex B stream into access units, each NAL unit behind
+    /// a 4-byte start code.
+    fn access_units(stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut units: Vec<Vec<u8>> = vec![];
+        let mut current = vec![];
+        let mut has_slice = false;
+        for nal in NalIter::new(stream) {
+            let starts_unit = match Codec::H264.classify(nal) {
+                NalKind::Slice { first, .. } => first,
+                _ => true,
+            };
+            if starts_unit && has_slice {
+                units.push(std::mem::take(&mut current));
+                has_slice = false;
+            }
+            has_slice |= matches!(Codec::H264.classify(nal), NalKind::Slice { .. });
+            current.extend_from_slice(&[0, 0, 0, 1]);
+            current.extend_from_slice(nal);
+        }
+        units.push(current);
+        units
+    }
+
+    #[test]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_recorder() {
+        let units = access_units(include_bytes!("./fixtures/input.264"));
+        assert_eq!(units.len(), 128);
+        let config = RecorderConfig {
+            width: 1280,
+            height: 720,
+            is_hevc: false,
+            sample_rate: 48000,
+            channel_count: 2,
+            bit_rate: 128000,
+        };
+        let mut recorder = Recorder::new(Cursor::new(vec![]), config).unwrap();
+        // 40 ms of a square wave per frame
+        let pcm: Vec<i16> = (0..2 * 1920)
+            .map(|i| if i / 200 % 2 == 0 { 4000 } else { -4000 })
+            .collect();
+        for (index, unit) in units.iter().enumerate() {
+            let pts = Duration::from_millis(1000 + 40 * index as u64);
+            recorder.push_video_frame(unit, pts).unwrap();
+            recorder.push_audio(&pcm).unwrap();
+        }
+        assert!(matches!(
+            recorder.push_video_frame(&units[0], Duration::from_millis(1000)),
+            Err(Error::Muxer(Minimp4Error::BadArguments))
+        ));
+        let summary = recorder.muxer.summary();
+        assert_eq!(summary.tracks[0].duration, 127 * 3600);
+        let output = recorder.finish().unwrap().into_inner();
+
+        let demuxer = Mp4Demuxer::new(Cursor::new(output)).unwrap();
+        assert_eq!(demuxer.track_count(), 2);
+        assert_eq!(demuxer.sample_count(0), Ok(128));
+        // 128 * 1920 samples make 240 frames, less the encoder delay
+        let audio_frames = demuxer.sample_count(1).unwrap();
+        assert!((236..=240).contains(&audio_frames), "{audio_frames}");
+    }
+
+    #[test]
+    fn test_unsupported_config() {
+        let config = RecorderConfig {
+            width: 1280,
+            height: 720,
+            is_hevc: false,
+            sample_rate: 48000,
+            channel_count: 3,
+            bit_rate: 128000,
+        };
+        assert!(matches!(
+            Recorder::new(Cursor::new(vec![]), config),
+            Err(Error::Encoder(_))
+        ));
+        let config = RecorderConfig {
+            sample_rate: 0,
+            channel_count: 2,
+            ..config
+        };
+        assert!(matches!(
+            Recorder::new(Cursor::new(vec![]), config),
+            Err(Error::Encoder(_))
+        ));
+    }
+
+    #[test]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_final_frame_duration() {
+        let units = access_units(include_bytes!("./fixtures/input.264"));
+        let config = RecorderConfig {
+            width: 1280,
+            height: 720,
+            is_hevc: false,
+            sample_rate: 48000,
+            channel_count: 1,
+            bit_rate: 64000,
+        };
+        // video duration in 90 kHz ticks of a recording of `frames` frames
+        let duration = |frames: usize, last: Option<Duration>| {
+            let mut recorder = Recorder::new(Cursor::new(vec![]), config).unwrap();
+            if let Some(last) = last {
+                recorder.set_final_frame_duration(last).unwrap();
+            }
+            for (index, unit) in units[..frames].iter().enumerate() {
+                let pts = Duration::from_millis(40 * index as u64);
+                recorder.push_video_frame(unit, pts).unwrap();
+            }
+            let output = recorder.finish().unwrap().into_inner();
+            let mdhd = crate::boxes::find_path(&output, &[b"moov", b"trak", b"mdia", b"mdhd"]).unwrap();
+            crate::boxes::read_u32(mdhd[3].payload(&output), 16)
+        };
+        assert_eq!(duration(10, None), 10 * 3600);
+        assert_eq!(duration(10, Some(Duration::from_millis(100))), 9 * 3600 + 9000);
+        assert_eq!(duration(1, None), to_90khz(DEFAULT_FRAME_DURATION) as u32);
+        assert_eq!(duration(1, Some(Duration::from_millis(40))), 3600);
+
+        let mut recorder = Recorder::new(Cursor::new(vec![]), config).unwrap();
+        assert_eq!(
+            recorder.set_final_frame_duration(Duration::ZERO),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            recorder.set_final_frame_duration(Duration::from_secs(48000)),
+            Err(Minimp4Error::BadArguments)
+        );
+    }
+
+    #[test]
+    #[ignore = "not complete yet, some platform cannot link fdk-aac"]
+    fn test_reorder_window() {
+        // largest step back in time from a sample to the next in the file
+        let disorder = |window: Duration| {
+            let units = access_units(include_bytes!("./fixtures/input.264"));
+            let config = RecorderConfig {
+                width: 1280,
+                height: 720,
+                is_hevc: false,
+                sample_rate: 48000,
+                channel_count: 1,
+                bit_rate: 64000,
+            };
+            let mut recorder = Recorder::new(Cursor::new(vec![]), config).unwrap();
+            recorder.set_reorder_window(window);
+            // the audio of a frame arrives three frames late
+            let pcm = vec![0i16; 1920];
+            for (index, unit) in units.iter().enumerate() {
+                recorder
+                    .push_video_frame(unit, Duration::from_millis(40 * index as u64))
+                    .unwrap();
+                if index >= 3 {
+                    recorder.push_audio(&pcm).unwrap();
+                }
+            }
+            let mut samples = vec![];
+            for track in recorder.muxer.tracks() {
+                let mut dts = 0;
+                for sample in crate::samples(track) {
+                    samples.push((sample.offset, dts * 90000 / track.info.time_scale as u64));
+                    dts += sample.duration as u64;
+                }
+            }
+            samples.sort();
+            recorder.finish().unwrap();
+            samples.windows(2).map(|w| w[0].1.saturating_sub(w[1].1)).max().unwrap()
+        };
+        // 120 ms late, less the delay of the encoder, an AAC frame or so
+        assert!(disorder(Duration::ZERO) >= 8000);
+        assert!(disorder(Duration::from_millis(200)) <= 1920);
+    }
+}
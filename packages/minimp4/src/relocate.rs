@@ -0,0 +1,226 @@
+//! Rewriting of the absolute file offsets of a finished MP4, for files that
+//! end up at another position than the one they were written for.
+
+use crate::{
+    boxes::{find_child_path, read_u32, read_u64, BoxIter, BoxRange},
+    Minimp4Error, Minimp4Result,
+};
+
+/// Adds `delta` to every chunk offset of the complete MP4 file in `mp4`, the
+/// `stco` and `co64` tables of its tracks, along with the `moof` positions
+/// of an `mfra` index. This keeps the file playable once `delta` bytes are
+/// prepended to it, such as the header of a wrapper embedding the MP4 in
+/// another container, or once that many are taken off with a negative
+/// `delta`.
+///
+/// The file is checked before anything is changed: top-level boxes that do
+/// not make up the whole of `mp4`, a missing `moov` and sample tables without
+/// a well-formed offset table fail with [`Minimp4Error::InvalidFile`], an
+/// offset shifted below zero or, for `stco`, beyond 32 bits with
+/// [`Minimp4Error::BadArguments`]. The tables keep their size: a file whose
+/// offsets outgrow `stco` has to be written again with the header counted in.
+pub fn shift_chunk_offsets(mp4: &mut [u8], delta: i64) -> Minimp4Result<()> {
+    let top: Vec<_> = BoxIter::new(mp4, 0..mp4.len()).collect();
+    if top.last().map_or(0, |b| b.end) != mp4.len() {
+        return Err(Minimp4Error::InvalidFile);
+    }
+    let moov = *top
+        .iter()
+        .find(|b| &b.fourcc == b"moov")
+        .ok_or(Minimp4Error::InvalidFile)?;
+
+    let mut patches = moov_patches(mp4, moov, delta)?;
+    if let Some(mfra) = top.iter().find(|b| &b.fourcc == b"mfra") {
+        for tfra in BoxIter::new(mp4, mfra.children()).filter(|b| &b.fourcc == b"tfra") {
+            patches.extend(tfra_offsets(mp4, &tfra, &mut |offset, max| shift(offset, max, delta))?);
+        }
+    }
+    apply(mp4, patches);
+    Ok(())
+}
+
+/// Adds `delta` to the chunk offsets of `moov`, a whole `moov` box on its
+/// own, as [`shift_chunk_offsets`] does for a complete file. Nothing is
+/// changed on failure.
+pub(crate) fn shift_moov_offsets(moov: &mut [u8], delta: i64) -> Minimp4Result<()> {
+    let root = BoxIter::new(moov, 0..moov.len())
+        .next()
+        .filter(|b| &b.fourcc == b"moov" && b.end == moov.len())
+        .ok_or(Minimp4Error::InvalidFile)?;
+    let patches = moov_patches(moov, root, delta)?;
+    apply(moov, patches);
+    Ok(())
+}
+
+/// `offset` moved by `delta`, unless it ends up below zero or above `max`.
+fn shift(offset: u64, max: u64, delta: i64) -> Minimp4Result<u64> {
+    match offset.checked_add_signed(delta) {
+        Some(offset) if offset <= max => Ok(offset),
+        _ => Err(Minimp4Error::BadArguments),
+    }
+}
+
+/// Every chunk offset of the tracks of `moov` as its position, width and
+/// value moved by `delta`.
+fn moov_patches(mp4: &[u8], moov: BoxRange, delta: i64) -> Minimp4Result<Vec<(usize, usize, u64)>> {
+    let mut patches = vec![];
+    let stbl = [b"mdia", b"minf", b"stbl"];
+    for trak in BoxIter::new(mp4, moov.children()).filter(|b| &b.fourcc == b"trak") {
+        let stbl = find_child_path(mp4, &[moov, trak], &stbl).ok_or(Minimp4Error::InvalidFile)?;
+        let table = BoxIter::new(mp4, stbl[4].children())
+            .find(|b| &b.fourcc == b"stco" || &b.fourcc == b"co64")
+            .ok_or(Minimp4Error::InvalidFile)?;
+        let width = if &table.fourcc == b"co64" { 8 } else { 4 };
+        for pos in entries(mp4, &table, width)? {
+            let offset = match width {
+                8 => read_u64(mp4, pos),
+                _ => read_u32(mp4, pos) as u64,
+            };
+            let max = if width == 8 { i64::MAX as u64 } else { u32::MAX as u64 };
+            patches.push((pos, width, shift(offset, max, delta)?));
+        }
+    }
+    Ok(patches)
+}
+
+fn apply(mp4: &mut [u8], patches: Vec<(usize, usize, u64)>) {
+    for (pos, width, offset) in patches {
+        match width {
+            8 => mp4[pos..pos + 8].copy_from_slice(&offset.to_be_bytes()),
+            _ => mp4[pos..pos + 4].copy_from_slice(&(offset as u32).to_be_bytes()),
+        }
+    }
+}
+
+/// Positions of the entries of `table`, a full box whose entry count follows
+/// its version and flags, each entry `stride` bytes long. Fails unless the
+/// entries fill the box.
+fn entries(mp4: &[u8], table: &BoxRange, stride: usize) -> Minimp4Result<Vec<usize>> {
+    if table.end - table.payload < 8 {
+        return Err(Minimp4Error::InvalidFile);
+    }
+    let count = read_u32(mp4, table.payload + 4) as usize;
+    let start = table.payload + 8;
+    if count.checked_mul(stride) != Some(table.end - start) {
+        return Err(Minimp4Error::InvalidFile);
+    }
+    Ok((0..count).map(|index| start + index * stride).collect())
+}
+
+/// New `moof` offsets of the entries of `tfra`, as [`shift_chunk_offsets`]
+/// patches them.
+fn tfra_offsets(
+    mp4: &[u8],
+    tfra: &BoxRange,
+    shift: &mut impl FnMut(u64, u64) -> Minimp4Result<u64>,
+) -> Minimp4Result<Vec<(usize, usize, u64)>> {
+    if tfra.end - tfra.payload < 16 {
+        return Err(Minimp4Error::InvalidFile);
+    }
+    let wide = mp4[tfra.payload] == 1;
+    let lengths = read_u32(mp4, tfra.payload + 8);
+    // traf, trun and sample numbers of 1 to 4 bytes each
+    let numbers: usize = (0..3)
+        .map(|field| ((lengths >> (4 - 2 * field)) & 3) as usize + 1)
+        .sum();
+    let width = if wide { 8 } else { 4 };
+    let count = read_u32(mp4, tfra.payload + 12) as usize;
+    let stride = 2 * width + numbers;
+    let start = tfra.payload + 16;
+    if count.checked_mul(stride) != Some(tfra.end - start) {
+        return Err(Minimp4Error::InvalidFile);
+    }
+    let max = if wide { i64::MAX as u64 } else { u32::MAX as u64 };
+    (0..count)
+        .map(|index| {
+            let pos = start + index * stride + width;
+            let offset = if wide {
+                read_u64(mp4, pos)
+            } else {
+                read_u32(mp4, pos) as u64
+            };
+            Ok((pos, width, shift(offset, max)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{boxes::find_path, Mp4Mode, Mp4Muxer};
+
+    fn mux(mode: Mp4Mode, smooth_streaming: bool) -> Vec<u8> {
+        let mut muxer = Mp4Muxer::new(Cursor::new(vec![]));
+        muxer.set_mode(mode).unwrap();
+        muxer.set_smooth_streaming(smooth_streaming).unwrap();
+        muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        muxer.close();
+        muxer.writer.into_inner()
+    }
+
+    fn stco(mp4: &[u8]) -> Vec<u32> {
+        let stco = find_path(mp4, &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco"]).unwrap();
+        let table = stco[5].payload(mp4);
+        (0..read_u32(table, 4) as usize)
+            .map(|index| read_u32(table, 8 + 4 * index))
+            .collect()
+    }
+
+    #[test]
+    fn test_shift_chunk_offsets() {
+        let original = mux(Mp4Mode::Default, false);
+        let mut shifted = original.clone();
+        shift_chunk_offsets(&mut shifted, 100).unwrap();
+        let before = stco(&original);
+        let after = stco(&shifted);
+        assert_eq!(after.len(), before.len());
+        let mut wrapped = vec![0xaa; 100];
+        wrapped.extend_from_slice(&shifted);
+        for (old, new) in before.iter().zip(&after) {
+            assert_eq!(*new, old + 100);
+            assert_eq!(wrapped[*new as usize..][..16], original[*old as usize..][..16]);
+        }
+        shift_chunk_offsets(&mut shifted, -100).unwrap();
+        assert_eq!(shifted, original);
+
+        // nothing is changed when an offset would go out of range
+        let mut unchanged = original.clone();
+        let below = -(before[0] as i64) - 1;
+        assert_eq!(
+            shift_chunk_offsets(&mut unchanged, below),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(
+            shift_chunk_offsets(&mut unchanged, u32::MAX as i64),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(unchanged, original);
+        assert_eq!(
+            shift_chunk_offsets(&mut unchanged[..original.len() - 1], 100),
+            Err(Minimp4Error::InvalidFile)
+        );
+        let ftyp = read_u32(&original, 0) as usize;
+        assert_eq!(
+            shift_chunk_offsets(&mut unchanged[..ftyp], 100),
+            Err(Minimp4Error::InvalidFile)
+        );
+    }
+
+    #[test]
+    fn test_shift_mfra() {
+        let original = mux(Mp4Mode::Fragmented, true);
+        let mut shifted = original.clone();
+        shift_chunk_offsets(&mut shifted, 1000).unwrap();
+        let tfra = |mp4: &[u8]| {
+            let mfra = BoxIter::new(mp4, 0..mp4.len()).last().unwrap();
+            let tfra = find_child_path(mp4, &[mfra], &[b"tfra"]).unwrap();
+            read_u64(mp4, tfra[1].payload + 16 + 8)
+        };
+        assert_eq!(tfra(&shifted), tfra(&original) + 1000);
+    }
+}
@@ -0,0 +1,434 @@
+//! Stream-copy remux of an MP4 file: the samples of every track are copied
+//! as they are, and only the container is written anew by the muxer.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    os::raw::c_void,
+    path::Path,
+    slice::from_raw_parts,
+};
+
+use crate::{
+    boxes::BoxIter,
+    c::{
+        e_audio, e_video, MP4E_add_track, MP4E_set_dsi, MP4E_set_pps, MP4E_set_sps, MP4E_set_vps, MP4E_track_t,
+        MP4E_track_t_AVConfig, MP4E_track_t_AVConfig_AudioConfig, MP4E_track_t_AVConfig_VideoConfig,
+        MP4_OBJECT_TYPE_AVC, MP4_OBJECT_TYPE_HEVC,
+    },
+    demux::Sample,
+    FaststartStrategy, Minimp4Error, Minimp4Result, Mp4Demuxer, Mp4Muxer, TableSample,
+};
+
+/// H.265 NAL unit types of the parameter sets an `hvcC` lists.
+const HEVC_VPS: u8 = 32;
+const HEVC_SPS: u8 = 33;
+const HEVC_PPS: u8 = 34;
+
+/// A track of the input, as the muxer sets it up again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TrackConfig {
+    /// H.264 or H.265 video, with the parameter sets of its decoder
+    /// configuration, VPS only for H.265.
+    Video {
+        hevc: bool,
+        width: u16,
+        height: u16,
+        vps: Vec<Vec<u8>>,
+        sps: Vec<Vec<u8>>,
+        pps: Vec<Vec<u8>>,
+    },
+    /// Audio of an `mp4a` sample entry, with the object type and decoder
+    /// specific info of its `esds`.
+    Audio {
+        object_type_indication: u32,
+        channel_count: u16,
+        dsi: Vec<u8>,
+    },
+}
+
+/// Copies the MP4 file `input` to `output`, changing only the container:
+/// every track keeps its samples byte for byte, their durations and sync
+/// samples, its timescale, language and edit list, and the movie keeps its
+/// metadata as [`Mp4Muxer::copy_metadata_from`] carries it over. The output
+/// is written by the muxer in [`crate::Mp4Mode::Default`] with the `moov`
+/// ahead of the media, [`Mp4Muxer::set_faststart`] staging it in a temporary
+/// file, and the samples of all tracks interleaved in decode order, so a
+/// file written for recording comes out ready for progressive download.
+///
+/// Every track is checked before `output` is created. Fails with
+/// [`Minimp4Error::InvalidFile`] if `input` cannot be read as MP4 or has a
+/// track with a timescale of zero, with
+/// [`Minimp4Error::UnsupportedTrack`] if a track is not H.264, H.265 or
+/// `mp4a` audio, has samples presented out of decode order, which the muxer
+/// writes no `ctts` for, or has empty samples, and with
+/// [`Minimp4Error::BadArguments`] if both paths name the same file. Once
+/// `output` is created, a failure, including an output that does not read
+/// back with the samples of the input, removes it again.
+pub fn remux(input: &Path, output: &Path) -> Minimp4Result<()> {
+    if let (Ok(input), Ok(output)) = (fs::canonicalize(input), fs::canonicalize(output)) {
+        if input == output {
+            return Err(Minimp4Error::BadArguments);
+        }
+    }
+    let file = File::open(input).map_err(|_| Minimp4Error::InvalidFile)?;
+    let mut demuxer = Mp4Demuxer::new(BufReader::new(file))?;
+    let configs = (0..demuxer.track_count())
+        .map(|track| track_config(&demuxer, track))
+        .collect::<Minimp4Result<Vec<_>>>()?;
+    let samples = decode_order(&demuxer)?;
+
+    let file = File::create(output).map_err(|_| Minimp4Error::FileWriteError)?;
+    let result = write(&mut demuxer, &configs, &samples, BufWriter::new(file)).and_then(|()| check(&demuxer, output));
+    if result.is_err() {
+        let _ = fs::remove_file(output);
+    }
+    result
+}
+
+/// How the muxer writes track `track` of `demuxer`, or
+/// [`Minimp4Error::UnsupportedTrack`] if it cannot.
+fn track_config<R: Read + Seek>(demuxer: &Mp4Demuxer<R>, track: u32) -> Minimp4Result<TrackConfig> {
+    let info = demuxer.track(track)?;
+    let entry = demuxer.sample_entry(track).ok_or(Minimp4Error::UnsupportedTrack)?;
+    let sample_entry = BoxIter::new(entry, 0..entry.len())
+        .next()
+        .ok_or(Minimp4Error::UnsupportedTrack)?;
+    if demuxer.has_composition_offsets(track) {
+        return Err(Minimp4Error::UnsupportedTrack);
+    }
+    let payload = &entry[sample_entry.payload..sample_entry.end];
+    let u16_at = |pos: usize| {
+        payload
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let config = |fourcc: &[u8; 4]| {
+        BoxIter::new(entry, sample_entry.children())
+            .find(|b| &b.fourcc == fourcc)
+            .map(|b| &entry[b.payload..b.end])
+    };
+    let video = match &sample_entry.fourcc {
+        b"avc1" | b"avc3" => config(b"avcC").and_then(avcc_parameter_sets).map(|sets| (false, sets)),
+        b"hvc1" | b"hev1" => config(b"hvcC").and_then(hvcc_parameter_sets).map(|sets| (true, sets)),
+        b"mp4a" => {
+            let channel_count = u16_at(16).ok_or(Minimp4Error::UnsupportedTrack)?;
+            let dsi = match info.dsi.is_null() {
+                true => vec![],
+                false => unsafe { from_raw_parts(info.dsi, info.dsi_bytes as usize) }.to_vec(),
+            };
+            return Ok(TrackConfig::Audio {
+                object_type_indication: info.object_type_indication,
+                channel_count,
+                dsi,
+            });
+        }
+        _ => None,
+    };
+    let ((hevc, (vps, sps, pps)), Some(width), Some(height)) =
+        (video.ok_or(Minimp4Error::UnsupportedTrack)?, u16_at(24), u16_at(26))
+    else {
+        return Err(Minimp4Error::UnsupportedTrack);
+    };
+    Ok(TrackConfig::Video {
+        hevc,
+        width,
+        height,
+        vps,
+        sps,
+        pps,
+    })
+}
+
+type ParameterSets = (Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+/// SPS and PPS of the `avcC` payload `avcc`, or `None` unless it can be read
+/// and gives NAL units 4-byte lengths, the samples minimp4 writes.
+fn avcc_parameter_sets(avcc: &[u8]) -> Option<ParameterSets> {
+    if avcc.get(4)? & 3 != 3 {
+        return None;
+    }
+    let mut pos = 5;
+    let sps_count = (avcc.get(pos)? & 0x1f) as usize;
+    pos += 1;
+    let sps = nal_units(avcc, &mut pos, sps_count)?;
+    let pps_count = *avcc.get(pos)? as usize;
+    pos += 1;
+    let pps = nal_units(avcc, &mut pos, pps_count)?;
+    Some((vec![], sps, pps))
+}
+
+/// VPS, SPS and PPS of the `hvcC` payload `hvcc`, or `None` unless it can
+/// be read and gives NAL units 4-byte lengths. SEI arrays are left out.
+fn hvcc_parameter_sets(hvcc: &[u8]) -> Option<ParameterSets> {
+    if hvcc.get(21)? & 3 != 3 {
+        return None;
+    }
+    let (mut vps, mut sps, mut pps) = (vec![], vec![], vec![]);
+    let mut pos = 23;
+    for _ in 0..*hvcc.get(22)? {
+        let nal_type = hvcc.get(pos)? & 0x3f;
+        let count = u16::from_be_bytes(hvcc.get(pos + 1..pos + 3)?.try_into().unwrap()) as usize;
+        pos += 3;
+        let units = nal_units(hvcc, &mut pos, count)?;
+        match nal_type {
+            HEVC_VPS => vps.extend(units),
+            HEVC_SPS => sps.extend(units),
+            HEVC_PPS => pps.extend(units),
+            _ => {}
+        }
+    }
+    Some((vps, sps, pps))
+}
+
+/// `count` NAL units of a decoder configuration, each behind a 16-bit
+/// length, read from `pos` on.
+fn nal_units(data: &[u8], pos: &mut usize, count: usize) -> Option<Vec<Vec<u8>>> {
+    (0..count)
+        .map(|_| {
+            let length = u16::from_be_bytes(data.get(*pos..*pos + 2)?.try_into().unwrap()) as usize;
+            let unit = data.get(*pos + 2..*pos + 2 + length)?;
+            *pos += 2 + length;
+            Some(unit.to_vec())
+        })
+        .collect()
+}
+
+/// The samples of all tracks of `demuxer`, ordered by decode time, those of
+/// a track in their order. Fails with [`Minimp4Error::UnsupportedTrack`] for
+/// a sample without data or duration, which the muxer does not store.
+fn decode_order<R: Read + Seek>(demuxer: &Mp4Demuxer<R>) -> Minimp4Result<Vec<(u32, Sample)>> {
+    let mut samples = vec![];
+    for track in 0..demuxer.track_count() {
+        for index in 0..demuxer.sample_count(track)? {
+            let sample = demuxer.sample(track, index)?;
+            if sample.size == 0 || sample.duration == 0 {
+                return Err(Minimp4Error::UnsupportedTrack);
+            }
+            samples.push((track, sample));
+        }
+    }
+    let timescales = (0..demuxer.track_count())
+        .map(|track| demuxer.track_timescale(track).map(|timescale| timescale.max(1) as u128))
+        .collect::<Minimp4Result<Vec<_>>>()?;
+    samples.sort_by_key(|(track, sample)| sample.timestamp as u128 * 1_000_000_000 / timescales[*track as usize]);
+    Ok(samples)
+}
+
+/// Muxes `samples` of `demuxer` to `writer`, with a track of `configs` for
+/// every track of `demuxer`.
+fn write<R: Read + Seek, W: Write + Seek>(
+    demuxer: &mut Mp4Demuxer<R>,
+    configs: &[TrackConfig],
+    samples: &[(u32, Sample)],
+    writer: W,
+) -> Minimp4Result<()> {
+    let mut muxer = Mp4Muxer::new(writer);
+    muxer.set_faststart(Some(FaststartStrategy::TempFile))?;
+    let result = write_tracks(&mut muxer, demuxer, configs, samples);
+    muxer.close();
+    match muxer.take_io_error() {
+        Some(_) => result.and(Err(Minimp4Error::FileWriteError)),
+        None => result,
+    }
+}
+
+fn write_tracks<R: Read + Seek, W: Write + Seek>(
+    muxer: &mut Mp4Muxer<W>,
+    demuxer: &mut Mp4Demuxer<R>,
+    configs: &[TrackConfig],
+    samples: &[(u32, Sample)],
+) -> Minimp4Result<()> {
+    muxer.open();
+    for (track, config) in configs.iter().enumerate() {
+        add_track(muxer, demuxer, track as u32, config)?;
+    }
+    let mut tables = vec![vec![]; configs.len()];
+    for (track, sample) in samples {
+        let data = demuxer.read_sample(sample)?;
+        tables[*track as usize].push(TableSample {
+            offset: muxer.write_media_data(&data)?,
+            size: sample.size,
+            duration: sample.duration,
+            keyframe: sample.sync,
+        });
+    }
+    for (track, table) in tables.iter().enumerate() {
+        let track = track as u32;
+        muxer.write_sample_table(track, table)?;
+        muxer.copy_edit_list_from(track, demuxer, track)?;
+    }
+    muxer.copy_metadata_from(demuxer)
+}
+
+/// Adds a track of `config` to `muxer` for track `track` of `demuxer`, with
+/// its timescale and language. Fails with [`Minimp4Error::InvalidFile`] for
+/// a timescale of zero.
+fn add_track<R: Read + Seek, W: Write + Seek>(
+    muxer: &mut Mp4Muxer<W>,
+    demuxer: &Mp4Demuxer<R>,
+    track: u32,
+    config: &TrackConfig,
+) -> Minimp4Result<()> {
+    let info = demuxer.track(track)?;
+    // the muxer divides by the timescale when it closes the file
+    if info.timescale == 0 {
+        return Err(Minimp4Error::InvalidFile);
+    }
+    let (object_type_indication, track_media_kind, u) = match config {
+        TrackConfig::Video {
+            hevc, width, height, ..
+        } => (
+            if *hevc {
+                MP4_OBJECT_TYPE_HEVC
+            } else {
+                MP4_OBJECT_TYPE_AVC
+            },
+            e_video,
+            MP4E_track_t_AVConfig {
+                v: MP4E_track_t_AVConfig_VideoConfig {
+                    width: *width as i32,
+                    height: *height as i32,
+                },
+            },
+        ),
+        TrackConfig::Audio {
+            object_type_indication,
+            channel_count,
+            ..
+        } => (
+            *object_type_indication,
+            e_audio,
+            MP4E_track_t_AVConfig {
+                a: MP4E_track_t_AVConfig_AudioConfig {
+                    channelcount: *channel_count as u32,
+                },
+            },
+        ),
+    };
+    let mut language = info.language;
+    language[3] = 0;
+    let track = MP4E_track_t {
+        object_type_indication,
+        language,
+        track_media_kind,
+        time_scale: info.timescale,
+        default_duration: 0,
+        u,
+    };
+    let mux = muxer.raw.muxer;
+    let track = unsafe { MP4E_add_track(mux, &track) };
+    Minimp4Error::check(track.min(0))?;
+    let set = |set: unsafe extern "C" fn(_, _, _, _) -> _, data: &Vec<u8>| {
+        Minimp4Error::check(unsafe { set(mux, track, data.as_ptr() as *const c_void, data.len() as i32) })
+    };
+    match config {
+        TrackConfig::Video { vps, sps, pps, .. } => {
+            vps.iter().try_for_each(|vps| set(MP4E_set_vps, vps))?;
+            sps.iter().try_for_each(|sps| set(MP4E_set_sps, sps))?;
+            pps.iter().try_for_each(|pps| set(MP4E_set_pps, pps))
+        }
+        TrackConfig::Audio { dsi, .. } if !dsi.is_empty() => set(MP4E_set_dsi, dsi),
+        TrackConfig::Audio { .. } => Ok(()),
+    }
+}
+
+/// Checks that `output` reads back with the tracks and samples of `demuxer`,
+/// failing with [`Minimp4Error::FileWriteError`] otherwise.
+fn check<R: Read + Seek>(demuxer: &Mp4Demuxer<R>, output: &Path) -> Minimp4Result<()> {
+    let file = File::open(output).map_err(|_| Minimp4Error::FileWriteError)?;
+    let written = Mp4Demuxer::new(BufReader::new(file)).map_err(|_| Minimp4Error::FileWriteError)?;
+    match sample_counts(&written) {
+        Ok(counts) if counts == sample_counts(demuxer)? => Ok(()),
+        _ => Err(Minimp4Error::FileWriteError),
+    }
+}
+
+fn sample_counts<R: Read + Seek>(demuxer: &Mp4Demuxer<R>) -> Minimp4Result<Vec<u32>> {
+    (0..demuxer.track_count())
+        .map(|track| demuxer.sample_count(track))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, path::PathBuf};
+
+    use super::*;
+
+    /// A path of the temporary directory for the test `name`.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("minimp4-remux-{}-{name}.mp4", std::process::id()))
+    }
+
+    #[test]
+    fn test_remux() {
+        let source = &include_bytes!("./fixtures/h264_output.mp4")[..];
+        let (input, output) = (temp_path("input"), temp_path("output"));
+        fs::write(&input, source).unwrap();
+        remux(&input, &output).unwrap();
+        let remuxed = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(remux(&input, &input), Err(Minimp4Error::BadArguments));
+        fs::remove_file(&input).unwrap();
+
+        let top: Vec<_> = BoxIter::new(&remuxed, 0..remuxed.len())
+            .map(|b| b.fourcc)
+            .filter(|fourcc| fourcc != b"free")
+            .collect();
+        assert_eq!(top, [*b"ftyp", *b"moov", *b"mdat"]);
+        let mut source = Mp4Demuxer::new(Cursor::new(source)).unwrap();
+        let mut remuxed = Mp4Demuxer::new(Cursor::new(remuxed)).unwrap();
+        assert_eq!(remuxed.track_count(), 1);
+        assert_eq!(remuxed.sample_count(0), Ok(128));
+        assert_eq!(remuxed.track_timescale(0), Ok(90000));
+        assert_eq!(remuxed.metadata(), source.metadata());
+        for index in 0..128 {
+            let (a, b) = (source.sample(0, index).unwrap(), remuxed.sample(0, index).unwrap());
+            assert_eq!((a.timestamp, a.duration, a.sync), (b.timestamp, b.duration, b.sync));
+            assert_eq!(source.read_sample(&a), remuxed.read_sample(&b));
+        }
+        let entry = remuxed.sample_entry(0).unwrap();
+        assert_eq!(source.sample_entry(0), Some(entry));
+    }
+
+    #[test]
+    fn test_unsupported_track() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut mp4muxer = Mp4Muxer::new(&mut buffer);
+        mp4muxer.init_video(1280, 720, false, "h264 stream").unwrap();
+        mp4muxer
+            .write_video_with_fps(include_bytes!("./fixtures/input.264"), 25)
+            .unwrap();
+        mp4muxer.init_webvtt_track().unwrap();
+        let second = std::time::Duration::from_secs(1);
+        mp4muxer.write_vtt_cue("hello", second, second).unwrap();
+        mp4muxer.close();
+        let (input, output) = (temp_path("webvtt"), temp_path("webvtt-output"));
+        fs::write(&input, buffer.into_inner()).unwrap();
+        assert_eq!(remux(&input, &output), Err(Minimp4Error::UnsupportedTrack));
+        assert!(!output.exists());
+        fs::remove_file(&input).unwrap();
+        assert_eq!(remux(&input, &output), Err(Minimp4Error::InvalidFile));
+
+        let mut data = include_bytes!("./fixtures/h264_output.mp4").to_vec();
+        let mdhd = crate::boxes::find_path(&data, &[b"moov", b"trak", b"mdia", b"mdhd"]).unwrap();
+        data[mdhd[3].payload + 12..mdhd[3].payload + 16].fill(0);
+        fs::write(&input, data).unwrap();
+        assert_eq!(remux(&input, &output), Err(Minimp4Error::InvalidFile));
+        assert!(!output.exists());
+        fs::remove_file(&input).unwrap();
+    }
+
+    #[test]
+    fn test_avcc_parameter_sets() {
+        let avcc = [1, 0x64, 0, 0x28, 0xff, 0xe1, 0, 2, 0x67, 0x64, 1, 0, 1, 0x68];
+        let sets = (vec![], vec![vec![0x67, 0x64]], vec![vec![0x68]]);
+        assert_eq!(avcc_parameter_sets(&avcc), Some(sets));
+        // 2-byte NAL unit lengths, a truncated PPS
+        let mut short = avcc;
+        short[4] = 0xfd;
+        assert_eq!(avcc_parameter_sets(&short), None);
+        assert_eq!(avcc_parameter_sets(&avcc[..13]), None);
+    }
+}
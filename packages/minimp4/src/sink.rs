@@ -0,0 +1,159 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// A writer that hands the output to a callback in fixed-size chunks, e.g. to
+/// feed the parts of a multipart upload without spooling to a temporary file.
+///
+/// Chunks are cut purely by byte count, regardless of box boundaries. Every
+/// chunk passed to the callback is exactly `chunk_size` bytes long, except for
+/// the last one emitted by [`ChunkedWriter::finish`] which carries whatever
+/// remains.
+///
+/// Bytes already handed to the callback cannot be rewritten, so seeking is only
+/// supported within the data that has not been emitted yet. Use it with
+/// [`Mp4Mode::Sequential`](crate::Mp4Mode::Sequential) or
+/// [`Mp4Mode::Fragmented`](crate::Mp4Mode::Fragmented), which never seek back.
+///
+/// A write whose chunk the callback fails on takes none of its bytes, so that
+/// writing them again emits that chunk once and none of the earlier ones
+/// again.
+pub struct ChunkedWriter<F> {
+    callback: F,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    /// Bytes already passed to the callback.
+    emitted: u64,
+    /// Current position, relative to the start of the stream.
+    position: u64,
+}
+
+impl<F: FnMut(&[u8]) -> io::Result<()>> ChunkedWriter<F> {
+    pub fn new(chunk_size: usize, callback: F) -> Self {
+        assert!(chunk_size > 0, "chunk size must not be zero");
+        Self {
+            callback,
+            chunk_size,
+            buffer: Vec::with_capacity(chunk_size),
+            emitted: 0,
+            position: 0,
+        }
+    }
+
+    /// Emits the remaining bytes as the last, possibly shorter, chunk.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.emit_full_chunks()?;
+        if !self.buffer.is_empty() {
+            (self.callback)(&self.buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Emits the complete chunks of the buffer, each dropped from it as soon
+    /// as the callback took it.
+    fn emit_full_chunks(&mut self) -> io::Result<()> {
+        while self.buffer.len() >= self.chunk_size {
+            (self.callback)(&self.buffer[..self.chunk_size])?;
+            self.buffer.drain(..self.chunk_size);
+            self.emitted += self.chunk_size as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8]) -> io::Result<()>> Write for ChunkedWriter<F> {
+    /// Takes `buf` up to the end of the current chunk, the rest is left to
+    /// the next call. Chunks of the zeros up to a position past the end are
+    /// emitted before any of `buf` is taken.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let offset = (self.position - self.emitted) as usize;
+        if offset > self.buffer.len() {
+            self.buffer.resize(offset, 0);
+        }
+        self.emit_full_chunks()?;
+        let offset = (self.position - self.emitted) as usize;
+        let taken = buf.len().min(self.chunk_size - offset);
+        let old_len = self.buffer.len();
+        let overlap = old_len.saturating_sub(offset).min(taken);
+        let overwritten = self.buffer[offset..offset + overlap].to_vec();
+        self.buffer[offset..offset + overlap].copy_from_slice(&buf[..overlap]);
+        self.buffer.extend_from_slice(&buf[overlap..taken]);
+        if self.buffer.len() == self.chunk_size {
+            if let Err(error) = (self.callback)(&self.buffer) {
+                self.buffer.truncate(old_len);
+                self.buffer[offset..offset + overlap].copy_from_slice(&overwritten);
+                return Err(error);
+            }
+            self.buffer.clear();
+            self.emitted += self.chunk_size as u64;
+        }
+        self.position += taken as u64;
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[u8]) -> io::Result<()>> Seek for ChunkedWriter<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let end = self.emitted + self.buffer.len() as u64;
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => end.checked_add_signed(delta),
+        };
+        match target {
+            Some(target) if target >= self.emitted => {
+                self.position = target;
+                Ok(target)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot seek into data already passed to the chunk callback",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let mut chunks = Vec::new();
+        let mut writer = ChunkedWriter::new(4, |chunk: &[u8]| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        });
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.write_all(&[4, 5, 6, 7, 8, 9, 10]).unwrap();
+        // patching the tail that has not been emitted yet is fine
+        writer.seek(SeekFrom::Start(9)).unwrap();
+        writer.write_all(&[0]).unwrap();
+        assert!(writer.seek(SeekFrom::Start(3)).is_err());
+        writer.finish().unwrap();
+        assert_eq!(chunks, vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8], vec![9, 0]]);
+    }
+
+    #[test]
+    fn test_failed_chunk() {
+        let mut chunks = Vec::new();
+        let mut fail = true;
+        let mut writer = ChunkedWriter::new(4, |chunk: &[u8]| {
+            if chunk[0] == 5 && std::mem::take(&mut fail) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            chunks.push(chunk.to_vec());
+            Ok(())
+        });
+        writer.write_all(&[1, 2, 3]).unwrap();
+        // the second chunk fails, none of its bytes are taken
+        assert_eq!(writer.write(&[4, 5, 6, 7, 8, 9]).unwrap(), 1);
+        assert!(writer.write(&[5, 6, 7, 8, 9]).is_err());
+        assert_eq!(writer.stream_position().unwrap(), 4);
+        writer.write_all(&[5, 6, 7, 8, 9]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(chunks, vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8], vec![9]]);
+    }
+}
@@ -0,0 +1,258 @@
+//! Picture size of H.264 and H.265 sequence parameter sets, ITU-T H.264
+//! 7.3.2.1 and H.265 7.3.2.2, read up to the cropping window and no further.
+
+use crate::nal::Codec;
+
+/// Exp-Golomb and fixed-width reader of the RBSP of a NAL unit, the
+/// emulation prevention bytes taken out on the way.
+struct BitReader<'a> {
+    data: &'a [u8],
+    byte: usize,
+    bit: u32,
+    zeros: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            byte: 0,
+            bit: 0,
+            zeros: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        if self.bit == 0 {
+            // 00 00 03 stands for 00 00
+            if self.zeros >= 2 && self.data.get(self.byte) == Some(&3) {
+                self.byte += 1;
+                self.zeros = 0;
+            }
+            let byte = *self.data.get(self.byte)?;
+            self.zeros = if byte == 0 { self.zeros + 1 } else { 0 };
+        }
+        let value = (self.data[self.byte] >> (7 - self.bit)) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.byte += 1;
+        }
+        Some(value as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        (0..count).try_fold(0, |value, _| Some(value << 1 | self.bit()?))
+    }
+
+    fn skip(&mut self, count: u32) -> Option<()> {
+        (0..count).try_for_each(|_| self.bit().map(drop))
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut leading = 0;
+        while self.bit()? == 0 {
+            leading += 1;
+            if leading > 31 {
+                return None;
+            }
+        }
+        Some(((1u64 << leading) - 1 + self.bits(leading)? as u64) as u32)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let value = self.ue()? as i64;
+        Some(if value % 2 == 1 { (value + 1) / 2 } else { -value / 2 } as i32)
+    }
+}
+
+/// Width and height of the pictures of `sps`, a NAL unit without start code,
+/// once cropped, along with the coded size before cropping. `None` when the
+/// SPS is truncated or malformed.
+pub(crate) fn picture_size(codec: Codec, sps: &[u8]) -> Option<((u32, u32), (u32, u32))> {
+    match codec {
+        Codec::H264 => h264_picture_size(sps),
+        Codec::H265 => h265_picture_size(sps),
+    }
+}
+
+/// The 12 bytes of the general profile, tier and level of `sps`, an H.265
+/// SPS without start code, from `general_profile_space` to
+/// `general_level_idc`, as `hvcC` stores them.
+pub(crate) fn h265_general_profile(sps: &[u8]) -> Option<[u8; 12]> {
+    let mut r = BitReader::new(sps.get(2..)?);
+    r.skip(8)?; // ids, sub-layer count and nesting flag
+    let mut profile = [0; 12];
+    for byte in &mut profile {
+        *byte = r.bits(8)? as u8;
+    }
+    Some(profile)
+}
+
+/// Horizontal and vertical chroma subsampling of `chroma_format_idc`.
+fn subsampling(chroma_format_idc: u32) -> (u32, u32) {
+    match chroma_format_idc {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    }
+}
+
+/// Applies the cropping `offsets`, left, right, top and bottom in units of
+/// `unit`, to `coded`.
+fn crop(coded: (u32, u32), unit: (u32, u32), offsets: [u32; 4]) -> Option<((u32, u32), (u32, u32))> {
+    let [left, right, top, bottom] = offsets.map(u64::from);
+    let width = (coded.0 as u64).checked_sub(unit.0 as u64 * (left + right))?;
+    let height = (coded.1 as u64).checked_sub(unit.1 as u64 * (top + bottom))?;
+    Some(((width as u32, height as u32), coded))
+}
+
+fn h264_picture_size(sps: &[u8]) -> Option<((u32, u32), (u32, u32))> {
+    let mut r = BitReader::new(sps.get(1..)?);
+    let profile_idc = r.bits(8)?;
+    r.skip(16)?; // constraint flags and level_idc
+    r.ue()?; // seq_parameter_set_id
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.bit()? == 1;
+        }
+        r.ue()?; // bit_depth_luma_minus8
+        r.ue()?; // bit_depth_chroma_minus8
+        r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for index in 0..lists {
+                if r.bit()? == 1 {
+                    let size = if index < 6 { 16 } else { 64 };
+                    let (mut last, mut next) = (8, 8);
+                    for _ in 0..size {
+                        if next != 0 {
+                            next = (last + r.se()? + 256) % 256;
+                        }
+                        if next != 0 {
+                            last = next;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.skip(1)?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = r.ue()? as u64 + 1;
+    let height_in_map_units = r.ue()? as u64 + 1;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.skip(1)?; // direct_8x8_inference_flag
+    let field_factor = 2 - frame_mbs_only;
+    let coded = (
+        u32::try_from(width_in_mbs * 16).ok()?,
+        u32::try_from(field_factor as u64 * height_in_map_units * 16).ok()?,
+    );
+    let mut offsets = [0; 4];
+    if r.bit()? == 1 {
+        for offset in &mut offsets {
+            *offset = r.ue()?;
+        }
+    }
+    let unit = match (separate_colour_plane, chroma_format_idc) {
+        (true, _) | (_, 0) => (1, field_factor),
+        (false, format) => {
+            let (x, y) = subsampling(format);
+            (x, y * field_factor)
+        }
+    };
+    crop(coded, unit, offsets)
+}
+
+fn h265_picture_size(sps: &[u8]) -> Option<((u32, u32), (u32, u32))> {
+    let mut r = BitReader::new(sps.get(2..)?);
+    r.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.bits(3)?;
+    r.skip(1)?; // sps_temporal_id_nesting_flag
+    r.skip(88 + 8)?; // general profile and level of profile_tier_level
+    let mut sub_layers = vec![];
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.bit()?, r.bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        r.skip(88 * profile_present + 8 * level_present)?;
+    }
+    r.ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.ue()?;
+    let mut separate_colour_plane = false;
+    if chroma_format_idc == 3 {
+        separate_colour_plane = r.bit()? == 1;
+    }
+    let coded = (r.ue()?, r.ue()?);
+    let mut offsets = [0; 4];
+    if r.bit()? == 1 {
+        for offset in &mut offsets {
+            *offset = r.ue()?;
+        }
+    }
+    let unit = match separate_colour_plane {
+        true => (1, 1),
+        false => subsampling(chroma_format_idc),
+    };
+    crop(coded, unit, offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nal::{NalIter, NalKind};
+
+    fn first_sps(codec: Codec, stream: &[u8]) -> &[u8] {
+        NalIter::new(stream)
+            .find(|nal| codec.classify(nal) == NalKind::Sps)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_picture_size() {
+        let sps = first_sps(Codec::H264, include_bytes!("./fixtures/input.264"));
+        assert_eq!(picture_size(Codec::H264, sps), Some(((1920, 1080), (1920, 1088))));
+        assert_eq!(picture_size(Codec::H264, &sps[..6]), None);
+        let sps = first_sps(Codec::H265, include_bytes!("./fixtures/input.265"));
+        assert_eq!(picture_size(Codec::H265, sps), Some(((1920, 1080), (1920, 1080))));
+
+        // baseline 1280x720 in 80x45 macroblocks, without cropping
+        let sps = [0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe4];
+        assert_eq!(picture_size(Codec::H264, &sps), Some(((1280, 720), (1280, 720))));
+    }
+
+    #[test]
+    fn test_emulation_prevention() {
+        let mut r = BitReader::new(&[0x00, 0x00, 0x03, 0x01, 0xff]);
+        assert_eq!(r.bits(16), Some(0));
+        assert_eq!(r.bits(8), Some(1));
+        assert_eq!(r.ue(), Some(0));
+    }
+}
@@ -0,0 +1,142 @@
+//! Conversion of frame durations to ticks of the track timescale.
+//!
+//! A frame rate or a 90 kHz duration is rarely a whole number of ticks, 30
+//! fps at a timescale of 1000 being 33.3 ticks a frame. Rounding every frame
+//! on its own loses or gains the remainder each time, a frame every few
+//! thousand. With error diffusion the end of every frame is rounded instead,
+//! so that the durations add up to the length of the stream rounded once.
+
+/// How a duration that is not a whole number of ticks is rounded, selected
+/// with [`crate::Mp4Muxer::set_duration_rounding`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Down, to the tick before.
+    #[default]
+    Floor,
+    /// Up, to the tick after.
+    Ceil,
+    /// To the nearest tick, halves up.
+    Nearest,
+    /// To the nearest tick, halves to the even one, so that halves are not
+    /// always rounded the same way.
+    HalfEven,
+}
+
+impl Rounding {
+    /// `numerator / denominator` rounded.
+    pub(crate) fn divide(self, numerator: u128, denominator: u128) -> u128 {
+        let (quotient, remainder) = (numerator / denominator, numerator % denominator);
+        let up = match self {
+            Rounding::Floor => false,
+            Rounding::Ceil => remainder != 0,
+            Rounding::Nearest => 2 * remainder >= denominator,
+            Rounding::HalfEven => 2 * remainder > denominator || 2 * remainder == denominator && quotient % 2 == 1,
+        };
+        quotient + up as u128
+    }
+}
+
+/// Converts the durations of the frames of a track to ticks, the remainders
+/// carried over from frame to frame with error diffusion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TickClock {
+    rounding: Rounding,
+    diffusion: bool,
+    /// Exact length of the frames so far in ticks, as a fraction.
+    elapsed: (u128, u128),
+    /// The length rounded, the sum of the durations returned.
+    ticks: u128,
+}
+
+impl TickClock {
+    pub fn new(rounding: Rounding, diffusion: bool) -> Self {
+        Self {
+            rounding,
+            diffusion,
+            elapsed: (0, 1),
+            ticks: 0,
+        }
+    }
+
+    /// Duration in ticks of a frame lasting `duration`, `numerator /
+    /// denominator` ticks, on its own.
+    pub fn duration(&self, (numerator, denominator): (u64, u64)) -> u32 {
+        saturate(self.rounding.divide(numerator as u128, denominator as u128))
+    }
+
+    /// Duration in ticks of the next frame, lasting `duration`, `numerator
+    /// / denominator` ticks.
+    pub fn next(&mut self, duration: (u64, u64)) -> u32 {
+        if !self.diffusion {
+            return self.duration(duration);
+        }
+        let (numerator, denominator) = (duration.0 as u128, duration.1 as u128);
+        let (elapsed, scale) = self.elapsed;
+        let common = scale / gcd(scale, denominator) * denominator;
+        let total = elapsed * (common / scale) + numerator * (common / denominator);
+        let divisor = gcd(total, common);
+        self.elapsed = (total / divisor, common / divisor);
+        let end = self.rounding.divide(self.elapsed.0, self.elapsed.1);
+        let ticks = end.saturating_sub(self.ticks);
+        self.ticks = end.max(self.ticks);
+        saturate(ticks)
+    }
+}
+
+impl Default for TickClock {
+    fn default() -> Self {
+        Self::new(Rounding::Floor, false)
+    }
+}
+
+fn saturate(ticks: u128) -> u32 {
+    ticks.try_into().unwrap_or(u32::MAX)
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding() {
+        let all = [Rounding::Floor, Rounding::Ceil, Rounding::Nearest, Rounding::HalfEven];
+        let rounded = |numerator, denominator| all.map(|rounding| rounding.divide(numerator, denominator));
+        assert_eq!(rounded(10, 4), [2, 3, 3, 2]);
+        assert_eq!(rounded(14, 4), [3, 4, 4, 4]);
+        assert_eq!(rounded(13, 4), [3, 4, 3, 3]);
+        assert_eq!(rounded(12, 4), [3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_error_diffusion() {
+        // 30 fps at a timescale of 1000, for ten minutes
+        let frames = 30 * 600;
+        for rounding in [Rounding::Floor, Rounding::Ceil, Rounding::Nearest, Rounding::HalfEven] {
+            let mut clock = TickClock::new(rounding, true);
+            let durations: Vec<_> = (0..frames).map(|_| clock.next((1000, 30))).collect();
+            assert_eq!(durations.iter().map(|d| *d as u64).sum::<u64>(), 600_000);
+            assert!(durations.iter().all(|d| (33..=34).contains(d)));
+        }
+        let mut clock = TickClock::new(Rounding::Floor, false);
+        let total: u64 = (0..frames).map(|_| clock.next((1000, 30)) as u64).sum();
+        assert_eq!(total, 33 * frames);
+
+        // 90 kHz durations of 29.97 fps, 3003 ticks, at a timescale of 1000
+        let mut clock = TickClock::new(Rounding::Nearest, true);
+        let total: u64 = (0..9000).map(|_| clock.next((3003 * 1000, 90000)) as u64).sum();
+        assert_eq!(total, 300_300);
+        // and frame rates that change
+        let mut clock = TickClock::new(Rounding::Floor, true);
+        let total: u64 = (0..100)
+            .map(|frame| clock.next(if frame % 2 == 0 { (1000, 30) } else { (1000, 25) }) as u64)
+            .sum();
+        assert_eq!(total, (50_000 / 30 + 50_000 / 25) as u64);
+    }
+}
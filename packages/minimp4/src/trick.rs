@@ -0,0 +1,189 @@
+//! Trick mode tracks: a second track of a video track holding only its
+//! keyframes, each lasting up to the next one, for players that fast forward
+//! by decoding keyframes alone.
+//!
+//! The track is a copy of the video `trak` with a sample table of its own
+//! that points into the same `mdat`, so the keyframes are stored once. Both
+//! tracks share an alternate group and the copy is disabled, so that players
+//! unaware of it keep playing the video track.
+
+use crate::boxes::{find_child_path, find_path, splice, BoxRange, BoxWriter};
+
+/// `track_in_movie`, the only `tkhd` flag of the trick mode track.
+const TRACK_IN_MOVIE: u32 = 2;
+
+/// A sample of the video track, as the trick mode track needs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct VideoSample {
+    pub offset: u64,
+    pub size: u32,
+    pub duration: u32,
+    pub sync: bool,
+}
+
+/// The keyframes of `samples`, each lasting until the next one. `None` when
+/// the track does not start with a keyframe, or a keyframe would outlast a
+/// sample duration.
+fn keyframes(samples: &[VideoSample]) -> Option<Vec<VideoSample>> {
+    if !samples.first()?.sync {
+        return None;
+    }
+    let mut keyframes: Vec<VideoSample> = vec![];
+    let mut duration = 0u64;
+    for sample in samples {
+        if sample.sync {
+            if let Some(last) = keyframes.last_mut() {
+                last.duration = duration.try_into().ok()?;
+            }
+            keyframes.push(*sample);
+            duration = 0;
+        }
+        duration += sample.duration as u64;
+    }
+    keyframes.last_mut()?.duration = duration.try_into().ok()?;
+    Some(keyframes)
+}
+
+/// Offset of `alternate_group` in the payload of a `tkhd` of `version`.
+fn alternate_group_offset(version: u8) -> usize {
+    // version and flags, times, track_ID, reserved, duration, reserved and
+    // layer
+    if version == 1 {
+        46
+    } else {
+        34
+    }
+}
+
+/// The `alternate_group` of the `tkhd` of `trak`, a `trak` box on its own,
+/// set to 1 when it has none. Returns the group.
+fn join_alternate_group(trak: &mut [u8]) -> Option<u16> {
+    let tkhd = find_path(trak, &[b"trak", b"tkhd"])?[1];
+    let pos = tkhd.payload + alternate_group_offset(trak[tkhd.payload]);
+    let field = trak.get_mut(pos..pos + 2).filter(|_| pos + 2 <= tkhd.end)?;
+    if field == [0, 0] {
+        field.copy_from_slice(&1u16.to_be_bytes());
+    }
+    Some(u16::from_be_bytes([field[0], field[1]]))
+}
+
+/// Adds the trick mode track of the video `trak` of `moov`, whose samples
+/// are `samples`, right after it, as track `track_id`. The video track joins
+/// an alternate group if it has none. Nothing changes when the samples have
+/// no trick mode track.
+pub(crate) fn add_trick_trak(
+    moov: &mut Vec<u8>,
+    root: BoxRange,
+    trak: BoxRange,
+    samples: &[VideoSample],
+    track_id: u32,
+) {
+    let Some(keyframes) = keyframes(samples) else {
+        return;
+    };
+    let mut video = moov[trak.start..trak.end].to_vec();
+    let Some(group) = join_alternate_group(&mut video) else {
+        return;
+    };
+    let Some(copy) = trick_trak(&video, group, &keyframes, track_id) else {
+        return;
+    };
+    // the group only takes the place of a zero, keeping the size
+    moov[trak.start..trak.end].copy_from_slice(&video);
+    splice(moov, &[root], trak.end..trak.end, &copy);
+}
+
+/// The copy of `video`, a `trak` box on its own, holding `keyframes`.
+fn trick_trak(video: &[u8], group: u16, keyframes: &[VideoSample], track_id: u32) -> Option<Vec<u8>> {
+    let mut trak = video.to_vec();
+    let root = find_path(&trak, &[b"trak"])?;
+    // a track comment would describe the video track
+    if let Some(udta) = find_child_path(&trak, &root, &[b"udta"]) {
+        splice(&mut trak, &root, udta[1].start..udta[1].end, &[]);
+    }
+    let tkhd = find_path(&trak, &[b"trak", b"tkhd"])?[1];
+    let version = trak[tkhd.payload];
+    let id = tkhd.payload + if version == 1 { 20 } else { 12 };
+    let group_pos = tkhd.payload + alternate_group_offset(version);
+    if group_pos + 2 > tkhd.end {
+        return None;
+    }
+    trak[tkhd.payload + 1..tkhd.payload + 4].copy_from_slice(&TRACK_IN_MOVIE.to_be_bytes()[1..]);
+    trak[id..id + 4].copy_from_slice(&track_id.to_be_bytes());
+    trak[group_pos..group_pos + 2].copy_from_slice(&group.to_be_bytes());
+
+    let stbl = find_path(&trak, &[b"trak", b"mdia", b"minf", b"stbl"])?;
+    let stsd = find_child_path(&trak, &stbl, &[b"stsd"])?[4];
+    let table = stbl_box(&trak[stsd.start..stsd.end], keyframes);
+    let stbl_box = stbl[3];
+    splice(&mut trak, &stbl[..3], stbl_box.start..stbl_box.end, &table);
+    Some(trak)
+}
+
+/// Sample table of `keyframes`, one chunk per sample and no `stss` as all
+/// samples are sync samples.
+fn stbl_box(stsd: &[u8], keyframes: &[VideoSample]) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"stbl").bytes(stsd);
+    // durations that follow each other run-length coded
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for keyframe in keyframes {
+        match runs.last_mut() {
+            Some((count, duration)) if *duration == keyframe.duration => *count += 1,
+            _ => runs.push((1, keyframe.duration)),
+        }
+    }
+    w.begin_full(b"stts", 0, 0).u32(runs.len() as u32);
+    for (count, duration) in runs {
+        w.u32(count).u32(duration);
+    }
+    w.end();
+    w.begin_full(b"stsc", 0, 0).u32(1).u32(1).u32(1).u32(1).end();
+    w.begin_full(b"stsz", 0, 0).u32(0).u32(keyframes.len() as u32);
+    for keyframe in keyframes {
+        w.u32(keyframe.size);
+    }
+    w.end();
+    let wide = keyframes.iter().any(|keyframe| keyframe.offset > u32::MAX as u64);
+    w.begin_full(if wide { b"co64" } else { b"stco" }, 0, 0)
+        .u32(keyframes.len() as u32);
+    for keyframe in keyframes {
+        match wide {
+            true => w.bytes(&keyframe.offset.to_be_bytes()),
+            false => w.u32(keyframe.offset as u32),
+        };
+    }
+    w.end();
+    w.end().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(offset: u64, duration: u32, sync: bool) -> VideoSample {
+        VideoSample {
+            offset,
+            size: 10,
+            duration,
+            sync,
+        }
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let samples = [
+            sample(0, 10, true),
+            sample(10, 10, false),
+            sample(20, 20, false),
+            sample(30, 10, true),
+            sample(40, 10, false),
+        ];
+        assert_eq!(
+            keyframes(&samples),
+            Some(vec![sample(0, 40, true), sample(30, 20, true)])
+        );
+        assert_eq!(keyframes(&samples[1..]), None);
+        assert_eq!(keyframes(&[]), None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Checks of an Annex B elementary stream before muxing it, with what the
+//! muxer needs to know about it.
+
+use crate::{
+    nal::{Codec, NalIter, NalKind},
+    sps,
+};
+
+/// What [`validate_stream`] learns from a well-formed stream, the width,
+/// height and codec being the arguments of
+/// [`Mp4Muxer::init_video`](crate::Mp4Muxer::init_video), `is_hevc` for
+/// [`Codec::H265`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoStreamInfo {
+    pub codec: Codec,
+    /// Picture size of the first SPS, once cropped.
+    pub width: u32,
+    pub height: u32,
+    /// `profile_idc` of the first SPS, `general_profile_idc` for H.265.
+    pub profile: u8,
+    /// `level_idc` of the first SPS, ten times the level for H.264 and
+    /// thirty times for H.265.
+    pub level: u8,
+    /// Pictures of the stream.
+    pub frames: usize,
+    /// IDR pictures for H.264, IRAP ones for H.265.
+    pub keyframes: usize,
+    /// Whether the first picture is a keyframe. The muxer leaves out the
+    /// pictures before the first one, which cannot be decoded.
+    pub starts_with_keyframe: bool,
+}
+
+/// Scans `data`, an Annex B byte stream of `codec`, as
+/// [`Mp4Muxer::write_video`](crate::Mp4Muxer::write_video) would take it,
+/// and returns what it holds, or every problem found in it, each a message
+/// naming the NAL unit it is about. A stream passes when it starts with a
+/// start code, all its NAL units have a valid header of `codec`, the
+/// parameter sets come before the first slice, every SPS can be read and
+/// gives the picture size of the first one, every picture starts with its
+/// first slice and there is at least one picture.
+///
+/// Nothing is decoded beyond the headers, so a stream that passes can still
+/// hold broken slices.
+pub fn validate_stream(data: &[u8], codec: Codec) -> Result<VideoStreamInfo, Vec<String>> {
+    let mut problems = vec![];
+    let mut first_sps = None;
+    let mut parameter_sets = [false; 3];
+    let (mut frames, mut keyframes) = (0, 0);
+    let mut starts_with_keyframe = false;
+    let mut in_picture = false;
+    let mut slices = 0;
+    let mut nals = NalIter::new(data).enumerate().peekable();
+    let empty = nals.peek().is_none();
+    if let Some((_, nal)) = nals.peek() {
+        // the three bytes of the start code come right before
+        let leading = &data[..offset(data, nal) - 3];
+        if let Some(last) = leading.iter().rposition(|byte| *byte != 0) {
+            problems.push(format!("{} bytes before the first start code", last + 1));
+        }
+    } else {
+        problems.push("no NAL unit, the stream has no start code".to_string());
+    }
+    for (index, nal) in nals {
+        let at = format!("NAL unit {index} at byte {}", offset(data, nal));
+        if !codec.accepts(nal) {
+            problems.push(format!("{at}: not a NAL unit header of {codec:?}"));
+            in_picture = false;
+            continue;
+        }
+        match codec.classify(nal) {
+            NalKind::Vps => parameter_sets[0] = true,
+            NalKind::Pps => parameter_sets[2] = true,
+            NalKind::Sps => {
+                parameter_sets[1] = true;
+                match (sps::picture_size(codec, nal), first_sps) {
+                    (None, _) => problems.push(format!("{at}: the SPS cannot be read")),
+                    (Some((size, _)), None) => first_sps = Some((nal, size)),
+                    (Some((size, _)), Some((_, first))) if size != first => problems.push(format!(
+                        "{at}: the SPS changes the picture size from {}x{} to {}x{}",
+                        first.0, first.1, size.0, size.1
+                    )),
+                    _ => {}
+                }
+            }
+            NalKind::Aud => in_picture = false,
+            NalKind::Slice { first, keyframe } => {
+                let missing: Vec<_> = ["VPS", "SPS", "PPS"]
+                    .iter()
+                    .zip(parameter_sets)
+                    .skip(if codec == Codec::H265 { 0 } else { 1 })
+                    .filter(|(_, seen)| !seen)
+                    .map(|(name, _)| *name)
+                    .collect();
+                if !missing.is_empty() && slices == 0 {
+                    problems.push(format!("{at}: slice before the {}", missing.join(" and ")));
+                }
+                slices += 1;
+                if first {
+                    starts_with_keyframe |= frames == 0 && keyframe;
+                    frames += 1;
+                    keyframes += keyframe as usize;
+                    in_picture = true;
+                } else if !in_picture {
+                    problems.push(format!("{at}: slice of a picture whose first slice is missing"));
+                }
+            }
+            NalKind::Other { .. } => {}
+        }
+    }
+    if frames == 0 && !empty {
+        problems.push("no picture".to_string());
+    }
+    let Some((sps, (width, height))) = first_sps.filter(|_| problems.is_empty()) else {
+        return Err(problems);
+    };
+    let (profile, level) = match codec {
+        Codec::H264 => (sps[1], sps[3]),
+        Codec::H265 => {
+            let profile = sps::h265_general_profile(sps).ok_or_else(|| vec!["the SPS cannot be read".to_string()])?;
+            (profile[0] & 0x1f, profile[11])
+        }
+    };
+    Ok(VideoStreamInfo {
+        codec,
+        width,
+        height,
+        profile,
+        level,
+        frames,
+        keyframes,
+        starts_with_keyframe,
+    })
+}
+
+/// Offset of `nal`, a slice of `data`, in it.
+fn offset(data: &[u8], nal: &[u8]) -> usize {
+    nal.as_ptr() as usize - data.as_ptr() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_stream() {
+        let h264 = include_bytes!("./fixtures/input.264");
+        let info = validate_stream(h264, Codec::H264).unwrap();
+        assert_eq!(
+            info,
+            VideoStreamInfo {
+                codec: Codec::H264,
+                width: 1920,
+                height: 1080,
+                profile: 100,
+                level: 40,
+                frames: 128,
+                keyframes: 2,
+                starts_with_keyframe: true,
+            }
+        );
+        let info = validate_stream(include_bytes!("./fixtures/input.265"), Codec::H265).unwrap();
+        assert_eq!((info.width, info.profile, info.level, info.frames), (1920, 1, 120, 128));
+
+        let problems = validate_stream(h264, Codec::H265).unwrap_err();
+        assert!(
+            problems[0].starts_with("NAL unit 0 at byte 4: not a NAL unit header"),
+            "{problems:?}"
+        );
+        // garbage ahead of the stream and the parameter sets left out
+        let mut broken = b"junk".to_vec();
+        for nal in NalIter::new(h264).skip(2) {
+            broken.extend([0, 0, 0, 1]);
+            broken.extend(nal);
+        }
+        assert_eq!(
+            validate_stream(&broken, Codec::H264).unwrap_err(),
+            [
+                "4 bytes before the first start code",
+                "NAL unit 1 at byte 699: slice before the SPS and PPS"
+            ]
+        );
+        // a delimiter, then a slice that is not the first of its picture
+        let second = NalIter::new(h264).nth(4).unwrap();
+        let end = offset(h264, second) - 4;
+        let mut cut = h264[..end].to_vec();
+        cut.extend([0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, second[0], 0x40]);
+        let problems = validate_stream(&cut, Codec::H264).unwrap_err();
+        assert!(problems[0].ends_with("first slice is missing"), "{problems:?}");
+        assert_eq!(
+            validate_stream(&[0; 16], Codec::H264).unwrap_err(),
+            ["no NAL unit, the stream has no start code"]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! VP9 in MP4 as the VP Codec ISO Media File Format Binding describes it: a
+//! `vp09` sample entry holding a `vpcC` configuration, and samples that are
+//! whole temporal units, superframes included.
+//!
+//! minimp4 only knows the sample entries of H.264 and H.265 and writes an
+//! empty `stsd` for any other video track, the `vp09` entry is then spliced
+//! into it.
+
+use crate::{
+    boxes::{find_child_path, find_path, splice, BoxIter, BoxWriter},
+    ticks::TickClock,
+    Minimp4Error, Minimp4Result,
+};
+
+/// Object type of VP9 video, as found in the object type tables of FFmpeg
+/// and others. minimp4 writes no sample entry for it.
+pub(crate) const MP4_OBJECT_TYPE_VP9: u32 = 0xb1;
+
+/// Decoder configuration of a VP9 track, the fields of its `vpcC` box.
+///
+/// The colour fields take the code points of ISO/IEC 23091-2, `2` standing
+/// for unspecified. The defaults describe 8-bit 4:2:0 video of profile 0 in
+/// BT.709, without a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VpccConfig {
+    /// VP9 profile, 0 to 3.
+    pub profile: u8,
+    /// Level times ten, such as 31 for level 3.1, or 0 when unknown.
+    pub level: u8,
+    /// Bits per sample, 8, 10 or 12.
+    pub bit_depth: u8,
+    /// 0 and 1 for 4:2:0 with vertically and co-sited chroma, 2 for 4:2:2
+    /// and 3 for 4:4:4.
+    pub chroma_subsampling: u8,
+    pub video_full_range: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+}
+
+impl Default for VpccConfig {
+    fn default() -> Self {
+        Self {
+            profile: 0,
+            level: 0,
+            bit_depth: 8,
+            chroma_subsampling: 1,
+            video_full_range: false,
+            colour_primaries: 1,
+            transfer_characteristics: 1,
+            matrix_coefficients: 1,
+        }
+    }
+}
+
+impl VpccConfig {
+    /// Whether the fields fit the `vpcC` box and describe a VP9 stream.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.profile <= 3 && matches!(self.bit_depth, 8 | 10 | 12) && self.chroma_subsampling <= 3
+    }
+
+    /// `vp09.PP.LL.DD`, the short form of the codec string.
+    pub(crate) fn codec_string(&self) -> String {
+        format!("vp09.{:02}.{:02}.{:02}", self.profile, self.level, self.bit_depth)
+    }
+}
+
+/// The VP9 track of [`crate::Mp4Muxer::init_video_vp9`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Vp9Track {
+    /// Index of the track in the muxer.
+    pub track: usize,
+    pub width: u16,
+    pub height: u16,
+    pub config: VpccConfig,
+    /// Frame durations so far.
+    pub clock: TickClock,
+}
+
+/// The frames of `data`, one temporal unit of a VP9 stream: the frames listed
+/// by its superframe index, or `data` itself without an index. `None` for
+/// an index whose frames do not fit the data.
+pub(crate) fn frames(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let Some(&marker) = data.last() else {
+        return Some(vec![]);
+    };
+    if marker & 0xe0 != 0xc0 {
+        return Some(vec![data]);
+    }
+    let count = (marker & 7) as usize + 1;
+    let size_bytes = ((marker >> 3) & 3) as usize + 1;
+    let index_size = 2 + size_bytes * count;
+    // the index starts with the marker byte as well
+    if data.len() < index_size || data[data.len() - index_size] != marker {
+        return Some(vec![data]);
+    }
+    let index = &data[data.len() - index_size + 1..data.len() - 1];
+    let mut frames = vec![];
+    let mut rest = &data[..data.len() - index_size];
+    for size in index.chunks(size_bytes) {
+        let size = size.iter().rev().fold(0, |size, byte| size << 8 | *byte as usize);
+        if size > rest.len() {
+            return None;
+        }
+        let (frame, tail) = rest.split_at(size);
+        frames.push(frame);
+        rest = tail;
+    }
+    Some(frames)
+}
+
+/// Whether the VP9 frame `frame` is shown and whether it is a key frame,
+/// read from the start of its uncompressed header. `None` for data that is
+/// no VP9 frame.
+pub(crate) fn frame_type(frame: &[u8]) -> Option<(bool, bool)> {
+    let header = u16::from_be_bytes(frame.get(..2)?.try_into().unwrap());
+    let mut bits = (0..16).rev().map(|bit| header >> bit & 1 == 1);
+    let mut bit = || bits.next().unwrap();
+    // frame_marker
+    if !bit() || bit() {
+        return None;
+    }
+    let profile = bit() as u8 | (bit() as u8) << 1;
+    if profile == 3 && bit() {
+        return None;
+    }
+    if bit() {
+        // show_existing_frame, showing a reference frame again
+        return Some((true, false));
+    }
+    let key_frame = !bit();
+    let show_frame = bit();
+    Some((show_frame, key_frame))
+}
+
+/// Whether the temporal unit `data` must be marked as a sync sample, or
+/// [`Minimp4Error::BadArguments`] unless it is a valid sample, exactly one
+/// of its frames shown.
+pub(crate) fn is_sync_sample(data: &[u8]) -> Minimp4Result<bool> {
+    let types = frames(data)
+        .ok_or(Minimp4Error::BadArguments)?
+        .into_iter()
+        .map(frame_type)
+        .collect::<Option<Vec<_>>>()
+        .ok_or(Minimp4Error::BadArguments)?;
+    if types.iter().filter(|(shown, _)| *shown).count() != 1 {
+        return Err(Minimp4Error::BadArguments);
+    }
+    Ok(types[0].1)
+}
+
+/// Writes the `vp09` sample entry of `vp9` into the empty `stsd` of the
+/// `trak` starting at `trak_start` of `moov`.
+pub(crate) fn patch_trak(moov: &mut Vec<u8>, trak_start: usize, vp9: &Vp9Track) {
+    let Some(root) = find_path(moov, &[b"moov"]) else {
+        return;
+    };
+    let Some(trak) = BoxIter::new(moov, root[0].children()).find(|b| b.start == trak_start) else {
+        return;
+    };
+    let path = [b"mdia", b"minf", b"stbl", b"stsd"];
+    let Some(stsd) = find_child_path(moov, &[root[0], trak], &path) else {
+        return;
+    };
+    // version, flags and the entry count minimp4 already set to 1
+    let end = stsd[5].end;
+    splice(moov, &stsd, end..end, &vp09_box(vp9));
+}
+
+fn vp09_box(vp9: &Vp9Track) -> Vec<u8> {
+    let config = &vp9.config;
+    let mut w = BoxWriter::new();
+    w.begin(b"vp09")
+        .bytes(&[0; 6])
+        .u16(1) // data_reference_index
+        .bytes(&[0; 16]) // pre_defined and reserved
+        .u16(vp9.width)
+        .u16(vp9.height)
+        .u32(0x0048_0000) // 72 dpi
+        .u32(0x0048_0000)
+        .u32(0)
+        .u16(1) // frame_count
+        .bytes(&[0; 32]) // compressorname
+        .u16(0x18) // depth
+        .u16(0xffff); // pre_defined
+    w.begin_full(b"vpcC", 1, 0)
+        .u8(config.profile)
+        .u8(config.level)
+        .u8(config.bit_depth << 4 | config.chroma_subsampling << 1 | config.video_full_range as u8)
+        .u8(config.colour_primaries)
+        .u8(config.transfer_characteristics)
+        .u8(config.matrix_coefficients)
+        .u16(0) // codecInitializationDataSize
+        .end();
+    w.end().finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A key frame and an inter frame of profile 0, their uncompressed
+    /// header cut short after the frame type.
+    pub(crate) const KEY_FRAME: [u8; 4] = [0x82, 0x49, 0x83, 0x42];
+    pub(crate) const INTER_FRAME: [u8; 2] = [0x86, 0x00];
+    /// A hidden inter frame, the alternate reference of a superframe.
+    const HIDDEN_FRAME: [u8; 2] = [0x84, 0x00];
+
+    /// `frames` behind a superframe index with one-byte sizes.
+    pub(crate) fn superframe(frames: &[&[u8]]) -> Vec<u8> {
+        let marker = 0xc0 | (frames.len() as u8 - 1);
+        let mut data = frames.concat();
+        data.push(marker);
+        data.extend(frames.iter().map(|frame| frame.len() as u8));
+        data.push(marker);
+        data
+    }
+
+    #[test]
+    fn test_frames() {
+        assert_eq!(frames(&KEY_FRAME), Some(vec![&KEY_FRAME[..]]));
+        let data = superframe(&[&HIDDEN_FRAME, &INTER_FRAME]);
+        assert_eq!(frames(&data), Some(vec![&HIDDEN_FRAME[..], &INTER_FRAME[..]]));
+        // sizes beyond the data
+        let mut data = superframe(&[&HIDDEN_FRAME, &INTER_FRAME]);
+        data[5] = 9;
+        assert_eq!(frames(&data), None);
+    }
+
+    #[test]
+    fn test_is_sync_sample() {
+        assert_eq!(is_sync_sample(&KEY_FRAME), Ok(true));
+        assert_eq!(is_sync_sample(&INTER_FRAME), Ok(false));
+        assert_eq!(is_sync_sample(&superframe(&[&HIDDEN_FRAME, &INTER_FRAME])), Ok(false));
+        // no frame shown, two frames shown, not a frame
+        assert_eq!(is_sync_sample(&HIDDEN_FRAME), Err(Minimp4Error::BadArguments));
+        assert_eq!(
+            is_sync_sample(&superframe(&[&KEY_FRAME, &INTER_FRAME])),
+            Err(Minimp4Error::BadArguments)
+        );
+        assert_eq!(is_sync_sample(&[0x00, 0x00]), Err(Minimp4Error::BadArguments));
+    }
+}
@@ -0,0 +1,143 @@
+//! WebVTT subtitles in MP4, ISO/IEC 14496-30: a `wvtt` sample entry with the
+//! WebVTT header in its `vttC` box, and samples of `vttc` cue boxes that
+//! cover the timeline without gaps, `vtte` empty cues where no cue is shown.
+//!
+//! minimp4 writes the track as an MPEG-4 private stream, the `trak` is then
+//! turned into a text track the way the ID3 one is.
+
+use crate::{
+    boxes::BoxWriter,
+    id3::{hdlr, patch_private_trak},
+    Minimp4Error, Minimp4Result,
+};
+
+/// Timescale of WebVTT tracks, cue times are kept in milliseconds.
+pub(crate) const TIMESCALE: u32 = 1000;
+
+/// The WebVTT track of [`crate::Mp4Muxer::init_webvtt_track`].
+///
+/// Cues may overlap, so a sample is only written once no cue can start
+/// within it any more: every sample carries the cues shown for the whole of
+/// its duration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WebVttTrack {
+    /// Index of the track in the muxer.
+    pub track: usize,
+    /// End of the samples written so far.
+    written: u64,
+    /// Start of the last cue, cues come in the order they start.
+    last_start: u64,
+    /// Cues started but not over at `written`, with their end.
+    pending: Vec<(u64, Vec<u8>)>,
+}
+
+impl WebVttTrack {
+    pub fn new(track: usize) -> Self {
+        Self {
+            track,
+            written: 0,
+            last_start: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Adds the cue `text`, shown from `start` to `end` in milliseconds, and
+    /// returns the samples, data and duration, that are complete with it.
+    /// Fails with [`Minimp4Error::BadArguments`] for a cue that starts before
+    /// the previous one, does not last or whose duration or time since the
+    /// previous samples do not fit a sample duration.
+    pub fn add_cue(&mut self, text: &str, start: u64, end: u64) -> Minimp4Result<Vec<(Vec<u8>, u32)>> {
+        let fits = |duration: u64| duration <= i32::MAX as u64;
+        if start < self.last_start || end <= start || !fits(end - start) || !fits(start.saturating_sub(self.written)) {
+            return Err(Minimp4Error::BadArguments);
+        }
+        let samples = self.samples_until(start);
+        self.last_start = start;
+        self.pending.push((end, cue_box(text)));
+        Ok(samples)
+    }
+
+    /// Samples up to the end of the last cue, once no more are coming.
+    pub fn finish(&mut self) -> Vec<(Vec<u8>, u32)> {
+        let end = self.pending.iter().map(|(end, _)| *end).max().unwrap_or(self.written);
+        self.samples_until(end)
+    }
+
+    /// Samples from the end of the previous ones up to `time`, split where
+    /// pending cues end, empty ones where no cue is shown.
+    fn samples_until(&mut self, time: u64) -> Vec<(Vec<u8>, u32)> {
+        let mut samples = vec![];
+        while self.written < time {
+            let next = self.pending.iter().map(|(end, _)| *end).min().unwrap_or(time).min(time);
+            let data = match self.pending.is_empty() {
+                true => empty_cue_box(),
+                false => self.pending.iter().flat_map(|(_, cue)| cue.iter().copied()).collect(),
+            };
+            samples.push((data, (next - self.written) as u32));
+            self.written = next;
+            self.pending.retain(|(end, _)| *end > next);
+        }
+        samples
+    }
+}
+
+/// `vttc` box of a cue showing `text`, its `payl` payload.
+fn cue_box(text: &str) -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"vttc").begin(b"payl").bytes(text.as_bytes()).end();
+    w.end().finish()
+}
+
+/// `vtte` box of a sample without any cue.
+fn empty_cue_box() -> Vec<u8> {
+    BoxWriter::new().begin(b"vtte").end().finish()
+}
+
+/// Turns the private stream `trak` starting at `trak_start` of `moov` into a
+/// WebVTT track: `text` handler, `nmhd` media header and `wvtt` sample entry
+/// with the `WEBVTT` header.
+pub(crate) fn patch_trak(moov: &mut Vec<u8>, trak_start: usize) {
+    patch_private_trak(moov, trak_start, &wvtt_box(), &hdlr(b"text", b"WebVTT"));
+}
+
+fn wvtt_box() -> Vec<u8> {
+    let mut w = BoxWriter::new();
+    w.begin(b"wvtt").bytes(&[0; 6]).u16(1); // reserved and data_reference_index
+    w.begin(b"vttC").bytes(b"WEBVTT").end();
+    w.end().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn durations(samples: &[(Vec<u8>, u32)]) -> Vec<(&[u8], u32)> {
+        samples
+            .iter()
+            .map(|(data, duration)| (&data[4..8], *duration))
+            .collect()
+    }
+
+    #[test]
+    fn test_add_cue() {
+        let mut track = WebVttTrack::new(1);
+        // an empty cue up to the first one
+        let samples = track.add_cue("first", 1000, 3000).unwrap();
+        assert_eq!(samples, [(empty_cue_box(), 1000)]);
+        // a gap, then overlapping cues
+        let samples = track.add_cue("second", 4000, 6000).unwrap();
+        assert_eq!(durations(&samples), [(&b"vttc"[..], 2000), (b"vtte", 1000)]);
+        assert_eq!(samples[0].0, cue_box("first"));
+        let samples = track.add_cue("third", 5000, 7000).unwrap();
+        assert_eq!(durations(&samples), [(&b"vttc"[..], 1000)]);
+        let samples = track.finish();
+        assert_eq!(durations(&samples), [(&b"vttc"[..], 1000), (b"vttc", 1000)]);
+        assert_eq!(samples[0].0, [cue_box("second"), cue_box("third")].concat());
+        assert_eq!(samples[1].0, cue_box("third"));
+        assert_eq!(track.finish(), vec![]);
+
+        assert_eq!(track.add_cue("earlier", 4000, 8000), Err(Minimp4Error::BadArguments));
+        assert_eq!(track.add_cue("empty", 8000, 8000), Err(Minimp4Error::BadArguments));
+        assert_eq!(track.add_cue("third", 8000, 9000).map(|s| s.len()), Ok(1));
+    }
+}
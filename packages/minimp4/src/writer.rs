@@ -1,8 +1,294 @@
-use super::c::{mp4_h26x_write_nal, mp4_h26x_writer_t};
+use super::{
+    c::{
+        c_void, mp4_h26x_write_nal, mp4_h26x_writer_t, track_t, MP4E_put_sample, MP4E_set_pps, MP4E_set_sps,
+        MP4E_set_vps, MP4E_SAMPLE_DEFAULT, MP4E_SAMPLE_RANDOM_ACCESS,
+    },
+    nal::{Codec, NalIter, NalKind, StreamInfo},
+    progress::Progress,
+    ticks::TickClock,
+    Minimp4Error, Minimp4Result,
+};
 
-fn get_nal_size(buf: &mut [u8], size: usize) -> usize {
+/// Assembles access units on the Rust side and hands them to minimp4 as whole
+/// samples. Used instead of the minimp4 H.26x writer when the sample data has
+/// to keep NAL units that writer would drop, such as access unit delimiters.
+///
+/// Parameter sets still go to the decoder configuration through
+/// `MP4E_set_sps` and friends, but unlike the minimp4 writer no SPS/PPS id
+/// renumbering takes place, so the stream must use consistent ids.
+pub(crate) struct AccessUnitWriter {
+    codec: Codec,
+    keep_aud: bool,
+    /// Keeps parameter sets in the samples, repeats included.
+    keep_params: bool,
+    /// Parameter sets belong in the samples, for `avc3`/`hev1` sample entries.
+    /// The decoder configuration then only holds the ones preceding the first
+    /// keyframe.
+    in_band: bool,
+    sample: Vec<u8>,
+    /// Header types of the NAL units of `sample`, counted as skipped when it
+    /// is dropped.
+    sample_types: Vec<u8>,
+    duration: u32,
+    has_slice: bool,
+    keyframe: bool,
+    /// Parameter sets seen so far, as a mask of `PARAM_*` bits.
+    params: u8,
+    /// Set once the first keyframe has been written, earlier pictures cannot be
+    /// decoded and are dropped like minimp4 does.
+    started: bool,
+}
+
+const PARAM_VPS: u8 = 1;
+const PARAM_SPS: u8 = 2;
+const PARAM_PPS: u8 = 4;
+
+impl AccessUnitWriter {
+    /// `in_band` implies `keep_params`.
+    pub fn new(codec: Codec, keep_aud: bool, keep_params: bool, in_band: bool) -> Self {
+        Self {
+            codec,
+            keep_aud,
+            keep_params: keep_params || in_band,
+            in_band,
+            sample: Vec::new(),
+            sample_types: Vec::new(),
+            duration: 0,
+            has_slice: false,
+            keyframe: false,
+            params: 0,
+            started: false,
+        }
+    }
+
+    /// Adds one NAL unit, without start code, to the access unit being built.
+    /// `duration` is used for the sample if this NAL is its first slice. Fails
+    /// with [`Minimp4Error::MissingParameterSets`] for a slice coming before
+    /// any complete set of parameter sets. NAL units left out of the samples
+    /// are counted by `stream`.
+    pub fn write_nal(
+        &mut self,
+        mp4wr: &mut mp4_h26x_writer_t,
+        stream: &mut StreamInfo,
+        nal: &[u8],
+        duration: u32,
+    ) -> Minimp4Result<()> {
+        let kind = self.codec.classify(nal);
+        if matches!(kind, NalKind::Slice { .. }) && self.params & self.required_params() != self.required_params() {
+            return Err(Minimp4Error::MissingParameterSets);
+        }
+        let starts_access_unit = match kind {
+            NalKind::Slice { first, .. } => first,
+            NalKind::Aud | NalKind::Vps | NalKind::Sps | NalKind::Pps | NalKind::Other { prefix: true } => true,
+            NalKind::Other { prefix: false } => false,
+        };
+        if starts_access_unit && self.has_slice {
+            self.flush(mp4wr, stream);
+        }
+        let (mux, track) = (mp4wr.mux, mp4wr.mux_track_id);
+        let (ptr, len) = (nal.as_ptr() as *const c_void, nal.len() as i32);
+        match kind {
+            NalKind::Vps | NalKind::Sps | NalKind::Pps => {
+                let param = match kind {
+                    NalKind::Vps => PARAM_VPS,
+                    NalKind::Sps => PARAM_SPS,
+                    _ => PARAM_PPS,
+                };
+                if !self.in_band || !self.started {
+                    let set = match kind {
+                        NalKind::Vps => MP4E_set_vps,
+                        NalKind::Sps => MP4E_set_sps,
+                        _ => MP4E_set_pps,
+                    };
+                    unsafe { set(mux, track, ptr, len) };
+                }
+                self.params |= param;
+                if self.keep_params {
+                    self.append(nal);
+                } else {
+                    stream.skip(self.codec.nal_type(nal));
+                }
+            }
+            NalKind::Aud if !self.keep_aud => stream.skip(self.codec.nal_type(nal)),
+            _ => {
+                if let NalKind::Slice { keyframe, .. } = kind {
+                    if !self.has_slice {
+                        self.duration = duration;
+                    }
+                    self.has_slice = true;
+                    self.keyframe |= keyframe;
+                }
+                self.append(nal);
+            }
+        }
+        Ok(())
+    }
+
+    fn required_params(&self) -> u8 {
+        match self.codec {
+            Codec::H264 => PARAM_SPS | PARAM_PPS,
+            Codec::H265 => PARAM_VPS | PARAM_SPS | PARAM_PPS,
+        }
+    }
+
+    fn append(&mut self, nal: &[u8]) {
+        self.sample.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        self.sample.extend_from_slice(nal);
+        self.sample_types.push(self.codec.nal_type(nal));
+    }
+
+    /// Writes the pending access unit, if any. One without a slice, or ahead
+    /// of the first keyframe, is dropped and its NAL units counted by
+    /// `stream`.
+    pub fn flush(&mut self, mp4wr: &mut mp4_h26x_writer_t, stream: &mut StreamInfo) {
+        let required = self.required_params();
+        if self.has_slice && self.keyframe && self.params & required == required {
+            self.started = true;
+        }
+        if self.has_slice && self.started {
+            let kind = if self.keyframe {
+                MP4E_SAMPLE_RANDOM_ACCESS
+            } else {
+                MP4E_SAMPLE_DEFAULT
+            };
+            unsafe {
+                MP4E_put_sample(
+                    mp4wr.mux,
+                    mp4wr.mux_track_id,
+                    self.sample.as_ptr() as *const c_void,
+                    self.sample.len() as i32,
+                    self.duration as i32,
+                    kind as i32,
+                )
+            };
+        } else {
+            self.sample_types.iter().for_each(|nal_type| stream.skip(*nal_type));
+        }
+        self.sample.clear();
+        self.sample_types.clear();
+        self.has_slice = false;
+        self.keyframe = false;
+    }
+}
+
+/// Timescale of the video track, durations passed to minimp4 are in its units.
+fn time_scale(mp4wr: &mp4_h26x_writer_t) -> u32 {
+    unsafe {
+        let tracks = (*mp4wr.mux).tracks.data as *const track_t;
+        (*tracks.add(mp4wr.mux_track_id as usize)).info.time_scale
+    }
+}
+
+/// Records the first frame duration as the default of the video track, the
+/// `trex` default of fragmented output.
+fn set_default_duration(mp4wr: &mut mp4_h26x_writer_t, duration: u32) {
+    unsafe {
+        let tracks = (*mp4wr.mux).tracks.data as *mut track_t;
+        let info = &mut (*tracks.add(mp4wr.mux_track_id as usize)).info;
+        if info.default_duration == 0 {
+            info.default_duration = duration;
+        }
+    }
+}
+
+/// A duration in 90 kHz ticks in ticks of the video track timescale, as a
+/// fraction for [`TickClock`].
+fn from_90khz(mp4wr: &mp4_h26x_writer_t, duration_90khz: u32) -> (u64, u64) {
+    (duration_90khz as u64 * time_scale(mp4wr) as u64, 90000)
+}
+
+/// A frame at `fps` in ticks of the video track timescale, as a fraction for
+/// [`TickClock`].
+fn from_fps(mp4wr: &mp4_h26x_writer_t, fps: i32) -> (u64, u64) {
+    (time_scale(mp4wr) as u64, fps as u64)
+}
+
+fn codec(mp4wr: &mp4_h26x_writer_t) -> Codec {
+    if mp4wr.is_hevc != 0 {
+        Codec::H265
+    } else {
+        Codec::H264
+    }
+}
+
+/// Writes `chunk`, one NAL unit preceded by its start code, through the
+/// assembler when given or through the minimp4 H.26x writer otherwise.
+///
+/// The minimp4 writer ignores H.264 access unit delimiters but would store an
+/// H.265 one as a sample of its own, so delimiters are stripped here. It also
+/// silently drops slices it has no parameter sets for, which for a stream
+/// joined mid-GOP ends up as a track without decoder configuration, so these
+/// fail with [`Minimp4Error::MissingParameterSets`] instead.
+///
+/// The first slice of every picture is counted as a frame by `progress`, and
+/// checked against its abort flag before anything of it is written, and
+/// advances `clock` by `duration`, a fraction of ticks. The sample takes the
+/// duration of its first slice, the other NAL units pass on `duration`
+/// rounded on its own. Every
+/// NAL unit is observed by `stream`, which also counts those left out of the
+/// samples: delimiters, parameter sets only kept in the decoder
+/// configuration and whatever minimp4 drops ahead of the first keyframe.
+fn write_nal(
+    mp4wr: &mut mp4_h26x_writer_t,
+    assembler: Option<&mut AccessUnitWriter>,
+    progress: &mut Progress,
+    stream: &mut StreamInfo,
+    clock: &mut TickClock,
+    chunk: &[u8],
+    duration: (u64, u64),
+) -> Minimp4Result<()> {
+    let Some(nal) = NalIter::new(chunk).next() else {
+        return Ok(());
+    };
+    let codec = codec(mp4wr);
+    let kind = codec.classify(nal);
+    let first_slice = matches!(kind, NalKind::Slice { first: true, .. });
+    if first_slice {
+        progress.check_abort()?;
+    }
+    stream.check_sps(codec, nal)?;
+    stream.observe(codec, nal);
+    let duration = match first_slice {
+        true => clock.next(duration),
+        false => clock.duration(duration),
+    };
+    match (assembler, kind) {
+        (Some(assembler), _) => assembler.write_nal(mp4wr, stream, nal, duration)?,
+        (None, NalKind::Aud) => stream.skip(codec.nal_type(nal)),
+        (None, NalKind::Slice { .. }) if mp4wr.need_vps != 0 || mp4wr.need_sps != 0 || mp4wr.need_pps != 0 => {
+            return Err(Minimp4Error::MissingParameterSets);
+        }
+        (None, _) => {
+            unsafe { mp4_h26x_write_nal(mp4wr, chunk.as_ptr(), chunk.len() as i32, duration) };
+            // minimp4 only stores other NAL units once it has all parameter
+            // sets and a keyframe
+            let started = mp4wr.need_vps == 0 && mp4wr.need_sps == 0 && mp4wr.need_pps == 0 && mp4wr.need_idr == 0;
+            if matches!(kind, NalKind::Vps | NalKind::Sps | NalKind::Pps) || !started {
+                stream.skip(codec.nal_type(nal));
+            }
+        }
+    }
+    if first_slice {
+        let mux = mp4wr.mux;
+        progress.frame(duration, time_scale(mp4wr), || unsafe { (*mux).write_pos as u64 });
+    }
+    Ok(())
+}
+
+/// Fails with [`Minimp4Error::BadArguments`] unless `data` starts with a NAL
+/// unit of the codec of the track, so that a stream of the other codec, or
+/// no Annex B stream at all, is rejected before minimp4 misparses it.
+fn check_codec(mp4wr: &mp4_h26x_writer_t, data: &[u8]) -> Minimp4Result<()> {
+    match NalIter::new(data).next() {
+        Some(nal) if codec(mp4wr).accepts(nal) => Ok(()),
+        _ => Err(Minimp4Error::BadArguments),
+    }
+}
+
+fn get_nal_size(buf: &[u8]) -> usize {
+    let size = buf.len();
     let mut pos = 3;
-    while size - pos > 3 {
+    while size.saturating_sub(pos) > 3 {
         if buf[pos] == 0 && buf[pos + 1] == 0 && buf[pos + 2] == 1 {
             return pos;
         }
@@ -14,72 +300,132 @@ fn get_nal_size(buf: &mut [u8], size: usize) -> usize {
     size
 }
 
-pub fn write_mp4(mp4wr: &mut mp4_h26x_writer_t, fps: i32, data: &[u8]) {
-    let mut data_size = data.len();
-    let mut data_ptr = data.as_ptr();
+pub fn write_mp4(
+    mp4wr: &mut mp4_h26x_writer_t,
+    assembler: Option<&mut AccessUnitWriter>,
+    progress: &mut Progress,
+    stream: &mut StreamInfo,
+    clock: &mut TickClock,
+    fps: i32,
+    data: &[u8],
+) -> Minimp4Result<()> {
+    check_codec(mp4wr, data)?;
+    let duration = from_fps(mp4wr, fps);
+    set_default_duration(mp4wr, clock.duration(duration));
+    write_nals(mp4wr, assembler, progress, stream, clock, duration, data)
+}
 
-    while data_size > 0 {
-        let buf = unsafe { std::slice::from_raw_parts_mut(data_ptr as *mut u8, data_size) };
-        let nal_size = get_nal_size(buf, data_size);
+/// Writes the NAL units of `data`, each frame lasting `duration` ticks of the
+/// track as `clock` rounds them.
+fn write_nals(
+    mp4wr: &mut mp4_h26x_writer_t,
+    mut assembler: Option<&mut AccessUnitWriter>,
+    progress: &mut Progress,
+    stream: &mut StreamInfo,
+    clock: &mut TickClock,
+    duration: (u64, u64),
+    data: &[u8],
+) -> Minimp4Result<()> {
+    let mut data = data;
+    while !data.is_empty() {
+        let nal_size = get_nal_size(data);
         if nal_size < 4 {
-            data_ptr = unsafe { data_ptr.add(1) };
-            data_size -= 1;
+            data = &data[1..];
             continue;
         }
-        unsafe { mp4_h26x_write_nal(mp4wr, data_ptr, nal_size as i32, (90000 / fps) as u32) };
-        data_ptr = unsafe { data_ptr.add(nal_size) };
-        data_size -= nal_size;
+        write_nal(
+            mp4wr,
+            assembler.as_deref_mut(),
+            progress,
+            stream,
+            clock,
+            &data[..nal_size],
+            duration,
+        )?;
+        data = &data[nal_size..];
     }
+    Ok(())
 }
 
-pub fn write_mp4_frame_with_duration(mp4wr: &mut mp4_h26x_writer_t, duration_90KHz: u32, data: &[u8]) {
-    let mut data_size = data.len();
-    let mut data_ptr = data.as_ptr();
+/// Writes `data`, a whole access unit in Annex B framing with any parameter
+/// sets ahead of it, lasting `duration_90khz` ticks of 90 kHz.
+#[cfg(feature = "aac")]
+pub fn write_mp4_access_unit(
+    mp4wr: &mut mp4_h26x_writer_t,
+    assembler: Option<&mut AccessUnitWriter>,
+    progress: &mut Progress,
+    stream: &mut StreamInfo,
+    clock: &mut TickClock,
+    duration_90khz: u32,
+    data: &[u8],
+) -> Minimp4Result<()> {
+    check_codec(mp4wr, data)?;
+    let duration = from_90khz(mp4wr, duration_90khz);
+    set_default_duration(mp4wr, clock.duration(duration));
+    write_nals(mp4wr, assembler, progress, stream, clock, duration, data)
+}
+
+pub fn write_mp4_frame_with_duration(
+    mp4wr: &mut mp4_h26x_writer_t,
+    mut assembler: Option<&mut AccessUnitWriter>,
+    progress: &mut Progress,
+    stream: &mut StreamInfo,
+    clock: &mut TickClock,
+    duration_90khz: u32,
+    data: &[u8],
+) -> Minimp4Result<()> {
+    check_codec(mp4wr, data)?;
+    // a frame may be preceded by its access unit delimiter, but is a single
+    // NAL unit otherwise
+    let mut nals = NalIter::new(data).skip_while(|nal| codec(mp4wr).classify(nal) == NalKind::Aud);
+    if nals.next().is_some() && nals.next().is_some() {
+        return Err(Minimp4Error::BadArguments);
+    }
+    let mut data = data;
+    let duration = from_90khz(mp4wr, duration_90khz);
+    set_default_duration(mp4wr, clock.duration(duration));
 
-    while data_size > 0 {
-        let buf = unsafe { std::slice::from_raw_parts_mut(data_ptr as *mut u8, data_size) };
-        let nal_size = get_nal_size(buf, data_size);
+    while !data.is_empty() {
+        let nal_size = get_nal_size(data);
         if nal_size < 4 {
-            data_ptr = unsafe { data_ptr.add(1) };
-            data_size -= 1;
+            data = &data[1..];
             continue;
         }
-        unsafe { mp4_h26x_write_nal(mp4wr, data_ptr, nal_size as i32, duration_90KHz) };
-        data_ptr = unsafe { data_ptr.add(nal_size) };
-        data_size -= nal_size;
-        assert_eq!(data_size, 0);
+        write_nal(
+            mp4wr,
+            assembler.as_deref_mut(),
+            progress,
+            stream,
+            clock,
+            &data[..nal_size],
+            duration,
+        )?;
+        data = &data[nal_size..];
     }
+    Ok(())
 }
 
-#[cfg(feature = "aac")]
-pub fn write_mp4_with_audio(
-    mp4wr: &mut mp4_h26x_writer_t,
-    fps: i32,
-    data: &[u8],
-    pcm: &[u8],
-    encoder_params: super::enc::EncoderParams,
-) {
+/// Adds an AAC track to `mux`, with `dsi` as its `AudioSpecificConfig`, and
+/// returns its id.
+pub(crate) fn add_aac_track(
+    mux: *mut super::c::MP4E_mux_t,
+    time_scale: u32,
+    default_duration: u32,
+    channel_count: u32,
+    dsi: &[u8],
+) -> Minimp4Result<i32> {
     use super::c::{
-        c_void, e_audio, MP4E_add_track, MP4E_put_sample, MP4E_set_dsi, MP4E_track_t, MP4E_track_t_AVConfig,
-        MP4E_track_t_AVConfig_AudioConfig, MP4E_SAMPLE_RANDOM_ACCESS, MP4_OBJECT_TYPE_AUDIO_ISO_IEC_14496_3,
+        e_audio, MP4E_add_track, MP4E_set_dsi, MP4E_track_t, MP4E_track_t_AVConfig, MP4E_track_t_AVConfig_AudioConfig,
+        MP4_OBJECT_TYPE_AUDIO_ISO_IEC_14496_3,
     };
 
-    let mut data_size = data.len();
-    let mut data_ptr = data.as_ptr();
-
-    let sample_rate = encoder_params.sample_rate;
-    let channel_count = encoder_params.channel_count;
-
-    let encoder = super::enc::Encoder::new(encoder_params).unwrap();
-    let info = encoder.info().unwrap();
-
     let language: [u8; 4] = [0x75, 0x6e, 0x64, 0x00]; // und\0
     let tr: MP4E_track_t = MP4E_track_t {
         object_type_indication: MP4_OBJECT_TYPE_AUDIO_ISO_IEC_14496_3,
         language,
         track_media_kind: e_audio,
-        time_scale: 90000,
-        default_duration: 0,
+        time_scale,
+        default_duration,
         u: MP4E_track_t_AVConfig {
             a: MP4E_track_t_AVConfig_AudioConfig {
                 channelcount: channel_count,
@@ -87,17 +433,154 @@ pub fn write_mp4_with_audio(
         },
     };
 
-    let mux = mp4wr.mux;
-    let audio_track_id = unsafe { MP4E_add_track(mux, &tr) };
+    let track_id = unsafe { MP4E_add_track(mux, &tr) };
+    if track_id < 0 {
+        return Err(Minimp4Error::check(track_id).unwrap_err());
+    }
+    Minimp4Error::check(unsafe { MP4E_set_dsi(mux, track_id, dsi.as_ptr() as *const c_void, dsi.len() as i32) })?;
+    Ok(track_id)
+}
 
-    unsafe {
-        MP4E_set_dsi(
-            mux,
-            audio_track_id,
-            info.confBuf.as_ptr() as *const c_void,
-            info.confSize.try_into().unwrap(),
-        )
-    };
+/// The AAC track fed by [`write_mp4_with_audio`], created once per muxer.
+#[cfg(feature = "aac")]
+pub(crate) struct AudioTrack {
+    encoder: super::enc::Encoder,
+    /// Parameters of `encoder`, to set up a new one on reset.
+    encoder_params: super::enc::EncoderParams,
+    track_id: i32,
+    sample_rate: u32,
+    channel_count: u32,
+    time_scale: u32,
+}
+
+#[cfg(feature = "aac")]
+impl AudioTrack {
+    /// Adds the track to `mux`, with the decoder specific info of a new encoder.
+    pub fn new(
+        mux: *mut super::c::MP4E_mux_t,
+        encoder_params: super::enc::EncoderParams,
+        time_scale: u32,
+    ) -> Result<Self, super::Error> {
+        let sample_rate = encoder_params.sample_rate;
+        let channel_count = encoder_params.channel_count;
+
+        // ADTS headers have no place in the samples
+        let encoder_params = super::enc::EncoderParams {
+            transport: super::enc::TransportFormat::Raw,
+            ..encoder_params
+        };
+        let encoder = super::enc::Encoder::new(encoder_params)?;
+        let info = encoder.info()?;
+
+        let dsi = &info.confBuf[..info.confSize as usize];
+        let track_id = add_aac_track(mux, time_scale, 1024 * time_scale / sample_rate, channel_count, dsi)?;
+
+        Ok(Self {
+            encoder,
+            encoder_params,
+            track_id,
+            sample_rate,
+            channel_count,
+            time_scale,
+        })
+    }
+
+    /// Samples of all channels, interleaved, making up one AAC frame.
+    pub fn frame_length(&self) -> usize {
+        super::enc::Encoder::FRAME_LENGTH as usize * self.channel_count as usize
+    }
+
+    /// Encodes `pcm`, one frame as of [`AudioTrack::frame_length`], and writes
+    /// the access unit the encoder returns, if any yet.
+    pub fn write_frame(&self, mux: *mut super::c::MP4E_mux_t, pcm: &[i16]) -> Result<(), super::Error> {
+        let mut output = [0u8; 2048];
+        let info = self.encoder.encode(pcm, &mut output)?;
+        Ok(self.put(mux, &output[..info.output_size])?)
+    }
+
+    /// Writes the frames the encoder still holds at the end of the input.
+    pub fn flush(&self, mux: *mut super::c::MP4E_mux_t) -> Result<(), super::Error> {
+        let mut output = [0u8; 2048];
+        loop {
+            let info = self.encoder.flush(&mut output)?;
+            if info.output_size == 0 {
+                return Ok(());
+            }
+            self.put(mux, &output[..info.output_size])?;
+        }
+    }
+
+    /// Flushes the encoder and replaces it with a new one of the same
+    /// parameters, whose decoder specific info is the one of the track.
+    pub fn reset(&mut self, mux: *mut super::c::MP4E_mux_t) -> Result<(), super::Error> {
+        self.flush(mux)?;
+        self.encoder = super::enc::Encoder::new(self.encoder_params)?;
+        Ok(())
+    }
+
+    /// Samples per channel the encoder output lags behind its input, in
+    /// ticks of the track.
+    pub fn delay(&self) -> u64 {
+        let delay = self.encoder.info().map_or(0, |info| info.nDelay);
+        delay as u64 * self.time_scale as u64 / self.sample_rate as u64
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn track_id(&self) -> i32 {
+        self.track_id
+    }
+
+    fn put(&self, mux: *mut super::c::MP4E_mux_t, frame: &[u8]) -> Minimp4Result<()> {
+        if frame.is_empty() {
+            return Ok(());
+        }
+        Minimp4Error::check(unsafe {
+            MP4E_put_sample(
+                mux,
+                self.track_id,
+                frame.as_ptr() as *const c_void,
+                frame.len() as i32,
+                (1024 * self.time_scale as u64 / self.sample_rate as u64) as i32,
+                MP4E_SAMPLE_RANDOM_ACCESS as i32,
+            )
+        })
+    }
+}
+
+/// Writes `data` interleaved with `pcm`, encoded to AAC. `window` is the
+/// interleave window of [`crate::Mp4Muxer::set_interleave_window`] in 90 kHz
+/// ticks.
+#[cfg(feature = "aac")]
+#[allow(clippy::too_many_arguments)]
+pub fn write_mp4_with_audio(
+    mp4wr: &mut mp4_h26x_writer_t,
+    mut assembler: Option<&mut AccessUnitWriter>,
+    progress: &mut Progress,
+    stream: &mut StreamInfo,
+    clock: &mut TickClock,
+    fps: i32,
+    data: &[u8],
+    pcm: &[u8],
+    audio: &AudioTrack,
+    window: u64,
+) -> Result<(), super::Error> {
+    check_codec(mp4wr, data)?;
+    let mut data = data;
+    let video_duration = from_fps(mp4wr, fps);
+    set_default_duration(mp4wr, clock.duration(video_duration));
+
+    let mux = mp4wr.mux;
+    let AudioTrack {
+        ref encoder,
+        encoder_params: _,
+        track_id: audio_track_id,
+        sample_rate,
+        channel_count,
+        time_scale: audio_time_scale,
+    } = *audio;
 
     let length: u64 = if channel_count == 1 { 1024 } else { 2048 };
     let mut input_buffer = vec![0i16; length as usize];
@@ -111,58 +594,70 @@ pub fn write_mp4_with_audio(
     let mut ats: u64 = 0;
 
     let in_args_num_in_samples = length;
-    let mut pcm_ptr = pcm.as_ptr();
+    // the input has no alignment guarantee, and is padded with silence once
+    // the video outlasts it
+    let mut pcm = pcm.chunks_exact(2).map(|b| i16::from_ne_bytes([b[0], b[1]]));
 
-    while data_size > 0 {
-        let buf = unsafe { std::slice::from_raw_parts_mut(data_ptr as *mut u8, data_size) };
-        let nal_size = get_nal_size(buf, data_size);
-        if nal_size < 4 {
-            data_ptr = unsafe { data_ptr.add(1) };
-            data_size -= 1;
-            continue;
-        }
-        unsafe { mp4_h26x_write_nal(mp4wr, data_ptr, nal_size as i32, (90000 / fps) as u32) };
-        data_ptr = unsafe { data_ptr.add(nal_size) };
-        data_size -= nal_size;
-
-        ts += 90000 / fps as u64;
-        while ats < ts {
+    // audio is written up to the video time whenever the video got `window`
+    // ahead of the last interleaving point
+    let mut write_audio = |until: u64| {
+        while ats < until {
             let bytes_to_read = std::cmp::min(total_samples, in_args_num_in_samples);
-            let bytes_read = bytes_to_read * 2; // 2 bytes per i16
-            let pcm_buf =
-                unsafe { std::slice::from_raw_parts(pcm_ptr as *const i16, bytes_to_read.try_into().unwrap()) };
             // Copy PCM data into input buffer
-            input_buffer[..bytes_to_read as usize].copy_from_slice(pcm_buf);
-            pcm_ptr = unsafe { pcm_ptr.add(bytes_read.try_into().unwrap()) };
+            for value in &mut input_buffer[..bytes_to_read as usize] {
+                *value = pcm.next().unwrap_or(0);
+            }
 
             // if total_samples < in_args_num_in_samples as u64 {
             //     total_samples = pcm_size ;
             // }
 
             // Encode audio data using AAC encoder
-            match encoder.encode(&input_buffer[..bytes_to_read as usize], &mut output_buffer) {
-                Ok(encoding_info) => {
-                    // Write encoded audio data to output buffer
-                    let buf = &output_buffer[..encoding_info.output_size];
-                    sample += 1024;
-                    // total_samples -= bytes_to_read;
-                    ats = sample * 90000 / sample_rate as u64;
-                    unsafe {
-                        MP4E_put_sample(
-                            mux,
-                            audio_track_id,
-                            buf.as_ptr() as *mut c_void,
-                            encoding_info.output_size.try_into().unwrap(),
-                            (1024 * 90000 / sample_rate as usize).try_into().unwrap(),
-                            MP4E_SAMPLE_RANDOM_ACCESS.try_into().unwrap(),
-                        )
-                    };
-                }
-                Err(e) => {
-                    println!("encode error:{}", e);
-                    break;
-                }
-            }
+            let encoding_info = encoder.encode(&input_buffer[..bytes_to_read as usize], &mut output_buffer)?;
+            // Write encoded audio data to output buffer
+            let buf = &output_buffer[..encoding_info.output_size];
+            sample += 1024;
+            // total_samples -= bytes_to_read;
+            ats = sample * 90000 / sample_rate as u64;
+            Minimp4Error::check(unsafe {
+                MP4E_put_sample(
+                    mux,
+                    audio_track_id,
+                    buf.as_ptr() as *mut c_void,
+                    encoding_info.output_size.try_into().unwrap(),
+                    (1024 * audio_time_scale as usize / sample_rate as usize)
+                        .try_into()
+                        .unwrap(),
+                    MP4E_SAMPLE_RANDOM_ACCESS.try_into().unwrap(),
+                )
+            })?;
+        }
+        Ok::<_, super::Error>(())
+    };
+    let mut interleaved_ts: u64 = 0;
+
+    while !data.is_empty() {
+        let nal_size = get_nal_size(data);
+        if nal_size < 4 {
+            data = &data[1..];
+            continue;
+        }
+        write_nal(
+            mp4wr,
+            assembler.as_deref_mut(),
+            progress,
+            stream,
+            clock,
+            &data[..nal_size],
+            video_duration,
+        )?;
+        data = &data[nal_size..];
+
+        ts += 90000 / fps as u64;
+        if ts - interleaved_ts >= window {
+            interleaved_ts = ts;
+            write_audio(ts)?;
         }
     }
+    write_audio(ts)
 }
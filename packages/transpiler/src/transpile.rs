@@ -242,5 +242,18 @@ pub use crate::c::environment::types::*;
     src = replace_identifier(src, "minimp4_printf", "minimp4_printf!", 0);
     src = replace_identifier(src, "minimp4_sprintf", "minimp4_sprintf!", 0);
     src = replace_identifier(src, "minimp4_sscanf", "minimp4_sscanf!", 0);
+    // internals of minimp4 the safe wrapper calls itself
+    src = src.replace(
+        "\nunsafe extern \"C\" fn mp4e_flush_index(",
+        "\npub unsafe extern \"C\" fn mp4e_flush_index(",
+    );
+    src = src.replace(
+        "\nunsafe extern \"C\" fn write_pending_data(",
+        "\npub unsafe extern \"C\" fn write_pending_data(",
+    );
+    src = src.replace(
+        "\nunsafe extern \"C\" fn minimp4_vector_put(",
+        "\npub unsafe extern \"C\" fn minimp4_vector_put(",
+    );
     write(output, src).unwrap();
 }